@@ -1,6 +1,7 @@
-use std::path::PathBuf;
-
+#[cfg(feature = "tree-sitter")]
 fn main() {
+    use std::path::PathBuf;
+
     let dir: PathBuf = ["..", "tree-sitter-lambda", "src"].iter().collect();
 
     cc::Build::new()
@@ -8,3 +9,6 @@ fn main() {
         .file(dir.join("parser.c"))
         .compile("tree-sitter-lambda");
 }
+
+#[cfg(not(feature = "tree-sitter"))]
+fn main() {}
@@ -0,0 +1,27 @@
+use std::io::{self, BufRead, Write};
+
+use lambda::repl::Repl;
+
+fn main() -> io::Result<()> {
+    let mut repl = Repl::new();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+    loop {
+        write!(stdout, "{}> ", repl.calculus())?;
+        stdout.flush()?;
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            writeln!(stdout)?;
+            return Ok(());
+        }
+        if matches!(line.trim(), ":quit" | ":q") {
+            return Ok(());
+        }
+        match repl.execute(&line) {
+            Ok(Some(out)) => writeln!(stdout, "{}", out)?,
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
@@ -1,9 +1,16 @@
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 pub mod parser;
 
 use super::*;
+use crate::nbe::{self, Core};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 #[derive(Debug, Clone, Eq)]
 pub enum Term {
@@ -211,51 +218,58 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::Abstraction(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Abstraction(
-                        v.to_owned(),
-                        ty.as_ref().map(|ty| Box::new(ty.as_ref().clone())),
-                        Box::new(t2),
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = ty.as_ref().map(|ty| Box::new(ty.as_ref().clone()));
+                    Some((
+                        Self::Abstraction(v.to_owned(), ty, Box::new(t2)),
+                        r.within(1),
                     ))
                 } else {
-                    ty.as_ref().and_then(|ty| ty.beta_reduce_lazy()).map(|ty2| {
-                        Self::Abstraction(
-                            v.to_owned(),
-                            Some(Box::new(ty2)),
-                            Box::new(t.as_ref().clone()),
+                    let ty = ty.as_ref()?;
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (
+                            Self::Abstraction(v.to_owned(), Some(Box::new(ty2)), t),
+                            r.within(0),
                         )
                     })
                 }
             }
             Self::Product(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Product(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
-                    ))
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((Self::Product(v.to_owned(), ty, Box::new(t2)), r.within(1)))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Product(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Product(v.to_owned(), Box::new(ty2), t), r.within(0))
                     })
                 }
             }
-            Self::Annotation(e, _ty) => Some(e.as_ref().clone()),
+            // Annotations are erased along with β-redexes.
+            Self::Annotation(e, _ty) => Some((e.as_ref().clone(), Redex::root(Rule::Beta))),
             Self::Variable(_) | Self::Sort(_) => None,
         }
     }
@@ -269,6 +283,84 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(self.clone(), vec![Self::beta_reduce_lazy_with_redex])
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
+    /// The same normal form as [`Self::evaluate`], found by normalisation by evaluation
+    /// rather than by repeatedly reducing the whole term. If the term is well-typed, this will
+    /// halt.
+    pub fn normalise(&self) -> Self {
+        self.normalise_with_fuel(usize::MAX)
+            .expect("the fuel should be unlimited")
+    }
+
+    /// Like [`Self::normalise`], but giving up once the fuel runs out, with this term as the
+    /// partial term.
+    pub fn normalise_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        let core = self.to_core(&mut Vec::new());
+        let normal_form = nbe::normalise(&core, false, fuel.into(), self)?;
+        Ok(Self::from_core(&normal_form, &mut Vec::new()))
+    }
+
+    fn to_core(&self, scope: &mut Vec<String>) -> Core {
+        match self {
+            Self::Variable(x) => Core::variable(x, scope),
+            Self::Sort(s) => Core::Sort(*s),
+            Self::Abstraction(x, ty, t) => {
+                let ty = ty.as_ref().map(|ty| Box::new(ty.to_core(scope)));
+                scope.push(x.clone());
+                let t = t.to_core(scope);
+                scope.pop();
+                Core::Abstraction(x.clone(), ty, Box::new(t))
+            }
+            Self::Product(x, ty, t) => {
+                let ty = Box::new(ty.to_core(scope));
+                scope.push(x.clone());
+                let t = t.to_core(scope);
+                scope.pop();
+                Core::Product(x.clone(), ty, Box::new(t))
+            }
+            Self::Application(t, u) => {
+                Core::Application(Box::new(t.to_core(scope)), Box::new(u.to_core(scope)))
+            }
+            // Annotations are erased along with β-redexes.
+            Self::Annotation(e, _) => e.to_core(scope),
+        }
+    }
+
+    fn from_core(t: &Core, scope: &mut Vec<String>) -> Self {
+        match t {
+            Core::Bound(i) => Self::Variable(Core::name(*i, scope)),
+            Core::Free(x) => Self::Variable(x.clone()),
+            Core::Sort(s) => Self::Sort(*s),
+            Core::Abstraction(x, ty, t) => {
+                let ty = ty.as_ref().map(|ty| Box::new(Self::from_core(ty, scope)));
+                scope.push(x.clone());
+                let t = Self::from_core(t, scope);
+                scope.pop();
+                Self::Abstraction(x.clone(), ty, Box::new(t))
+            }
+            Core::Product(x, ty, t) => {
+                let ty = Box::new(Self::from_core(ty, scope));
+                scope.push(x.clone());
+                let t = Self::from_core(t, scope);
+                scope.pop();
+                Self::Product(x.clone(), ty, Box::new(t))
+            }
+            Core::Application(t, u) => Self::Application(
+                Box::new(Self::from_core(t, scope)),
+                Box::new(Self::from_core(u, scope)),
+            ),
+        }
+    }
+
     // TODO: make sure types are evaluated when necessary.
     /// The type of this term in the given environment, if the term is well-typed and the type is inferrable.
     pub fn synthesise_type_in(&self, env: &Environment) -> Result<Term, TypeError> {
@@ -283,7 +375,7 @@ impl Term {
             Self::Application(t, u) => match t.synthesise_type_in(env)? {
                 Term::Product(v, a1, b) => {
                     u.check_type_in(env, &a1)?;
-                    Ok(b.substitute(&v, u).evaluate())
+                    Ok(b.substitute(&v, u).normalise())
                 }
                 ty => Err(TypeError::NotFunType {
                     term: t.as_ref().clone(),
@@ -327,7 +419,7 @@ impl Term {
                     return Err(TypeError::NotType(ty.as_ref().clone()));
                 }
                 e.check_type_in(env, ty)?;
-                return Ok(ty.as_ref().clone());
+                Ok(ty.as_ref().clone())
             }
             Self::Sort(Sort::Universal) => Err(TypeError::NotTypeable(self.clone())),
         }
@@ -335,20 +427,17 @@ impl Term {
 
     /// Assert that this term inhabits the given type.
     pub fn check_type_in(&self, env: &Environment, ty: &Term) -> Result<(), TypeError> {
-        match self {
-            Self::Abstraction(x, None, t) => {
-                if let Term::Product(_, a, b) = ty {
-                    let mut inner_env = env.clone();
-                    inner_env.push((x.to_owned(), a.as_ref().clone()));
-                    return t.check_type_in(&inner_env, b);
-                } else {
-                    return Err(TypeError::NotFunType {
-                        term: self.clone(),
-                        ty: ty.clone(),
-                    });
-                }
+        if let Self::Abstraction(x, None, t) = self {
+            if let Term::Product(_, a, b) = ty {
+                let mut inner_env = env.clone();
+                inner_env.push((x.to_owned(), a.as_ref().clone()));
+                return t.check_type_in(&inner_env, b);
+            } else {
+                return Err(TypeError::NotFunType {
+                    term: self.clone(),
+                    ty: ty.clone(),
+                });
             }
-            _ => {}
         }
         let actual = self.synthesise_type_in(env)?;
         if ty == &actual {
@@ -372,6 +461,91 @@ impl Term {
     }
 }
 
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let leaf = prop_oneof![
+        crate::arb_var().prop_map(Term::Variable),
+        any::<Sort>().prop_map(Term::Sort)
+    ];
+    leaf.prop_recursive(16, 256, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (
+                crate::arb_var(),
+                proptest::option::of(inner.clone()),
+                inner.clone()
+            )
+                .prop_map(|(x, t, b)| Term::Abstraction(
+                    x,
+                    t.map(Box::new),
+                    Box::new(b)
+                )),
+            (crate::arb_var(), inner.clone(), inner.clone()).prop_map(|(x, t, b)| Term::Product(
+                x,
+                Box::new(t),
+                Box::new(b)
+            )),
+            (inner.clone(), inner).prop_map(|(e, t)| Term::Annotation(Box::new(e), Box::new(t))),
+        ]
+    })
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..)
+            | Self::Application(..)
+            | Self::Product(..)
+            | Self::Annotation(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, Some(t), _), 0)
+            | (Self::Product(_, t, _), 0)
+            | (Self::Application(t, _), 0)
+            | (Self::Annotation(t, _), 0) => Some(t),
+            (Self::Abstraction(_, _, u), 1)
+            | (Self::Product(_, _, u), 1)
+            | (Self::Application(_, u), 1)
+            | (Self::Annotation(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, _, t), 0) => Self::Abstraction(x.clone(), Some(child), t.clone()),
+            (Self::Abstraction(x, ty, _), 1) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Product(x, _, t), 0) => Self::Product(x.clone(), child, t.clone()),
+            (Self::Product(x, ty, _), 1) => Self::Product(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            (Self::Annotation(_, ty), 0) => Self::Annotation(child, ty.clone()),
+            (Self::Annotation(e, _), 1) => Self::Annotation(e.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
 impl PartialEq for Term {
     /// Alpha equivalence.
     fn eq(&self, other: &Self) -> bool {
@@ -379,19 +553,41 @@ impl PartialEq for Term {
     }
 }
 
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::NotType(t) => write!(f, "{} is not a type", t),
+            Self::NotTypeable(t) => write!(f, "{} has no type", t),
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::CouldNotInfer(t) => write!(f, "couldn't infer the type of {}", t),
+        }
+    }
+}
+
+impl error::Error for TypeError {}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Sort(Sort::Type) => write!(f, "*"),
-            Self::Sort(Sort::Universal) => write!(f, "□"),
+            Self::Sort(Sort::Universal) => write!(f, "{}", n.universe()),
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, Some(ty), t) => write!(f, "λ{}: {}. {}", x, ty, t),
-            Self::Abstraction(x, None, t) => write!(f, "λ{}. {}", x, t),
+            Self::Abstraction(x, Some(ty), t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
+            Self::Abstraction(x, None, t) => write!(f, "{}{}. {}", n.lambda(), x, n.show(t)),
             Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
                 write_term(ty, f)?;
-                write!(f, " -> {}", t)
+                write!(f, " -> {}", n.show(t))
             }
-            Self::Product(x, ty, t) => write!(f, "Π{}: {}. {}", x, ty, t),
+            Self::Product(x, ty, t) => write!(f, "{}{}: {}. {}", n.pi(), x, n.show(ty), n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -406,21 +602,81 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Sort(Sort::Type) => pretty::atom("*"),
+            Self::Sort(Sort::Universal) => pretty::atom(n.universe()),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, Some(ty), t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Abstraction(x, None, t) => {
+                let mut bound = vec![x.as_str()];
+                let mut body = t.as_ref();
+                // The tree-sitter grammar doesn't abbreviate nested abstractions.
+                if cfg!(not(feature = "tree-sitter")) {
+                    while let Self::Abstraction(y, None, u) = body {
+                        bound.push(y);
+                        body = u;
+                    }
+                }
+                let head = Doc::text(format!("{}{}.", n.lambda(), bound.join(" ")));
+                pretty::binder(head, body.to_doc(n))
+            }
+            Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
+                pretty::infix(at(ty.as_ref(), n, Prec::Application), " ->", t.to_doc(n))
+            }
+            Self::Product(x, ty, t) => {
+                let head = Doc::text(format!("{}{}: ", n.pi(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+            Self::Annotation(e, ty) => {
+                pretty::infix(at(e.as_ref(), n, Prec::Application), ":", ty.to_doc(n))
+            }
+        }
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn annotation_checks_unannotated_abstraction() {
+        let id = parser::parse("(λa. λx. x): Πa: *. a -> a").unwrap();
+        let ty = parser::parse("Πa: *. a -> a").unwrap();
+        assert_eq!(id.synthesise_type_closed().unwrap(), ty);
+    }
 }
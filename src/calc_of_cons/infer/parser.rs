@@ -1,209 +1,226 @@
-use std::{error, fmt, str::Utf8Error};
-
-use tree_sitter::{Language, Node, Parser, TreeCursor};
+#[cfg(feature = "tree-sitter")]
+use tree_sitter::{Node, TreeCursor};
 
 use super::*;
-
-extern "C" {
-    fn tree_sitter_lambda() -> Language;
-}
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum ParseError {
-    Encoding(Utf8Error),
-    Other(String),
-}
-
-impl ParseError {
-    fn msg(s: impl Into<String>) -> Self {
-        Self::Other(s.into())
+use crate::parsing;
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+use crate::parsing::descent::{Descent, Grammar};
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+use crate::parsing::lexer::Token;
+#[cfg(feature = "tree-sitter")]
+use crate::parsing::tree::{unexpected, Syntax, Walker};
+use crate::span::SpanTable;
+
+pub use crate::parsing::ParseError;
+
+struct CalcOfCons;
+
+#[cfg(feature = "tree-sitter")]
+impl Syntax for CalcOfCons {
+    type Term = Term;
+
+    fn hole(name: String) -> Term {
+        Term::Variable(name)
+    }
+
+    fn node<'a>(
+        w: &mut Walker<'a, Self>,
+        node: Node<'a>,
+        cursor: &mut TreeCursor<'a>,
+    ) -> Result<Term, ParseError> {
+        match node.kind() {
+            "sort" => {
+                let text = w.text(&node)?;
+                Ok(Term::Sort(match text {
+                    "*" => Sort::Type,
+                    "□" => Sort::Universal,
+                    _ => return Err(ParseError::at(&node, format!("unexpected sort {}", text))),
+                }))
+            }
+            "variable" => Ok(Term::Variable(w.identifier(cursor)?)),
+            "abstraction" => {
+                w.enter(&node, cursor)?;
+                let bound = w.identifier(cursor)?;
+                w.next(&node, cursor, "type or body")?;
+                let ty = match w.field(cursor)? {
+                    Some("bind_type") => {
+                        let ty = Box::new(w.child(0, cursor)?);
+                        w.next(&node, cursor, "body")?;
+                        Some(ty)
+                    }
+                    Some("body") => None,
+                    _ => return Err(unexpected(cursor, "abstraction")),
+                };
+                let body = Box::new(w.child(1, cursor)?);
+                w.leave(cursor);
+                Ok(Term::Abstraction(bound, ty, body))
+            }
+            "product" => {
+                w.enter(&node, cursor)?;
+                let bound = match w.field(cursor)? {
+                    Some("input_name") => {
+                        let bound = w.identifier(cursor)?;
+                        w.next(&node, cursor, "input type")?;
+                        Some(bound)
+                    }
+                    Some("input") => None,
+                    _ => return Err(unexpected(cursor, "product")),
+                };
+                let ty = Box::new(w.child(0, cursor)?);
+                w.next(&node, cursor, "output type")?;
+                let body = Box::new(w.child(1, cursor)?);
+                w.leave(cursor);
+                let bound = bound.unwrap_or_else(|| fresh_var(&body.free_vars()));
+                Ok(Term::Product(bound, ty, body))
+            }
+            "application" => {
+                let (func, arg) = w.pair(&node, cursor, "RHS")?;
+                Ok(Term::Application(Box::new(func), Box::new(arg)))
+            }
+            "annotation" => {
+                let (expr, ty) = w.pair(&node, cursor, "type")?;
+                Ok(Term::Annotation(Box::new(expr), Box::new(ty)))
+            }
+            _ => Err(ParseError::at(
+                &node,
+                format!("unknown term type {}", node.kind()),
+            )),
+        }
     }
 }
 
-impl From<Utf8Error> for ParseError {
-    fn from(value: Utf8Error) -> Self {
-        Self::Encoding(value)
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+impl Grammar for CalcOfCons {
+    type Term = Term;
+
+    fn hole(name: String) -> Term {
+        Term::Variable(name)
     }
-}
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Other(s) => write!(f, "parse error: {}", s),
-            Self::Encoding(e) => e.fmt(f),
+    fn term(d: &mut Descent<'_, Self>) -> Result<Term, ParseError> {
+        if d.tokens.eat(&Token::Lambda) {
+            return abstraction(d);
+        }
+        if d.tokens.eat(&Token::Pi) {
+            let bound = d.tokens.identifier()?;
+            d.tokens.expect(&Token::Colon)?;
+            let ty = d.child(0, Self::term)?;
+            d.tokens.expect(&Token::Dot)?;
+            let body = d.child(1, Self::term)?;
+            return Ok(Term::Product(bound, Box::new(ty), Box::new(body)));
+        }
+        let mark = d.mark();
+        let t = application(d)?;
+        let infix = d.tokens.peek().cloned();
+        if !matches!(infix, Some(Token::Arrow | Token::Colon)) {
+            return Ok(t);
+        }
+        // The span of the left operand must be recorded before the operator is consumed.
+        d.wrap(mark);
+        d.tokens.skip();
+        let rhs = d.child(1, Self::term)?;
+        if infix == Some(Token::Arrow) {
+            let bound = fresh_var(&rhs.free_vars());
+            Ok(Term::Product(bound, Box::new(t), Box::new(rhs)))
+        } else {
+            Ok(Term::Annotation(Box::new(t), Box::new(rhs)))
         }
     }
 }
 
-impl error::Error for ParseError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match self {
-            Self::Encoding(e) => Some(e),
-            Self::Other(_) => None,
-        }
-    }
+/// The rest of an abstraction after the `λ`, where `λx y. t` abbreviates `λx. λy. t`.
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn abstraction(d: &mut Descent<'_, CalcOfCons>) -> Result<Term, ParseError> {
+    let bound = d.tokens.identifier()?;
+    let ty = if d.tokens.eat(&Token::Colon) {
+        Some(Box::new(d.child(0, CalcOfCons::term)?))
+    } else {
+        None
+    };
+    let body = if ty.is_none() && d.tokens.at_identifier() {
+        d.child(1, abstraction)?
+    } else {
+        d.tokens.expect(&Token::Dot)?;
+        d.child(1, CalcOfCons::term)?
+    };
+    Ok(Term::Abstraction(bound, ty, Box::new(body)))
 }
 
-pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
-    let mut parser = Parser::new();
-    let language = unsafe { tree_sitter_lambda() };
-    parser
-        .set_language(language)
-        .expect("language should be compiled with a compatible version of tree-sitter");
-
-    let source = source.as_ref();
-
-    let tree = parser.parse(source, None).expect("parser should succeed");
-    let mut cursor = tree.walk();
-    let node = advance(&mut cursor)?.ok_or(ParseError::msg("missing root"))?;
-    if node.kind() != "source" {
-        return Err(ParseError::msg(format!(
-            "expected root node but got {}",
-            node.kind()
-        )));
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction or product.
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn application(d: &mut Descent<'_, CalcOfCons>) -> Result<Term, ParseError> {
+    let mark = d.mark();
+    let mut t = atom(d)?;
+    loop {
+        let arg: fn(&mut Descent<'_, CalcOfCons>) -> _ = match d.tokens.peek() {
+            Some(Token::Lambda | Token::Pi) => CalcOfCons::term,
+            Some(Token::LParen | Token::Star | Token::Box) => atom,
+            _ if d.tokens.at_identifier() => atom,
+            _ => return Ok(t),
+        };
+        d.wrap(mark);
+        t = Term::Application(Box::new(t), Box::new(d.child(1, arg)?));
     }
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("root node should have children"));
-    }
-    walk_term(source, &mut cursor)
 }
 
-fn walk_identifier<'a>(
-    source: &'a [u8],
-    cursor: &mut TreeCursor<'a>,
-) -> Result<String, ParseError> {
-    let node = advance(cursor)?.ok_or(ParseError::msg("missing variable"))?;
-    if node.kind() != "variable" {
-        return Err(ParseError::msg(format!(
-            "expected variable but got {}",
-            node.kind()
-        )));
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn atom(d: &mut Descent<'_, CalcOfCons>) -> Result<Term, ParseError> {
+    if d.tokens.eat(&Token::LParen) {
+        let t = CalcOfCons::term(d)?;
+        d.tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if d.tokens.eat(&Token::Star) {
+        Ok(Term::Sort(Sort::Type))
+    } else if d.tokens.eat(&Token::Box) {
+        Ok(Term::Sort(Sort::Universal))
+    } else if d.tokens.at_identifier() {
+        Ok(Term::Variable(d.tokens.identifier()?))
+    } else {
+        Err(d.tokens.unexpected("a term"))
     }
-    let text = node.utf8_text(source)?.to_owned();
-    Ok(text)
 }
 
-fn walk_term<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    let node = advance(cursor)?.ok_or(ParseError::msg("missing term"))?;
-    match node.kind() {
-        "sort" => {
-            let text = node.utf8_text(source)?;
-            Ok(Term::Sort(match text {
-                "*" => Sort::Type,
-                "□" => Sort::Universal,
-                _ => return Err(ParseError::msg(format!("unexpected sort {}", text))),
-            }))
-        }
-        "variable" => Ok(Term::Variable(walk_identifier(source, cursor)?)),
-        "abstraction" => Ok(walk_abstraction(source, cursor)?),
-        "product" => Ok(walk_product(source, cursor)?),
-        "application" => Ok(walk_application(source, cursor)?),
-        "annotation" => Ok(walk_annotation(source, cursor)?),
-        _ => Err(ParseError::msg(format!(
-            "unknown term type {}",
-            node.kind()
-        ))),
-    }
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    parse_with_spans(source).map(|(term, _)| term)
 }
 
-fn walk_application<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("application should have children"));
-    }
-    let func = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("application should have RHS"));
-    }
-    let arg = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Application(func, arg))
+/// Parse a term, also returning the source span of each of its subterms.
+pub fn parse_with_spans(source: impl AsRef<[u8]>) -> Result<(Term, SpanTable), ParseError> {
+    parsing::parse_with_spans::<CalcOfCons>(source.as_ref())
 }
 
-fn walk_annotation<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("annotation should have children"));
-    }
-    let expr = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("annotation should have type"));
-    }
-    let ty = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Annotation(expr, ty))
+/// Parse a term even if it has syntax errors, returning every error in the source.
+///
+/// Subterms that could not be parsed are replaced by holes, variables named `?0`, `?1`, …
+/// which cannot be written in the source.
+pub fn parse_recovering(source: impl AsRef<[u8]>) -> (Term, Vec<ParseError>) {
+    parsing::parse_recovering::<CalcOfCons>(source.as_ref())
 }
 
-fn walk_product<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("product should have children"));
-    }
-    advance(cursor)?;
-    let bound = if matches!(cursor.field_name(), Some("input_name")) {
-        let res = Some(walk_identifier(source, cursor)?);
-        if !cursor.goto_next_sibling() {
-            return Err(ParseError::msg("product should have input type"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
         }
-        res
-    } else if matches!(cursor.field_name(), Some("input")) {
-        None
-    } else {
-        return Err(ParseError::msg(format!(
-            "unexpected node in product: {}",
-            cursor.node().to_sexp()
-        )));
-    };
-    let ty = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("product should have output type"));
-    }
-    let body = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    let bound = bound.unwrap_or_else(|| fresh_var(&body.free_vars()));
-    Ok(Term::Product(bound, ty, body))
-}
 
-fn walk_abstraction<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("abstraction should have children"));
-    }
-    let bound = walk_identifier(source, cursor)?;
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("not enough children for abstraction"));
-    }
-    advance(cursor)?;
-    let ty = if matches!(cursor.field_name(), Some("bind_type")) {
-        let res = Some(Box::new(walk_term(source, cursor)?));
-        if !cursor.goto_next_sibling() {
-            return Err(ParseError::msg("abstraction should have body"));
+        #[test]
+        #[cfg(not(feature = "tree-sitter"))]
+        fn pretty_output_parses_correctly(f in any::<Term>(), width in 1usize..80) {
+            let s = crate::pretty::Pretty::pretty(&f, width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
         }
-        res
-    } else if matches!(cursor.field_name(), Some("body")) {
-        None
-    } else {
-        return Err(ParseError::msg(format!(
-            "unexpected node in abstraction: {}",
-            cursor.node().to_sexp()
-        )));
-    };
-    let body = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Abstraction(bound, ty, body))
-}
 
-fn advance<'a>(cursor: &mut TreeCursor<'a>) -> Result<Option<Node<'a>>, ParseError> {
-    while cursor.node().is_extra() || !cursor.node().is_named() {
-        if !cursor.goto_next_sibling() {
-            return Ok(None);
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse(s);
         }
     }
-    let node = cursor.node();
-    if node.is_error() {
-        Err(ParseError::msg(format!("parse error: {}", node.to_sexp())))
-    } else {
-        Ok(Some(node))
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 }
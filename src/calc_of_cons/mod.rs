@@ -1,5 +1,5 @@
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
 
 pub mod infer;
 pub mod parser;
@@ -11,13 +11,19 @@ use proptest::prelude::*;
 use proptest_derive::Arbitrary;
 
 use super::*;
+use crate::locally_nameless::{Ln, LocallyNameless, Scope};
+use crate::nbe::{self, Core};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::strategy::{Form, Reduce};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 /// The possible kinds.
 ///
 /// `Sort::Type` and `Sort::Universal` are sometimes referred to as
 /// Prop and Type respectively.
 #[cfg_attr(test, derive(Arbitrary))]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Sort {
     Type,
     Universal,
@@ -32,6 +38,25 @@ pub enum Term {
     Product(String, Box<Term>, Box<Term>),
 }
 
+/// Why a term is ill-typed.
+#[derive(Debug, Clone)]
+pub enum TypeError {
+    UndefinedVar(String),
+    /// A term is applied to an argument, but its type isn't a product.
+    NotFunType {
+        term: Term,
+        ty: Term,
+    },
+    Mismatch {
+        expected: Term,
+        actual: Term,
+    },
+    /// A term used as a type, whose type isn't a sort.
+    NotType(Term),
+    /// A term with no type, such as □.
+    NotTypeable(Term),
+}
+
 pub type Environment = Vec<(String, Term)>;
 pub type EnvRef<'a> = &'a [(String, Term)];
 
@@ -111,19 +136,7 @@ impl Term {
     }
 
     pub fn alpha_equivalent(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Sort(x), Self::Sort(y)) => x == y,
-            (Self::Variable(x), Self::Variable(y)) => x == y,
-            (Self::Abstraction(x, ty1, t), Self::Abstraction(y, ty2, u))
-            | (Self::Product(x, ty1, t), Self::Product(y, ty2, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                ty1.alpha_equivalent(ty2) && t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            (Self::Application(t1, u1), Self::Application(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            _ => false,
-        }
+        self.to_locally_nameless() == other.to_locally_nameless()
     }
 
     /// Substitute the given term avoiding capture.
@@ -172,43 +185,52 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::Abstraction(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Abstraction(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((
+                        Self::Abstraction(v.to_owned(), ty, Box::new(t2)),
+                        r.within(1),
                     ))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Abstraction(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (
+                            Self::Abstraction(v.to_owned(), Box::new(ty2), t),
+                            r.within(0),
+                        )
                     })
                 }
             }
             Self::Product(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Product(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
-                    ))
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((Self::Product(v.to_owned(), ty, Box::new(t2)), r.within(1)))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Product(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Product(v.to_owned(), Box::new(ty2), t), r.within(0))
                     })
                 }
             }
@@ -219,48 +241,57 @@ impl Term {
     /// Eta-convert the left-outermost thing if one exists.
     /// This does not check the type.
     pub fn eta_convert_lazy(&self) -> Option<Self> {
+        self.eta_convert_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_convert_lazy`], also returning the redex contracted.
+    pub fn eta_convert_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(v, ty, t) => {
                 if let Self::Application(f, a) = t.as_ref() {
                     if let Self::Variable(x) = a.as_ref() {
                         if x == v && !f.free_vars().contains(v) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                if let Some(t2) = t.eta_convert_lazy() {
-                    Some(Self::Abstraction(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
+                if let Some((t2, r)) = t.eta_convert_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((
+                        Self::Abstraction(v.to_owned(), ty, Box::new(t2)),
+                        r.within(1),
                     ))
                 } else {
-                    ty.eta_convert_lazy().map(|ty2| {
-                        Self::Abstraction(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.eta_convert_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (
+                            Self::Abstraction(v.to_owned(), Box::new(ty2), t),
+                            r.within(0),
+                        )
                     })
                 }
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_convert_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_convert_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_convert_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_convert_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::Product(v, ty, t) => {
-                if let Some(t2) = t.eta_convert_lazy() {
-                    Some(Self::Product(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
-                    ))
+                if let Some((t2, r)) = t.eta_convert_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((Self::Product(v.to_owned(), ty, Box::new(t2)), r.within(1)))
                 } else {
-                    ty.eta_convert_lazy().map(|ty2| {
-                        Self::Product(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.eta_convert_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Product(v.to_owned(), Box::new(ty2), t), r.within(0))
                     })
                 }
             }
@@ -281,67 +312,158 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_convert_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
+    /// The same normal form as [`Self::evaluate`], found by normalisation by evaluation
+    /// rather than by repeatedly reducing the whole term. If the term is well-typed, this will
+    /// halt.
+    pub fn normalise(&self) -> Self {
+        self.normalise_with_fuel(usize::MAX)
+            .expect("the fuel should be unlimited")
+    }
+
+    /// Like [`Self::normalise`], but giving up once the fuel runs out, with this term as the
+    /// partial term.
+    pub fn normalise_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        let core = self.to_core(&mut Vec::new());
+        let normal_form = nbe::normalise(&core, true, fuel.into(), self)?;
+        Ok(Self::from_core(&normal_form, &mut Vec::new()))
+    }
+
+    fn to_core(&self, scope: &mut Vec<String>) -> Core {
+        match self {
+            Self::Variable(x) => Core::variable(x, scope),
+            Self::Sort(s) => Core::Sort(*s),
+            Self::Abstraction(x, ty, t) => {
+                let ty = Some(Box::new(ty.to_core(scope)));
+                scope.push(x.clone());
+                let t = t.to_core(scope);
+                scope.pop();
+                Core::Abstraction(x.clone(), ty, Box::new(t))
+            }
+            Self::Product(x, ty, t) => {
+                let ty = Box::new(ty.to_core(scope));
+                scope.push(x.clone());
+                let t = t.to_core(scope);
+                scope.pop();
+                Core::Product(x.clone(), ty, Box::new(t))
+            }
+            Self::Application(t, u) => {
+                Core::Application(Box::new(t.to_core(scope)), Box::new(u.to_core(scope)))
+            }
+        }
+    }
+
+    fn from_core(t: &Core, scope: &mut Vec<String>) -> Self {
+        match t {
+            Core::Bound(i) => Self::Variable(Core::name(*i, scope)),
+            Core::Free(x) => Self::Variable(x.clone()),
+            Core::Sort(s) => Self::Sort(*s),
+            Core::Abstraction(x, ty, t) => {
+                let ty = ty
+                    .as_ref()
+                    .map(|ty| Box::new(Self::from_core(ty, scope)))
+                    .expect("abstractions should keep their types");
+                scope.push(x.clone());
+                let t = Self::from_core(t, scope);
+                scope.pop();
+                Self::Abstraction(x.clone(), ty, Box::new(t))
+            }
+            Core::Product(x, ty, t) => {
+                let ty = Box::new(Self::from_core(ty, scope));
+                scope.push(x.clone());
+                let t = Self::from_core(t, scope);
+                scope.pop();
+                Self::Product(x.clone(), ty, Box::new(t))
+            }
+            Core::Application(t, u) => Self::Application(
+                Box::new(Self::from_core(t, scope)),
+                Box::new(Self::from_core(u, scope)),
+            ),
+        }
+    }
+
     /// The type of this term in the given environment, if it is well-typed.
     /// This assumes that the environment is valid and all types in it are in a canonical form.
     /// If this holds, the result will be in canonical form.
-    pub fn type_in(&self, env: EnvRef<'_>) -> Option<Term> {
+    pub fn type_in(&self, env: EnvRef<'_>) -> Result<Term, TypeError> {
         match self {
-            Self::Sort(Sort::Type) => Some(Term::Sort(Sort::Universal)),
+            Self::Sort(Sort::Type) => Ok(Term::Sort(Sort::Universal)),
             Self::Variable(x) => env
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
-            Self::Application(t, u) => {
-                if let Some(Term::Product(v, a1, b)) = t.type_in(env) {
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
+            Self::Application(t, u) => match t.type_in(env)? {
+                Term::Product(v, a1, b) => {
                     let a2 = u.type_in(env)?;
                     // If type_in returns canonical terms, then
                     // alpha-equivalence is sufficient.
-                    if a1.as_ref() == &a2 {
-                        Some(b.substitute(&v, u).evaluate())
+                    if *a1 == a2 {
+                        Ok(b.substitute(&v, u).normalise())
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
             Self::Product(x, ty, t) => {
                 // The only canonical form for a sort is a Term::Sort.
                 if !matches!(ty.type_in(env)?, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(ty.as_ref().clone()));
                 }
                 let mut inner_env = env.to_owned();
                 inner_env.push((x.to_owned(), ty.as_ref().clone()));
                 let b = t.type_in(&inner_env)?;
                 if !matches!(b, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(t.as_ref().clone()));
                 }
-                Some(b)
+                Ok(b)
             }
             Self::Abstraction(x, ty, t) => {
                 // The only canonical form for a sort is a Term::Sort.
                 if !matches!(ty.type_in(env)?, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(ty.as_ref().clone()));
                 }
                 let mut inner_env = env.to_owned();
                 inner_env.push((x.to_owned(), ty.as_ref().clone()));
                 let b = t.type_in(&inner_env)?;
                 if !matches!(b.type_in(&inner_env)?, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(b));
                 }
-                Some(Term::Product(
+                Ok(Term::Product(
                     x.to_owned(),
-                    Box::new(ty.evaluate()),
+                    Box::new(ty.normalise()),
                     Box::new(b),
                 ))
             }
-            Self::Sort(Sort::Universal) => None,
+            Self::Sort(Sort::Universal) => Err(TypeError::NotTypeable(self.clone())),
         }
     }
 
     /// The type of this term in the empty environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Term> {
+    pub fn type_closed(&self) -> Result<Term, TypeError> {
         self.type_in(&Environment::new())
     }
 }
@@ -359,29 +481,129 @@ impl Arbitrary for Term {
 #[cfg(test)]
 fn arb_term() -> impl Strategy<Value = Term> {
     let leaf = prop_oneof![
-        "[a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω_][a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω0-9_]*'*".prop_map(Term::Variable),
+        crate::arb_var().prop_map(Term::Variable),
         any::<Sort>().prop_map(Term::Sort)
     ];
     leaf.prop_recursive(16, 256, 2, |inner| {
         prop_oneof![
             (inner.clone(), inner.clone())
                 .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
-            (
-                "[a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω_][a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω0-9_]*'*",
-                inner.clone(),
-                inner.clone()
-            )
+            (crate::arb_var(), inner.clone(), inner.clone())
                 .prop_map(|(x, t, b)| Term::Abstraction(x, Box::new(t), Box::new(b))),
-            (
-                "[a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω_][a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω0-9_]*'*",
-                inner.clone(),
-                inner
-            )
-                .prop_map(|(x, t, b)| Term::Product(x, Box::new(t), Box::new(b))),
+            (crate::arb_var(), inner.clone(), inner).prop_map(|(x, t, b)| Term::Product(
+                x,
+                Box::new(t),
+                Box::new(b)
+            )),
         ]
     })
 }
 
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) | Self::Application(..) | Self::Product(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, t, _), 0)
+            | (Self::Product(_, t, _), 0)
+            | (Self::Application(t, _), 0) => Some(t),
+            (Self::Abstraction(_, _, u), 1)
+            | (Self::Product(_, _, u), 1)
+            | (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, _, t), 0) => Self::Abstraction(x.clone(), child, t.clone()),
+            (Self::Abstraction(x, ty, _), 1) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Product(x, _, t), 0) => Self::Product(x.clone(), child, t.clone()),
+            (Self::Product(x, ty, _), 1) => Self::Product(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+impl Reduce for Term {
+    fn form(&self) -> Form {
+        match self {
+            Self::Application(..) => Form::Application {
+                term_argument: true,
+            },
+            Self::Abstraction(..) | Self::Product(..) => Form::Binder { body: 1 },
+            _ => Form::Other,
+        }
+    }
+
+    fn contract(&self) -> Option<Self> {
+        let Self::Application(t, u) = self else {
+            return None;
+        };
+        let Self::Abstraction(x, _, b) = t.as_ref() else {
+            return None;
+        };
+        Some(b.substitute(x, u))
+    }
+}
+
+/// The constructors of a [`Term`] in locally nameless form.
+///
+/// An abstraction or product has the type of its variable as its first child, followed by the
+/// binder for its body.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Sort(Sort),
+    Abstraction,
+    Application,
+    Product,
+}
+
+impl LocallyNameless for Term {
+    type Node = Node;
+
+    fn to_locally_nameless(&self) -> Ln<Node> {
+        to_locally_nameless(self, &mut Scope::new())
+    }
+}
+
+fn to_locally_nameless(term: &Term, scope: &mut Scope) -> Ln<Node> {
+    match term {
+        Term::Variable(x) => scope.variable((), x),
+        Term::Sort(s) => Ln::node(Node::Sort(*s), []),
+        Term::Abstraction(x, ty, t) => Ln::node(
+            Node::Abstraction,
+            [
+                to_locally_nameless(ty, scope),
+                scope.bind((), x, |scope| to_locally_nameless(t, scope)),
+            ],
+        ),
+        Term::Product(x, ty, t) => Ln::node(
+            Node::Product,
+            [
+                to_locally_nameless(ty, scope),
+                scope.bind((), x, |scope| to_locally_nameless(t, scope)),
+            ],
+        ),
+        Term::Application(t, u) => Ln::node(
+            Node::Application,
+            [to_locally_nameless(t, scope), to_locally_nameless(u, scope)],
+        ),
+    }
+}
+
 impl PartialEq for Term {
     /// Alpha equivalence.
     fn eq(&self, other: &Self) -> bool {
@@ -389,18 +611,39 @@ impl PartialEq for Term {
     }
 }
 
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+            Self::NotType(t) => write!(f, "{} is not a type", t),
+            Self::NotTypeable(t) => write!(f, "{} has no type", t),
+        }
+    }
+}
+
+impl error::Error for TypeError {}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Sort(Sort::Type) => write!(f, "*"),
-            Self::Sort(Sort::Universal) => write!(f, "□"),
+            Self::Sort(Sort::Universal) => write!(f, "{}", n.universe()),
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
                 write_term(ty, f)?;
-                write!(f, " -> {}", t)
+                write!(f, " -> {}", n.show(t))
             }
-            Self::Product(x, ty, t) => write!(f, "Π{}: {}. {}", x, ty, t),
+            Self::Product(x, ty, t) => write!(f, "{}{}: {}. {}", n.pi(), x, n.show(ty), n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -410,17 +653,54 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Sort(Sort::Type) => pretty::atom("*"),
+            Self::Sort(Sort::Universal) => pretty::atom(n.universe()),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
+                pretty::infix(at(ty.as_ref(), n, Prec::Application), " ->", t.to_doc(n))
+            }
+            Self::Product(x, ty, t) => {
+                let head = Doc::text(format!("{}{}: ", n.pi(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -448,7 +728,7 @@ mod tests {
                 Box::new(Term::Variable("a".into())),
             )),
         );
-        assert_eq!(i.type_closed(), Some(expected));
+        assert_eq!(i.type_closed().unwrap(), expected);
     }
 
     #[test]
@@ -487,7 +767,7 @@ mod tests {
                 )),
             )),
         );
-        assert_eq!(k.type_closed(), Some(expected));
+        assert_eq!(k.type_closed().unwrap(), expected);
     }
 
     #[test]
@@ -575,7 +855,7 @@ mod tests {
                 )),
             )),
         );
-        assert_eq!(s.type_closed(), Some(expected));
+        assert_eq!(s.type_closed().unwrap(), expected);
     }
 
     #[test]
@@ -628,6 +908,6 @@ mod tests {
                 )),
             )),
         );
-        assert_eq!(xx.type_closed(), Some(expected));
+        assert_eq!(xx.type_closed().unwrap(), expected);
     }
 }
@@ -1,186 +1,179 @@
-use std::{error, fmt, str::Utf8Error};
-
-use tree_sitter::{Language, Node, Parser, TreeCursor};
+#[cfg(feature = "tree-sitter")]
+use tree_sitter::{Node, TreeCursor};
 
 use super::*;
-
-extern "C" {
-    fn tree_sitter_lambda() -> Language;
-}
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum ParseError {
-    Encoding(Utf8Error),
-    Other(String),
-}
-
-impl ParseError {
-    fn msg(s: impl Into<String>) -> Self {
-        Self::Other(s.into())
-    }
-}
-
-impl From<Utf8Error> for ParseError {
-    fn from(value: Utf8Error) -> Self {
-        Self::Encoding(value)
-    }
-}
-
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Other(s) => write!(f, "parse error: {}", s),
-            Self::Encoding(e) => e.fmt(f),
+use crate::parsing;
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+use crate::parsing::descent::{Descent, Grammar};
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+use crate::parsing::lexer::Token;
+#[cfg(feature = "tree-sitter")]
+use crate::parsing::tree::{unexpected, Syntax, Walker};
+use crate::span::SpanTable;
+
+pub use crate::parsing::ParseError;
+
+struct CalcOfCons;
+
+#[cfg(feature = "tree-sitter")]
+impl Syntax for CalcOfCons {
+    type Term = Term;
+
+    fn hole(name: String) -> Term {
+        Term::Variable(name)
+    }
+
+    fn node<'a>(
+        w: &mut Walker<'a, Self>,
+        node: Node<'a>,
+        cursor: &mut TreeCursor<'a>,
+    ) -> Result<Term, ParseError> {
+        match node.kind() {
+            "sort" => {
+                let text = w.text(&node)?;
+                Ok(Term::Sort(match text {
+                    "*" => Sort::Type,
+                    "□" => Sort::Universal,
+                    _ => return Err(ParseError::at(&node, format!("unexpected sort {}", text))),
+                }))
+            }
+            "variable" => Ok(Term::Variable(w.identifier(cursor)?)),
+            "abstraction" => {
+                w.enter(&node, cursor)?;
+                let bound = w.identifier(cursor)?;
+                w.next(&node, cursor, "type")?;
+                let ty = Box::new(w.child(0, cursor)?);
+                w.next(&node, cursor, "body")?;
+                let body = Box::new(w.child(1, cursor)?);
+                w.leave(cursor);
+                Ok(Term::Abstraction(bound, ty, body))
+            }
+            "product" => {
+                w.enter(&node, cursor)?;
+                let bound = match w.field(cursor)? {
+                    Some("input_name") => {
+                        let bound = w.identifier(cursor)?;
+                        w.next(&node, cursor, "input type")?;
+                        Some(bound)
+                    }
+                    Some("input") => None,
+                    _ => return Err(unexpected(cursor, "product")),
+                };
+                let ty = Box::new(w.child(0, cursor)?);
+                w.next(&node, cursor, "output type")?;
+                let body = Box::new(w.child(1, cursor)?);
+                w.leave(cursor);
+                let bound = bound.unwrap_or_else(|| fresh_var(&body.free_vars()));
+                Ok(Term::Product(bound, ty, body))
+            }
+            "application" => {
+                let (func, arg) = w.pair(&node, cursor, "RHS")?;
+                Ok(Term::Application(Box::new(func), Box::new(arg)))
+            }
+            _ => Err(ParseError::at(
+                &node,
+                format!("unknown term type {}", node.kind()),
+            )),
         }
     }
 }
 
-impl error::Error for ParseError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match self {
-            Self::Encoding(e) => Some(e),
-            Self::Other(_) => None,
-        }
-    }
-}
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+impl Grammar for CalcOfCons {
+    type Term = Term;
 
-pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
-    let mut parser = Parser::new();
-    let language = unsafe { tree_sitter_lambda() };
-    parser
-        .set_language(language)
-        .expect("language should be compiled with a compatible version of tree-sitter");
-
-    let source = source.as_ref();
-
-    let tree = parser.parse(source, None).expect("parser should succeed");
-    let mut cursor = tree.walk();
-    let node = advance(&mut cursor)?.ok_or(ParseError::msg("missing root"))?;
-    if node.kind() != "source" {
-        return Err(ParseError::msg(format!(
-            "expected root node but got {}",
-            node.kind()
-        )));
+    fn hole(name: String) -> Term {
+        Term::Variable(name)
     }
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("root node should have children"));
-    }
-    walk_term(source, &mut cursor)
-}
 
-fn walk_identifier<'a>(
-    source: &'a [u8],
-    cursor: &mut TreeCursor<'a>,
-) -> Result<String, ParseError> {
-    let node = advance(cursor)?.ok_or(ParseError::msg("missing variable"))?;
-    if node.kind() != "variable" {
-        return Err(ParseError::msg(format!(
-            "expected variable but got {}",
-            node.kind()
-        )));
+    fn term(d: &mut Descent<'_, Self>) -> Result<Term, ParseError> {
+        let binder = match d.tokens.peek() {
+            Some(Token::Lambda) => Term::Abstraction,
+            Some(Token::Pi) => Term::Product,
+            _ => return arrow(d),
+        };
+        d.tokens.skip();
+        let bound = d.tokens.identifier()?;
+        d.tokens.expect(&Token::Colon)?;
+        let ty = d.child(0, Self::term)?;
+        d.tokens.expect(&Token::Dot)?;
+        let body = d.child(1, Self::term)?;
+        Ok(binder(bound, Box::new(ty), Box::new(body)))
     }
-    let text = node.utf8_text(source)?.to_owned();
-    Ok(text)
 }
 
-fn walk_term<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    let node = advance(cursor)?.ok_or(ParseError::msg("missing term"))?;
-    match node.kind() {
-        "sort" => {
-            let text = node.utf8_text(source)?;
-            Ok(Term::Sort(match text {
-                "*" => Sort::Type,
-                "□" => Sort::Universal,
-                _ => return Err(ParseError::msg(format!("unexpected sort {}", text))),
-            }))
-        }
-        "variable" => Ok(Term::Variable(walk_identifier(source, cursor)?)),
-        "abstraction" => Ok(walk_abstraction(source, cursor)?),
-        "product" => Ok(walk_product(source, cursor)?),
-        "application" => Ok(walk_application(source, cursor)?),
-        _ => Err(ParseError::msg(format!(
-            "unknown term type {}",
-            node.kind()
-        ))),
-    }
+/// An application, or a non-dependent product written with an arrow.
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn arrow(d: &mut Descent<'_, CalcOfCons>) -> Result<Term, ParseError> {
+    let mark = d.mark();
+    let t = application(d)?;
+    if d.tokens.peek() != Some(&Token::Arrow) {
+        return Ok(t);
+    }
+    // The span of the domain must be recorded before the arrow is consumed.
+    d.wrap(mark);
+    d.tokens.skip();
+    let body = d.child(1, CalcOfCons::term)?;
+    let bound = fresh_var(&body.free_vars());
+    Ok(Term::Product(bound, Box::new(t), Box::new(body)))
 }
 
-fn walk_application<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("application should have children"));
-    }
-    let func = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("application should have RHS"));
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction or product.
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn application(d: &mut Descent<'_, CalcOfCons>) -> Result<Term, ParseError> {
+    let mark = d.mark();
+    let mut t = atom(d)?;
+    loop {
+        let arg: fn(&mut Descent<'_, CalcOfCons>) -> _ = match d.tokens.peek() {
+            Some(Token::Lambda | Token::Pi) => CalcOfCons::term,
+            Some(Token::LParen | Token::Star | Token::Box) => atom,
+            _ if d.tokens.at_identifier() => atom,
+            _ => return Ok(t),
+        };
+        d.wrap(mark);
+        t = Term::Application(Box::new(t), Box::new(d.child(1, arg)?));
     }
-    let arg = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Application(func, arg))
 }
 
-fn walk_product<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("product should have children"));
-    }
-    advance(cursor)?;
-    let bound = if matches!(cursor.field_name(), Some("input_name")) {
-        let res = Some(walk_identifier(source, cursor)?);
-        if !cursor.goto_next_sibling() {
-            return Err(ParseError::msg("product should have input type"));
-        }
-        res
-    } else if matches!(cursor.field_name(), Some("input")) {
-        None
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn atom(d: &mut Descent<'_, CalcOfCons>) -> Result<Term, ParseError> {
+    if d.tokens.eat(&Token::LParen) {
+        let t = CalcOfCons::term(d)?;
+        d.tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if d.tokens.eat(&Token::Star) {
+        Ok(Term::Sort(Sort::Type))
+    } else if d.tokens.eat(&Token::Box) {
+        Ok(Term::Sort(Sort::Universal))
+    } else if d.tokens.at_identifier() {
+        Ok(Term::Variable(d.tokens.identifier()?))
     } else {
-        return Err(ParseError::msg(format!(
-            "unexpected node in product: {}",
-            cursor.node().to_sexp()
-        )));
-    };
-    let ty = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("product should have output type"));
+        Err(d.tokens.unexpected("a term"))
     }
-    let body = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    let bound = bound.unwrap_or_else(|| fresh_var(&body.free_vars()));
-    Ok(Term::Product(bound, ty, body))
 }
 
-fn walk_abstraction<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("abstraction should have children"));
-    }
-    let bound = walk_identifier(source, cursor)?;
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("abstraction should have type"));
-    }
-    let ty = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("abstraction should have body"));
-    }
-    let body = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Abstraction(bound, ty, body))
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    parse_with_spans(source).map(|(term, _)| term)
 }
 
-fn advance<'a>(cursor: &mut TreeCursor<'a>) -> Result<Option<Node<'a>>, ParseError> {
-    while cursor.node().is_extra() || !cursor.node().is_named() {
-        if !cursor.goto_next_sibling() {
-            return Ok(None);
-        }
-    }
-    let node = cursor.node();
-    if node.is_error() {
-        Err(ParseError::msg(format!("parse error: {}", node.to_sexp())))
-    } else {
-        Ok(Some(node))
-    }
+/// Parse a term, also returning the source span of each of its subterms.
+pub fn parse_with_spans(source: impl AsRef<[u8]>) -> Result<(Term, SpanTable), ParseError> {
+    parsing::parse_with_spans::<CalcOfCons>(source.as_ref())
+}
+
+/// Parse a term even if it has syntax errors, returning every error in the source.
+///
+/// Subterms that could not be parsed are replaced by holes, variables named `?0`, `?1`, …
+/// which cannot be written in the source.
+pub fn parse_recovering(source: impl AsRef<[u8]>) -> (Term, Vec<ParseError>) {
+    parsing::parse_recovering::<CalcOfCons>(source.as_ref())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::trace::Subterms;
     use proptest::prelude::*;
 
     proptest! {
@@ -191,10 +184,67 @@ mod tests {
             prop_assert_eq!(f2, f);
         }
 
+        #[test]
+        fn spans_cover_printed_subterms(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let (_, spans) = parse_with_spans(&s).expect("printed output should be parseable");
+            let whole = spans.get(&[]).expect("root should have a span");
+            prop_assert_eq!((whole.start, whole.end), (0, s.len()));
+        }
+
+        #[test]
+        fn redex_paths_agree_with_spans(f in any::<Term>()) {
+            for step in f.reduction_trace().take(8) {
+                let Some(redex) = step.redex else { break };
+                let s = format!("{}", step.term);
+                let (_, spans) = parse_with_spans(&s).expect("printed output should be parseable");
+                let span = spans.get(&redex.path).expect("redex should have a span");
+                let printed = parse(&s[span.start..span.end]).expect("span should be a term");
+                prop_assert_eq!(Some(&printed), step.term.subterm(&redex.path));
+            }
+        }
+
+
+        // The tree-sitter grammar only has the Unicode spellings.
+        #[test]
+        #[cfg(not(feature = "tree-sitter"))]
+        fn ascii_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{:#}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        #[cfg(not(feature = "tree-sitter"))]
+        fn pretty_output_parses_correctly(f in any::<Term>(), width in 1usize..80) {
+            let s = crate::pretty::Pretty::pretty(&f, width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
 
         #[test]
         fn parser_doesnt_crash(s in "\\PC*") {
             let _ = parse(s);
         }
+
+        #[test]
+        fn recovering_agrees_with_parse(s in "\\PC*") {
+            let (term, errors) = parse_recovering(&s);
+            match parse(&s) {
+                Ok(t) => {
+                    prop_assert!(errors.is_empty());
+                    prop_assert_eq!(term, t);
+                }
+                Err(_) => prop_assert!(!errors.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn error_locations() {
+        let source = "λx: *.\n  Πy: x. )";
+        let err = parse(source).unwrap_err();
+        let span = err.span().expect("syntax errors should have a location");
+        assert_eq!(span.start_position.line, 1);
     }
 }
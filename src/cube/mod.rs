@@ -1,7 +1,15 @@
+pub mod parser;
+
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 use super::*;
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Sort {
@@ -162,43 +170,52 @@ impl<const F: bool, const W: bool, const P: bool> Term<F, W, P> {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::Abstraction(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Abstraction(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((
+                        Self::Abstraction(v.to_owned(), ty, Box::new(t2)),
+                        r.within(1),
                     ))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Abstraction(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (
+                            Self::Abstraction(v.to_owned(), Box::new(ty2), t),
+                            r.within(0),
+                        )
                     })
                 }
             }
             Self::Product(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Product(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
-                    ))
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((Self::Product(v.to_owned(), ty, Box::new(t2)), r.within(1)))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Product(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Product(v.to_owned(), Box::new(ty2), t), r.within(0))
                     })
                 }
             }
@@ -215,52 +232,54 @@ impl<const F: bool, const W: bool, const P: bool> Term<F, W, P> {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(self.clone(), vec![Self::beta_reduce_lazy_with_redex])
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given environment, if it is well-typed.
-    pub fn type_in(&self, env: &Environment<F, W, P>) -> Option<Term<F, W, P>> {
+    pub fn type_in(&self, env: &Environment<F, W, P>) -> Result<Term<F, W, P>, TypeError<F, W, P>> {
         match self {
-            Self::Sort(Sort::Type) => Some(Term::Sort(Sort::Universal)),
+            Self::Sort(Sort::Type) => Ok(Term::Sort(Sort::Universal)),
             Self::Variable(x) => env
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
-            Self::Application(t, u) => {
-                if let Some(Term::Product(v, a1, b)) = t.type_in(env) {
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
+            Self::Application(t, u) => match t.type_in(env)? {
+                Term::Product(v, a1, b) => {
                     let a2 = u.type_in(env)?;
-                    if a1.as_ref() == &a2 {
-                        Some(b.substitute(&v, u).evaluate())
+                    if *a1 == a2 {
+                        Ok(b.substitute(&v, u).evaluate())
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
             Self::Product(x, ty, t) => {
                 let s1 = ty.type_in(env)?;
                 let mut inner_env = env.clone();
                 inner_env.push((x.to_owned(), ty.as_ref().clone()));
                 let s2 = t.type_in(&inner_env)?;
                 match (&s1, &s2) {
-                    (Term::Sort(Sort::Type), Term::Sort(Sort::Type)) => {}
-                    (Term::Sort(Sort::Type), Term::Sort(Sort::Universal)) => {
-                        if !P {
-                            return None;
-                        }
-                    }
-                    (Term::Sort(Sort::Universal), Term::Sort(Sort::Type)) => {
-                        if !F {
-                            return None;
-                        }
-                    }
-                    (Term::Sort(Sort::Universal), Term::Sort(Sort::Universal)) => {
-                        if !W {
-                            return None;
-                        }
-                    }
-                    _ => return None,
+                    (Term::Sort(s1), Term::Sort(s2)) => check_rule(*s1, *s2)?,
+                    (Term::Sort(_), _) => return Err(TypeError::NotType(t.as_ref().clone())),
+                    _ => return Err(TypeError::NotType(ty.as_ref().clone())),
                 }
-                Some(s2)
+                Ok(s2)
             }
             Self::Abstraction(x, ty, t) => {
                 let s1 = ty.type_in(env)?;
@@ -269,40 +288,139 @@ impl<const F: bool, const W: bool, const P: bool> Term<F, W, P> {
                 let b = t.type_in(&inner_env)?;
                 let s2 = b.type_in(&inner_env)?;
                 match (&s1, &s2) {
-                    (Term::Sort(Sort::Type), Term::Sort(Sort::Type)) => {}
-                    (Term::Sort(Sort::Type), Term::Sort(Sort::Universal)) => {
-                        if !P {
-                            return None;
-                        }
-                    }
-                    (Term::Sort(Sort::Universal), Term::Sort(Sort::Type)) => {
-                        if !F {
-                            return None;
-                        }
-                    }
-                    (Term::Sort(Sort::Universal), Term::Sort(Sort::Universal)) => {
-                        if !W {
-                            return None;
-                        }
-                    }
-                    _ => return None,
+                    (Term::Sort(s1), Term::Sort(s2)) => check_rule(*s1, *s2)?,
+                    (Term::Sort(_), _) => return Err(TypeError::NotType(b)),
+                    _ => return Err(TypeError::NotType(ty.as_ref().clone())),
                 }
-                Some(Term::Product(
+                Ok(Term::Product(
                     x.to_owned(),
                     Box::new(ty.as_ref().clone()),
                     Box::new(b),
                 ))
             }
-            Self::Sort(Sort::Universal) => None,
+            Self::Sort(Sort::Universal) => Err(TypeError::NotTypeable(self.clone())),
         }
     }
 
     /// The type of this term in the empty environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Term<F, W, P>> {
+    pub fn type_closed(&self) -> Result<Term<F, W, P>, TypeError<F, W, P>> {
         self.type_in(&Environment::new())
     }
 }
 
+#[cfg(test)]
+impl<const F: bool, const W: bool, const P: bool> Arbitrary for Term<F, W, P> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        let var = crate::arb_var();
+        let leaf = prop_oneof![
+            var.clone().prop_map(Term::Variable),
+            Just(Term::Sort(Sort::Type)),
+            Just(Term::Sort(Sort::Universal)),
+        ];
+        leaf.prop_recursive(16, 256, 2, move |inner| {
+            prop_oneof![
+                (inner.clone(), inner.clone())
+                    .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+                (var.clone(), inner.clone(), inner.clone())
+                    .prop_map(|(x, t, b)| Term::Abstraction(x, Box::new(t), Box::new(b))),
+                (var.clone(), inner.clone(), inner).prop_map(|(x, t, b)| Term::Product(
+                    x,
+                    Box::new(t),
+                    Box::new(b)
+                )),
+            ]
+        })
+        .boxed()
+    }
+}
+
+impl<const F: bool, const W: bool, const P: bool> Subterms for Term<F, W, P> {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) | Self::Application(..) | Self::Product(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, t, _), 0)
+            | (Self::Product(_, t, _), 0)
+            | (Self::Application(t, _), 0) => Some(t),
+            (Self::Abstraction(_, _, u), 1)
+            | (Self::Product(_, _, u), 1)
+            | (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, _, t), 0) => Self::Abstraction(x.clone(), child, t.clone()),
+            (Self::Abstraction(x, ty, _), 1) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Product(x, _, t), 0) => Self::Product(x.clone(), child, t.clone()),
+            (Self::Product(x, ty, _), 1) => Self::Product(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+/// Why a term is ill-typed.
+#[derive(Debug, Clone)]
+pub enum TypeError<const F: bool, const W: bool, const P: bool> {
+    UndefinedVar(String),
+    /// A term is applied to an argument, but its type isn't a product.
+    NotFunType {
+        term: Term<F, W, P>,
+        ty: Term<F, W, P>,
+    },
+    Mismatch {
+        expected: Term<F, W, P>,
+        actual: Term<F, W, P>,
+    },
+    /// A term used as a type, whose type isn't a sort.
+    NotType(Term<F, W, P>),
+    /// A term with no type, which is only □.
+    NotTypeable(Term<F, W, P>),
+    /// A product from a term of one sort to another, which this corner of the cube doesn't
+    /// allow.
+    IllegalProduct {
+        domain: Sort,
+        codomain: Sort,
+    },
+}
+
+/// Check that products from `s1` to `s2` are allowed.
+fn check_rule<const F: bool, const W: bool, const P: bool>(
+    s1: Sort,
+    s2: Sort,
+) -> Result<(), TypeError<F, W, P>> {
+    let allowed = match (s1, s2) {
+        (Sort::Type, Sort::Type) => true,
+        (Sort::Type, Sort::Universal) => P,
+        (Sort::Universal, Sort::Type) => F,
+        (Sort::Universal, Sort::Universal) => W,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(TypeError::IllegalProduct {
+            domain: s1,
+            codomain: s2,
+        })
+    }
+}
+
 impl<const F: bool, const W: bool, const P: bool> PartialEq for Term<F, W, P> {
     /// Alpha equivalence.
     fn eq(&self, other: &Self) -> bool {
@@ -310,18 +428,45 @@ impl<const F: bool, const W: bool, const P: bool> PartialEq for Term<F, W, P> {
     }
 }
 
+impl<const F: bool, const W: bool, const P: bool> fmt::Display for TypeError<F, W, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+            Self::NotType(t) => write!(f, "{} is not a type", t),
+            Self::NotTypeable(t) => write!(f, "{} has no type", t),
+            Self::IllegalProduct { domain, codomain } => write!(
+                f,
+                "products from {} to {} are not allowed",
+                Term::<F, W, P>::Sort(*domain),
+                Term::<F, W, P>::Sort(*codomain)
+            ),
+        }
+    }
+}
+
+impl<const F: bool, const W: bool, const P: bool> error::Error for TypeError<F, W, P> {}
+
 impl<const F: bool, const W: bool, const P: bool> fmt::Display for Term<F, W, P> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Sort(Sort::Type) => write!(f, "*"),
-            Self::Sort(Sort::Universal) => write!(f, "□"),
+            Self::Sort(Sort::Universal) => write!(f, "{}", n.universe()),
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
                 write_term(ty, f)?;
-                write!(f, " -> {}", t)
+                write!(f, " -> {}", n.show(t))
             }
-            Self::Product(x, ty, t) => write!(f, "Π{}: {}. {}", x, ty, t),
+            Self::Product(x, ty, t) => write!(f, "{}{}: {}. {}", n.pi(), x, n.show(ty), n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -331,13 +476,50 @@ impl<const F: bool, const W: bool, const P: bool> fmt::Display for Term<F, W, P>
     }
 }
 
+impl<const F: bool, const W: bool, const P: bool> Pretty for Term<F, W, P> {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Sort(Sort::Type) => pretty::atom("*"),
+            Self::Sort(Sort::Universal) => pretty::atom(n.universe()),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
+                pretty::infix(at(ty.as_ref(), n, Prec::Application), " ->", t.to_doc(n))
+            }
+            Self::Product(x, ty, t) => {
+                let head = Doc::text(format!("{}{}: ", n.pi(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 fn write_term<const F: bool, const W: bool, const P: bool>(
     t: &Term<F, W, P>,
     f: &mut fmt::Formatter<'_>,
 ) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -347,7 +529,7 @@ fn write_func<const F: bool, const W: bool, const P: bool>(
 ) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -0,0 +1,126 @@
+use super::*;
+use crate::parsing::lexer::{Token, Tokens};
+
+pub use crate::parsing::ParseError;
+
+pub fn parse<const F: bool, const W: bool, const P: bool>(
+    source: impl AsRef<[u8]>,
+) -> Result<Term<F, W, P>, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), &[])?;
+    let t = term(&mut tokens)?;
+    tokens.finish()?;
+    Ok(t)
+}
+
+fn term<const F: bool, const W: bool, const P: bool>(
+    tokens: &mut Tokens,
+) -> Result<Term<F, W, P>, ParseError> {
+    let binder = match tokens.peek() {
+        Some(Token::Lambda) => Term::Abstraction,
+        Some(Token::Pi) => Term::Product,
+        _ => {
+            let t = application(tokens)?;
+            return if tokens.eat(&Token::Arrow) {
+                let body = term(tokens)?;
+                let x = fresh_var(&body.free_vars());
+                Ok(Term::Product(x, Box::new(t), Box::new(body)))
+            } else {
+                Ok(t)
+            };
+        }
+    };
+    tokens.eat(&Token::Lambda);
+    tokens.eat(&Token::Pi);
+    let x = tokens.identifier()?;
+    tokens.expect(&Token::Colon)?;
+    let ty = term(tokens)?;
+    tokens.expect(&Token::Dot)?;
+    Ok(binder(x, Box::new(ty), Box::new(term(tokens)?)))
+}
+
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction or product.
+fn application<const F: bool, const W: bool, const P: bool>(
+    tokens: &mut Tokens,
+) -> Result<Term<F, W, P>, ParseError> {
+    let mut t = atom(tokens)?;
+    loop {
+        t = match tokens.peek() {
+            Some(Token::Lambda | Token::Pi) => {
+                return Ok(Term::Application(Box::new(t), Box::new(term(tokens)?)))
+            }
+            Some(Token::LParen | Token::Star | Token::Box) => {
+                Term::Application(Box::new(t), Box::new(atom(tokens)?))
+            }
+            _ if tokens.at_identifier() => Term::Application(Box::new(t), Box::new(atom(tokens)?)),
+            _ => return Ok(t),
+        }
+    }
+}
+
+fn atom<const F: bool, const W: bool, const P: bool>(
+    tokens: &mut Tokens,
+) -> Result<Term<F, W, P>, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = term(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.eat(&Token::Star) {
+        Ok(Term::Sort(Sort::Type))
+    } else if tokens.eat(&Token::Box) {
+        Ok(Term::Sort(Sort::Universal))
+    } else if tokens.at_identifier() {
+        Ok(Term::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a term"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term<true, true, true>>()) {
+            let s = format!("{}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn ascii_output_parses_correctly(f in any::<Term<true, true, true>>()) {
+            let s = format!("{:#}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn pretty_output_parses_correctly(f in any::<Term<true, true, true>>(), width in 1usize..80) {
+            let s = f.pretty(width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse::<true, true, true>(s);
+        }
+    }
+
+    #[test]
+    fn polymorphic_identity() {
+        let id: Term<true, false, false> = parse("λa: *. λx: a. x").unwrap();
+        let ty: Term<true, false, false> = parse("Πa: *. a -> a").unwrap();
+        assert_eq!(id.type_closed().unwrap(), ty);
+    }
+
+    #[test]
+    fn simply_typed_corner_has_no_polymorphism() {
+        let id: Term<false, false, false> = parse("λa: *. λx: a. x").unwrap();
+        let err = id.type_closed().unwrap_err();
+        assert_eq!(err.to_string(), "products from □ to * are not allowed");
+    }
+}
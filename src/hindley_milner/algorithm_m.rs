@@ -0,0 +1,203 @@
+//! Algorithm M, which infers types top-down, checking each term against the type its context
+//! expects of it.
+//!
+//! Since the expected type is known before a subterm is reached, a mismatch is found at the
+//! first subterm that can't have it. In `(λx. add x 1) true` it is `true` that is blamed for
+//! not being an `Int`, where [`Term::type_in`] only finds that the application is ill-typed.
+
+use super::*;
+
+/// Infer the most general type of `t` in the given type environment.
+pub fn type_in(t: &Term, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
+    let mut checker = Checker {
+        data: Vec::new(),
+        subs: HashMap::new(),
+        var_count: 0,
+    };
+    let ty = checker.fresh();
+    checker.check(t, &mut type_env.clone(), &ty)?;
+    Ok(ty.substitute(&checker.subs))
+}
+
+struct Checker {
+    data: Vec<DataDecl>,
+    subs: HashMap<String, Type>,
+    var_count: usize,
+}
+
+impl Checker {
+    fn fresh(&mut self) -> Type {
+        let a = Type::Variable(format!("t{}", self.var_count));
+        self.var_count += 1;
+        a
+    }
+
+    fn unify(&mut self, term: &Term, expected: &Type, actual: Type) -> Result<(), TypeError> {
+        let equality = (
+            actual.substitute(&self.subs),
+            expected.substitute(&self.subs),
+        );
+        unify(vec![equality], &mut self.subs, term)
+    }
+
+    fn generalise(&self, ty: &Type, type_env: &TypeEnvironment) -> Type {
+        let fv = type_env
+            .iter()
+            .flat_map(|(_, t)| t.substitute(&self.subs).vars())
+            .collect();
+        ty.substitute(&self.subs).generalise(&fv)
+    }
+
+    /// Check that `t` can have the type `expected`, refining the substitution so that it does.
+    fn check(
+        &mut self,
+        t: &Term,
+        type_env: &mut TypeEnvironment,
+        expected: &Type,
+    ) -> Result<(), TypeError> {
+        match t {
+            Term::Variable(x) => {
+                let (_, ty) = type_env
+                    .iter()
+                    .rev()
+                    .find(|(v, _)| v == x)
+                    .ok_or_else(|| TypeError::UndefinedVar(x.clone()))?;
+                let ty = ty.instantiate(&mut self.var_count);
+                self.unify(t, expected, ty)
+            }
+            Term::Primitive(p) => {
+                let ty = p.ty().instantiate(&mut self.var_count);
+                self.unify(t, expected, ty)
+            }
+            Term::Constructor(c) => {
+                let ty = declaring(&self.data, c)?
+                    .constructor_type(c)
+                    .expect("the constructor is declared")
+                    .instantiate(&mut self.var_count);
+                self.unify(t, expected, ty)
+            }
+            Term::Abstraction(x, b) => {
+                let a = self.fresh();
+                let bt = self.fresh();
+                let ty = Type::Fn(Box::new(a.clone()), Box::new(bt.clone()));
+                self.unify(t, expected, ty)?;
+                type_env.push((x.clone(), a));
+                let res = self.check(b, type_env, &bt);
+                type_env.pop();
+                res
+            }
+            Term::Application(f, u) => {
+                let a = self.fresh();
+                let ft = Type::Fn(Box::new(a.clone()), Box::new(expected.clone()));
+                self.check(f, type_env, &ft)?;
+                self.check(u, type_env, &a)
+            }
+            Term::Let(x, b, o) => {
+                let bt = self.fresh();
+                self.check(b, type_env, &bt)?;
+                let scheme = self.generalise(&bt, type_env);
+                type_env.push((x.clone(), scheme));
+                let res = self.check(o, type_env, expected);
+                type_env.pop();
+                res
+            }
+            Term::LetRec(bindings, o) => {
+                check_rebound(t, bindings)?;
+                let depth = type_env.len();
+                let res = self
+                    .check_groups(bindings, type_env)
+                    .and_then(|()| self.check(o, type_env, expected));
+                type_env.truncate(depth);
+                res
+            }
+            Term::Case(s, branches) => {
+                let st = self.fresh();
+                self.check(s, type_env, &st)?;
+                for (c, xs, b) in branches {
+                    let (fields, pt) = pattern_types(&self.data, c, xs, &mut self.var_count)?;
+                    self.unify(s, &pt, st.clone())?;
+                    for (x, field) in xs.iter().zip(fields) {
+                        type_env.push((x.clone(), field.substitute(&self.subs)));
+                    }
+                    let res = self.check(b, type_env, expected);
+                    type_env.truncate(type_env.len() - xs.len());
+                    res?;
+                }
+                check_coverage(&self.data, t, &st.substitute(&self.subs), branches)
+            }
+            Term::Data(d, o) => {
+                d.check(&self.data)?;
+                self.data.push(d.as_ref().clone());
+                let res = self.check(o, type_env, expected);
+                self.data.pop();
+                res
+            }
+        }
+    }
+
+    /// Push the type schemes of the bindings of a `let rec` onto the environment, one binding
+    /// group at a time.
+    fn check_groups(
+        &mut self,
+        bindings: &[(String, Term)],
+        type_env: &mut TypeEnvironment,
+    ) -> Result<(), TypeError> {
+        for group in binding_groups(bindings) {
+            let vars: Vec<_> = group.iter().map(|_| self.fresh()).collect();
+            for (&i, a) in group.iter().zip(&vars) {
+                type_env.push((bindings[i].0.clone(), a.clone()));
+            }
+            for (&i, a) in group.iter().zip(&vars) {
+                self.check(&bindings[i].1, type_env, a)?;
+            }
+            type_env.truncate(type_env.len() - group.len());
+            for (&i, a) in group.iter().zip(&vars) {
+                let scheme = self.generalise(a, type_env);
+                type_env.push((bindings[i].0.clone(), scheme));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatched_arguments_are_blamed() {
+        let term = parser::parse("(λx. add x 1) true").unwrap();
+        let Err(TypeError::Clash { term: blamed, .. }) = type_in(&term, &vec![]) else {
+            panic!("expected a clash")
+        };
+        assert_eq!(blamed.to_string(), "true");
+        let Err(TypeError::Clash { term: blamed, .. }) = term.type_closed() else {
+            panic!("expected a clash")
+        };
+        assert_eq!(*blamed, term);
+    }
+
+    #[test]
+    fn errors_are_found_before_the_rest_of_the_term_is_checked() {
+        // Algorithm W infers the argument before finding that the `if` isn't a function.
+        let term = parser::parse("if true 1 2 (undefined 3)").unwrap();
+        assert!(matches!(
+            type_in(&term, &vec![]),
+            Err(TypeError::Clash { .. })
+        ));
+        assert!(matches!(
+            term.type_closed(),
+            Err(TypeError::UndefinedVar(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn agrees_with_algorithm_w(t in prop_oneof![any::<Term>(), arb_data_term()]) {
+            match (type_in(&t, &vec![]), t.type_closed()) {
+                (Ok(ty), Ok(w)) => prop_assert!(ty.equivalent(&w), "{} and {}", ty, w),
+                (ty, w) => prop_assert_eq!(ty.is_ok(), w.is_ok()),
+            }
+        }
+    }
+}
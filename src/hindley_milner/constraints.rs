@@ -0,0 +1,281 @@
+//! Type inference by generating equality constraints between types, and then solving them.
+//!
+//! Where [`Term::type_in`] unifies as soon as it reaches each application, this first records
+//! a [`Constraint`] for each node that restricts the types of its children, so the whole set
+//! can be inspected before it is solved. The exceptions are a `let`, whose type scheme depends
+//! on the solution of the constraints of its bound term, and a `case` with no branches, whose
+//! coverage depends on the type of its scrutinee: those generated so far are solved when they
+//! are reached.
+
+use std::fmt;
+
+use super::*;
+
+/// A requirement that the type found for `term` equals the type expected of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub term: Term,
+    pub expected: Type,
+    pub actual: Type,
+}
+
+/// The constraints on the types of the subterms of a term, in the order they were generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraints {
+    /// The type of the term, before the constraints are solved.
+    pub ty: Type,
+    pub constraints: Vec<Constraint>,
+}
+
+impl Constraints {
+    /// The constraints on the type of `t` in the given type environment.
+    pub fn generate(t: &Term, type_env: &TypeEnvironment) -> Result<Self, TypeError> {
+        let mut generator = Generator {
+            constraints: Vec::new(),
+            data: Vec::new(),
+            subs: HashMap::new(),
+            solved: 0,
+            var_count: 0,
+        };
+        let ty = generator.generate(t, &mut type_env.clone())?;
+        Ok(Self {
+            ty,
+            constraints: generator.constraints,
+        })
+    }
+
+    /// The most general type satisfying the constraints.
+    pub fn solve(&self) -> Result<Type, TypeError> {
+        Ok(self.ty.substitute(&solve(&self.constraints)?))
+    }
+}
+
+/// The most general substitution satisfying the constraints, or the error from the first one
+/// which can't be satisfied along with those before it.
+pub fn solve(constraints: &[Constraint]) -> Result<HashMap<String, Type>, TypeError> {
+    let mut subs = HashMap::new();
+    for c in constraints {
+        let equality = (c.actual.substitute(&subs), c.expected.substitute(&subs));
+        unify(vec![equality], &mut subs, &c.term)?;
+    }
+    Ok(subs)
+}
+
+/// Infer the most general type of `t` in the given type environment by solving its
+/// constraints.
+pub fn type_in(t: &Term, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
+    Constraints::generate(t, type_env)?.solve()
+}
+
+struct Generator {
+    constraints: Vec<Constraint>,
+    data: Vec<DataDecl>,
+    /// The solution of the constraints generalised over so far.
+    subs: HashMap<String, Type>,
+    /// How many of the constraints have been solved.
+    solved: usize,
+    var_count: usize,
+}
+
+impl Generator {
+    fn fresh(&mut self) -> Type {
+        let a = Type::Variable(format!("t{}", self.var_count));
+        self.var_count += 1;
+        a
+    }
+
+    fn require(&mut self, term: &Term, expected: Type, actual: Type) {
+        self.constraints.push(Constraint {
+            term: term.clone(),
+            expected,
+            actual,
+        });
+    }
+
+    /// Extend the solution to the constraints generated since it was last extended.
+    fn solve_so_far(&mut self) -> Result<(), TypeError> {
+        for c in &self.constraints[self.solved..] {
+            let equality = (
+                c.actual.substitute(&self.subs),
+                c.expected.substitute(&self.subs),
+            );
+            unify(vec![equality], &mut self.subs, &c.term)?;
+        }
+        self.solved = self.constraints.len();
+        Ok(())
+    }
+
+    /// The type scheme of `ty`, once the constraints generated so far are solved.
+    fn generalise(&mut self, ty: &Type, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
+        self.solve_so_far()?;
+        let fv = type_env
+            .iter()
+            .flat_map(|(_, t)| t.substitute(&self.subs).vars())
+            .collect();
+        Ok(ty.substitute(&self.subs).generalise(&fv))
+    }
+
+    fn generate(&mut self, t: &Term, type_env: &mut TypeEnvironment) -> Result<Type, TypeError> {
+        match t {
+            Term::Variable(x) => {
+                let (_, ty) = type_env
+                    .iter()
+                    .rev()
+                    .find(|(v, _)| v == x)
+                    .ok_or_else(|| TypeError::UndefinedVar(x.clone()))?;
+                Ok(ty.instantiate(&mut self.var_count))
+            }
+            Term::Primitive(p) => Ok(p.ty().instantiate(&mut self.var_count)),
+            Term::Constructor(c) => {
+                let ty = declaring(&self.data, c)?
+                    .constructor_type(c)
+                    .expect("the constructor is declared");
+                Ok(ty.instantiate(&mut self.var_count))
+            }
+            Term::Abstraction(x, b) => {
+                let a = self.fresh();
+                type_env.push((x.clone(), a.clone()));
+                let bt = self.generate(b, type_env);
+                type_env.pop();
+                Ok(Type::Fn(Box::new(a), Box::new(bt?)))
+            }
+            Term::Application(f, u) => {
+                let ft = self.generate(f, type_env)?;
+                let ut = self.generate(u, type_env)?;
+                let res = self.fresh();
+                let expected = Type::Fn(Box::new(ut), Box::new(res.clone()));
+                self.require(t, expected, ft);
+                Ok(res)
+            }
+            Term::Let(x, b, o) => {
+                let bt = self.generate(b, type_env)?;
+                let scheme = self.generalise(&bt, type_env)?;
+                type_env.push((x.clone(), scheme));
+                let ot = self.generate(o, type_env);
+                type_env.pop();
+                ot
+            }
+            Term::LetRec(bindings, o) => {
+                check_rebound(t, bindings)?;
+                let depth = type_env.len();
+                let ot = self
+                    .generate_groups(bindings, type_env)
+                    .and_then(|()| self.generate(o, type_env));
+                type_env.truncate(depth);
+                ot
+            }
+            Term::Case(s, branches) => {
+                let st = self.generate(s, type_env)?;
+                let res = self.fresh();
+                for (c, xs, b) in branches {
+                    let (fields, pt) = pattern_types(&self.data, c, xs, &mut self.var_count)?;
+                    self.require(s, pt, st.clone());
+                    type_env.extend(xs.iter().cloned().zip(fields));
+                    let bt = self.generate(b, type_env);
+                    type_env.truncate(type_env.len() - xs.len());
+                    self.require(b, res.clone(), bt?);
+                }
+                if branches.is_empty() {
+                    self.solve_so_far()?;
+                }
+                check_coverage(&self.data, t, &st.substitute(&self.subs), branches)?;
+                Ok(res)
+            }
+            Term::Data(d, o) => {
+                d.check(&self.data)?;
+                self.data.push(d.as_ref().clone());
+                let ot = self.generate(o, type_env);
+                self.data.pop();
+                ot
+            }
+        }
+    }
+
+    /// Push the type schemes of the bindings of a `let rec` onto the environment, one binding
+    /// group at a time.
+    fn generate_groups(
+        &mut self,
+        bindings: &[(String, Term)],
+        type_env: &mut TypeEnvironment,
+    ) -> Result<(), TypeError> {
+        for group in binding_groups(bindings) {
+            let vars: Vec<_> = group.iter().map(|_| self.fresh()).collect();
+            for (&i, a) in group.iter().zip(&vars) {
+                type_env.push((bindings[i].0.clone(), a.clone()));
+            }
+            for (&i, a) in group.iter().zip(&vars) {
+                let b = &bindings[i].1;
+                let bt = self.generate(b, type_env)?;
+                self.require(b, a.clone(), bt);
+            }
+            type_env.truncate(type_env.len() - group.len());
+            for (&i, a) in group.iter().zip(&vars) {
+                let scheme = self.generalise(a, type_env)?;
+                type_env.push((bindings[i].0.clone(), scheme));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}, from {}", self.actual, self.expected, self.term)
+    }
+}
+
+impl fmt::Display for Constraints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ty)?;
+        for c in &self.constraints {
+            write!(f, "\n  {}", c)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applications_constrain_their_functions() {
+        let term = parser::parse("λf x. f x").unwrap();
+        let cs = Constraints::generate(&term, &vec![]).unwrap();
+        let v = |n: usize| Type::Variable(format!("t{}", n));
+        let f = |t, u| Type::Fn(Box::new(t), Box::new(u));
+        assert_eq!(cs.ty, f(v(0), f(v(1), v(2))));
+        assert_eq!(cs.to_string(), "t0 -> t1 -> t2\n  t0 = t1 -> t2, from f x");
+        assert_eq!(cs.solve(), Ok(f(f(v(1), v(2)), f(v(1), v(2)))));
+    }
+
+    #[test]
+    fn the_first_unsatisfiable_constraint_is_blamed() {
+        let term = parser::parse("λx. if x (add x 1) 0").unwrap();
+        let cs = Constraints::generate(&term, &vec![]).unwrap();
+        // Using `x` as a condition is constrained first, so using it as an `Int` is blamed.
+        let err = solve(&cs.constraints).unwrap_err();
+        assert!(matches!(&err, TypeError::Clash { term, .. } if term.to_string() == "add x"));
+        assert_eq!(type_in(&term, &vec![]), Err(err));
+    }
+
+    #[test]
+    fn let_bindings_are_generalised() {
+        let term = parser::parse("let id = λx. x in if (id true) (id 1) 0").unwrap();
+        assert_eq!(type_in(&term, &vec![]), Ok(Type::Int));
+        let fact = "let rec fact = λn. if (eq n 0) 1 (mul n (fact (sub n 1))) in fact";
+        let fact = parser::parse(fact).unwrap();
+        let int = || Box::new(Type::Int);
+        assert_eq!(type_in(&fact, &vec![]), Ok(Type::Fn(int(), int())));
+    }
+
+    proptest! {
+        #[test]
+        fn agrees_with_algorithm_w(t in prop_oneof![any::<Term>(), arb_data_term()]) {
+            match (type_in(&t, &vec![]), t.type_closed()) {
+                (Ok(ty), Ok(w)) => prop_assert!(ty.equivalent(&w), "{} and {}", ty, w),
+                (ty, w) => prop_assert_eq!(ty.is_ok(), w.is_ok()),
+            }
+        }
+    }
+}
@@ -47,52 +47,182 @@ impl Proof {
     }
 }
 
+/// A derivation that got stuck: each rule on the way to the failure, with the premises proven
+/// before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialProof {
+    pub premises: Vec<Proof>,
+    /// The premise that couldn't be derived, unless this rule itself failed.
+    pub stuck: Option<Box<PartialProof>>,
+    pub rule: &'static str,
+    pub lhs: Vec<(String, Type)>,
+    pub term: Term,
+}
+
+/// Why a derivation couldn't be found, and how far it got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stuck {
+    pub error: TypeError,
+    pub proof: Box<PartialProof>,
+}
+
+impl PartialProof {
+    fn substitute(&self, mapping: &HashMap<String, Type>) -> Self {
+        Self {
+            premises: self
+                .premises
+                .iter()
+                .map(|p| p.substitute(mapping))
+                .collect(),
+            stuck: self.stuck.as_ref().map(|p| Box::new(p.substitute(mapping))),
+            rule: self.rule,
+            lhs: self
+                .lhs
+                .iter()
+                .map(|(v, t)| (v.clone(), t.substitute(mapping)))
+                .collect(),
+            term: self.term.clone(),
+        }
+    }
+}
+
+impl Stuck {
+    /// This failure as a premise of `rule`, after proving `premises`.
+    fn within(
+        self,
+        rule: &'static str,
+        premises: Vec<Proof>,
+        type_env: &TypeEnvironment,
+        term: &Term,
+    ) -> Self {
+        Self {
+            error: self.error,
+            proof: Box::new(PartialProof {
+                premises,
+                stuck: Some(self.proof),
+                rule,
+                lhs: type_env.clone(),
+                term: term.clone(),
+            }),
+        }
+    }
+}
+
 fn type_deriv_impl(
     t: &Term,
     type_env: &mut TypeEnvironment,
+    data: &mut Vec<DataDecl>,
     subs: &mut HashMap<String, Type>,
     var_count: &mut usize,
-) -> Option<Proof> {
+) -> Result<Proof, Stuck> {
+    let stuck = |error, rule, premises, type_env: &TypeEnvironment| Stuck {
+        error,
+        proof: Box::new(PartialProof {
+            premises,
+            stuck: None,
+            rule,
+            lhs: type_env.clone(),
+            term: t.clone(),
+        }),
+    };
     match t {
         Term::Variable(x) => {
-            let ty = type_env
-                .iter()
-                .rev()
-                .find(|(v, _)| v == x)
-                .map(|(_, t)| t)?;
-            let var_p = Proof {
-                premises: vec![],
-                rule: "Var",
+            let Some((_, ty)) = type_env.iter().rev().find(|(v, _)| v == x) else {
+                let error = TypeError::UndefinedVar(x.clone());
+                return Err(stuck(error, "Var", vec![], type_env));
+            };
+            Ok(instantiated("Var", ty.clone(), type_env, t, var_count))
+        }
+        Term::Primitive(p) => Ok(instantiated("Const", p.ty(), type_env, t, var_count)),
+        Term::Constructor(c) => match declaring(data, c) {
+            Ok(decl) => {
+                let ty = decl
+                    .constructor_type(c)
+                    .expect("the constructor is declared");
+                Ok(instantiated("Con", ty, type_env, t, var_count))
+            }
+            Err(error) => Err(stuck(error, "Con", vec![], type_env)),
+        },
+        Term::Case(s, branches) => {
+            let sp = type_deriv_impl(s, type_env, data, subs, var_count)
+                .map_err(|e| e.within("Case", vec![], type_env, t))?;
+            let res = Type::Variable(format!("t{}", var_count));
+            *var_count += 1;
+            let mut premises = vec![sp];
+            let proven = |premises: &[Proof], subs: &HashMap<_, _>| {
+                premises.iter().map(|p| p.substitute(subs)).collect()
+            };
+            for (c, xs, b) in branches {
+                let pattern = pattern_types(data, c, xs, var_count).and_then(|(fields, pt)| {
+                    let st = premises[0].ty().substitute(subs);
+                    unify(vec![(st, pt)], subs, s)?;
+                    Ok(fields)
+                });
+                let fields = match pattern {
+                    Ok(fields) => fields,
+                    Err(error) => {
+                        return Err(stuck(error, "Case", proven(&premises, subs), type_env))
+                    }
+                };
+                for (_, t) in type_env.iter_mut() {
+                    *t = t.substitute(subs);
+                }
+                for (x, field) in xs.iter().zip(fields) {
+                    type_env.push((x.clone(), field.substitute(subs)));
+                }
+                let bp = type_deriv_impl(b, type_env, data, subs, var_count);
+                type_env.truncate(type_env.len() - xs.len());
+                let bp = bp.map_err(|e| e.within("Case", proven(&premises, subs), type_env, t))?;
+                let bt = bp.ty().substitute(subs);
+                premises.push(bp);
+                if let Err(error) = unify(vec![(bt, res.substitute(subs))], subs, b) {
+                    return Err(stuck(error, "Case", proven(&premises, subs), type_env));
+                }
+                for (_, t) in type_env.iter_mut() {
+                    *t = t.substitute(subs);
+                }
+            }
+            let st = premises[0].ty().substitute(subs);
+            if let Err(error) = check_coverage(data, t, &st, branches) {
+                return Err(stuck(error, "Case", proven(&premises, subs), type_env));
+            }
+            Ok(Proof {
+                premises: proven(&premises, subs),
+                rule: "Case",
                 conclusion: Judgement {
                     lhs: type_env.clone(),
-                    rhs: (t.clone(), ty.clone()),
+                    rhs: (t.clone(), res.substitute(subs)),
                 },
-            };
-            let inst_ty = ty.instantiate(var_count);
-            if &inst_ty != ty {
-                let inst_p = Proof {
-                    premises: vec![var_p],
-                    rule: "Inst",
-                    conclusion: Judgement {
-                        lhs: type_env.clone(),
-                        rhs: (t.clone(), inst_ty),
-                    },
-                };
-                Some(inst_p)
-            } else {
-                Some(var_p)
+            })
+        }
+        Term::Data(d, body) => {
+            if let Err(error) = d.check(data) {
+                return Err(stuck(error, "Data", vec![], type_env));
             }
+            data.push(d.as_ref().clone());
+            let bp = type_deriv_impl(body, type_env, data, subs, var_count);
+            data.pop();
+            let bp = bp.map_err(|e| e.within("Data", vec![], type_env, t))?;
+            let ty = bp.ty().clone();
+            Ok(Proof {
+                premises: vec![bp],
+                rule: "Data",
+                conclusion: Judgement {
+                    lhs: type_env.clone(),
+                    rhs: (t.clone(), ty),
+                },
+            })
         }
         Term::Abstraction(x, body) => {
             let a = Type::Variable(format!("t{}", var_count));
             *var_count += 1;
             type_env.push((x.to_owned(), a.clone()));
-            let b = type_deriv_impl(body, type_env, subs, var_count);
+            let b = type_deriv_impl(body, type_env, data, subs, var_count);
             type_env.pop();
-            let b = b?;
+            let b = b.map_err(|e| e.within("Abs", vec![], type_env, t))?;
             let a = a.substitute(subs);
             let ty = Type::Fn(Box::new(a), Box::new(b.ty().clone()));
-            Some(Proof {
+            Ok(Proof {
                 premises: vec![b],
                 rule: "Abs",
                 conclusion: Judgement {
@@ -102,20 +232,27 @@ fn type_deriv_impl(
             })
         }
         Term::Application(l, r) => {
-            let f = type_deriv_impl(l, type_env, subs, var_count)?;
-            let a = type_deriv_impl(r, type_env, subs, var_count)?;
+            let f = type_deriv_impl(l, type_env, data, subs, var_count)
+                .map_err(|e| e.within("App", vec![], type_env, t))?;
+            let a = type_deriv_impl(r, type_env, data, subs, var_count).map_err(|e| {
+                let f = f.substitute(subs);
+                e.within("App", vec![f], type_env, t)
+            })?;
             let f = f.substitute(subs);
             let b = Type::Variable(format!("t{}", var_count));
             *var_count += 1;
             let rhs = Type::Fn(Box::new(a.ty().clone()), Box::new(b.clone()));
-            unify(vec![(f.ty().clone(), rhs)], subs)?;
+            if let Err(error) = unify(vec![(f.ty().clone(), rhs)], subs, t) {
+                let premises = vec![f.substitute(subs), a.substitute(subs)];
+                return Err(stuck(error, "App", premises, type_env));
+            }
             for (_, t) in type_env.iter_mut() {
                 *t = t.substitute(subs);
             }
             let f = f.substitute(subs);
             let a = a.substitute(subs);
             let b = b.substitute(subs);
-            Some(Proof {
+            Ok(Proof {
                 premises: vec![f, a],
                 rule: "App",
                 conclusion: Judgement {
@@ -124,14 +261,76 @@ fn type_deriv_impl(
                 },
             })
         }
+        Term::LetRec(bindings, body) => {
+            if let Err(error) = check_rebound(t, bindings) {
+                return Err(stuck(error, "LetRec", vec![], type_env));
+            }
+            let depth = type_env.len();
+            let mut premises = Vec::new();
+            let proven = |premises: &[Proof], subs: &HashMap<_, _>| {
+                premises.iter().map(|p| p.substitute(subs)).collect()
+            };
+            for group in binding_groups(bindings) {
+                let mut vars = Vec::new();
+                for &i in &group {
+                    let a = Type::Variable(format!("t{}", var_count));
+                    *var_count += 1;
+                    type_env.push((bindings[i].0.clone(), a.clone()));
+                    vars.push(a);
+                }
+                for (&i, a) in group.iter().zip(&vars) {
+                    let b = &bindings[i].1;
+                    let bp = match type_deriv_impl(b, type_env, data, subs, var_count) {
+                        Ok(bp) => bp,
+                        Err(e) => {
+                            type_env.truncate(depth);
+                            let premises = proven(&premises, subs);
+                            return Err(e.within("LetRec", premises, type_env, t));
+                        }
+                    };
+                    let bt = bp.ty().substitute(subs);
+                    premises.push(bp);
+                    if let Err(error) = unify(vec![(bt, a.substitute(subs))], subs, b) {
+                        type_env.truncate(depth);
+                        return Err(stuck(error, "LetRec", proven(&premises, subs), type_env));
+                    }
+                    for (_, t) in type_env.iter_mut() {
+                        *t = t.substitute(subs);
+                    }
+                }
+                type_env.truncate(type_env.len() - group.len());
+                let fv = free_vars(type_env);
+                for (&i, a) in group.iter().zip(vars) {
+                    let scheme = a.substitute(subs).generalise(&fv);
+                    type_env.push((bindings[i].0.clone(), scheme));
+                }
+            }
+            let bp = type_deriv_impl(body, type_env, data, subs, var_count);
+            type_env.truncate(depth);
+            let bp = bp.map_err(|e| e.within("LetRec", proven(&premises, subs), type_env, t))?;
+            let ty = bp.ty().clone();
+            premises.push(bp);
+            Ok(Proof {
+                premises: proven(&premises, subs),
+                rule: "LetRec",
+                conclusion: Judgement {
+                    lhs: type_env.clone(),
+                    rhs: (t.clone(), ty),
+                },
+            })
+        }
         Term::Let(x, b, o) => {
-            let bp = type_deriv_impl(b, type_env, subs, var_count)?;
+            let bp = type_deriv_impl(b, type_env, data, subs, var_count)
+                .map_err(|e| e.within("Let", vec![], type_env, t))?;
             let fv = free_vars(type_env);
             let gen_t = bp.ty().generalise(&fv);
             type_env.push((x.to_owned(), gen_t.clone()));
-            let op = type_deriv_impl(o, type_env, subs, var_count);
+            let op = type_deriv_impl(o, type_env, data, subs, var_count);
             type_env.pop();
-            let op = op?;
+            let op = op.map_err(|e| {
+                let bp = bp.substitute(subs);
+                e.within("Let", vec![bp], type_env, t)
+            })?;
             let bp = bp.substitute(subs);
             let bt = bp.ty().clone();
             let let_p = Proof {
@@ -151,16 +350,55 @@ fn type_deriv_impl(
                         rhs: (t.clone(), gen_t),
                     },
                 };
-                Some(gen_p)
+                Ok(gen_p)
             } else {
-                Some(let_p)
+                Ok(let_p)
             }
         }
     }
 }
 
-pub fn type_deriv(t: &Term, type_env: &TypeEnvironment) -> Option<Proof> {
-    type_deriv_impl(t, &mut type_env.clone(), &mut HashMap::new(), &mut 0)
+/// The axiom giving `t` the type scheme `ty`, followed by an instantiation if it is polymorphic.
+fn instantiated(
+    rule: &'static str,
+    ty: Type,
+    type_env: &TypeEnvironment,
+    t: &Term,
+    var_count: &mut usize,
+) -> Proof {
+    let inst_ty = ty.instantiate(var_count);
+    let polymorphic = inst_ty != ty;
+    let axiom = Proof {
+        premises: vec![],
+        rule,
+        conclusion: Judgement {
+            lhs: type_env.clone(),
+            rhs: (t.clone(), ty),
+        },
+    };
+    if polymorphic {
+        Proof {
+            premises: vec![axiom],
+            rule: "Inst",
+            conclusion: Judgement {
+                lhs: type_env.clone(),
+                rhs: (t.clone(), inst_ty),
+            },
+        }
+    } else {
+        axiom
+    }
+}
+
+/// A derivation of the type of `t`, or the partial derivation found before it got stuck.
+pub fn type_deriv(t: &Term, type_env: &TypeEnvironment) -> Result<Proof, Stuck> {
+    let mut subs = HashMap::new();
+    type_deriv_impl(t, &mut type_env.clone(), &mut Vec::new(), &mut subs, &mut 0).map_err(|e| {
+        Stuck {
+            error: e.error,
+            proof: Box::new(e.proof.substitute(&subs)),
+        }
+    })
 }
 
 impl fmt::Display for Judgement {
@@ -202,22 +440,58 @@ impl fmt::Display for Proof {
     }
 }
 
+impl fmt::Display for PartialProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        partial_lines(self).join("\n").fmt(f)
+    }
+}
+
+impl fmt::Display for Stuck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.error, self.proof)
+    }
+}
+
 fn proof_lines(p: &Proof) -> Vec<String> {
-    let mut top = join(p.premises.iter().map(proof_lines));
-    let top_rule_width = if p.premises.len() == 1 {
+    let premises: Vec<_> = p.premises.iter().map(proof_lines).collect();
+    rule_lines(premises, p.conclusion.to_string(), p.rule)
+}
+
+/// The lines of a partial proof, whose stuck judgements have `?` for a type.
+fn partial_lines(p: &PartialProof) -> Vec<String> {
+    let premises = p
+        .premises
+        .iter()
+        .map(proof_lines)
+        .chain(p.stuck.iter().map(|s| partial_lines(s)))
+        .collect();
+    let lhs = WriteSep {
+        xs: &p.lhs,
+        sep: ", ",
+        w: |pair: &(String, Type), f: &mut fmt::Formatter<'_>| write!(f, "{}: {}", pair.0, pair.1),
+    };
+    rule_lines(premises, format!("{} ⊢ {}: ?", lhs, p.term), p.rule)
+}
+
+/// The lines of an inference rule, with the lines of each premise side by side above the
+/// conclusion.
+fn rule_lines(premises: Vec<Vec<String>>, conclusion: String, rule: &str) -> Vec<String> {
+    let single = premises.len() == 1;
+    let mut top = join(premises.into_iter());
+    let top_rule_width = if single {
         measure_text_width(top[top.len() - 2].trim_start_matches('─'))
     } else {
         0
     };
     let top_width =
         measure_text_width(top.first().map(|s| s.as_str()).unwrap_or("")) - top_rule_width;
-    let mut bottom = p.conclusion.to_string();
+    let mut bottom = conclusion;
     let n = top_width.max(measure_text_width(&bottom));
     for line in top.iter_mut() {
         *line = pad_str(line, n + top_rule_width, Alignment::Right, None).to_string();
     }
     bottom = pad_str(&bottom, n, Alignment::Center, None).to_string();
-    let rule_tag = format!(" [{}]", p.rule);
+    let rule_tag = format!(" [{}]", rule);
     let rule_width = top_rule_width.max(measure_text_width(&rule_tag));
     for line in top.iter_mut() {
         *line = pad_str(line, n + rule_width, Alignment::Left, None).to_string();
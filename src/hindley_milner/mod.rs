@@ -1,16 +1,30 @@
 use std::collections::{HashMap, HashSet};
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 use super::*;
+use crate::locally_nameless::{Ln, LocallyNameless, Scope};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
+pub mod algorithm_m;
+pub mod constraints;
 pub mod derivation;
+pub mod parser;
 pub mod rec_scheme;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Variable(String),
     GenVariable(String),
     Fn(Box<Type>, Box<Type>),
+    Int,
+    Bool,
+    /// A declared data type applied to its parameters.
+    Data(String, Vec<Type>),
 }
 
 impl Type {
@@ -23,11 +37,15 @@ impl Type {
                     self.clone()
                 }
             }
-            Self::GenVariable(_) => self.clone(),
+            Self::GenVariable(_) | Self::Int | Self::Bool => self.clone(),
             Self::Fn(t, u) => Self::Fn(
                 Box::new(t.substitute(mapping)),
                 Box::new(u.substitute(mapping)),
             ),
+            Self::Data(d, args) => Self::Data(
+                d.clone(),
+                args.iter().map(|t| t.substitute(mapping)).collect(),
+            ),
         }
     }
 
@@ -40,43 +58,50 @@ impl Type {
                     self.clone()
                 }
             }
-            Self::Variable(_) => self.clone(),
+            Self::Variable(_) | Self::Int | Self::Bool => self.clone(),
             Self::Fn(t, u) => Self::Fn(
                 Box::new(t.gen_substitute(mapping)),
                 Box::new(u.gen_substitute(mapping)),
             ),
+            Self::Data(d, args) => Self::Data(
+                d.clone(),
+                args.iter().map(|t| t.gen_substitute(mapping)).collect(),
+            ),
         }
     }
 
     pub fn vars(&self) -> HashSet<String> {
         match self {
             Self::Variable(v) => [v.to_owned()].into(),
-            Self::GenVariable(_) => HashSet::new(),
+            Self::GenVariable(_) | Self::Int | Self::Bool => HashSet::new(),
             Self::Fn(t, u) => {
                 let mut vs = t.vars();
                 vs.extend(u.vars());
                 vs
             }
+            Self::Data(_, args) => args.iter().flat_map(Self::vars).collect(),
         }
     }
 
     pub fn gen_vars(&self) -> HashSet<String> {
         match self {
             Self::GenVariable(v) => [v.to_owned()].into(),
-            Self::Variable(_) => HashSet::new(),
+            Self::Variable(_) | Self::Int | Self::Bool => HashSet::new(),
             Self::Fn(t, u) => {
                 let mut vs = t.gen_vars();
                 vs.extend(u.gen_vars());
                 vs
             }
+            Self::Data(_, args) => args.iter().flat_map(Self::gen_vars).collect(),
         }
     }
 
     pub fn contains_var(&self, var: &str) -> bool {
         match self {
             Self::Variable(v) => v == var,
-            Self::GenVariable(_) => false,
+            Self::GenVariable(_) | Self::Int | Self::Bool => false,
             Self::Fn(t, u) => t.contains_var(var) || u.contains_var(var),
+            Self::Data(_, args) => args.iter().any(|t| t.contains_var(var)),
         }
     }
 
@@ -100,6 +125,10 @@ impl Type {
                 Box::new(t.generalise(free_vars)),
                 Box::new(u.generalise(free_vars)),
             ),
+            Self::Data(d, args) => Self::Data(
+                d.clone(),
+                args.iter().map(|t| t.generalise(free_vars)).collect(),
+            ),
             _ => self.clone(),
         }
     }
@@ -137,19 +166,93 @@ impl Type {
             (Self::Fn(t1, u1), Self::Fn(t2, u2)) => {
                 t1.equivalent_impl(names, gen_names, t2) && u1.equivalent_impl(names, gen_names, u2)
             }
+            (Self::Int, Self::Int) | (Self::Bool, Self::Bool) => true,
+            (Self::Data(d1, args1), Self::Data(d2, args2)) => {
+                d1 == d2
+                    && args1.len() == args2.len()
+                    && args1
+                        .iter()
+                        .zip(args2)
+                        .all(|(t, u)| t.equivalent_impl(names, gen_names, u))
+            }
             _ => false,
         }
     }
 }
 
-fn unify(mut equalities: Vec<(Type, Type)>, mapping: &mut HashMap<String, Type>) -> Option<()> {
+/// Why a term has no type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    UndefinedVar(String),
+    /// Two types built with different constructors had to be equal while checking `term`.
+    Clash {
+        term: Box<Term>,
+        expected: Type,
+        actual: Type,
+    },
+    /// A type variable had to equal a type containing it while checking `term`, which would
+    /// make it infinite.
+    OccursCheck {
+        term: Box<Term>,
+        var: String,
+        ty: Type,
+    },
+    UndefinedConstructor(String),
+    UndefinedType(String),
+    /// A type variable in a data declaration that isn't one of its parameters, or is a free
+    /// [`Type::Variable`] rather than a parameter.
+    UndefinedTypeVar(String),
+    /// A data type applied to the wrong number of parameters.
+    TypeArity {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// A pattern binding a different number of variables than its constructor has fields.
+    PatternArity {
+        constructor: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// A `case` with two branches for the same constructor.
+    Redundant {
+        term: Box<Term>,
+        constructor: String,
+    },
+    /// A `case` with no branch for some constructors of the type of its scrutinee.
+    NonExhaustive {
+        term: Box<Term>,
+        missing: Vec<String>,
+    },
+    /// A `let rec` binding the same variable twice.
+    Rebound {
+        term: Box<Term>,
+        var: String,
+    },
+}
+
+/// Unify each pair of types, the actual type first and the expected one second, extending
+/// `mapping` with the substitution found. Errors blame `term`, and have the substitution
+/// found so far applied to them.
+fn unify(
+    mut equalities: Vec<(Type, Type)>,
+    mapping: &mut HashMap<String, Type>,
+    term: &Term,
+) -> Result<(), TypeError> {
     while let Some(equality) = equalities.pop() {
         match equality {
             (lhs, rhs) if lhs == rhs => continue,
             (Type::Fn(t1, u1), Type::Fn(t2, u2)) => {
-                equalities.push((*t1, *t2));
+                // The domains swap roles: the expected function type's domain is the actual
+                // argument type.
+                equalities.push((*t2, *t1));
                 equalities.push((*u1, *u2));
             }
+            (Type::Data(d1, args1), Type::Data(d2, args2))
+                if d1 == d2 && args1.len() == args2.len() =>
+            {
+                equalities.extend(args1.into_iter().zip(args2));
+            }
             (Type::Variable(v), t) | (t, Type::Variable(v)) if !t.contains_var(&v) => {
                 let new_mapping = [(v, t)].into();
                 for (a, b) in equalities.iter_mut() {
@@ -161,10 +264,47 @@ fn unify(mut equalities: Vec<(Type, Type)>, mapping: &mut HashMap<String, Type>)
                 }
                 mapping.extend(new_mapping);
             }
-            _ => return None,
+            (Type::Variable(var), ty) | (ty, Type::Variable(var)) => {
+                return Err(TypeError::OccursCheck {
+                    term: Box::new(term.clone()),
+                    var,
+                    ty: ty.substitute(mapping),
+                })
+            }
+            (actual, expected) => {
+                return Err(TypeError::Clash {
+                    term: Box::new(term.clone()),
+                    expected: expected.substitute(mapping),
+                    actual: actual.substitute(mapping),
+                })
+            }
         }
     }
-    Some(())
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Prim {
+    Lit(Lit),
+    Func(PrimFunc),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lit {
+    IntLit(isize),
+    BoolLit(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrimFunc {
+    Add,
+    Sub,
+    Mul,
+    IntEq,
+    Gt,
+    Or,
+    Not,
+    If,
 }
 
 #[derive(Debug, Clone, Eq)]
@@ -173,10 +313,316 @@ pub enum Term {
     Abstraction(String, Box<Term>),
     Application(Box<Term>, Box<Term>),
     Let(String, Box<Term>, Box<Term>),
+    /// A group of mutually recursive bindings, each in scope in all of them and in the term.
+    LetRec(Vec<(String, Term)>, Box<Term>),
+    Primitive(Prim),
+    Constructor(String),
+    /// A `case` on the scrutinee, with a branch for each constructor binding its fields.
+    Case(Box<Term>, Vec<(String, Vec<String>, Term)>),
+    /// A data declaration, in scope in the term.
+    Data(Box<DataDecl>, Box<Term>),
 }
 
 pub type TypeEnvironment = Vec<(String, Type)>;
 
+/// A declaration `data T a = C a (T a) | D` of a data type with parameters, and its
+/// constructors with the types of their fields.
+///
+/// The parameters appear in the fields as [`Type::GenVariable`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DataDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub constructors: Vec<(String, Vec<Type>)>,
+}
+
+impl DataDecl {
+    /// The type scheme of one of the constructors, as a curried function of its fields.
+    pub fn constructor_type(&self, constructor: &str) -> Option<Type> {
+        let (_, fields) = self.constructors.iter().find(|(c, _)| c == constructor)?;
+        let params = self.params.iter().cloned().map(Type::GenVariable).collect();
+        let ty = fields
+            .iter()
+            .rev()
+            .fold(Type::Data(self.name.clone(), params), |ty, field| {
+                Type::Fn(Box::new(field.clone()), Box::new(ty))
+            });
+        Some(ty)
+    }
+
+    /// Check that the fields only use the parameters and data types declared so far, or this
+    /// one.
+    fn check(&self, data: &[DataDecl]) -> Result<(), TypeError> {
+        let mut fields: Vec<_> = self.constructors.iter().flat_map(|(_, f)| f).collect();
+        while let Some(ty) = fields.pop() {
+            match ty {
+                // A free variable wouldn't be instantiated afresh at each use of a constructor.
+                Type::GenVariable(a) if !self.params.contains(a) => {
+                    return Err(TypeError::UndefinedTypeVar(a.clone()))
+                }
+                Type::Variable(a) => return Err(TypeError::UndefinedTypeVar(a.clone())),
+                Type::Fn(t, u) => fields.extend([t.as_ref(), u.as_ref()]),
+                Type::Data(name, args) => {
+                    let expected = if name == &self.name {
+                        self.params.len()
+                    } else {
+                        let decl = data.iter().rev().find(|d| &d.name == name);
+                        decl.ok_or_else(|| TypeError::UndefinedType(name.clone()))?
+                            .params
+                            .len()
+                    };
+                    if args.len() != expected {
+                        return Err(TypeError::TypeArity {
+                            name: name.clone(),
+                            expected,
+                            actual: args.len(),
+                        });
+                    }
+                    fields.extend(args);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The innermost declaration of the constructor.
+fn declaring<'a>(data: &'a [DataDecl], constructor: &str) -> Result<&'a DataDecl, TypeError> {
+    data.iter()
+        .rev()
+        .find(|d| d.constructors.iter().any(|(c, _)| c == constructor))
+        .ok_or_else(|| TypeError::UndefinedConstructor(constructor.to_owned()))
+}
+
+/// The types of the variables bound by a pattern, and the type of the value it matches.
+fn pattern_types(
+    data: &[DataDecl],
+    constructor: &str,
+    vars: &[String],
+    var_count: &mut usize,
+) -> Result<(Vec<Type>, Type), TypeError> {
+    let decl = declaring(data, constructor)?;
+    let mut ty = decl
+        .constructor_type(constructor)
+        .expect("the constructor is declared")
+        .instantiate(var_count);
+    let mut fields = Vec::new();
+    while let Type::Fn(field, rest) = ty {
+        fields.push(*field);
+        ty = *rest;
+    }
+    if fields.len() != vars.len() {
+        return Err(TypeError::PatternArity {
+            constructor: constructor.to_owned(),
+            expected: fields.len(),
+            actual: vars.len(),
+        });
+    }
+    Ok((fields, ty))
+}
+
+/// Check that the branches of `term` cover each constructor of the type they match exactly
+/// once, where `scrutinee` is the type found for what they match so far.
+fn check_coverage(
+    data: &[DataDecl],
+    term: &Term,
+    scrutinee: &Type,
+    branches: &[(String, Vec<String>, Term)],
+) -> Result<(), TypeError> {
+    for (i, (c, _, _)) in branches.iter().enumerate() {
+        if branches[..i].iter().any(|(d, _, _)| c == d) {
+            return Err(TypeError::Redundant {
+                term: Box::new(term.clone()),
+                constructor: c.clone(),
+            });
+        }
+    }
+    let decl = match (branches.first(), scrutinee) {
+        (Some((c, _, _)), _) => declaring(data, c)?,
+        // With no branches, only the type of the scrutinee says which constructors are missed.
+        (None, Type::Data(name, _)) => match data.iter().rev().find(|d| &d.name == name) {
+            Some(decl) => decl,
+            None => return Ok(()),
+        },
+        (None, _) => return Ok(()),
+    };
+    let missing: Vec<_> = decl
+        .constructors
+        .iter()
+        .map(|(d, _)| d)
+        .filter(|d| !branches.iter().any(|(c, _, _)| &c == d))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(TypeError::NonExhaustive {
+            term: Box::new(term.clone()),
+            missing,
+        })
+    }
+}
+
+/// The branch of a `case` chosen by its scrutinee, with the fields substituted, if the
+/// scrutinee is a constructor applied to all of them.
+fn select_branch(scrutinee: &Term, branches: &[(String, Vec<String>, Term)]) -> Option<Term> {
+    let mut head = scrutinee;
+    let mut args = Vec::new();
+    while let Term::Application(t, u) = head {
+        args.push(u.as_ref());
+        head = t;
+    }
+    args.reverse();
+    let Term::Constructor(c) = head else {
+        return None;
+    };
+    let (_, xs, body) = branches
+        .iter()
+        .find(|(d, xs, _)| c == d && xs.len() == args.len())?;
+    // Rename the fields apart first, so that substituting one argument can't capture another.
+    let mut vars = body.vars();
+    vars.extend(args.iter().flat_map(|a| a.vars()));
+    vars.extend(xs.iter().cloned());
+    let mut fresh = Vec::new();
+    let mut body = body.clone();
+    // Later fields shadow earlier ones with the same name.
+    for (x, arg) in xs.iter().zip(args).rev() {
+        let y = fresh_var(&vars);
+        vars.insert(y.clone());
+        body = body.substitute(x, &Term::Variable(y.clone()));
+        fresh.push((y, arg));
+    }
+    Some(
+        fresh
+            .iter()
+            .fold(body, |body, (y, arg)| body.substitute(y, arg)),
+    )
+}
+
+impl Prim {
+    /// The type scheme of the primitive.
+    pub fn ty(self) -> Type {
+        match self {
+            Self::Lit(Lit::IntLit(_)) => Type::Int,
+            Self::Lit(Lit::BoolLit(_)) => Type::Bool,
+            Self::Func(f) => f.ty(),
+        }
+    }
+
+    /// A Church encoding of the primitive, where an integer is a pair of numerals whose
+    /// difference it is.
+    fn church(self) -> untyped::Term {
+        const ZERO: &str = "(λf x. x)";
+        const PLUS: &str = "(λm n f x. m f (n f x))";
+        const TIMES: &str = "(λm n f. m (n f))";
+        const NOT: &str = "(λp t f. p f t)";
+        let leq = "(λm n. (λk. k (λx t f. f) (λt f. t)) \
+            (n (λn f x. n (λg h. h (g f)) (λu. x) (λu. u)) m))";
+        let source = match self {
+            Self::Lit(Lit::IntLit(n)) if n < 0 => {
+                format!("λs. s {} {}", ZERO, numeral(n.unsigned_abs()))
+            }
+            Self::Lit(Lit::IntLit(n)) => format!("λs. s {} {}", numeral(n as usize), ZERO),
+            Self::Lit(Lit::BoolLit(true)) => "λt f. t".into(),
+            Self::Lit(Lit::BoolLit(false)) => "λt f. f".into(),
+            Self::Func(PrimFunc::Add) => {
+                format!("λi j s. i (λa b. j (λc d. s ({PLUS} a c) ({PLUS} b d)))")
+            }
+            Self::Func(PrimFunc::Sub) => {
+                format!("λi j s. i (λa b. j (λc d. s ({PLUS} a d) ({PLUS} b c)))")
+            }
+            Self::Func(PrimFunc::Mul) => format!(
+                "λi j s. i (λa b. j (λc d. s \
+                    ({PLUS} ({TIMES} a c) ({TIMES} b d)) ({PLUS} ({TIMES} a d) ({TIMES} b c))))"
+            ),
+            Self::Func(PrimFunc::IntEq) => format!(
+                "λi j. i (λa b. j (λc d. (λp q t f. p (q t f) f) \
+                    ({leq} ({PLUS} a d) ({PLUS} b c)) ({leq} ({PLUS} b c) ({PLUS} a d))))"
+            ),
+            Self::Func(PrimFunc::Gt) => {
+                format!("λi j. i (λa b. j (λc d. {NOT} ({leq} ({PLUS} a d) ({PLUS} b c))))")
+            }
+            Self::Func(PrimFunc::Or) => "λp q t f. p t (q t f)".into(),
+            Self::Func(PrimFunc::Not) => NOT.into(),
+            Self::Func(PrimFunc::If) => "λc t e. c t e".into(),
+        };
+        parser::parse(source)
+            .expect("encodings should parse")
+            .into()
+    }
+}
+
+/// The source of a Church numeral, built by doubling so that it stays small.
+fn numeral(n: usize) -> String {
+    const DOUBLE: &str = "(λn f x. n f (n f x))";
+    const SUCC: &str = "(λn f x. f (n f x))";
+    match n {
+        0 => "(λf x. x)".into(),
+        n if n % 2 == 0 => format!("({} {})", DOUBLE, numeral(n / 2)),
+        n => format!("({} ({} {}))", SUCC, DOUBLE, numeral(n / 2)),
+    }
+}
+
+impl PrimFunc {
+    pub fn ty(self) -> Type {
+        let f = |t, u| Type::Fn(Box::new(t), Box::new(u));
+        match self {
+            Self::Add | Self::Sub | Self::Mul => f(Type::Int, f(Type::Int, Type::Int)),
+            Self::IntEq | Self::Gt => f(Type::Int, f(Type::Int, Type::Bool)),
+            Self::Or => f(Type::Bool, f(Type::Bool, Type::Bool)),
+            Self::Not => f(Type::Bool, Type::Bool),
+            Self::If => {
+                let a = || Type::GenVariable("a".into());
+                f(Type::Bool, f(a(), f(a(), a())))
+            }
+        }
+    }
+
+    pub fn reduce_unary(self, arg: &Term) -> Option<Term> {
+        match (self, arg) {
+            (Self::Not, Term::Primitive(Prim::Lit(Lit::BoolLit(b)))) => {
+                Some(Term::Primitive(Prim::Lit(Lit::BoolLit(!b))))
+            }
+            (Self::If, Term::Primitive(Prim::Lit(Lit::BoolLit(cond)))) => {
+                let taken = if *cond { "t" } else { "e" };
+                Some(Term::Abstraction(
+                    "t".into(),
+                    Box::new(Term::Abstraction(
+                        "e".into(),
+                        Box::new(Term::Variable(taken.into())),
+                    )),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn reduce_binary(self, a: &Term, b: &Term) -> Option<Term> {
+        let lit = match (self, a, b) {
+            (
+                _,
+                Term::Primitive(Prim::Lit(Lit::IntLit(x))),
+                Term::Primitive(Prim::Lit(Lit::IntLit(y))),
+            ) => match self {
+                Self::Add => Lit::IntLit(x.wrapping_add(*y)),
+                Self::Sub => Lit::IntLit(x.wrapping_sub(*y)),
+                Self::Mul => Lit::IntLit(x.wrapping_mul(*y)),
+                Self::IntEq => Lit::BoolLit(x == y),
+                Self::Gt => Lit::BoolLit(x > y),
+                Self::Or | Self::Not | Self::If => return None,
+            },
+            (
+                Self::Or,
+                Term::Primitive(Prim::Lit(Lit::BoolLit(x))),
+                Term::Primitive(Prim::Lit(Lit::BoolLit(y))),
+            ) => Lit::BoolLit(x | y),
+            _ => return None,
+        };
+        Some(Term::Primitive(Prim::Lit(lit)))
+    }
+}
+
 impl Term {
     pub fn vars(&self) -> HashSet<String> {
         match self {
@@ -201,6 +647,24 @@ impl Term {
                 vars.insert(v.to_owned());
                 vars
             }
+            Self::LetRec(bindings, t) => {
+                let mut vars = t.vars();
+                for (f, b) in bindings {
+                    vars.extend(b.vars());
+                    vars.insert(f.to_owned());
+                }
+                vars
+            }
+            Self::Primitive(_) | Self::Constructor(_) => HashSet::new(),
+            Self::Case(t, branches) => {
+                let mut vars = t.vars();
+                for (_, xs, b) in branches {
+                    vars.extend(b.vars());
+                    vars.extend(xs.iter().cloned());
+                }
+                vars
+            }
+            Self::Data(_, t) => t.vars(),
         }
     }
 
@@ -227,6 +691,29 @@ impl Term {
                 vars.extend(b.free_vars());
                 vars
             }
+            Self::LetRec(bindings, t) => {
+                let mut vars = t.free_vars();
+                for (_, b) in bindings {
+                    vars.extend(b.free_vars());
+                }
+                for (f, _) in bindings {
+                    vars.remove(f);
+                }
+                vars
+            }
+            Self::Primitive(_) | Self::Constructor(_) => HashSet::new(),
+            Self::Case(t, branches) => {
+                let mut vars = t.free_vars();
+                for (_, xs, b) in branches {
+                    let mut bvars = b.free_vars();
+                    for x in xs {
+                        bvars.remove(x);
+                    }
+                    vars.extend(bvars);
+                }
+                vars
+            }
+            Self::Data(_, t) => t.free_vars(),
         }
     }
 
@@ -234,7 +721,7 @@ impl Term {
     fn rename(&self, from: &str, to: &str) -> Self {
         match self {
             Self::Variable(v) if v == from => Self::Variable(to.to_owned()),
-            Self::Variable(_) => self.clone(),
+            Self::Variable(_) | Self::Primitive(_) | Self::Constructor(_) => self.clone(),
             Self::Abstraction(v, t) => Self::Abstraction(
                 if v == from {
                     to.to_owned()
@@ -255,32 +742,43 @@ impl Term {
                 Box::new(b.rename(from, to)),
                 Box::new(t.rename(from, to)),
             ),
+            Self::LetRec(bindings, t) => {
+                let bindings = bindings
+                    .iter()
+                    .map(|(f, b)| {
+                        let f = if f == from { to.to_owned() } else { f.clone() };
+                        (f, b.rename(from, to))
+                    })
+                    .collect();
+                Self::LetRec(bindings, Box::new(t.rename(from, to)))
+            }
+            Self::Case(t, branches) => {
+                let rename = |x: &String| if x == from { to.to_owned() } else { x.clone() };
+                let branches = branches
+                    .iter()
+                    .map(|(c, xs, b)| {
+                        (
+                            c.clone(),
+                            xs.iter().map(rename).collect(),
+                            b.rename(from, to),
+                        )
+                    })
+                    .collect();
+                Self::Case(Box::new(t.rename(from, to)), branches)
+            }
+            Self::Data(d, t) => Self::Data(d.clone(), Box::new(t.rename(from, to))),
         }
     }
 
     pub fn alpha_equivalent(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Variable(x), Self::Variable(y)) => x == y,
-            (Self::Abstraction(x, t), Self::Abstraction(y, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            (Self::Application(t1, u1), Self::Application(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            (Self::Let(x, b1, t), Self::Let(y, b2, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                b1.alpha_equivalent(b2) && t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            _ => false,
-        }
+        self.to_locally_nameless() == other.to_locally_nameless()
     }
 
     /// Substitute the given term avoiding capture.
     pub fn substitute(&self, from: &str, to: &Term) -> Self {
         match self {
             Self::Variable(v) if v == from => to.to_owned(),
-            Self::Variable(_) => self.clone(),
+            Self::Variable(_) | Self::Primitive(_) | Self::Constructor(_) => self.clone(),
             Self::Abstraction(v, _) if v == from => self.clone(),
             Self::Abstraction(v, t) => {
                 let mut vars = self.vars();
@@ -303,30 +801,118 @@ impl Term {
                 let new_body = Box::new(t.rename(v, &w).substitute(from, to));
                 Self::Let(w, Box::new(b.substitute(from, to)), new_body)
             }
+            Self::LetRec(bindings, _) if bindings.iter().any(|(f, _)| f == from) => self.clone(),
+            Self::LetRec(bindings, t) => {
+                let mut vars = self.vars();
+                vars.extend(to.vars());
+                vars.insert(from.to_owned());
+                let mut bindings = bindings.clone();
+                let mut body = t.as_ref().clone();
+                // Later bindings shadow earlier ones with the same name.
+                for i in (0..bindings.len()).rev() {
+                    let f = bindings[i].0.clone();
+                    let w = fresh_var(&vars);
+                    vars.insert(w.clone());
+                    for (_, b) in bindings.iter_mut() {
+                        *b = b.rename(&f, &w);
+                    }
+                    body = body.rename(&f, &w);
+                    bindings[i].0 = w;
+                }
+                let bindings = bindings
+                    .into_iter()
+                    .map(|(f, b)| (f, b.substitute(from, to)))
+                    .collect();
+                Self::LetRec(bindings, Box::new(body.substitute(from, to)))
+            }
+            Self::Case(t, branches) => {
+                let branches = branches
+                    .iter()
+                    .map(|(c, xs, b)| {
+                        if xs.iter().any(|x| x == from) {
+                            return (c.clone(), xs.clone(), b.clone());
+                        }
+                        let mut vars = b.vars();
+                        vars.extend(to.vars());
+                        vars.extend(xs.iter().cloned());
+                        vars.insert(from.to_owned());
+                        let mut body = b.clone();
+                        let mut ws = Vec::new();
+                        // Later fields shadow earlier ones with the same name.
+                        for x in xs.iter().rev() {
+                            let w = fresh_var(&vars);
+                            vars.insert(w.clone());
+                            body = body.rename(x, &w);
+                            ws.push(w);
+                        }
+                        ws.reverse();
+                        (c.clone(), ws, body.substitute(from, to))
+                    })
+                    .collect();
+                Self::Case(Box::new(t.substitute(from, to)), branches)
+            }
+            Self::Data(d, t) => Self::Data(d.clone(), Box::new(t.substitute(from, to))),
         }
     }
 
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
-                    ))
-                } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    return Some((b.substitute(x, u), Redex::root(Rule::Beta)));
+                }
+                if let Self::Primitive(Prim::Func(f)) = t.as_ref() {
+                    if let Some(res) = f.reduce_unary(u) {
+                        return Some((res, Redex::root(Rule::Delta)));
+                    }
                 }
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    return Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
+                    ));
+                }
+                if let Self::Application(op, a) = t.as_ref() {
+                    if let Self::Primitive(Prim::Func(f)) = op.as_ref() {
+                        if let Some(res) = f.reduce_binary(a, u) {
+                            return Some((res, Redex::root(Rule::Delta)));
+                        }
+                    }
+                }
+                u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                    let t = Box::new(t.as_ref().clone());
+                    (Self::Application(t, Box::new(u2)), r.within(1))
+                })
             }
             Self::Abstraction(x, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), Box::new(t2))),
-            Self::Let(x, b, t) => Some(t.substitute(x, b)),
+                .beta_reduce_lazy_with_redex()
+                .map(|(t2, r)| (Self::Abstraction(x.to_owned(), Box::new(t2)), r.within(0))),
+            Self::Let(x, b, t) => Some((t.substitute(x, b), Redex::root(Rule::Zeta))),
+            Self::LetRec(bindings, t) => Some((unfold(bindings, t), Redex::root(Rule::Zeta))),
+            Self::Case(t, branches) => {
+                if let Some(res) = select_branch(t, branches) {
+                    return Some((res, Redex::root(Rule::Iota)));
+                }
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    return Some((Self::Case(Box::new(t2), branches.clone()), r.within(0)));
+                }
+                branches.iter().enumerate().find_map(|(i, (_, _, b))| {
+                    let (b2, r) = b.beta_reduce_lazy_with_redex()?;
+                    let mut branches = branches.clone();
+                    branches[i].2 = b2;
+                    Some((Self::Case(t.clone(), branches), r.within(i + 1)))
+                })
+            }
+            Self::Data(d, t) => t
+                .beta_reduce_lazy_with_redex()
+                .map(|(t2, r)| (Self::Data(d.clone(), Box::new(t2)), r.within(0))),
             _ => None,
         }
     }
@@ -336,10 +922,20 @@ impl Term {
     pub fn parallel_reduct(&self) -> Option<Self> {
         match self {
             Self::Application(t, u) => {
+                let delta = match t.as_ref() {
+                    Self::Primitive(Prim::Func(f)) => f.reduce_unary(u),
+                    Self::Application(op, a) => match op.as_ref() {
+                        Self::Primitive(Prim::Func(f)) => f.reduce_binary(a, u),
+                        _ => None,
+                    },
+                    _ => None,
+                };
                 if let Self::Abstraction(x, b) = t.as_ref() {
                     let b2 = b.parallel_reduct().unwrap_or_else(|| b.as_ref().clone());
                     let u2 = u.parallel_reduct().unwrap_or_else(|| u.as_ref().clone());
                     Some(b2.substitute(x, &u2))
+                } else if delta.is_some() {
+                    delta
                 } else {
                     match (t.parallel_reduct(), u.parallel_reduct()) {
                         (None, None) => None,
@@ -360,6 +956,39 @@ impl Term {
                 let t2 = t.parallel_reduct().unwrap_or_else(|| t.as_ref().clone());
                 Some(t2.substitute(x, &b2))
             }
+            Self::LetRec(bindings, t) => {
+                let bindings: Vec<_> = bindings
+                    .iter()
+                    .map(|(f, b)| (f.clone(), b.parallel_reduct().unwrap_or_else(|| b.clone())))
+                    .collect();
+                let t2 = t.parallel_reduct().unwrap_or_else(|| t.as_ref().clone());
+                Some(unfold(&bindings, &t2))
+            }
+            Self::Case(t, branches) => {
+                if let Some(res) = select_branch(t, branches) {
+                    return Some(res);
+                }
+                let t2 = t.parallel_reduct();
+                let b2: Vec<_> = branches
+                    .iter()
+                    .map(|(_, _, b)| b.parallel_reduct())
+                    .collect();
+                if t2.is_none() && b2.iter().all(Option::is_none) {
+                    return None;
+                }
+                let branches = branches
+                    .iter()
+                    .zip(b2)
+                    .map(|((c, xs, b), b2)| {
+                        (c.clone(), xs.clone(), b2.unwrap_or_else(|| b.clone()))
+                    })
+                    .collect();
+                let t2 = t2.unwrap_or_else(|| t.as_ref().clone());
+                Some(Self::Case(Box::new(t2), branches))
+            }
+            Self::Data(d, t) => t
+                .parallel_reduct()
+                .map(|t2| Self::Data(d.clone(), Box::new(t2))),
             _ => None,
         }
     }
@@ -367,47 +996,83 @@ impl Term {
     /// Eta-reduce the left-outermost simplification if one exists.
     /// This does not check the type.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex()
+                    .map(|(u2, r)| (Self::Abstraction(x.to_owned(), Box::new(u2)), r.within(0)))
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::Let(x, b, t) => {
-                if let Some(b2) = b.eta_reduce_lazy() {
-                    Some(Self::Let(
-                        x.to_owned(),
-                        Box::new(b2),
-                        Box::new(t.as_ref().clone()),
-                    ))
+                if let Some((b2, r)) = b.eta_reduce_lazy_with_redex() {
+                    let t = Box::new(t.as_ref().clone());
+                    Some((Self::Let(x.to_owned(), Box::new(b2), t), r.within(0)))
                 } else {
-                    t.eta_reduce_lazy().map(|t2| {
-                        Self::Let(x.to_owned(), Box::new(b.as_ref().clone()), Box::new(t2))
+                    t.eta_reduce_lazy_with_redex().map(|(t2, r)| {
+                        let b = Box::new(b.as_ref().clone());
+                        (Self::Let(x.to_owned(), b, Box::new(t2)), r.within(1))
                     })
                 }
             }
+            Self::LetRec(bindings, t) => {
+                let n = bindings.len();
+                bindings
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, (_, b))| {
+                        let (b2, r) = b.eta_reduce_lazy_with_redex()?;
+                        let mut bindings = bindings.clone();
+                        bindings[i].1 = b2;
+                        Some((Self::LetRec(bindings, t.clone()), r.within(i)))
+                    })
+                    .or_else(|| {
+                        let (t2, r) = t.eta_reduce_lazy_with_redex()?;
+                        Some((Self::LetRec(bindings.clone(), Box::new(t2)), r.within(n)))
+                    })
+            }
+            Self::Case(t, branches) => {
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    return Some((Self::Case(Box::new(t2), branches.clone()), r.within(0)));
+                }
+                branches.iter().enumerate().find_map(|(i, (_, _, b))| {
+                    let (b2, r) = b.eta_reduce_lazy_with_redex()?;
+                    let mut branches = branches.clone();
+                    branches[i].2 = b2;
+                    Some((Self::Case(t.clone(), branches), r.within(i + 1)))
+                })
+            }
+            Self::Data(d, t) => t
+                .eta_reduce_lazy_with_redex()
+                .map(|(t2, r)| (Self::Data(d.clone(), Box::new(t2)), r.within(0))),
             _ => None,
         }
     }
 
-    /// Fully beta- and eta-reduce the term. If the term is well-typed, this will halt.
+    /// Fully beta- and eta-reduce the term. If the term is well-typed and has no `let rec`, this
+    /// will halt.
     pub fn evaluate(&self) -> Self {
         let mut res = self.clone();
         while let Some(new_res) = res.beta_reduce_lazy() {
@@ -419,63 +1084,127 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// Infer the most general type of this term in the given type environment, if it is well-typed.
-    pub fn type_in(&self, type_env: &mut TypeEnvironment) -> Option<Type> {
-        self.type_in_impl(type_env, &mut HashMap::new(), &mut 0)
+    pub fn type_in(&self, type_env: &mut TypeEnvironment) -> Result<Type, TypeError> {
+        self.type_in_impl(type_env, &mut Vec::new(), &mut HashMap::new(), &mut 0)
     }
 
     fn type_in_impl(
         &self,
         type_env: &mut TypeEnvironment,
+        data: &mut Vec<DataDecl>,
         subs: &mut HashMap<String, Type>,
         var_count: &mut usize,
-    ) -> Option<Type> {
+    ) -> Result<Type, TypeError> {
         match self {
             Self::Variable(x) => {
                 let t = type_env
                     .iter()
                     .rev()
                     .find(|(v, _)| v == x)
-                    .map(|(_, t)| t)?;
-                Some(t.instantiate(var_count))
+                    .map(|(_, t)| t)
+                    .ok_or_else(|| TypeError::UndefinedVar(x.clone()))?;
+                Ok(t.instantiate(var_count))
             }
             Self::Abstraction(x, t) => {
                 let a = Type::Variable(format!("t{}", var_count));
                 *var_count += 1;
                 type_env.push((x.to_owned(), a.clone()));
-                let b = t.type_in_impl(type_env, subs, var_count);
+                let b = t.type_in_impl(type_env, data, subs, var_count);
                 type_env.pop();
                 let a = a.substitute(subs);
-                Some(Type::Fn(Box::new(a), Box::new(b?)))
+                Ok(Type::Fn(Box::new(a), Box::new(b?)))
             }
             Self::Application(t, u) => {
-                let f = t.type_in_impl(type_env, subs, var_count)?;
-                let a = u.type_in_impl(type_env, subs, var_count)?;
+                let f = t.type_in_impl(type_env, data, subs, var_count)?;
+                let a = u.type_in_impl(type_env, data, subs, var_count)?;
                 let f = f.substitute(subs);
                 let b = Type::Variable(format!("t{}", var_count));
                 *var_count += 1;
                 let rhs = Type::Fn(Box::new(a), Box::new(b.clone()));
-                unify(vec![(f, rhs)], subs)?;
+                unify(vec![(f, rhs)], subs, self)?;
                 for (_, t) in type_env.iter_mut() {
                     *t = t.substitute(subs);
                 }
                 let b = b.substitute(subs);
-                Some(b)
+                Ok(b)
             }
+            Self::Primitive(p) => Ok(p.ty().instantiate(var_count)),
             Self::Let(x, b, o) => {
-                let bt = b.type_in_impl(type_env, subs, var_count)?;
+                let bt = b.type_in_impl(type_env, data, subs, var_count)?;
                 let fv = free_vars(type_env);
                 let bt = bt.generalise(&fv);
                 type_env.push((x.to_owned(), bt));
-                let ot = o.type_in_impl(type_env, subs, var_count);
+                let ot = o.type_in_impl(type_env, data, subs, var_count);
                 type_env.pop();
                 ot
             }
+            Self::LetRec(bindings, t) => {
+                check_rebound(self, bindings)?;
+                let depth = type_env.len();
+                let ty = type_groups(bindings, type_env, data, subs, var_count)
+                    .and_then(|()| t.type_in_impl(type_env, data, subs, var_count));
+                type_env.truncate(depth);
+                ty
+            }
+            Self::Constructor(c) => {
+                let decl = declaring(data, c)?;
+                let ty = decl
+                    .constructor_type(c)
+                    .expect("the constructor is declared");
+                Ok(ty.instantiate(var_count))
+            }
+            Self::Case(t, branches) => {
+                let st = t.type_in_impl(type_env, data, subs, var_count)?;
+                let res = Type::Variable(format!("t{}", var_count));
+                *var_count += 1;
+                for (c, xs, b) in branches {
+                    let (fields, pt) = pattern_types(data, c, xs, var_count)?;
+                    unify(vec![(st.substitute(subs), pt)], subs, t)?;
+                    for (_, t) in type_env.iter_mut() {
+                        *t = t.substitute(subs);
+                    }
+                    for (x, field) in xs.iter().zip(fields) {
+                        type_env.push((x.clone(), field.substitute(subs)));
+                    }
+                    let bt = b.type_in_impl(type_env, data, subs, var_count);
+                    type_env.truncate(type_env.len() - xs.len());
+                    unify(vec![(bt?, res.substitute(subs))], subs, b)?;
+                    for (_, t) in type_env.iter_mut() {
+                        *t = t.substitute(subs);
+                    }
+                }
+                check_coverage(data, self, &st.substitute(subs), branches)?;
+                Ok(res.substitute(subs))
+            }
+            Self::Data(d, t) => {
+                d.check(data)?;
+                data.push(d.as_ref().clone());
+                let ty = t.type_in_impl(type_env, data, subs, var_count);
+                data.pop();
+                ty
+            }
         }
     }
 
     /// The type of this term in the empty type environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Type> {
+    pub fn type_closed(&self) -> Result<Type, TypeError> {
         self.type_in(&mut TypeEnvironment::new())
     }
 }
@@ -486,47 +1215,685 @@ fn free_vars(type_env: &TypeEnvironment) -> HashSet<String> {
         .fold(HashSet::new(), |vs, (_, t)| &vs | &t.vars())
 }
 
+/// The body of a `let rec` with each variable it binds replaced by its definition, still
+/// under the `let rec`, unfolding the recursion once.
+fn unfold(bindings: &[(String, Term)], t: &Term) -> Term {
+    // Later bindings shadow earlier ones with the same name.
+    bindings.iter().rev().fold(t.clone(), |t, (f, b)| {
+        t.substitute(f, &Term::LetRec(bindings.to_vec(), Box::new(b.clone())))
+    })
+}
+
+fn check_rebound(term: &Term, bindings: &[(String, Term)]) -> Result<(), TypeError> {
+    let mut seen = HashSet::new();
+    match bindings.iter().find(|(f, _)| !seen.insert(f)) {
+        Some((f, _)) => Err(TypeError::Rebound {
+            term: Box::new(term.clone()),
+            var: f.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The strongly connected components of the bindings of a `let rec` under which refer to
+/// which, each after the components it refers to, as indices into the bindings.
+fn binding_groups(bindings: &[(String, Term)]) -> Vec<Vec<usize>> {
+    struct Tarjan {
+        refs: Vec<Vec<usize>>,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        groups: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan {
+        fn visit(&mut self, v: usize, next: &mut usize) {
+            self.index[v] = Some(*next);
+            self.low[v] = *next;
+            *next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for w in self.refs[v].clone() {
+                match self.index[w] {
+                    None => {
+                        self.visit(w, next);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(i) if self.on_stack[w] => self.low[v] = self.low[v].min(i),
+                    Some(_) => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                let mut group = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    group.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                group.sort_unstable();
+                self.groups.push(group);
+            }
+        }
+    }
+
+    let n = bindings.len();
+    let refs = bindings
+        .iter()
+        .map(|(_, b)| {
+            let fv = b.free_vars();
+            (0..n).filter(|&j| fv.contains(&bindings[j].0)).collect()
+        })
+        .collect();
+    let mut tarjan = Tarjan {
+        refs,
+        index: vec![None; n],
+        low: vec![0; n],
+        stack: Vec::new(),
+        on_stack: vec![false; n],
+        groups: Vec::new(),
+    };
+    let mut next = 0;
+    for v in 0..n {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v, &mut next);
+        }
+    }
+    tarjan.groups
+}
+
+/// Push the type schemes of the bindings of a `let rec` onto the environment, inferring the
+/// types in each binding group monomorphically and then generalising them.
+fn type_groups(
+    bindings: &[(String, Term)],
+    type_env: &mut TypeEnvironment,
+    data: &mut Vec<DataDecl>,
+    subs: &mut HashMap<String, Type>,
+    var_count: &mut usize,
+) -> Result<(), TypeError> {
+    for group in binding_groups(bindings) {
+        let depth = type_env.len();
+        let mut vars = Vec::new();
+        for &i in &group {
+            let a = Type::Variable(format!("t{}", var_count));
+            *var_count += 1;
+            type_env.push((bindings[i].0.clone(), a.clone()));
+            vars.push(a);
+        }
+        for (&i, a) in group.iter().zip(&vars) {
+            let b = &bindings[i].1;
+            let bt = b.type_in_impl(type_env, data, subs, var_count)?;
+            unify(vec![(bt.substitute(subs), a.substitute(subs))], subs, b)?;
+            for (_, t) in type_env.iter_mut() {
+                *t = t.substitute(subs);
+            }
+        }
+        type_env.truncate(depth);
+        let fv = free_vars(type_env);
+        for (&i, a) in group.iter().zip(vars) {
+            let scheme = a.substitute(subs).generalise(&fv);
+            type_env.push((bindings[i].0.clone(), scheme));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn arb_var() -> impl Strategy<Value = String> {
+    crate::arb_var().prop_filter("reserved words and constructors aren't variables", |x| {
+        !parser::RESERVED.contains(&x.as_str()) && !x.starts_with(char::is_uppercase)
+    })
+}
+
+#[cfg(test)]
+fn arb_constructor() -> impl Strategy<Value = String> {
+    crate::arb_var().prop_filter("constructors are capitalised", |x| {
+        x.starts_with(char::is_uppercase)
+    })
+}
+
+#[cfg(test)]
+fn arb_data_decl() -> impl Strategy<Value = DataDecl> {
+    let name = || arb_constructor().prop_filter("base types", |x| x != "Int" && x != "Bool");
+    let leaf = prop_oneof![
+        Just(Type::Int),
+        Just(Type::Bool),
+        arb_var().prop_map(Type::GenVariable),
+        name().prop_map(|d| Type::Data(d, Vec::new())),
+    ];
+    let field = leaf.prop_recursive(4, 16, 3, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(t, u)| Type::Fn(Box::new(t), Box::new(u))),
+            (name(), prop::collection::vec(inner, 1..3)).prop_map(|(d, args)| Type::Data(d, args)),
+        ]
+    });
+    let constructor = (arb_constructor(), prop::collection::vec(field, 0..3));
+    (
+        name(),
+        prop::collection::vec(arb_var(), 0..3),
+        prop::collection::vec(constructor, 1..3),
+    )
+        .prop_map(|(name, params, constructors)| DataDecl {
+            name,
+            params,
+            constructors,
+        })
+}
+
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let leaf = prop_oneof![
+        4 => arb_var().prop_map(Term::Variable),
+        1 => arb_prim().prop_map(Term::Primitive),
+        1 => arb_constructor().prop_map(Term::Constructor),
+    ];
+    leaf.prop_recursive(16, 256, 3, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (arb_var(), inner.clone()).prop_map(|(x, b)| Term::Abstraction(x, Box::new(b))),
+            (arb_var(), inner.clone(), inner.clone()).prop_map(|(x, b, t)| Term::Let(
+                x,
+                Box::new(b),
+                Box::new(t)
+            )),
+            (
+                prop::collection::vec((arb_var(), inner.clone()), 1..3),
+                inner.clone()
+            )
+                .prop_map(|(bindings, t)| Term::LetRec(bindings, Box::new(t))),
+            (
+                inner.clone(),
+                prop::collection::vec(
+                    (
+                        arb_constructor(),
+                        prop::collection::vec(arb_var(), 0..3),
+                        inner.clone()
+                    ),
+                    1..3
+                )
+            )
+                .prop_map(|(t, branches)| Term::Case(Box::new(t), branches)),
+            (arb_data_decl(), inner).prop_map(|(d, t)| Term::Data(Box::new(d), Box::new(t))),
+        ]
+    })
+}
+
+/// Terms under a well-formed data declaration, built from its constructors, exhaustive cases
+/// over it and a few variables bound outermost, so that unlike [`arb_term`] they are often
+/// well-typed.
+#[cfg(test)]
+fn arb_data_term() -> impl Strategy<Value = Term> {
+    let param = || Type::GenVariable("a".into());
+    let field = prop_oneof![
+        Just(Type::Int),
+        Just(Type::Bool),
+        Just(param()),
+        Just(Type::Data("T".into(), vec![param()])),
+    ];
+    let fields = prop::collection::vec(prop::collection::vec(field, 0..3), 1..4);
+    fields.prop_flat_map(|fields| {
+        let constructors: Vec<_> = ["A", "B", "C"]
+            .iter()
+            .map(|c| c.to_string())
+            .zip(fields)
+            .collect();
+        let decl = DataDecl {
+            name: "T".into(),
+            params: vec!["a".into()],
+            constructors: constructors.clone(),
+        };
+        let var = || prop::sample::select(vec!["x", "y", "z"]).prop_map(String::from);
+        let names: Vec<_> = constructors.iter().map(|(c, _)| c.clone()).collect();
+        let leaf = prop_oneof![
+            3 => var().prop_map(Term::Variable),
+            1 => arb_prim().prop_map(Term::Primitive),
+            2 => prop::sample::select(names).prop_map(Term::Constructor),
+        ];
+        let body = leaf.prop_recursive(8, 64, 3, move |inner| {
+            let branches: Vec<_> = constructors
+                .iter()
+                .map(|(c, fs)| {
+                    let c = c.clone();
+                    (prop::collection::vec(var(), fs.len()), inner.clone())
+                        .prop_map(move |(xs, b)| (c.clone(), xs, b))
+                })
+                .collect();
+            prop_oneof![
+                (inner.clone(), inner.clone())
+                    .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+                (var(), inner.clone()).prop_map(|(x, b)| Term::Abstraction(x, Box::new(b))),
+                (var(), inner.clone(), inner.clone()).prop_map(|(x, b, t)| Term::Let(
+                    x,
+                    Box::new(b),
+                    Box::new(t)
+                )),
+                (inner, branches).prop_map(|(t, branches)| Term::Case(Box::new(t), branches)),
+            ]
+        });
+        body.prop_map(move |t| {
+            let t = ["z", "y", "x"]
+                .iter()
+                .fold(t, |t, x| Term::Abstraction(x.to_string(), Box::new(t)));
+            Term::Data(Box::new(decl.clone()), Box::new(t))
+        })
+    })
+}
+
+#[cfg(test)]
+fn arb_prim() -> impl Strategy<Value = Prim> {
+    prop_oneof![
+        any::<isize>().prop_map(|n| Prim::Lit(Lit::IntLit(n))),
+        any::<bool>().prop_map(|b| Prim::Lit(Lit::BoolLit(b))),
+        prop_oneof![
+            Just(PrimFunc::Add),
+            Just(PrimFunc::Sub),
+            Just(PrimFunc::Mul),
+            Just(PrimFunc::IntEq),
+            Just(PrimFunc::Gt),
+            Just(PrimFunc::Or),
+            Just(PrimFunc::Not),
+            Just(PrimFunc::If),
+        ]
+        .prop_map(Prim::Func),
+    ]
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) | Self::Data(..) => 1,
+            Self::Application(..) | Self::Let(..) => 2,
+            Self::Case(_, branches) => 1 + branches.len(),
+            Self::LetRec(bindings, _) => bindings.len() + 1,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, t), 0)
+            | (Self::Application(t, _), 0)
+            | (Self::Let(_, t, _), 0)
+            | (Self::Case(t, _), 0)
+            | (Self::Data(_, t), 0) => Some(t),
+            (Self::Application(_, u), 1) | (Self::Let(_, _, u), 1) => Some(u),
+            (Self::Case(_, branches), i) => branches.get(i - 1).map(|(_, _, b)| b),
+            (Self::LetRec(bindings, t), i) if i == bindings.len() => Some(t),
+            (Self::LetRec(bindings, _), i) => bindings.get(i).map(|(_, b)| b),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, _), 0) => Self::Abstraction(x.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            (Self::Let(x, _, t), 0) => Self::Let(x.clone(), child, t.clone()),
+            (Self::Let(x, b, _), 1) => Self::Let(x.clone(), b.clone(), child),
+            (Self::Case(_, branches), 0) => Self::Case(child, branches.clone()),
+            (Self::Case(t, branches), i) if i <= branches.len() => {
+                let mut branches = branches.clone();
+                branches[i - 1].2 = *child;
+                Self::Case(t.clone(), branches)
+            }
+            (Self::Data(d, _), 0) => Self::Data(d.clone(), child),
+            (Self::LetRec(bindings, _), i) if i == bindings.len() => {
+                Self::LetRec(bindings.clone(), child)
+            }
+            (Self::LetRec(bindings, t), i) if i < bindings.len() => {
+                let mut bindings = bindings.clone();
+                bindings[i].1 = *child;
+                Self::LetRec(bindings, t.clone())
+            }
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+/// The constructors of a [`Term`] in locally nameless form.
+///
+/// A `let` has the bound term as its first child, followed by the binder for its body. A
+/// `let rec` has a child for each bound term followed by one for its body, each under binders
+/// for all of its variables. A `case` is labelled with the constructors of its branches, and has the scrutinee as its first
+/// child, followed by a binder for each field of each branch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Abstraction,
+    Application,
+    Let,
+    LetRec,
+    Primitive(Prim),
+    Constructor(String),
+    Case(Vec<String>),
+    Data(DataDecl),
+}
+
+impl LocallyNameless for Term {
+    type Node = Node;
+
+    fn to_locally_nameless(&self) -> Ln<Node> {
+        to_locally_nameless(self, &mut Scope::new())
+    }
+}
+
+fn to_locally_nameless(term: &Term, scope: &mut Scope) -> Ln<Node> {
+    match term {
+        Term::Variable(x) => scope.variable((), x),
+        Term::Abstraction(x, t) => Ln::node(
+            Node::Abstraction,
+            [scope.bind((), x, |scope| to_locally_nameless(t, scope))],
+        ),
+        Term::Application(t, u) => Ln::node(
+            Node::Application,
+            [to_locally_nameless(t, scope), to_locally_nameless(u, scope)],
+        ),
+        Term::Primitive(p) => Ln::node(Node::Primitive(*p), []),
+        Term::Let(x, b, t) => Ln::node(
+            Node::Let,
+            [
+                to_locally_nameless(b, scope),
+                scope.bind((), x, |scope| to_locally_nameless(t, scope)),
+            ],
+        ),
+        Term::LetRec(bindings, t) => {
+            let fs: Vec<_> = bindings.iter().map(|(f, _)| f.clone()).collect();
+            let mut children: Vec<_> = bindings
+                .iter()
+                .map(|(_, b)| bind_fields(&fs, b, scope))
+                .collect();
+            children.push(bind_fields(&fs, t, scope));
+            Ln::node(Node::LetRec, children)
+        }
+        Term::Constructor(c) => Ln::node(Node::Constructor(c.clone()), []),
+        Term::Case(t, branches) => {
+            let constructors = branches.iter().map(|(c, _, _)| c.clone()).collect();
+            let mut children = vec![to_locally_nameless(t, scope)];
+            for (_, xs, b) in branches {
+                children.push(bind_fields(xs, b, scope));
+            }
+            Ln::node(Node::Case(constructors), children)
+        }
+        Term::Data(d, t) => Ln::node(
+            Node::Data(d.as_ref().clone()),
+            [to_locally_nameless(t, scope)],
+        ),
+    }
+}
+
+fn bind_fields(xs: &[String], body: &Term, scope: &mut Scope) -> Ln<Node> {
+    match xs.split_first() {
+        None => to_locally_nameless(body, scope),
+        Some((x, rest)) => scope.bind((), x, |scope| bind_fields(rest, body, scope)),
+    }
+}
+
 impl PartialEq for Term {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
     }
 }
 
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::Clash {
+                term,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "expected type {}, found {}, in {}",
+                expected, actual, term
+            ),
+            Self::OccursCheck { term, var, ty } => write!(
+                f,
+                "{} occurs in {}, so {} would have an infinite type",
+                var, ty, term
+            ),
+            Self::UndefinedConstructor(c) => write!(f, "undefined constructor {}", c),
+            Self::UndefinedType(d) => write!(f, "undefined type {}", d),
+            Self::UndefinedTypeVar(a) => write!(f, "undefined type variable {}", a),
+            Self::TypeArity {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has {} parameters, but is given {}",
+                name, expected, actual
+            ),
+            Self::PatternArity {
+                constructor,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has {} fields, but the pattern binds {}",
+                constructor, expected, actual
+            ),
+            Self::Redundant { term, constructor } => {
+                write!(f, "{} has two branches for {}", term, constructor)
+            }
+            Self::NonExhaustive { term, missing } => {
+                write!(f, "{} has no branch for {}", term, missing.join(", "))
+            }
+            Self::Rebound { term, var } => write!(f, "{} binds {} twice", term, var),
+        }
+    }
+}
+
+impl error::Error for TypeError {}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, t) => write!(f, "λ{}. {}", x, t),
+            Self::Abstraction(x, t) => write!(f, "{}{}. {}", n.lambda(), x, n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
                 write_term(u, f)
             }
-            Self::Let(x, b, t) => write!(f, "let {} = {} in {}", x, b, t),
+            Self::Let(x, b, t) => write!(f, "let {} = {} in {}", x, n.show(b), n.show(t)),
+            Self::LetRec(bindings, t) => {
+                for (i, (x, b)) in bindings.iter().enumerate() {
+                    let keyword = if i == 0 { "let rec" } else { " and" };
+                    write!(f, "{} {} = {}", keyword, x, n.show(b))?;
+                }
+                write!(f, " in {}", n.show(t))
+            }
+            Self::Primitive(p) => p.fmt(f),
+            Self::Constructor(c) => c.fmt(f),
+            Self::Case(t, branches) => {
+                write!(f, "case {} of", n.show(t))?;
+                for (i, (c, xs, b)) in branches.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " |" };
+                    write!(f, "{} {}", sep, c)?;
+                    for x in xs {
+                        write!(f, " {}", x)?;
+                    }
+                    write!(f, " -> ")?;
+                    // Only the last branch can end with a binder or a `case` unparenthesised.
+                    if i + 1 == branches.len() {
+                        write!(f, "{}", n.show(b))?;
+                    } else {
+                        write_func(b, f)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::Data(d, t) => write!(f, "{} in {}", d, n.show(t)),
+        }
+    }
+}
+
+impl fmt::Display for DataDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "data {}", self.name)?;
+        for a in &self.params {
+            write!(f, " {}", a)?;
+        }
+        for (i, (c, fields)) in self.constructors.iter().enumerate() {
+            write!(f, " {} {}", if i == 0 { "=" } else { "|" }, c)?;
+            for field in fields {
+                write!(f, " ")?;
+                write_ty(field, f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Variable(x) => pretty::atom(x),
+            Self::Primitive(p) => pretty::atom(p.to_string()),
+            Self::Abstraction(x, t) => {
+                let mut bound = vec![x.as_str()];
+                let mut body = t.as_ref();
+                while let Self::Abstraction(y, u) = body {
+                    bound.push(y);
+                    body = u;
+                }
+                let head = Doc::text(format!("{}{}.", n.lambda(), bound.join(" ")));
+                pretty::binder(head, body.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                while let Self::Application(t, u) = head {
+                    args.push(at(u.as_ref(), n, Prec::Atom));
+                    head = t;
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+            Self::Let(x, b, t) => {
+                let head = Doc::text(format!("let {} =", x))
+                    + (Doc::line() + b.to_doc(n)).nest(2)
+                    + Doc::line()
+                    + Doc::text("in");
+                pretty::binder(head.group(), t.to_doc(n))
+            }
+            Self::LetRec(bindings, t) => {
+                let mut head = Doc::nil();
+                for (i, (x, b)) in bindings.iter().enumerate() {
+                    let keyword = if i == 0 { "let rec" } else { "and" };
+                    if i > 0 {
+                        head = head + Doc::line();
+                    }
+                    head = head
+                        + Doc::text(format!("{} {} =", keyword, x))
+                        + (Doc::line() + b.to_doc(n)).nest(2);
+                }
+                let head = head + Doc::line() + Doc::text("in");
+                pretty::binder(head.group(), t.to_doc(n))
+            }
+            Self::Constructor(c) => pretty::atom(c),
+            Self::Case(t, branches) => {
+                let head = Doc::text("case")
+                    + (Doc::line() + t.to_doc(n)).nest(2)
+                    + Doc::line()
+                    + Doc::text("of");
+                let last = branches.len().saturating_sub(1);
+                let mut doc = head.group();
+                for (i, (c, xs, b)) in branches.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "| " };
+                    let pattern = std::iter::once(c).chain(xs).cloned().collect::<Vec<_>>();
+                    let pattern = Doc::text(format!("{}{} ->", sep, pattern.join(" ")));
+                    let body = if i == last {
+                        b.to_doc(n)
+                    } else {
+                        at(b, n, Prec::Application)
+                    };
+                    doc = doc + (Doc::line() + pretty::binder(pattern, body).0).nest(2);
+                }
+                (doc.group(), Prec::Binder)
+            }
+            Self::Data(d, t) => pretty::binder(Doc::text(format!("{} in", d)), t.to_doc(n)),
+        }
+    }
+}
+
+impl fmt::Display for Prim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lit(l) => l.fmt(f),
+            Self::Func(o) => o.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Lit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IntLit(i) => i.fmt(f),
+            Self::BoolLit(b) => b.fmt(f),
         }
     }
 }
 
+impl fmt::Display for PrimFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Mul => "mul",
+            Self::IntEq => "eq",
+            Self::Gt => "gt",
+            Self::Or => "or",
+            Self::Not => "not",
+            Self::If => "if",
+        };
+        f.write_str(s)
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
-        Term::Variable(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        Term::Variable(_) | Term::Primitive(_) | Term::Constructor(_) => fmt::Display::fmt(t, f),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
-        Term::Variable(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        Term::Variable(_) | Term::Primitive(_) | Term::Constructor(_) | Term::Application(_, _) => {
+            fmt::Display::fmt(t, f)
+        }
+        _ => parens(t, f),
     }
 }
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         let gen_vars = self.gen_vars();
         let mut var_iter = gen_vars.into_iter();
         if let Some(v) = var_iter.next() {
-            write!(f, "∀{}", v)?;
+            write!(f, "{}{}", n.for_all(), v)?;
             for v in var_iter {
                 write!(f, " {}", v)?;
             }
@@ -536,34 +1903,200 @@ impl fmt::Display for Type {
     }
 }
 
+impl Pretty for Type {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        let mut gen_vars: Vec<_> = self.gen_vars().into_iter().collect();
+        gen_vars.sort();
+        if gen_vars.is_empty() {
+            ty_doc(self)
+        } else {
+            let head = Doc::text(format!("{}{}.", n.for_all(), gen_vars.join(" ")));
+            pretty::binder(head, ty_doc(self).0)
+        }
+    }
+}
+
+/// The document for a type without its quantifier.
+fn ty_doc(ty: &Type) -> (Doc, Prec) {
+    match ty {
+        Type::Variable(v) | Type::GenVariable(v) => pretty::atom(v),
+        Type::Int | Type::Bool => pretty::atom(ty.to_string()),
+        Type::Data(d, args) if args.is_empty() => pretty::atom(d),
+        Type::Data(d, args) => {
+            let args = args.iter().map(|t| match ty_doc(t) {
+                (doc, Prec::Atom) => doc,
+                (doc, _) => doc.parens(),
+            });
+            pretty::application(Doc::text(d), args)
+        }
+        Type::Fn(t, u) => {
+            let (lhs, prec) = ty_doc(t);
+            let lhs = if prec < Prec::Application {
+                lhs.parens()
+            } else {
+                lhs
+            };
+            pretty::infix(lhs, " ->", ty_doc(u).0)
+        }
+    }
+}
+
+/// Write a type without its quantifier.
 fn write_ty_unparen(ty: &Type, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match ty {
         Type::Variable(v) => fmt::Display::fmt(v, f),
         Type::GenVariable(v) => fmt::Display::fmt(v, f),
+        Type::Int => write!(f, "Int"),
+        Type::Bool => write!(f, "Bool"),
+        Type::Data(d, args) => {
+            write!(f, "{}", d)?;
+            for t in args {
+                write!(f, " ")?;
+                write_ty(t, f)?;
+            }
+            Ok(())
+        }
         Type::Fn(t, u) => {
-            write_ty(t, f)?;
-            write!(f, " -> {}", u)
+            if let Type::Fn(..) = t.as_ref() {
+                write_ty(t, f)?;
+            } else {
+                write_ty_unparen(t, f)?;
+            }
+            write!(f, " -> ")?;
+            write_ty_unparen(u, f)
         }
     }
 }
 
+/// Write a type without its quantifier, parenthesised unless it is atomic.
 fn write_ty(ty: &Type, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    match ty {
-        Type::Variable(_) | Type::GenVariable(_) => fmt::Display::fmt(ty, f),
-        Type::Fn(_, _) => write!(f, "({})", ty),
+    let atomic = match ty {
+        Type::Fn(..) => false,
+        Type::Data(_, args) => args.is_empty(),
+        _ => true,
+    };
+    if atomic {
+        write_ty_unparen(ty, f)
+    } else {
+        write!(f, "(")?;
+        write_ty_unparen(ty, f)?;
+        write!(f, ")")
     }
 }
 
 impl From<Term> for untyped::Term {
     fn from(val: Term) -> Self {
-        match val {
-            Term::Variable(x) => untyped::Term::Variable(x),
-            Term::Abstraction(x, t) => untyped::Term::Abstraction(x, t.into()),
-            Term::Application(t, u) => untyped::Term::Application(t.into(), u.into()),
-            Term::Let(x, b, t) => untyped::Term::Application(
-                Box::new(untyped::Term::Abstraction(x, t.into())),
-                b.into(),
-            ),
+        to_untyped(val, &mut Vec::new())
+    }
+}
+
+/// The untyped term, with primitives Church encoded and data Scott encoded: a value is a
+/// function taking a continuation for each constructor of its type, and passing its fields to
+/// the one for its own constructor.
+///
+/// A `let rec` takes the fixed point of a function returning its bindings as a Scott-encoded
+/// tuple, so that each binding can refer to the others through it.
+///
+/// Constructors without a declaration in scope become free variables, as do the branches
+/// missing from a `case`.
+fn to_untyped(term: Term, data: &mut Vec<DataDecl>) -> untyped::Term {
+    use untyped::Term as U;
+    match term {
+        Term::Variable(x) => U::Variable(x),
+        Term::Abstraction(x, t) => U::Abstraction(x, Box::new(to_untyped(*t, data))),
+        Term::Application(t, u) => U::Application(
+            Box::new(to_untyped(*t, data)),
+            Box::new(to_untyped(*u, data)),
+        ),
+        Term::Let(x, b, t) => U::Application(
+            Box::new(U::Abstraction(x, Box::new(to_untyped(*t, data)))),
+            Box::new(to_untyped(*b, data)),
+        ),
+        Term::LetRec(bindings, t) => {
+            const FIX: &str = "λf. (λx. f (x x)) (λx. f (x x))";
+            let fix: untyped::Term = parser::parse(FIX).expect("Y should parse").into();
+            let app = |t, u| U::Application(Box::new(t), Box::new(u));
+            let abs = |x: &String, t| U::Abstraction(x.clone(), Box::new(t));
+            let fs: Vec<_> = bindings.iter().map(|(f, _)| f.clone()).collect();
+            // Bind the variables of the group, in an untyped term, to the given terms.
+            let bind = |t: untyped::Term, args: &[untyped::Term]| {
+                let f = fs.iter().rev().fold(t, |t, f| abs(f, t));
+                args.iter().cloned().fold(f, app)
+            };
+            let mut vars: HashSet<_> = bindings.iter().flat_map(|(_, b)| b.vars()).collect();
+            vars.extend(fs.iter().cloned());
+            let r = fresh_var(&vars);
+            vars.insert(r.clone());
+            let s = fresh_var(&vars);
+            // The projection of the `i`th component of a tuple.
+            let project = |i: usize| {
+                let xs: Vec<_> = (0..fs.len()).map(|j| format!("x{}", j)).collect();
+                let x = U::Variable(xs[i].clone());
+                xs.iter().rev().fold(x, |t, x| abs(x, t))
+            };
+            let within = |tuple: &untyped::Term| -> Vec<_> {
+                (0..fs.len())
+                    .map(|i| app(tuple.clone(), project(i)))
+                    .collect()
+            };
+            let recursive = within(&U::Variable(r.clone()));
+            let components = bindings
+                .into_iter()
+                .map(|(_, b)| bind(to_untyped(b, data), &recursive));
+            let tuple = components.fold(U::Variable(s.clone()), app);
+            let fixed = app(fix, abs(&r, abs(&s, tuple)));
+            bind(to_untyped(*t, data), &within(&fixed))
+        }
+        Term::Primitive(p) => p.church(),
+        Term::Constructor(c) => {
+            let Ok(decl) = declaring(data, &c) else {
+                return U::Variable(c);
+            };
+            let i = decl
+                .constructors
+                .iter()
+                .position(|(d, _)| d == &c)
+                .expect("the constructor is declared");
+            let xs: Vec<_> = (0..decl.constructors[i].1.len())
+                .map(|j| format!("x{}", j))
+                .collect();
+            let ks: Vec<_> = (0..decl.constructors.len())
+                .map(|j| format!("k{}", j))
+                .collect();
+            let body = xs.iter().fold(U::Variable(ks[i].clone()), |t, x| {
+                U::Application(Box::new(t), Box::new(U::Variable(x.clone())))
+            });
+            xs.into_iter()
+                .chain(ks)
+                .rev()
+                .fold(body, |t, x| U::Abstraction(x, Box::new(t)))
+        }
+        Term::Case(t, mut branches) => {
+            let order: Vec<_> = match branches.first().map(|(c, _, _)| declaring(data, c)) {
+                Some(Ok(decl)) => decl.constructors.iter().map(|(c, _)| c.clone()).collect(),
+                _ => branches.iter().map(|(c, _, _)| c.clone()).collect(),
+            };
+            let mut res = to_untyped(*t, data);
+            for c in order {
+                let arg = match branches.iter().position(|(d, _, _)| d == &c) {
+                    Some(i) => {
+                        let (_, xs, b) = branches.remove(i);
+                        let b = to_untyped(b, data);
+                        xs.into_iter()
+                            .rev()
+                            .fold(b, |t, x| U::Abstraction(x, Box::new(t)))
+                    }
+                    None => U::Variable(c),
+                };
+                res = U::Application(Box::new(res), Box::new(arg));
+            }
+            res
+        }
+        Term::Data(d, t) => {
+            data.push(*d);
+            let t = to_untyped(*t, data);
+            data.pop();
+            t
         }
     }
 }
@@ -657,4 +2190,271 @@ mod tests {
         let expected_ty = fn_t(fn_t(v_t("a"), v_t("a")), fn_t(v_t("a"), v_t("a")));
         assert!(term.type_closed().unwrap().equivalent(&expected_ty));
     }
+
+    #[test]
+    fn self_application_fails_the_occurs_check() {
+        let term = abstraction("x", application(variable("x"), variable("x")));
+        assert!(matches!(
+            term.type_closed(),
+            Err(TypeError::OccursCheck { .. })
+        ));
+        let err = application(variable("f"), variable("x")).type_closed();
+        assert_eq!(err.unwrap_err().to_string(), "undefined variable f");
+    }
+
+    #[test]
+    fn stuck_derivations_keep_the_premises_proven() {
+        let term = parser::parse("λf. let g = λx. x in g (f f)").unwrap();
+        let stuck = derivation::type_deriv(&term, &vec![]).unwrap_err();
+        let TypeError::OccursCheck { term: blamed, .. } = &stuck.error else {
+            panic!("expected an occurs check failure")
+        };
+        assert_eq!(**blamed, parser::parse("f f").unwrap());
+        // Abs, then Let and App with their first premises proven, then the failing App.
+        let let_p = stuck.proof.stuck.as_ref().unwrap();
+        assert_eq!((let_p.rule, let_p.premises.len()), ("Let", 1));
+        let app_p = let_p.stuck.as_ref().unwrap();
+        assert_eq!((app_p.rule, app_p.premises.len()), ("App", 1));
+        let app_p = app_p.stuck.as_ref().unwrap();
+        assert_eq!((app_p.rule, app_p.premises.len()), ("App", 2));
+        assert!(app_p.stuck.is_none());
+    }
+
+    #[test]
+    fn primitives_have_base_types() {
+        let ty = |s| parser::parse(s).unwrap().type_closed();
+        assert_eq!(ty("if (gt 2 1) (add 1 2) 0"), Ok(Type::Int));
+        assert_eq!(
+            ty("λx. or x (eq 1 x)").unwrap_err().to_string(),
+            "expected type Int, found Bool, in eq 1 x"
+        );
+        let expected = fn_t(Type::Bool, fn_t(v_t("a"), fn_t(v_t("a"), v_t("a"))));
+        assert!(ty("if").unwrap().equivalent(&expected));
+        assert!(matches!(ty("add true 1"), Err(TypeError::Clash { .. })));
+    }
+
+    #[test]
+    fn primitives_reduce_to_literals() {
+        let eval = |s| parser::parse(s).unwrap().evaluate();
+        let int = |n| Term::Primitive(Prim::Lit(Lit::IntLit(n)));
+        assert_eq!(eval("if (gt 2 1) (add 1 2) 0"), int(3));
+        assert_eq!(eval("let sq = λx. mul x x in sub (sq 3) (sq 4)"), int(-7));
+        assert_eq!(
+            eval("(λx. not x) (or false (eq 2 2))"),
+            parser::parse("false").unwrap()
+        );
+    }
+
+    #[test]
+    fn church_encodings_agree_with_delta_rules() {
+        let church = |s| untyped::Term::from(parser::parse(s).unwrap()).evaluate();
+        let truth = church("true");
+        assert_eq!(church("gt (mul 2 -3) (add -7 0)"), truth);
+        assert_eq!(church("eq (sub 1 3) -2"), truth);
+        assert_eq!(church("if (not false) (eq 5 (add 2 3)) false"), truth);
+        assert_eq!(church("or (gt 1 1) (eq 0 1)"), church("false"));
+    }
+
+    const LIST: &str = "data List a = Nil | Cons a (List a) in ";
+
+    #[test]
+    fn constructors_and_cases_have_polymorphic_types() {
+        let ty = |s: &str| {
+            parser::parse(format!("{}{}", LIST, s))
+                .unwrap()
+                .type_closed()
+        };
+        let list = |t| Type::Data("List".into(), vec![t]);
+        let cons = fn_t(v_t("a"), fn_t(list(v_t("a")), list(v_t("a"))));
+        assert!(ty("Cons").unwrap().equivalent(&cons));
+        let head = "λd xs. case xs of Nil -> d | Cons y ys -> y";
+        let expected = fn_t(v_t("a"), fn_t(list(v_t("a")), v_t("a")));
+        assert!(ty(head).unwrap().equivalent(&expected));
+        assert_eq!(
+            ty("Cons 1 (Cons true Nil)").unwrap_err().to_string(),
+            "expected type Int, found Bool, in Cons 1 (Cons true Nil)"
+        );
+    }
+
+    #[test]
+    fn branches_see_the_scrutinee_type() {
+        let ty = |s: &str| parser::parse(s).unwrap().type_closed();
+        let misused = ty("data P = P Int in λx. case x of P n -> add x 1");
+        assert!(matches!(misused, Err(TypeError::Clash { .. })));
+        let p = Type::Data("P".into(), Vec::new());
+        let identity = ty("data P = P Int in λx. case x of P n -> x");
+        assert_eq!(identity, Ok(fn_t(p.clone(), p)));
+    }
+
+    #[test]
+    fn cases_must_match_each_constructor_once() {
+        let err = |s: &str| {
+            parser::parse(format!("{}{}", LIST, s))
+                .unwrap()
+                .type_closed()
+                .unwrap_err()
+        };
+        let missing = err("λxs. case xs of Cons y ys -> y");
+        assert!(matches!(missing, TypeError::NonExhaustive { missing, .. } if missing == ["Nil"]));
+        let twice = err("case Nil of Nil -> 0 | Cons y ys -> 1 | Nil -> 2");
+        assert!(matches!(twice, TypeError::Redundant { constructor, .. } if constructor == "Nil"));
+        assert!(matches!(
+            err("case Nil of Nil x -> 0 | Cons y ys -> 1"),
+            TypeError::PatternArity { .. }
+        ));
+        // The parser has no empty cases, but they must still name every constructor.
+        let Term::Data(list, _) = parser::parse(format!("{}Nil", LIST)).unwrap() else {
+            unreachable!("the source declares a data type")
+        };
+        let nil = Box::new(Term::Constructor("Nil".into()));
+        let empty = Term::Data(list, Box::new(Term::Case(nil, Vec::new())));
+        for ty in [
+            empty.type_closed(),
+            algorithm_m::type_in(&empty, &Vec::new()),
+            constraints::type_in(&empty, &Vec::new()),
+        ] {
+            let missing = ty.unwrap_err();
+            assert!(
+                matches!(missing, TypeError::NonExhaustive { missing, .. } if missing == ["Nil", "Cons"])
+            );
+        }
+        let undeclared = parser::parse("data T = A (List Int) in A")
+            .unwrap()
+            .type_closed();
+        assert_eq!(undeclared, Err(TypeError::UndefinedType("List".into())));
+    }
+
+    #[test]
+    fn declared_fields_have_no_free_type_variables() {
+        let decl = DataDecl {
+            name: "Cell".into(),
+            params: vec!["a".into()],
+            constructors: vec![("Cell".into(), vec![Type::Variable("a".into())])],
+        };
+        // Otherwise `Cell 1` and `Cell true` would both fix the type of the same `a`.
+        let t = Term::Data(Box::new(decl), Box::new(Term::Constructor("Cell".into())));
+        assert_eq!(
+            t.type_closed(),
+            Err(TypeError::UndefinedTypeVar("a".into()))
+        );
+    }
+
+    #[test]
+    fn cases_choose_the_branch_of_the_constructor() {
+        let source = "data Pair a b = Pair a b in case Pair 1 true of Pair x y -> if y x 0";
+        let term = parser::parse(source).unwrap();
+        let trace: Vec<_> = term
+            .reduction_trace()
+            .filter_map(|step| step.redex)
+            .map(|r| r.rule)
+            .collect();
+        assert_eq!(trace.first(), Some(&Rule::Iota));
+        assert_eq!(
+            term.evaluate(),
+            parser::parse("data Pair a b = Pair a b in 1").unwrap()
+        );
+        let church = untyped::Term::from(
+            parser::parse(format!(
+                "{}case Cons 1 Nil of Nil -> true | Cons y ys -> false",
+                LIST
+            ))
+            .unwrap(),
+        );
+        assert_eq!(
+            church.evaluate(),
+            untyped::Term::from(parser::parse("false").unwrap()).evaluate()
+        );
+    }
+
+    #[test]
+    fn chosen_branches_are_capture_free() {
+        let pair = "data Pair a b = Pair a b in ";
+        let eval = |s: &str| parser::parse(format!("{}{}", pair, s)).unwrap().evaluate();
+        // The fields are named like the variables picked to rename them apart.
+        let fields = eval("case Pair 1 true of Pair x0 x1 -> x1");
+        assert_eq!(fields, eval("true"));
+        let scrutinee = eval("λx0. case Pair x0 1 of Pair x1 x0 -> x1");
+        assert_eq!(scrutinee, eval("λx0. x0"));
+    }
+
+    #[test]
+    fn derivations_use_the_data_rules() {
+        let term = parser::parse(format!(
+            "{}case Cons 1 Nil of Nil -> 0 | Cons y ys -> y",
+            LIST
+        ))
+        .unwrap();
+        let proof = derivation::type_deriv(&term, &vec![]).unwrap();
+        assert_eq!(proof.rule, "Data");
+        let case = &proof.premises[0];
+        assert_eq!((case.rule, case.premises.len()), ("Case", 3));
+        assert_eq!(case.conclusion.rhs.1, Type::Int);
+        let cons = &case.premises[0].premises[0].premises[0];
+        assert_eq!((cons.rule, cons.premises[0].rule), ("Inst", "Con"));
+    }
+
+    const FACT: &str = "let rec fact = λn. if (eq n 0) 1 (mul n (fact (sub n 1))) in ";
+    const PARITY: &str = "let rec even = λn. if (eq n 0) true (odd (sub n 1)) \
+        and odd = λn. if (eq n 0) false (even (sub n 1)) in ";
+
+    #[test]
+    fn recursive_bindings_unfold() {
+        let eval = |s: String| parser::parse(s).unwrap().evaluate();
+        let int = |n| Term::Primitive(Prim::Lit(Lit::IntLit(n)));
+        assert_eq!(eval(format!("{}fact 5", FACT)), int(120));
+        let odd = eval(format!("{}odd 7", PARITY));
+        assert_eq!(odd, Term::Primitive(Prim::Lit(Lit::BoolLit(true))));
+        let length =
+            "let rec length = λxs. case xs of Nil -> 0 | Cons y ys -> add 1 (length ys) in ";
+        let list = eval(format!(
+            "{}{}length (Cons true (Cons false Nil))",
+            LIST, length
+        ));
+        assert_eq!(list, parser::parse(format!("{}2", LIST)).unwrap());
+    }
+
+    #[test]
+    fn fixed_points_agree_with_unfolding() {
+        let church = |s: String| untyped::Term::from(parser::parse(s).unwrap()).normalise();
+        let truth = church("true".into());
+        assert_eq!(church(format!("{}eq (fact 3) 6", FACT)), truth);
+        assert_eq!(church(format!("{}even 2", PARITY)), truth);
+    }
+
+    #[test]
+    fn binding_groups_follow_dependencies() {
+        let bindings: Vec<_> = [("a", "b"), ("b", "λx. a x"), ("c", "a"), ("d", "1")]
+            .iter()
+            .map(|(f, b)| (f.to_string(), parser::parse(b).unwrap()))
+            .collect();
+        assert_eq!(binding_groups(&bindings), [vec![0, 1], vec![2], vec![3]]);
+        let later: Vec<_> = bindings.into_iter().rev().collect();
+        assert_eq!(binding_groups(&later), [vec![0], vec![2, 3], vec![1]]);
+    }
+
+    #[test]
+    fn recursion_is_monomorphic_within_a_group() {
+        let ty = |s: &str| parser::parse(s).unwrap().type_closed();
+        let fact = ty(&format!("{}fact", FACT)).unwrap();
+        assert_eq!(fact, fn_t(Type::Int, Type::Int));
+        let parity = ty(&format!("{}λn. or (even n) (odd n)", PARITY)).unwrap();
+        assert_eq!(parity, fn_t(Type::Int, Type::Bool));
+        // `id` is generalised before `n` and `b` use it, since it doesn't depend on them.
+        let separate = "let rec n = id 1 and b = id true and id = λx. x in b";
+        assert_eq!(ty(separate), Ok(Type::Bool));
+        let together = "let rec n = id 1 and b = id true and id = λx. if b x x in b";
+        assert!(matches!(ty(together), Err(TypeError::Clash { .. })));
+        let twice = ty("let rec f = 1 and f = 2 in f");
+        assert!(matches!(twice, Err(TypeError::Rebound { var, .. }) if var == "f"));
+    }
+
+    #[test]
+    fn derivations_use_the_recursive_rule() {
+        let term = parser::parse(format!("{}even", PARITY)).unwrap();
+        let proof = derivation::type_deriv(&term, &vec![]).unwrap();
+        assert_eq!((proof.rule, proof.premises.len()), ("LetRec", 3));
+        assert_eq!(proof.conclusion.rhs.1, fn_t(Type::Int, Type::Bool));
+        let env = &proof.premises[2].conclusion.lhs;
+        assert_eq!(env[0], ("even".into(), fn_t(Type::Int, Type::Bool)));
+    }
 }
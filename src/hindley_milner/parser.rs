@@ -0,0 +1,337 @@
+use super::*;
+use crate::parsing::lexer::{Token, Tokens};
+
+pub use crate::parsing::ParseError;
+
+pub(super) const RESERVED: &[&str] = &[
+    "let", "rec", "and", "in", "case", "of", "data", "true", "false", "add", "sub", "mul", "eq",
+    "gt", "or", "not", "if",
+];
+
+/// The primitives spelt as reserved words.
+const PRIMITIVES: &[(&str, Prim)] = &[
+    ("true", Prim::Lit(Lit::BoolLit(true))),
+    ("false", Prim::Lit(Lit::BoolLit(false))),
+    ("add", Prim::Func(PrimFunc::Add)),
+    ("sub", Prim::Func(PrimFunc::Sub)),
+    ("mul", Prim::Func(PrimFunc::Mul)),
+    ("eq", Prim::Func(PrimFunc::IntEq)),
+    ("gt", Prim::Func(PrimFunc::Gt)),
+    ("or", Prim::Func(PrimFunc::Or)),
+    ("not", Prim::Func(PrimFunc::Not)),
+    ("if", Prim::Func(PrimFunc::If)),
+];
+
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let t = term(&mut tokens)?;
+    tokens.finish()?;
+    Ok(t)
+}
+
+fn term(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::Lambda) {
+        abstraction(tokens)
+    } else if tokens.eat_keyword("let") {
+        if tokens.eat_keyword("rec") {
+            let mut bindings = vec![binding(tokens)?];
+            while tokens.eat_keyword("and") {
+                bindings.push(binding(tokens)?);
+            }
+            tokens.expect_keyword("in")?;
+            return Ok(Term::LetRec(bindings, Box::new(term(tokens)?)));
+        }
+        let x = variable(tokens)?;
+        tokens.expect(&Token::Equals)?;
+        let bound = term(tokens)?;
+        tokens.expect_keyword("in")?;
+        Ok(Term::Let(x, Box::new(bound), Box::new(term(tokens)?)))
+    } else if tokens.eat_keyword("case") {
+        let scrutinee = term(tokens)?;
+        tokens.expect_keyword("of")?;
+        let mut branches = vec![branch(tokens)?];
+        while tokens.eat(&Token::Bar) {
+            branches.push(branch(tokens)?);
+        }
+        Ok(Term::Case(Box::new(scrutinee), branches))
+    } else if tokens.eat_keyword("data") {
+        let decl = data_decl(tokens)?;
+        tokens.expect_keyword("in")?;
+        Ok(Term::Data(Box::new(decl), Box::new(term(tokens)?)))
+    } else {
+        application(tokens)
+    }
+}
+
+/// The rest of an abstraction after the `λ`, where `λx y. t` abbreviates `λx. λy. t`.
+fn abstraction(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    let x = variable(tokens)?;
+    let body = if at_variable(tokens) {
+        abstraction(tokens)?
+    } else {
+        tokens.expect(&Token::Dot)?;
+        term(tokens)?
+    };
+    Ok(Term::Abstraction(x, Box::new(body)))
+}
+
+/// A binding `f = t` of a `let rec`.
+fn binding(tokens: &mut Tokens) -> Result<(String, Term), ParseError> {
+    let f = variable(tokens)?;
+    tokens.expect(&Token::Equals)?;
+    Ok((f, term(tokens)?))
+}
+
+/// A branch `C x y -> t` of a `case`.
+fn branch(tokens: &mut Tokens) -> Result<(String, Vec<String>, Term), ParseError> {
+    let c = constructor(tokens)?;
+    let mut xs = Vec::new();
+    while at_variable(tokens) {
+        xs.push(variable(tokens)?);
+    }
+    tokens.expect(&Token::Arrow)?;
+    Ok((c, xs, term(tokens)?))
+}
+
+/// The rest of a data declaration after `data`, up to the `in`.
+fn data_decl(tokens: &mut Tokens) -> Result<DataDecl, ParseError> {
+    if tokens.at_keyword("Int") || tokens.at_keyword("Bool") {
+        return Err(tokens.unexpected("a type name"));
+    }
+    let name = constructor(tokens)?;
+    let mut params = Vec::new();
+    while at_variable(tokens) {
+        params.push(variable(tokens)?);
+    }
+    tokens.expect(&Token::Equals)?;
+    let mut constructors = Vec::new();
+    loop {
+        let c = constructor(tokens)?;
+        let mut fields = Vec::new();
+        while at_ty_atom(tokens) {
+            fields.push(ty_atom(tokens)?);
+        }
+        constructors.push((c, fields));
+        if !tokens.eat(&Token::Bar) {
+            return Ok(DataDecl {
+                name,
+                params,
+                constructors,
+            });
+        }
+    }
+}
+
+/// A field type, where type variables are the parameters of the declaration.
+fn ty(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    let lhs = if at_constructor(tokens) && !at_base_type(tokens) {
+        let name = constructor(tokens)?;
+        let mut args = Vec::new();
+        while at_ty_atom(tokens) {
+            args.push(ty_atom(tokens)?);
+        }
+        Type::Data(name, args)
+    } else {
+        ty_atom(tokens)?
+    };
+    if tokens.eat(&Token::Arrow) {
+        Ok(Type::Fn(Box::new(lhs), Box::new(ty(tokens)?)))
+    } else {
+        Ok(lhs)
+    }
+}
+
+fn ty_atom(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = ty(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.eat_keyword("Int") {
+        Ok(Type::Int)
+    } else if tokens.eat_keyword("Bool") {
+        Ok(Type::Bool)
+    } else if at_constructor(tokens) {
+        Ok(Type::Data(constructor(tokens)?, Vec::new()))
+    } else if at_variable(tokens) {
+        Ok(Type::GenVariable(variable(tokens)?))
+    } else {
+        Err(tokens.unexpected("a type"))
+    }
+}
+
+fn at_ty_atom(tokens: &Tokens) -> bool {
+    tokens.peek() == Some(&Token::LParen) || tokens.at_identifier()
+}
+
+fn at_base_type(tokens: &Tokens) -> bool {
+    tokens.at_keyword("Int") || tokens.at_keyword("Bool")
+}
+
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction, `let`, `case` or data declaration.
+fn application(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    let mut t = atom(tokens)?;
+    loop {
+        t = match tokens.peek() {
+            Some(Token::Lambda) => {
+                return Ok(Term::Application(Box::new(t), Box::new(term(tokens)?)))
+            }
+            _ if ["let", "case", "data"].iter().any(|k| tokens.at_keyword(k)) => {
+                return Ok(Term::Application(Box::new(t), Box::new(term(tokens)?)))
+            }
+            Some(Token::LParen | Token::Int(_)) => {
+                Term::Application(Box::new(t), Box::new(atom(tokens)?))
+            }
+            _ if tokens.at_identifier() || at_primitive(tokens) => {
+                Term::Application(Box::new(t), Box::new(atom(tokens)?))
+            }
+            _ => return Ok(t),
+        }
+    }
+}
+
+fn atom(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = term(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if at_constructor(tokens) {
+        Ok(Term::Constructor(constructor(tokens)?))
+    } else if tokens.at_identifier() {
+        Ok(Term::Variable(variable(tokens)?))
+    } else if let Some(n) = tokens.eat_int() {
+        Ok(Term::Primitive(Prim::Lit(Lit::IntLit(n))))
+    } else if let Some(&(_, p)) = PRIMITIVES.iter().find(|(k, _)| tokens.eat_keyword(k)) {
+        Ok(Term::Primitive(p))
+    } else {
+        Err(tokens.unexpected("a term"))
+    }
+}
+
+fn at_primitive(tokens: &Tokens) -> bool {
+    PRIMITIVES.iter().any(|(k, _)| tokens.at_keyword(k))
+}
+
+/// Whether the next token is a constructor, or the name of a data type, which unlike a
+/// variable starts with a capital letter.
+fn at_constructor(tokens: &Tokens) -> bool {
+    tokens.at_identifier()
+        && matches!(tokens.peek(), Some(Token::Ident(x)) if x.starts_with(char::is_uppercase))
+}
+
+fn at_variable(tokens: &Tokens) -> bool {
+    tokens.at_identifier() && !at_constructor(tokens)
+}
+
+fn constructor(tokens: &mut Tokens) -> Result<String, ParseError> {
+    if !at_constructor(tokens) {
+        return Err(tokens.unexpected("a constructor"));
+    }
+    tokens.identifier()
+}
+
+fn variable(tokens: &mut Tokens) -> Result<String, ParseError> {
+    if at_constructor(tokens) {
+        return Err(tokens.unexpected("a variable"));
+    }
+    tokens.identifier()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn pretty_output_parses_correctly(f in any::<Term>(), width in 1usize..80) {
+            let s = f.pretty(width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse(s);
+        }
+    }
+
+    #[test]
+    fn let_body_extends_right() {
+        let t = parse("let id = λx. x in id id").unwrap();
+        let expected = Term::Let(
+            "id".into(),
+            Box::new(Term::Abstraction(
+                "x".into(),
+                Box::new(Term::Variable("x".into())),
+            )),
+            Box::new(Term::Application(
+                Box::new(Term::Variable("id".into())),
+                Box::new(Term::Variable("id".into())),
+            )),
+        );
+        assert_eq!(t, expected);
+    }
+
+    #[test]
+    fn primitives_are_atoms() {
+        let t = parse("if (gt 2 -1) (add 1 2) 0").unwrap();
+        let prim = |p| Box::new(Term::Primitive(p));
+        let int = |n| prim(Prim::Lit(Lit::IntLit(n)));
+        let app = |t, u| Box::new(Term::Application(t, u));
+        let expected = Term::Application(
+            app(
+                app(
+                    prim(Prim::Func(PrimFunc::If)),
+                    app(app(prim(Prim::Func(PrimFunc::Gt)), int(2)), int(-1)),
+                ),
+                app(app(prim(Prim::Func(PrimFunc::Add)), int(1)), int(2)),
+            ),
+            int(0),
+        );
+        assert_eq!(t, expected);
+        assert!(parse("λif. if").is_err());
+    }
+
+    #[test]
+    fn case_branches_extend_to_the_next_bar() {
+        let t = parse("data B = T | F in λb. case b of T -> not b | F -> b").unwrap();
+        let Term::Data(decl, body) = t else {
+            panic!("expected a data declaration")
+        };
+        assert_eq!(decl.to_string(), "data B = T | F");
+        let Term::Abstraction(_, body) = *body else {
+            panic!("expected an abstraction")
+        };
+        let Term::Case(_, branches) = *body else {
+            panic!("expected a case")
+        };
+        let names: Vec<_> = branches
+            .iter()
+            .map(|(c, xs, _)| (c.as_str(), xs.len()))
+            .collect();
+        assert_eq!(names, [("T", 0), ("F", 0)]);
+        assert!(parse("case b of t -> b").is_err());
+        assert!(parse("data b = T in T").is_err());
+    }
+
+    #[test]
+    fn recursive_bindings_are_separated_by_and() {
+        let t = parse("let rec f = λx. g x and g = λy. f y in f").unwrap();
+        let Term::LetRec(bindings, body) = t else {
+            panic!("expected a let rec")
+        };
+        let names: Vec<_> = bindings.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(names, ["f", "g"]);
+        assert_eq!(*body, Term::Variable("f".into()));
+        assert!(parse("let rec f = 1 in and").is_err());
+        assert!(parse("let rec in f").is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use super::*;
+use crate::hindley_milner;
 
 // To be more memory efficient, this could try to free up space after unifying
 // since the unified terms would now be identical.
@@ -34,7 +35,7 @@ impl FlatTypeEnvironment {
     fn vars(&self, t: FlatTypeRef) -> HashSet<String> {
         self.reduce(t, &mut |l| match l {
             Type::Var(v) => [v.to_owned()].into(),
-            Type::GenVar(_) => HashSet::new(),
+            Type::GenVar(_) | Type::Int | Type::Bool => HashSet::new(),
             Type::Fn { lhs, rhs } => lhs | rhs,
         })
     }
@@ -42,7 +43,7 @@ impl FlatTypeEnvironment {
     fn gen_vars(&self, t: FlatTypeRef) -> HashSet<String> {
         self.reduce(t, &mut |l| match l {
             Type::GenVar(v) => [v.to_owned()].into(),
-            Type::Var(_) => HashSet::new(),
+            Type::Var(_) | Type::Int | Type::Bool => HashSet::new(),
             Type::Fn { lhs, rhs } => lhs | rhs,
         })
     }
@@ -58,18 +59,39 @@ impl FlatTypeEnvironment {
             .rev()
             .find(|(v, _)| v == x)
             .map(|(_, t)| *t)?;
+        Some(self.instantiate_ref(t))
+    }
+
+    fn instantiate_ref(&mut self, t: FlatTypeRef) -> FlatTypeRef {
         let mapping = self
             .gen_vars(t)
             .into_iter()
             .map(|gv| (gv, self.new_var()))
             .collect();
-        Some(self.inst_gv(&mapping, t))
+        self.inst_gv(&mapping, t)
+    }
+
+    /// Insert the type scheme of a primitive, which is built from base types and generalised
+    /// variables.
+    fn insert_prim_type(&mut self, ty: &hindley_milner::Type) -> FlatTypeRef {
+        let node = match ty {
+            hindley_milner::Type::Variable(v) => Type::Var(v.clone()),
+            hindley_milner::Type::GenVariable(v) => Type::GenVar(v.clone()),
+            hindley_milner::Type::Fn(t, u) => Type::Fn {
+                lhs: self.insert_prim_type(t),
+                rhs: self.insert_prim_type(u),
+            },
+            hindley_milner::Type::Int => Type::Int,
+            hindley_milner::Type::Bool => Type::Bool,
+            hindley_milner::Type::Data(..) => unreachable!("no primitive involves data types"),
+        };
+        self.insert_type(node)
     }
 
     fn new_var(&mut self) -> FlatTypeRef {
         let a = format!("t{}", self.var_count);
         self.var_count += 1;
-        self.insert_type(Type::GenVar(a))
+        self.insert_type(Type::Var(a))
     }
 
     fn push_binding(&mut self, var: String, t: FlatTypeRef) {
@@ -88,6 +110,7 @@ impl FlatTypeEnvironment {
             }
             match (&self[lhs_ref], &self[rhs_ref]) {
                 (Type::Var(x), Type::Var(y)) if x == y => continue,
+                (Type::Int, Type::Int) | (Type::Bool, Type::Bool) => continue,
                 (Type::Fn { lhs: t1, rhs: u1 }, Type::Fn { lhs: t2, rhs: u2 }) => {
                     equalities.push((*t1, *t2));
                     equalities.push((*u1, *u2));
@@ -132,7 +155,7 @@ impl FlatTypeEnvironment {
                     t
                 }
             }
-            Type::Var(_) => t,
+            Type::Var(_) | Type::Int | Type::Bool => t,
         }
     }
 
@@ -151,7 +174,7 @@ impl FlatTypeEnvironment {
                     t
                 }
             }
-            Type::GenVar(_) => t,
+            Type::GenVar(_) | Type::Int | Type::Bool => t,
         }
     }
 
@@ -199,12 +222,16 @@ pub trait HMTermArena {
             }
             Term::Let { var, expr, body } => {
                 let ex_ty = self.type_in(expr, type_env)?;
-                type_env.generalise(ex_ty);
+                let ex_ty = type_env.generalise(ex_ty);
                 type_env.push_binding(var.to_owned(), ex_ty);
                 let body_ty = self.type_in(body, type_env);
                 type_env.pop_binding().unwrap();
                 body_ty
             }
+            Term::Prim(p) => {
+                let t = type_env.insert_prim_type(&p.ty());
+                Some(type_env.instantiate_ref(t))
+            }
         }
     }
 
@@ -245,3 +272,48 @@ impl HMTermArena for BoxedTermArena {
         &node.root
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(t: Term<BoxedTerm>) -> BoxedTerm {
+        BoxedTerm { root: Box::new(t) }
+    }
+
+    fn from_hm(t: &hindley_milner::Term) -> BoxedTerm {
+        boxed(match t {
+            hindley_milner::Term::Variable(x) => Term::Var(x.clone()),
+            hindley_milner::Term::Abstraction(var, body) => Term::Abs {
+                var: var.clone(),
+                body: from_hm(body),
+            },
+            hindley_milner::Term::Application(lhs, rhs) => Term::App {
+                lhs: from_hm(lhs),
+                rhs: from_hm(rhs),
+            },
+            hindley_milner::Term::Let(var, expr, body) => Term::Let {
+                var: var.clone(),
+                expr: from_hm(expr),
+                body: from_hm(body),
+            },
+            hindley_milner::Term::Primitive(p) => Term::Prim(*p),
+            t => panic!("{} has no arena representation", t),
+        })
+    }
+
+    fn root_type(source: &str) -> Option<Type<FlatTypeRef>> {
+        let term = from_hm(&hindley_milner::parser::parse(source).unwrap());
+        let ty = BoxedTermArena.type_closed(&term)?;
+        let root = ty.root();
+        Some(ty[root].clone())
+    }
+
+    #[test]
+    fn primitives_have_base_types() {
+        let ty = root_type("let id = λx. x in if (id true) (add (id 1) 2) 0");
+        assert!(matches!(ty, Some(Type::Int)));
+        assert!(matches!(root_type("not (gt 1 2)"), Some(Type::Bool)));
+        assert!(root_type("add true 1").is_none());
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 use std::ops::Index;
 use std::rc::Rc;
 
+use super::Prim;
+
 mod calc;
 pub use calc::*;
 
@@ -11,6 +13,7 @@ pub enum Term<A> {
     App { lhs: A, rhs: A },
     Abs { var: String, body: A },
     Let { var: String, expr: A, body: A },
+    Prim(Prim),
 }
 
 #[derive(Debug, Clone)]
@@ -18,6 +21,8 @@ pub enum Type<A> {
     Var(String),
     GenVar(String),
     Fn { lhs: A, rhs: A },
+    Int,
+    Bool,
 }
 
 impl<A> Term<A> {
@@ -25,6 +30,7 @@ impl<A> Term<A> {
     fn map<B, F: FnMut(A) -> B>(self, mut f: F) -> Term<B> {
         match self {
             Self::Var(v) => Term::Var(v),
+            Self::Prim(p) => Term::Prim(p),
             Self::App { lhs, rhs } => Term::App {
                 lhs: f(lhs),
                 rhs: f(rhs),
@@ -42,6 +48,7 @@ impl<A> Term<A> {
     fn map_ref<B, F: FnMut(&A) -> B>(&self, mut f: F) -> Term<B> {
         match self {
             Self::Var(v) => Term::Var(v.clone()),
+            Self::Prim(p) => Term::Prim(*p),
             Self::App { lhs, rhs } => Term::App {
                 lhs: f(lhs),
                 rhs: f(rhs),
@@ -65,6 +72,8 @@ impl<A> Type<A> {
         match self {
             Self::Var(v) => Type::Var(v),
             Self::GenVar(v) => Type::GenVar(v),
+            Self::Int => Type::Int,
+            Self::Bool => Type::Bool,
             Self::Fn { lhs, rhs } => Type::Fn {
                 lhs: f(lhs),
                 rhs: f(rhs),
@@ -77,6 +86,8 @@ impl<A> Type<A> {
         match self {
             Self::Var(v) => Type::Var(v.clone()),
             Self::GenVar(v) => Type::GenVar(v.clone()),
+            Self::Int => Type::Int,
+            Self::Bool => Type::Bool,
             Self::Fn { lhs, rhs } => Type::Fn {
                 lhs: f(lhs),
                 rhs: f(rhs),
@@ -108,12 +119,6 @@ impl FlatTerm {
         }
     }
 
-    fn push(&mut self, t: BoxedTerm) -> FlatTermRef {
-        let node = t.root.map(|child| self.push(child));
-        self.nodes.push(node);
-        self.root()
-    }
-
     fn push_rc(
         &mut self,
         m: &mut HashMap<*const Term<RcTerm>, FlatTermRef>,
@@ -217,12 +222,6 @@ impl FlatType {
         }
     }
 
-    fn push(&mut self, t: BoxedType) -> FlatTypeRef {
-        let node = t.root.map(|child| self.push(child));
-        self.nodes.push(node);
-        self.root()
-    }
-
     fn push_rc(
         &mut self,
         m: &mut HashMap<*const Type<RcType>, FlatTypeRef>,
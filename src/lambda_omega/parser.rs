@@ -0,0 +1,161 @@
+use super::*;
+use crate::parsing::lexer::{Token, Tokens};
+
+pub use crate::parsing::ParseError;
+
+/// `ι` is the base type rather than a variable.
+pub(super) const RESERVED: &[&str] = &["ι", "iota"];
+
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let t = term(&mut tokens)?;
+    tokens.finish()?;
+    Ok(t)
+}
+
+pub fn parse_type(source: impl AsRef<[u8]>) -> Result<Type, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let ty = ty(&mut tokens)?;
+    tokens.finish()?;
+    Ok(ty)
+}
+
+fn term(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::Lambda) {
+        let x = tokens.identifier()?;
+        tokens.expect(&Token::Colon)?;
+        let ty = ty(tokens)?;
+        tokens.expect(&Token::Dot)?;
+        Ok(Term::Abstraction(x, ty, Box::new(term(tokens)?)))
+    } else {
+        application(tokens)
+    }
+}
+
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction.
+fn application(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    let mut t = atom(tokens)?;
+    loop {
+        t = match tokens.peek() {
+            Some(Token::Lambda) => {
+                return Ok(Term::Application(Box::new(t), Box::new(term(tokens)?)))
+            }
+            Some(Token::LParen) => Term::Application(Box::new(t), Box::new(atom(tokens)?)),
+            _ if tokens.at_identifier() => Term::Application(Box::new(t), Box::new(atom(tokens)?)),
+            _ => return Ok(t),
+        }
+    }
+}
+
+fn atom(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = term(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.at_identifier() {
+        Ok(Term::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a term"))
+    }
+}
+
+fn ty(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    if tokens.eat(&Token::Lambda) {
+        let a = tokens.identifier()?;
+        tokens.expect(&Token::DoubleColon)?;
+        let k = kind(tokens)?;
+        tokens.expect(&Token::Dot)?;
+        return Ok(Type::Abstraction(a, k, Box::new(ty(tokens)?)));
+    }
+    let mut t = ty_atom(tokens)?;
+    while matches!(tokens.peek(), Some(Token::LParen)) || tokens.at_identifier() || tokens.at_base()
+    {
+        t = Type::Application(Box::new(t), Box::new(ty_atom(tokens)?));
+    }
+    if tokens.eat(&Token::Arrow) {
+        Ok(Type::Fn(Box::new(t), Box::new(ty(tokens)?)))
+    } else {
+        Ok(t)
+    }
+}
+
+fn ty_atom(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = ty(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.eat_base() {
+        Ok(Type::Base)
+    } else if tokens.at_identifier() {
+        Ok(Type::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a type"))
+    }
+}
+
+fn kind(tokens: &mut Tokens) -> Result<Kind, ParseError> {
+    let k = if tokens.eat(&Token::LParen) {
+        let k = kind(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        k
+    } else if tokens.eat(&Token::Star) {
+        Kind::Type
+    } else {
+        return Err(tokens.unexpected("a kind"));
+    };
+    if tokens.eat(&Token::Arrow) {
+        Ok(Kind::Fn(Box::new(k), Box::new(kind(tokens)?)))
+    } else {
+        Ok(k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+    use proptest::prelude::*;
+
+    // Types are compared up to evaluation, which may not halt for arbitrary types,
+    // so the printed forms are compared instead.
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let f2 = parse(&s).expect("printed output should be parseable");
+            prop_assert_eq!(f2.to_string(), s);
+        }
+
+        #[test]
+        fn printed_type_parses_correctly(t in any::<Type>()) {
+            let s = format!("{}", t);
+            let t2 = parse_type(&s).expect("printed output should be parseable");
+            prop_assert_eq!(t2.to_string(), s);
+        }
+
+        #[test]
+        fn ascii_output_parses_correctly(t in any::<Type>()) {
+            let t2 = parse_type(format!("{:#}", t)).expect("printed output should be parseable");
+            prop_assert_eq!(t2.to_string(), t.to_string());
+        }
+
+        #[test]
+        fn pretty_type_parses_correctly(t in any::<Type>(), width in 1usize..80) {
+            let t2 = parse_type(t.pretty(width)).expect("pretty output should be parseable");
+            prop_assert_eq!(t2.to_string(), t.to_string());
+        }
+
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse(s);
+        }
+    }
+
+    #[test]
+    fn type_operator() {
+        let t = parse_type("(λf :: * -> *. f ι) (λa :: *. a -> a)").unwrap();
+        assert_eq!(t.kind_closed(), Ok(Kind::Type));
+        assert_eq!(t, Type::Fn(Box::new(Type::Base), Box::new(Type::Base)));
+    }
+}
@@ -1,7 +1,15 @@
+pub mod parser;
+
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 use super::{fresh_var, untyped};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Kind {
@@ -281,33 +289,38 @@ impl Type {
     }
 
     /// The kind of this type in the given type environment, if it is well-kinded.
-    pub fn kind_in(&self, type_env: &TypeEnvironment) -> Option<Kind> {
+    pub fn kind_in(&self, type_env: &TypeEnvironment) -> Result<Kind, TypeError> {
         match self {
-            Self::Base => Some(Kind::Type),
-            Self::Fn(_, _, _) => Some(Kind::Type),
+            Self::Base => Ok(Kind::Type),
+            Self::Fn(_, _, _) => Ok(Kind::Type),
             Self::TermAbstraction(x, ty, t) => {
                 let mut inner_env = type_env.clone();
                 inner_env.push((x.to_owned(), ty.as_ref().clone()));
                 t.kind_in(&inner_env)
                     .map(|t| Kind::TermFn(ty.as_ref().clone(), Box::new(t)))
             }
-            Self::TermApplication(t, u) => {
-                if let Some(Kind::TermFn(a1, b)) = t.kind_in(type_env) {
+            Self::TermApplication(t, u) => match t.kind_in(type_env)? {
+                Kind::TermFn(a1, b) => {
                     let a2 = u.type_in(type_env)?;
                     if a1 == a2 {
-                        Some(b.as_ref().clone())
+                        Ok(*b)
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                kind => Err(TypeError::NotFunKind {
+                    ty: t.as_ref().clone(),
+                    kind,
+                }),
+            },
         }
     }
 
     /// The kind of this type in the empty type environment, if it is well-kinded.
-    pub fn kind_closed(&self) -> Option<Kind> {
+    pub fn kind_closed(&self) -> Result<Kind, TypeError> {
         self.kind_in(&TypeEnvironment::new())
     }
 }
@@ -420,23 +433,31 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
-            Self::Abstraction(x, ty, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(t2))),
+            Self::Abstraction(x, ty, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (Self::Abstraction(x.to_owned(), ty.clone(), t2), r.within(0))
+            }),
             _ => None,
         }
     }
@@ -472,27 +493,36 @@ impl Term {
     /// Eta-reduce the left-outermost simplification if one exists.
     /// This does not check the type.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, ty, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                    let u2 = Box::new(u2);
+                    (Self::Abstraction(x.to_owned(), ty.clone(), u2), r.within(0))
+                })
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             _ => None,
@@ -511,41 +541,174 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given type environment, if it is well-typed.
-    pub fn type_in(&self, type_env: &TypeEnvironment) -> Option<Type> {
+    pub fn type_in(&self, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
         match self {
             Self::Variable(x) => type_env
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
             Self::Abstraction(x, ty, t) => {
                 let mut inner_env = type_env.clone();
                 inner_env.push((x.to_owned(), ty.as_ref().clone()));
                 t.type_in(&inner_env)
                     .map(|t| Type::Fn(x.to_owned(), Box::new(ty.as_ref().clone()), Box::new(t)))
             }
-            Self::Application(t, u) => {
-                if let Some(Type::Fn(v, a1, b)) = t.type_in(type_env) {
+            Self::Application(t, u) => match t.type_in(type_env)? {
+                Type::Fn(v, a1, b) => {
                     let a2 = u.type_in(type_env)?;
-                    if a1.as_ref() == &a2 {
-                        Some(b.term_substitute(&v, u))
+                    if *a1 == a2 {
+                        Ok(b.term_substitute(&v, u))
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
         }
     }
 
     /// The type of this term in the empty type environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Type> {
+    pub fn type_closed(&self) -> Result<Type, TypeError> {
         self.type_in(&TypeEnvironment::new())
     }
 }
 
+#[cfg(test)]
+impl Arbitrary for Type {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_type().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_type() -> impl Strategy<Value = Type> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    Just(Type::Base).prop_recursive(8, 64, 2, move |inner| {
+        prop_oneof![
+            (var(), inner.clone(), inner.clone()).prop_map(|(x, t, u)| Type::Fn(
+                x,
+                Box::new(t),
+                Box::new(u)
+            )),
+            (var(), inner.clone(), inner.clone()).prop_map(|(x, t, u)| Type::TermAbstraction(
+                x,
+                Box::new(t),
+                Box::new(u)
+            )),
+            (inner, arb_term()).prop_map(|(t, u)| Type::TermApplication(Box::new(t), Box::new(u))),
+        ]
+    })
+}
+
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+/// Terms whose type annotations are only `ι`, so that types and terms can be generated
+/// without unbounded mutual recursion.
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = var().prop_map(Term::Variable);
+    leaf.prop_recursive(8, 64, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (var(), inner).prop_map(|(x, b)| Term::Abstraction(
+                x,
+                Box::new(Type::Base),
+                Box::new(b)
+            )),
+        ]
+    })
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) => 1,
+            Self::Application(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, _, t), 0) | (Self::Application(t, _), 0) => Some(t),
+            (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, ty, _), 0) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+/// Why a type is ill-kinded, or a term ill-typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    UndefinedVar(String),
+    /// A type is applied to a term, but its kind isn't a function kind.
+    NotFunKind {
+        ty: Type,
+        kind: Kind,
+    },
+    /// A term is applied to an argument, but its type isn't a function type.
+    NotFunType {
+        term: Term,
+        ty: Type,
+    },
+    Mismatch {
+        expected: Type,
+        actual: Type,
+    },
+}
+
 impl PartialEq for Term {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -554,9 +717,12 @@ impl PartialEq for Term {
 
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -566,26 +732,57 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
-            Self::Base => write!(f, "ι"),
-            Self::Fn(v, t, u) => write!(f, "Π{}: {}. {}", v, t, u),
-            Self::TermAbstraction(x, ty, t) => write!(f, "Λ{}: {}. {}", x, ty, t),
+            Self::Base => write!(f, "{}", n.base()),
+            Self::Fn(v, t, u) => write!(f, "{}{}: {}. {}", n.pi(), v, n.show(t), n.show(u)),
+            Self::TermAbstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.big_lambda(), x, n.show(ty), n.show(t))
+            }
             Self::TermApplication(t, u) => {
                 write_ty(t, f)?;
                 write!(f, " ")?;
@@ -595,10 +792,74 @@ impl fmt::Display for Type {
     }
 }
 
+impl Pretty for Type {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Base => pretty::atom(n.base()),
+            Self::Fn(x, t, u) => {
+                let head = Doc::text(format!("{}{}: ", n.pi(), x)) + t.to_doc(n) + Doc::text(".");
+                pretty::binder(head, u.to_doc(n))
+            }
+            Self::TermAbstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.big_lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::TermApplication(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::TermApplication(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
+        match self {
+            Self::Type => write!(f, "*"),
+            Self::TermFn(t, k) => {
+                write_ty(t, f)?;
+                write!(f, " -> {}", n.show(k))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::NotFunKind { ty, kind } => {
+                write!(f, "{} is applied to a term, but has kind {}", ty, kind)
+            }
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl error::Error for TypeError {}
+
 fn write_ty(ty: &Type, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match ty {
         Type::Base | Type::TermApplication(_, _) => fmt::Display::fmt(ty, f),
-        _ => write!(f, "({})", ty),
+        _ => parens(ty, f),
     }
 }
 
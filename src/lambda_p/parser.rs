@@ -0,0 +1,131 @@
+use super::*;
+use crate::parsing::lexer::{Token, Tokens};
+
+pub use crate::parsing::ParseError;
+
+/// `ι` is the base type rather than a variable.
+pub(super) const RESERVED: &[&str] = &["ι", "iota"];
+
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let t = term(&mut tokens)?;
+    tokens.finish()?;
+    Ok(t)
+}
+
+pub fn parse_type(source: impl AsRef<[u8]>) -> Result<Type, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let ty = ty(&mut tokens)?;
+    tokens.finish()?;
+    Ok(ty)
+}
+
+fn term(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::Lambda) {
+        let x = tokens.identifier()?;
+        tokens.expect(&Token::Colon)?;
+        let ty = ty(tokens)?;
+        tokens.expect(&Token::Dot)?;
+        Ok(Term::Abstraction(x, Box::new(ty), Box::new(term(tokens)?)))
+    } else {
+        let head = atom(tokens)?;
+        arguments(tokens, head, Term::Application)
+    }
+}
+
+/// Apply `head` to a sequence of term arguments, where the last argument may be an
+/// unparenthesised abstraction.
+fn arguments<T>(
+    tokens: &mut Tokens,
+    mut head: T,
+    apply: impl Fn(Box<T>, Box<Term>) -> T,
+) -> Result<T, ParseError> {
+    loop {
+        head = match tokens.peek() {
+            Some(Token::Lambda) => return Ok(apply(Box::new(head), Box::new(term(tokens)?))),
+            Some(Token::LParen) => apply(Box::new(head), Box::new(atom(tokens)?)),
+            _ if tokens.at_identifier() => apply(Box::new(head), Box::new(atom(tokens)?)),
+            _ => return Ok(head),
+        }
+    }
+}
+
+fn atom(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = term(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.at_identifier() {
+        Ok(Term::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a term"))
+    }
+}
+
+fn ty(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    let binder = match tokens.peek() {
+        Some(Token::Pi) => Type::Fn,
+        Some(Token::BigLambda) => Type::TermAbstraction,
+        _ => {
+            let head = if tokens.eat(&Token::LParen) {
+                let t = ty(tokens)?;
+                tokens.expect(&Token::RParen)?;
+                t
+            } else if tokens.eat_base() {
+                Type::Base
+            } else {
+                return Err(tokens.unexpected("a type"));
+            };
+            return arguments(tokens, head, Type::TermApplication);
+        }
+    };
+    tokens.eat(&Token::Pi);
+    tokens.eat(&Token::BigLambda);
+    let x = tokens.identifier()?;
+    tokens.expect(&Token::Colon)?;
+    let input = ty(tokens)?;
+    tokens.expect(&Token::Dot)?;
+    Ok(binder(x, Box::new(input), Box::new(ty(tokens)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn printed_type_parses_correctly(t in any::<Type>()) {
+            let s = format!("{}", t);
+            let t2 = parse_type(s).expect("printed output should be parseable");
+            prop_assert_eq!(t2, t);
+        }
+
+        #[test]
+        fn ascii_output_parses_correctly(f in any::<Type>()) {
+            let s = format!("{:#}", f);
+            let f2 = parse_type(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn pretty_output_parses_correctly(f in any::<Type>(), width in 1usize..80) {
+            let s = f.pretty(width);
+            let f2 = parse_type(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse(s);
+        }
+    }
+}
@@ -1,21 +1,35 @@
 use std::collections::HashSet;
-use std::fmt;
 use std::iter;
+use std::{error, fmt};
+
+pub mod parser;
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+#[cfg(test)]
+use proptest_derive::Arbitrary;
 
 use super::*;
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
+#[cfg_attr(test, derive(Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Sort {
     Type,
     Universal,
 }
 
+#[cfg_attr(test, derive(Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Prim {
     Lit(Lit),
     Func(PrimFunc),
 }
 
+#[cfg_attr(test, derive(Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Lit {
     IntLit(isize),
@@ -24,6 +38,7 @@ pub enum Lit {
     BoolType,
 }
 
+#[cfg_attr(test, derive(Arbitrary))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PrimFunc {
     Add,
@@ -48,6 +63,27 @@ pub enum Term {
     Product(String, Box<Term>, Box<Term>),
 }
 
+/// Why a term is ill-typed.
+#[derive(Debug, Clone)]
+pub enum TypeError {
+    UndefinedVar(String),
+    /// A term is applied to an argument, but its type isn't a product.
+    NotFunType {
+        term: Term,
+        ty: Term,
+    },
+    Mismatch {
+        expected: Term,
+        actual: Term,
+    },
+    /// A term used as a type, whose type isn't a sort.
+    NotType(Term),
+    /// A term with no type, such as □.
+    NotTypeable(Term),
+    /// A type that isn't safe to evaluate while type checking.
+    NotConstSafe(Term),
+}
+
 pub type Environment = Vec<(String, Term)>;
 
 impl PrimFunc {
@@ -292,11 +328,13 @@ impl PrimFunc {
                             "x".into(),
                             Box::new(a.clone()),
                             Box::new(
-                                iter::repeat(Term::Variable("f".into()))
-                                    .take((*n).try_into().unwrap_or(0))
-                                    .fold(Term::Variable("x".into()), |x, f| {
-                                        Term::Application(Box::new(f), Box::new(x))
-                                    }),
+                                iter::repeat_n(
+                                    Term::Variable("f".into()),
+                                    (*n).try_into().unwrap_or(0),
+                                )
+                                .fold(Term::Variable("x".into()), |x, f| {
+                                    Term::Application(Box::new(f), Box::new(x))
+                                }),
                             ),
                         )),
                     ))
@@ -467,60 +505,67 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    return Some(b.substitute(x, u));
+                    return Some((b.substitute(x, u), Redex::root(Rule::Beta)));
                 }
                 if let Self::Primitive(Prim::Func(f)) = t.as_ref() {
                     if let Some(res) = f.reduce_unary(u) {
-                        return Some(res);
+                        return Some((res, Redex::root(Rule::Delta)));
                     }
                 }
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    return Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    return Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ));
                 }
                 if let Self::Application(op, a) = t.as_ref() {
                     if let Self::Primitive(Prim::Func(f)) = op.as_ref() {
                         if let Some(res) = f.reduce_binary(a, u) {
-                            return Some(res);
+                            return Some((res, Redex::root(Rule::Delta)));
                         }
                     }
                 }
-                if let Some(u2) = u.beta_reduce_lazy() {
-                    return Some(Self::Application(
-                        Box::new(t.as_ref().clone()),
-                        Box::new(u2),
+                if let Some((u2, r)) = u.beta_reduce_lazy_with_redex() {
+                    return Some((
+                        Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)),
+                        r.within(1),
                     ));
                 }
                 None
             }
             Self::Abstraction(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Abstraction(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((
+                        Self::Abstraction(v.to_owned(), ty, Box::new(t2)),
+                        r.within(1),
                     ))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Abstraction(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (
+                            Self::Abstraction(v.to_owned(), Box::new(ty2), t),
+                            r.within(0),
+                        )
                     })
                 }
             }
             Self::Product(v, ty, t) => {
-                if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Product(
-                        v.to_owned(),
-                        Box::new(ty.as_ref().clone()),
-                        Box::new(t2),
-                    ))
+                if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    let ty = Box::new(ty.as_ref().clone());
+                    Some((Self::Product(v.to_owned(), ty, Box::new(t2)), r.within(1)))
                 } else {
-                    ty.beta_reduce_lazy().map(|ty2| {
-                        Self::Product(v.to_owned(), Box::new(ty2), Box::new(t.as_ref().clone()))
+                    ty.beta_reduce_lazy_with_redex().map(|(ty2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Product(v.to_owned(), Box::new(ty2), t), r.within(0))
                     })
                 }
             }
@@ -528,7 +573,8 @@ impl Term {
         }
     }
 
-    /// Fully beta-reduce the term. This will halt if the term is well-typed and const-safe.
+    /// Fully beta-reduce the term. This will halt if the term is well-typed and const-safe, and
+    /// otherwise may unfold `rec` forever; [`Self::evaluate_with_fuel`] always halts.
     pub fn evaluate(&self) -> Self {
         let mut res = self.clone();
         while let Some(new_res) = res.beta_reduce_lazy() {
@@ -537,75 +583,165 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(self.clone(), vec![Self::beta_reduce_lazy_with_redex])
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given environment, if it is well-typed. This will always halt
     /// as rec may not appear in types.
-    pub fn type_in(&self, env: &Environment) -> Option<Term> {
+    pub fn type_in(&self, env: &Environment) -> Result<Term, TypeError> {
         match self {
-            Self::Primitive(p) => Some(p.ty()),
-            Self::Sort(Sort::Type) => Some(Term::Sort(Sort::Universal)),
+            Self::Primitive(p) => Ok(p.ty()),
+            Self::Sort(Sort::Type) => Ok(Term::Sort(Sort::Universal)),
             Self::Variable(x) => env
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
-            Self::Application(t, u) => {
-                if let Some(Term::Product(v, a1, b)) = t.type_in(env) {
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
+            Self::Application(t, u) => match t.type_in(env)? {
+                Term::Product(v, a1, b) => {
                     let a2 = u.type_in(env)?;
-                    if a1.as_ref() == &a2 {
+                    if *a1 == a2 {
                         let res = b.substitute(&v, u);
                         if !res.is_const_safe() {
-                            return None;
+                            return Err(TypeError::NotConstSafe(res));
                         }
-                        Some(res.evaluate())
+                        Ok(res.evaluate())
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
             Self::Product(x, ty, t) => {
                 if !matches!(ty.type_in(env)?, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(ty.as_ref().clone()));
                 }
                 if !ty.is_const_safe() {
-                    return None;
+                    return Err(TypeError::NotConstSafe(ty.as_ref().clone()));
                 }
                 let ty = ty.evaluate();
                 let mut inner_env = env.clone();
                 inner_env.push((x.to_owned(), ty));
                 let b = t.type_in(&inner_env)?;
                 if !matches!(b, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(t.as_ref().clone()));
                 }
-                Some(b)
+                Ok(b)
             }
             Self::Abstraction(x, ty, t) => {
                 if !matches!(ty.type_in(env)?, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(ty.as_ref().clone()));
                 }
                 if !ty.is_const_safe() {
-                    return None;
+                    return Err(TypeError::NotConstSafe(ty.as_ref().clone()));
                 }
                 let ty = ty.evaluate();
                 let mut inner_env = env.clone();
                 inner_env.push((x.to_owned(), ty.clone()));
                 let b = t.type_in(&inner_env)?;
                 if !matches!(b.type_in(&inner_env)?, Term::Sort(_)) {
-                    return None;
+                    return Err(TypeError::NotType(b));
                 }
-                Some(Term::Product(x.to_owned(), Box::new(ty), Box::new(b)))
+                Ok(Term::Product(x.to_owned(), Box::new(ty), Box::new(b)))
             }
-            Self::Sort(Sort::Universal) => None,
+            Self::Sort(Sort::Universal) => Err(TypeError::NotTypeable(self.clone())),
         }
     }
 
     /// The type of this term in the empty environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Term> {
+    pub fn type_closed(&self) -> Result<Term, TypeError> {
         self.type_in(&Environment::new())
     }
 }
 
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = prop_oneof![
+        any::<Prim>().prop_map(Term::Primitive),
+        var().prop_map(Term::Variable),
+        any::<Sort>().prop_map(Term::Sort)
+    ];
+    leaf.prop_recursive(16, 256, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (var(), inner.clone(), inner.clone()).prop_map(|(x, t, b)| Term::Abstraction(
+                x,
+                Box::new(t),
+                Box::new(b)
+            )),
+            (var(), inner.clone(), inner).prop_map(|(x, t, b)| Term::Product(
+                x,
+                Box::new(t),
+                Box::new(b)
+            )),
+        ]
+    })
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) | Self::Application(..) | Self::Product(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, t, _), 0)
+            | (Self::Product(_, t, _), 0)
+            | (Self::Application(t, _), 0) => Some(t),
+            (Self::Abstraction(_, _, u), 1)
+            | (Self::Product(_, _, u), 1)
+            | (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, _, t), 0) => Self::Abstraction(x.clone(), child, t.clone()),
+            (Self::Abstraction(x, ty, _), 1) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Product(x, _, t), 0) => Self::Product(x.clone(), child, t.clone()),
+            (Self::Product(x, ty, _), 1) => Self::Product(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
 impl PartialEq for Term {
     /// Alpha equivalence.
     fn eq(&self, other: &Self) -> bool {
@@ -613,19 +749,41 @@ impl PartialEq for Term {
     }
 }
 
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+            Self::NotType(t) => write!(f, "{} is not a type", t),
+            Self::NotTypeable(t) => write!(f, "{} has no type", t),
+            Self::NotConstSafe(t) => write!(f, "{} can't be evaluated in a type", t),
+        }
+    }
+}
+
+impl error::Error for TypeError {}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Primitive(p) => p.fmt(f),
             Self::Sort(Sort::Type) => write!(f, "*"),
-            Self::Sort(Sort::Universal) => write!(f, "□"),
+            Self::Sort(Sort::Universal) => write!(f, "{}", n.universe()),
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
                 write_term(ty, f)?;
-                write!(f, " -> {}", t)
+                write!(f, " -> {}", n.show(t))
             }
-            Self::Product(x, ty, t) => write!(f, "Π{}: {}. {}", x, ty, t),
+            Self::Product(x, ty, t) => write!(f, "{}{}: {}. {}", n.pi(), x, n.show(ty), n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -635,6 +793,44 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Primitive(p) => pretty::atom(n.show(p).to_string()),
+            Self::Sort(Sort::Type) => pretty::atom("*"),
+            Self::Sort(Sort::Universal) => pretty::atom(n.universe()),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Product(x, ty, t) if !t.free_vars().contains(x) => {
+                pretty::infix(at(ty.as_ref(), n, Prec::Application), " ->", t.to_doc(n))
+            }
+            Self::Product(x, ty, t) => {
+                let head = Doc::text(format!("{}{}: ", n.pi(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 impl fmt::Display for Prim {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -657,14 +853,16 @@ impl fmt::Display for Lit {
 
 impl fmt::Display for PrimFunc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Add => write!(f, "+"),
-            Self::Sub => write!(f, "−"),
-            Self::Mul => write!(f, "×"),
+            Self::Sub => write!(f, "{}", n.pick("−", "sub")),
+            // `*` is the sort of types.
+            Self::Mul => write!(f, "{}", n.pick("×", "mul")),
             Self::IntEq => write!(f, "="),
             Self::Gt => write!(f, ">"),
-            Self::Or => write!(f, "∨"),
-            Self::Not => write!(f, "¬"),
+            Self::Or => write!(f, "{}", n.pick("∨", "or")),
+            Self::Not => write!(f, "{}", n.pick("¬", "not")),
             Self::Rec => write!(f, "rec"),
             Self::If => write!(f, "if"),
             Self::For => write!(f, "for"),
@@ -675,7 +873,7 @@ impl fmt::Display for PrimFunc {
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Sort(_) | Term::Primitive(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -684,9 +882,52 @@ fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         Term::Variable(_) | Term::Sort(_) | Term::Primitive(_) | Term::Application(_, _) => {
             fmt::Display::fmt(t, f)
         }
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::trace::Limit;
+
+    #[test]
+    fn primitive_applications_are_delta_redexes() {
+        let int = |i| Box::new(Term::Primitive(Prim::Lit(Lit::IntLit(i))));
+        let add = |a, b| {
+            let op = Box::new(Term::Primitive(Prim::Func(PrimFunc::Add)));
+            Term::Application(Box::new(Term::Application(op, a)), b)
+        };
+        let int_type = Box::new(Term::Primitive(Prim::Lit(Lit::IntType)));
+        let x = Box::new(Term::Variable("x".into()));
+        let inc = Term::Abstraction("x".into(), int_type, Box::new(add(x, int(1))));
+        let t = Term::Application(Box::new(inc), Box::new(add(int(2), int(3))));
+        let steps: Vec<_> = t.reduction_trace().collect();
+        let redexes: Vec<_> = steps.iter().map(|s| s.redex.clone()).collect();
+        assert_eq!(
+            redexes,
+            vec![
+                Some(Redex::root(Rule::Beta)),
+                Some(Redex::root(Rule::Delta).within(1).within(0)),
+                Some(Redex::root(Rule::Delta)),
+                None
+            ]
+        );
+        assert_eq!(steps[3].term, *int(6));
+    }
+
+    #[test]
+    fn rec_runs_out_of_fuel() {
+        let int_type = Box::new(Term::Primitive(Prim::Lit(Lit::IntType)));
+        let rec = Box::new(Term::Primitive(Prim::Func(PrimFunc::Rec)));
+        let id = Term::Abstraction(
+            "x".into(),
+            int_type.clone(),
+            Box::new(Term::Variable("x".into())),
+        );
+        let t = Term::Application(Box::new(Term::Application(rec, int_type)), Box::new(id));
+        let err = t.evaluate_with_fuel(100).unwrap_err();
+        assert_eq!(err.steps, 100);
+        assert_eq!(err.limit, Limit::Steps);
+    }
+}
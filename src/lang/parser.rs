@@ -0,0 +1,171 @@
+use super::*;
+use crate::parsing::lexer::{Token, Tokens};
+
+pub use crate::parsing::ParseError;
+
+pub(super) const RESERVED: &[&str] = &[
+    "int", "bool", "true", "false", "if", "for", "rec", "sub", "mul", "or", "not",
+];
+
+/// The primitives spelt as reserved words, including the ASCII spellings of symbols.
+const PRIMITIVES: &[(&str, Prim)] = &[
+    ("int", Prim::Lit(Lit::IntType)),
+    ("bool", Prim::Lit(Lit::BoolType)),
+    ("true", Prim::Lit(Lit::BoolLit(true))),
+    ("false", Prim::Lit(Lit::BoolLit(false))),
+    ("if", Prim::Func(PrimFunc::If)),
+    ("for", Prim::Func(PrimFunc::For)),
+    ("rec", Prim::Func(PrimFunc::Rec)),
+    ("sub", Prim::Func(PrimFunc::Sub)),
+    ("mul", Prim::Func(PrimFunc::Mul)),
+    ("or", Prim::Func(PrimFunc::Or)),
+    ("not", Prim::Func(PrimFunc::Not)),
+];
+
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let t = term(&mut tokens)?;
+    tokens.finish()?;
+    Ok(t)
+}
+
+fn term(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    let binder = match tokens.peek() {
+        Some(Token::Lambda) => Term::Abstraction,
+        Some(Token::Pi) => Term::Product,
+        _ => {
+            let t = application(tokens)?;
+            return if tokens.eat(&Token::Arrow) {
+                let body = term(tokens)?;
+                let x = fresh_var(&body.free_vars());
+                Ok(Term::Product(x, Box::new(t), Box::new(body)))
+            } else {
+                Ok(t)
+            };
+        }
+    };
+    tokens.eat(&Token::Lambda);
+    tokens.eat(&Token::Pi);
+    let x = tokens.identifier()?;
+    tokens.expect(&Token::Colon)?;
+    let ty = term(tokens)?;
+    tokens.expect(&Token::Dot)?;
+    Ok(binder(x, Box::new(ty), Box::new(term(tokens)?)))
+}
+
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction or product.
+fn application(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    let mut t = atom(tokens)?;
+    loop {
+        t = match tokens.peek() {
+            Some(Token::Lambda | Token::Pi) => {
+                return Ok(Term::Application(Box::new(t), Box::new(term(tokens)?)))
+            }
+            Some(
+                Token::LParen
+                | Token::Star
+                | Token::Box
+                | Token::Ident(_)
+                | Token::Int(_)
+                | Token::Plus
+                | Token::Minus
+                | Token::Times
+                | Token::Equals
+                | Token::Greater
+                | Token::Or
+                | Token::Not,
+            ) => Term::Application(Box::new(t), Box::new(atom(tokens)?)),
+            _ => return Ok(t),
+        }
+    }
+}
+
+fn atom(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = term(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.eat(&Token::Star) {
+        Ok(Term::Sort(Sort::Type))
+    } else if tokens.eat(&Token::Box) {
+        Ok(Term::Sort(Sort::Universal))
+    } else if let Some(n) = tokens.eat_int() {
+        Ok(Term::Primitive(Prim::Lit(Lit::IntLit(n))))
+    } else if let Some(p) = primitive(tokens) {
+        Ok(Term::Primitive(p))
+    } else if tokens.at_identifier() {
+        Ok(Term::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a term"))
+    }
+}
+
+/// A primitive written as a symbol or a reserved word.
+fn primitive(tokens: &mut Tokens) -> Option<Prim> {
+    let symbols = [
+        (Token::Plus, PrimFunc::Add),
+        (Token::Minus, PrimFunc::Sub),
+        (Token::Times, PrimFunc::Mul),
+        (Token::Equals, PrimFunc::IntEq),
+        (Token::Greater, PrimFunc::Gt),
+        (Token::Or, PrimFunc::Or),
+        (Token::Not, PrimFunc::Not),
+    ];
+    if let Some((_, f)) = symbols.into_iter().find(|(t, _)| tokens.eat(t)) {
+        return Some(Prim::Func(f));
+    }
+    PRIMITIVES
+        .iter()
+        .find(|(word, _)| tokens.eat_keyword(word))
+        .map(|&(_, p)| p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn ascii_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{:#}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn pretty_output_parses_correctly(f in any::<Term>(), width in 1usize..80) {
+            let s = f.pretty(width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse(s);
+        }
+    }
+
+    #[test]
+    fn primitives_are_typed_and_evaluated() {
+        let t = parse("(λx: int. if int (> x 2) (× x x) (− 0 x)) 3").unwrap();
+        assert_eq!(t.type_closed().unwrap(), parse("int").unwrap());
+        assert_eq!(t.evaluate(), Term::Primitive(Prim::Lit(Lit::IntLit(9))));
+    }
+
+    #[test]
+    fn ascii_multiplication_is_not_a_sort() {
+        let t = parse("λA: *. mul 2 3").unwrap();
+        assert_eq!(format!("{:#}", t), "\\A: *. mul 2 3");
+        assert_eq!(format!("{}", t), "λA: *. × 2 3");
+    }
+}
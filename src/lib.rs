@@ -1,14 +1,26 @@
 use std::collections::HashSet;
 
+#[cfg(not(any(feature = "rust-parser", feature = "tree-sitter")))]
+compile_error!("either the `rust-parser` or the `tree-sitter` feature must be enabled");
+
 pub mod calc_of_cons;
 pub mod cube;
 pub mod hindley_milner;
 pub mod lambda_omega;
 pub mod lambda_p;
 pub mod lang;
+pub mod locally_nameless;
+pub mod nbe;
+pub mod notation;
+pub mod parsing;
+pub mod pretty;
+pub mod repl;
 pub mod simply_typed;
+pub mod span;
+pub mod strategy;
 pub mod system_f;
 pub mod system_f_sub;
+pub mod trace;
 pub mod untyped;
 
 pub fn fresh_var(vs: &HashSet<String>) -> String {
@@ -22,3 +34,26 @@ pub fn fresh_var(vs: &HashSet<String>) -> String {
     // this will panic in debug mode and loop infinitely in release mode
     unreachable!()
 }
+
+/// Variable names accepted by every parser, for property tests.
+#[cfg(test)]
+fn arb_var() -> impl proptest::strategy::Strategy<Value = String> + Clone {
+    use proptest::strategy::Strategy;
+
+    "[a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω_][a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω0-9_]*'*"
+        .prop_filter("ASCII spellings of symbols are not variables", |x| {
+            !parsing::lexer::KEYWORDS.contains(&x.as_str())
+        })
+}
+
+/// Variable names accepted by a parser which also reserves the given words, for property tests.
+#[cfg(test)]
+fn arb_var_except(
+    reserved: &'static [&'static str],
+) -> impl proptest::strategy::Strategy<Value = String> + Clone {
+    use proptest::strategy::Strategy;
+
+    arb_var().prop_filter("reserved words are not variables", move |x| {
+        !reserved.contains(&x.as_str())
+    })
+}
@@ -0,0 +1,269 @@
+//! A locally nameless representation of terms, shared by the calculi.
+//!
+//! Bound variables are De Bruijn indices, counting every binder between a variable and the one
+//! binding it, and free variables keep their names. Binders remember the names they were
+//! written with only as hints, which equality ignores, so alpha-equivalent terms are
+//! structurally equal and can be compared or hashed without renaming anything.
+//!
+//! Each calculus implements [`LocallyNameless`] by translating its constructors into
+//! [`Ln::Node`]s labelled with a type of its own, and resolving names with a [`Scope`].
+
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// A term in locally nameless form, whose constructors are labelled by `K`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ln<K> {
+    /// A bound variable, by De Bruijn index.
+    Bound(usize),
+    Free(String),
+    /// A binder over its body.
+    Bind(Hint, Box<Ln<K>>),
+    /// Any other constructor, applied to its subterms.
+    Node(K, Vec<Ln<K>>),
+}
+
+/// The name a binder was written with, which doesn't affect equality.
+#[derive(Debug, Clone, Default)]
+pub struct Hint(pub String);
+
+impl PartialEq for Hint {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Hint {}
+
+impl Hash for Hint {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<K: Clone> Ln<K> {
+    pub fn node(label: K, children: impl IntoIterator<Item = Ln<K>>) -> Self {
+        Self::Node(label, children.into_iter().collect())
+    }
+
+    pub fn free_vars(&self) -> HashSet<String> {
+        let mut vars = HashSet::new();
+        self.visit_free(&mut |x| {
+            vars.insert(x.to_owned());
+        });
+        vars
+    }
+
+    fn visit_free(&self, f: &mut impl FnMut(&str)) {
+        match self {
+            Self::Bound(_) => {}
+            Self::Free(x) => f(x),
+            Self::Bind(_, body) => body.visit_free(f),
+            Self::Node(_, children) => children.iter().for_each(|c| c.visit_free(f)),
+        }
+    }
+
+    /// Whether no bound variable points outside the term.
+    pub fn is_locally_closed(&self) -> bool {
+        self.max_dangling(0).is_none()
+    }
+
+    fn max_dangling(&self, depth: usize) -> Option<usize> {
+        match self {
+            Self::Bound(i) => i.checked_sub(depth),
+            Self::Free(_) => None,
+            Self::Bind(_, body) => body.max_dangling(depth + 1),
+            Self::Node(_, children) => children.iter().filter_map(|c| c.max_dangling(depth)).max(),
+        }
+    }
+
+    /// Replace each variable bound at `depth` binders outside this term, by `with`.
+    fn replace_bound(&self, depth: usize, with: &Ln<K>) -> Self {
+        match self {
+            Self::Bound(i) if *i == depth => with.clone(),
+            Self::Bound(_) | Self::Free(_) => self.clone(),
+            Self::Bind(x, body) => {
+                Self::Bind(x.clone(), Box::new(body.replace_bound(depth + 1, with)))
+            }
+            Self::Node(k, children) => Self::Node(
+                k.clone(),
+                children
+                    .iter()
+                    .map(|c| c.replace_bound(depth, with))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The body of a [`Ln::Bind`] with the variable it binds replaced by `with`, which should
+    /// be locally closed.
+    pub fn open(&self, with: &Ln<K>) -> Self {
+        self.replace_bound(0, with)
+    }
+
+    /// Bind the free variable `x` in this term, giving the body of a [`Ln::Bind`].
+    pub fn close(&self, x: &str) -> Self {
+        self.close_at(x, 0)
+    }
+
+    fn close_at(&self, x: &str, depth: usize) -> Self {
+        match self {
+            Self::Free(y) if x == y => Self::Bound(depth),
+            Self::Bound(_) | Self::Free(_) => self.clone(),
+            Self::Bind(h, body) => Self::Bind(h.clone(), Box::new(body.close_at(x, depth + 1))),
+            Self::Node(k, children) => Self::Node(
+                k.clone(),
+                children.iter().map(|c| c.close_at(x, depth)).collect(),
+            ),
+        }
+    }
+
+    /// Substitute `with`, which should be locally closed, for the free variable `x`. Since
+    /// bound variables have no names, this can't capture anything.
+    pub fn substitute(&self, x: &str, with: &Ln<K>) -> Self {
+        match self {
+            Self::Free(y) if x == y => with.clone(),
+            Self::Bound(_) | Self::Free(_) => self.clone(),
+            Self::Bind(h, body) => Self::Bind(h.clone(), Box::new(body.substitute(x, with))),
+            Self::Node(k, children) => Self::Node(
+                k.clone(),
+                children.iter().map(|c| c.substitute(x, with)).collect(),
+            ),
+        }
+    }
+}
+
+/// Terms with a locally nameless form, which is equal for alpha-equivalent terms.
+pub trait LocallyNameless {
+    /// The labels of the constructors other than variables and binders.
+    type Node: Clone + Eq + Hash + fmt::Debug;
+
+    fn to_locally_nameless(&self) -> Ln<Self::Node>;
+}
+
+/// The names bound around a subterm being converted to locally nameless form, innermost last.
+///
+/// Each name belongs to a namespace `N`, for calculi such as System F where term and type
+/// variables can have the same name without one shadowing the other.
+#[derive(Debug, Clone, Default)]
+pub struct Scope<N = ()> {
+    names: Vec<(N, String)>,
+}
+
+impl<N: PartialEq> Scope<N> {
+    pub fn new() -> Self {
+        Self { names: Vec::new() }
+    }
+
+    /// The variable `x` in namespace `ns`, bound if it is in scope and free otherwise.
+    pub fn variable<K>(&self, ns: N, x: &str) -> Ln<K> {
+        match self
+            .names
+            .iter()
+            .rev()
+            .position(|(m, y)| *m == ns && y == x)
+        {
+            Some(i) => Ln::Bound(i),
+            None => Ln::Free(x.to_owned()),
+        }
+    }
+
+    /// A binder for `x` in namespace `ns`, with the body converted by `body` in the scope of
+    /// `x`.
+    pub fn bind<K>(&mut self, ns: N, x: &str, body: impl FnOnce(&mut Self) -> Ln<K>) -> Ln<K> {
+        self.names.push((ns, x.to_owned()));
+        let body = body(self);
+        self.names.pop();
+        Ln::Bind(Hint(x.to_owned()), Box::new(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calc_of_cons, hindley_milner, simply_typed, system_f, untyped};
+    use proptest::prelude::*;
+
+    fn ln(source: &str) -> Ln<untyped::Node> {
+        untyped::parser::parse(source)
+            .unwrap()
+            .to_locally_nameless()
+    }
+
+    proptest! {
+        #[test]
+        fn untyped_equality_agrees_with_de_bruijn(t in any::<untyped::Term>(), u in any::<untyped::Term>()) {
+            let mut context: Vec<_> = (&t.free_vars() | &u.free_vars()).into_iter().collect();
+            context.sort();
+            prop_assert_eq!(
+                t.to_locally_nameless() == u.to_locally_nameless(),
+                t.to_de_bruijn(&context) == u.to_de_bruijn(&context)
+            );
+        }
+
+        #[test]
+        fn substitution_agrees(t in any::<untyped::Term>(), u in any::<untyped::Term>()) {
+            let Some(x) = t.free_vars().into_iter().min() else { return Ok(()) };
+            prop_assert_eq!(
+                t.to_locally_nameless().substitute(&x, &u.to_locally_nameless()),
+                t.substitute(&x, &u).to_locally_nameless()
+            );
+        }
+
+        #[test]
+        fn renaming_bound_variables_preserves_equality(t in any::<calc_of_cons::Term>()) {
+            // Substitution renames every binder it goes under.
+            let renamed = t.substitute("x", &calc_of_cons::Term::Variable("x".into()));
+            prop_assert_eq!(t.to_locally_nameless(), renamed.to_locally_nameless());
+        }
+    }
+
+    #[test]
+    fn alpha_equivalent_terms_are_equal() {
+        assert_eq!(ln("λx. λy. x y z"), ln("λa. λb. a b z"));
+        assert_ne!(ln("λx. λy. x y z"), ln("λx. λy. y x z"));
+        assert_ne!(ln("λx. x"), ln("λx. y"));
+        let hm = |s| {
+            hindley_milner::parser::parse(s)
+                .unwrap()
+                .to_locally_nameless()
+        };
+        assert_eq!(hm("let f = λx. x in f f"), hm("let g = λy. y in g g"));
+        assert_ne!(hm("let x = x in x"), hm("let y = x in x"));
+        let st = |s| {
+            simply_typed::parser::parse(s)
+                .unwrap()
+                .to_locally_nameless()
+        };
+        assert_eq!(st("λx: ι -> ι. x"), st("λy: ι -> ι. y"));
+        assert_ne!(st("λx: ι -> ι. x"), st("λy: ι. y"));
+    }
+
+    #[test]
+    fn system_f_keeps_term_and_type_variables_apart() {
+        let f = |s| system_f::parser::parse(s).unwrap().to_locally_nameless();
+        assert_eq!(f("Λa. λa: a. a"), f("Λb. λx: b. x"));
+        assert_ne!(f("Λa. λa: a. a"), f("Λb. λx: b. b"));
+        assert_eq!(f("Λa. λx: ∀b. a -> b. x"), f("Λc. λy: ∀a. c -> a. y"));
+    }
+
+    #[test]
+    fn open_and_close_are_inverse() {
+        let Ln::Node(_, children) = ln("λx. f x (λy. x y)") else {
+            panic!("expected an abstraction")
+        };
+        let Ln::Bind(_, body) = &children[0] else {
+            panic!("expected a binder")
+        };
+        let opened = body.open(&Ln::Free("z".into()));
+        assert_eq!(opened, ln("f z (λy. z y)"));
+        assert_eq!(opened.close("z"), **body);
+        assert!(!body.is_locally_closed());
+        assert!(opened.is_locally_closed());
+    }
+}
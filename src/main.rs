@@ -1,9 +1,29 @@
-use std::{env, fs};
+use std::{env, fs, process};
 
 use lambda::untyped::*;
 
 fn main() {
-    let term = parser::parse(fs::read_to_string(env::args().nth(1).unwrap()).unwrap()).unwrap();
-    println!("{}", term);
-    println!("\n{}", compile::compile_toplevel(&term));
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: lambda <file>");
+        process::exit(2);
+    };
+    let source = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+    let (program, errors) = parser::parse_program_recovering(&source);
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("{}: {}", path, e.render(&source));
+        }
+        process::exit(1);
+    }
+    println!("{}", program.elaborate(Elaboration::Substitution));
+    match compile::compile_program(&program) {
+        Some(ast) => println!("\n{}", ast),
+        None => {
+            eprintln!("{}: the main term should be an abstraction", path);
+            process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,411 @@
+//! Normalisation by evaluation.
+//!
+//! Terms are translated into a [`Core`] syntax with De Bruijn indices, evaluated into values in
+//! which abstractions are closures, and then read back into normal forms, with bound variables
+//! stood in for by De Bruijn levels. Arguments are only evaluated when needed, so a term is
+//! normalised whenever normal order reduction would normalise it.
+//!
+//! Each β-step uses up a step of the [`Fuel`] given, so that terms without a normal form
+//! are given up on. No intermediate terms are built, so the size limit doesn't apply.
+
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::calc_of_cons::Sort;
+use crate::fresh_var;
+use crate::trace::{Fuel, Limit, OutOfFuel};
+
+/// The syntax shared by the calculi normalised here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Core {
+    /// A bound variable, by De Bruijn index.
+    Bound(usize),
+    Free(String),
+    Sort(Sort),
+    Abstraction(String, Option<Box<Core>>, Box<Core>),
+    Product(String, Box<Core>, Box<Core>),
+    Application(Box<Core>, Box<Core>),
+}
+
+impl Core {
+    /// The variable with the given name, where `scope` lists the bound names, innermost last.
+    pub fn variable(x: &str, scope: &[String]) -> Self {
+        match scope.iter().rev().position(|y| y == x) {
+            Some(i) => Self::Bound(i),
+            None => Self::Free(x.to_owned()),
+        }
+    }
+
+    /// The name of the bound variable with the given index.
+    pub fn name(index: usize, scope: &[String]) -> String {
+        scope[scope.len() - 1 - index].clone()
+    }
+
+    fn free_vars(&self, vars: &mut HashSet<String>) {
+        match self {
+            Self::Free(x) => {
+                vars.insert(x.clone());
+            }
+            Self::Bound(_) | Self::Sort(_) => {}
+            Self::Abstraction(_, ty, t) => {
+                if let Some(ty) = ty {
+                    ty.free_vars(vars);
+                }
+                t.free_vars(vars);
+            }
+            Self::Product(_, t, u) | Self::Application(t, u) => {
+                t.free_vars(vars);
+                u.free_vars(vars);
+            }
+        }
+    }
+
+    /// Whether the bound variable with the given index occurs in this term.
+    fn mentions(&self, index: usize) -> bool {
+        match self {
+            Self::Bound(i) => *i == index,
+            Self::Free(_) | Self::Sort(_) => false,
+            Self::Abstraction(_, ty, t) => {
+                ty.as_ref().is_some_and(|ty| ty.mentions(index)) || t.mentions(index + 1)
+            }
+            Self::Product(_, ty, t) => ty.mentions(index) || t.mentions(index + 1),
+            Self::Application(t, u) => t.mentions(index) || u.mentions(index),
+        }
+    }
+
+    /// This term with a binder above it removed, where the variable it bound isn't used.
+    fn unshift(&self, cutoff: usize) -> Self {
+        let under = |t: &Core| Box::new(t.unshift(cutoff + 1));
+        match self {
+            Self::Bound(i) if *i > cutoff => Self::Bound(i - 1),
+            Self::Bound(_) | Self::Free(_) | Self::Sort(_) => self.clone(),
+            Self::Abstraction(x, ty, t) => Self::Abstraction(
+                x.clone(),
+                ty.as_ref().map(|ty| Box::new(ty.unshift(cutoff))),
+                under(t),
+            ),
+            Self::Product(x, ty, t) => {
+                Self::Product(x.clone(), Box::new(ty.unshift(cutoff)), under(t))
+            }
+            Self::Application(t, u) => {
+                Self::Application(Box::new(t.unshift(cutoff)), Box::new(u.unshift(cutoff)))
+            }
+        }
+    }
+}
+
+/// The normal form of a term, also η-reduced if `eta` is set, or the number of steps taken
+/// before the fuel ran out, with `original` as the partial term.
+///
+/// Binders keep their names unless that would capture a variable, or shadow another binder.
+pub(crate) fn normalise<T: Clone>(
+    term: &Core,
+    eta: bool,
+    fuel: Fuel,
+    original: &T,
+) -> Result<Core, OutOfFuel<T>> {
+    let mut free = HashSet::new();
+    term.free_vars(&mut free);
+    let mut quote = Quote {
+        free,
+        names: Vec::new(),
+        eta,
+        budget: Budget {
+            fuel,
+            steps: Cell::new(0),
+        },
+    };
+    eval(term, &Env::default(), &quote.budget)
+        .and_then(|v| quote.value(&v))
+        .map_err(|limit| OutOfFuel {
+            steps: quote.budget.steps.get(),
+            partial_term: original.clone(),
+            limit,
+        })
+}
+
+/// The β-steps taken so far.
+struct Budget {
+    fuel: Fuel,
+    steps: Cell<usize>,
+}
+
+impl Budget {
+    /// Take a β-step, unless the fuel has run out.
+    fn step(&self) -> Result<(), Limit> {
+        let steps = self.steps.get();
+        match self.fuel.limit_reached_with(steps, || 0) {
+            Some(limit) => Err(limit),
+            None => {
+                self.steps.set(steps + 1);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value<'a> {
+    Abstraction(&'a str, Option<Rc<Thunk<'a>>>, Closure<'a>),
+    Product(&'a str, Rc<Thunk<'a>>, Closure<'a>),
+    Sort(Sort),
+    /// A variable, or a value that isn't a function, applied to arguments.
+    Neutral(Head<'a>, Vec<Rc<Thunk<'a>>>),
+}
+
+#[derive(Debug, Clone)]
+enum Head<'a> {
+    Free(&'a str),
+    /// A variable bound while reading back, by De Bruijn level.
+    Level(usize),
+    /// A sort or product, which can't be applied.
+    Stuck(Rc<Value<'a>>),
+}
+
+#[derive(Debug, Clone)]
+struct Closure<'a> {
+    body: &'a Core,
+    env: Env<'a>,
+}
+
+impl<'a> Closure<'a> {
+    fn apply(&self, arg: Rc<Thunk<'a>>, budget: &Budget) -> Result<Value<'a>, Limit> {
+        eval(self.body, &self.env.bind(arg), budget)
+    }
+}
+
+/// A term whose value is found the first time it is needed.
+#[derive(Debug)]
+struct Thunk<'a> {
+    /// The term and environment, until they are evaluated and can be let go of.
+    term: RefCell<Option<(&'a Core, Env<'a>)>>,
+    value: OnceCell<Value<'a>>,
+}
+
+impl<'a> Thunk<'a> {
+    fn delay(term: &'a Core, env: &Env<'a>) -> Rc<Self> {
+        Rc::new(Self {
+            term: RefCell::new(Some((term, env.clone()))),
+            value: OnceCell::new(),
+        })
+    }
+
+    fn ready(value: Value<'a>) -> Rc<Self> {
+        Rc::new(Self {
+            term: RefCell::new(None),
+            value: OnceCell::from(value),
+        })
+    }
+
+    fn force(&self, budget: &Budget) -> Result<&Value<'a>, Limit> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let (term, env) = self
+            .term
+            .take()
+            .expect("thunk should have a value or a term");
+        let value = eval(term, &env, budget)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+}
+
+/// The values of the bound variables, innermost first.
+#[derive(Debug, Clone, Default)]
+struct Env<'a>(Option<Rc<(Rc<Thunk<'a>>, Env<'a>)>>);
+
+impl<'a> Env<'a> {
+    fn bind(&self, value: Rc<Thunk<'a>>) -> Self {
+        Self(Some(Rc::new((value, self.clone()))))
+    }
+
+    fn get(&self, index: usize) -> &Rc<Thunk<'a>> {
+        let mut env = self;
+        for _ in 0..index {
+            env = &env.0.as_ref().expect("bound variable should be in scope").1;
+        }
+        &env.0.as_ref().expect("bound variable should be in scope").0
+    }
+}
+
+fn eval<'a>(mut term: &'a Core, env: &Env<'a>, budget: &Budget) -> Result<Value<'a>, Limit> {
+    let mut env = env.clone();
+    // The body of a β-redex is evaluated by going round the loop rather than by recursion, so
+    // that terms like `(λx. x x) (λx. x x)` run out of fuel rather than stack.
+    let value = loop {
+        break match term {
+            Core::Bound(i) => env.get(*i).force(budget)?.clone(),
+            Core::Free(x) => Value::Neutral(Head::Free(x), Vec::new()),
+            Core::Sort(s) => Value::Sort(*s),
+            Core::Abstraction(x, ty, body) => Value::Abstraction(
+                x,
+                ty.as_ref().map(|ty| Thunk::delay(ty, &env)),
+                Closure {
+                    body,
+                    env: env.clone(),
+                },
+            ),
+            Core::Product(x, ty, body) => Value::Product(
+                x,
+                Thunk::delay(ty, &env),
+                Closure {
+                    body,
+                    env: env.clone(),
+                },
+            ),
+            Core::Application(t, u) => match eval(t, &env, budget)? {
+                Value::Abstraction(_, _, f) => {
+                    budget.step()?;
+                    env = f.env.bind(Thunk::delay(u, &env));
+                    term = f.body;
+                    continue;
+                }
+                Value::Neutral(head, mut args) => {
+                    args.push(Thunk::delay(u, &env));
+                    Value::Neutral(head, args)
+                }
+                v => Value::Neutral(Head::Stuck(Rc::new(v)), vec![Thunk::delay(u, &env)]),
+            },
+        };
+    };
+    Ok(value)
+}
+
+/// Reading values back into terms.
+struct Quote {
+    /// The free variables of the term being normalised.
+    free: HashSet<String>,
+    /// The names of the variables bound so far, by De Bruijn level.
+    names: Vec<String>,
+    eta: bool,
+    budget: Budget,
+}
+
+impl Quote {
+    fn value(&mut self, value: &Value<'_>) -> Result<Core, Limit> {
+        Ok(match value {
+            Value::Sort(s) => Core::Sort(*s),
+            Value::Abstraction(x, ty, f) => {
+                let ty = match ty {
+                    Some(ty) => Some(Box::new(self.thunk(ty)?)),
+                    None => None,
+                };
+                let (x, body) = self.under(x, f)?;
+                match body {
+                    Core::Application(g, arg)
+                        if self.eta && *arg == Core::Bound(0) && !g.mentions(0) =>
+                    {
+                        g.unshift(0)
+                    }
+                    body => Core::Abstraction(x, ty, Box::new(body)),
+                }
+            }
+            Value::Product(x, ty, f) => {
+                let ty = Box::new(self.thunk(ty)?);
+                let (x, body) = self.under(x, f)?;
+                Core::Product(x, ty, Box::new(body))
+            }
+            Value::Neutral(head, args) => {
+                let head = match head {
+                    Head::Free(x) => Core::Free(x.to_string()),
+                    Head::Level(l) => Core::Bound(self.names.len() - 1 - l),
+                    Head::Stuck(v) => self.value(v)?,
+                };
+                args.iter().try_fold(head, |t, arg| {
+                    Ok(Core::Application(Box::new(t), Box::new(self.thunk(arg)?)))
+                })?
+            }
+        })
+    }
+
+    fn thunk(&mut self, thunk: &Thunk<'_>) -> Result<Core, Limit> {
+        let value = thunk.force(&self.budget)?;
+        self.value(value)
+    }
+
+    /// The body of a binder, with the name given to its variable.
+    fn under(&mut self, x: &str, f: &Closure<'_>) -> Result<(String, Core), Limit> {
+        let x = if self.free.contains(x) || self.names.iter().any(|y| y == x) {
+            let mut used = self.free.clone();
+            used.extend(self.names.iter().cloned());
+            fresh_var(&used)
+        } else {
+            x.to_owned()
+        };
+        let var = Value::Neutral(Head::Level(self.names.len()), Vec::new());
+        let body = f.apply(Thunk::ready(var), &self.budget)?;
+        self.names.push(x);
+        let body = self.value(&body);
+        let x = self.names.pop().expect("binder should be in scope");
+        Ok((x, body?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trace::Fuel;
+    use crate::{calc_of_cons, untyped};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn untyped_agrees_with_evaluate(t in any::<untyped::Term>()) {
+            if let Ok(normal_form) = t.evaluate_with_fuel(Fuel::steps(200).with_size_limit(2000)) {
+                prop_assert_eq!(t.normalise(), normal_form);
+            }
+        }
+
+        #[test]
+        fn calc_of_cons_agrees_with_evaluate(t in any::<calc_of_cons::Term>()) {
+            if let Ok(normal_form) = t.evaluate_with_fuel(Fuel::steps(200).with_size_limit(2000)) {
+                prop_assert_eq!(t.normalise(), normal_form);
+            }
+        }
+
+        #[test]
+        fn infer_agrees_with_evaluate(t in any::<calc_of_cons::infer::Term>()) {
+            if let Ok(normal_form) = t.evaluate_with_fuel(Fuel::steps(200).with_size_limit(2000)) {
+                prop_assert_eq!(t.normalise(), normal_form);
+            }
+        }
+    }
+
+    #[test]
+    fn unused_arguments_are_not_evaluated() {
+        let t = untyped::parser::parse("(λx. λy. y) ((λx. x x) (λx. x x))").unwrap();
+        assert_eq!(t.normalise(), untyped::parser::parse("λy. y").unwrap());
+    }
+
+    #[test]
+    fn divergent_terms_run_out_of_fuel() {
+        use crate::trace::Limit;
+
+        let omega = untyped::parser::parse("(λx. x x) (λx. x x)").unwrap();
+        let err = omega.normalise_with_fuel(100_000).unwrap_err();
+        assert_eq!((err.steps, err.limit), (100_000, Limit::Steps));
+        assert_eq!(err.partial_term, omega);
+        // Each unfolding is read back under the last.
+        let fix = untyped::parser::parse("λf. (λx. f (x x)) (λx. f (x x))").unwrap();
+        assert!(fix.normalise_with_fuel(100).is_err());
+    }
+
+    #[test]
+    fn binders_are_renamed_to_avoid_capture() {
+        let t = untyped::parser::parse("(λf. λx. f x x) (λy. x y)").unwrap();
+        let normal_form = t.normalise();
+        assert_eq!(normal_form, untyped::parser::parse("λz. x z z").unwrap());
+        assert_eq!(normal_form.to_string(), "λx0. x x0 x0");
+    }
+
+    #[test]
+    fn church_exponentiation() {
+        let t = untyped::parser::parse("(λm. λn. n m) (λf. λx. f (f x)) (λf. λx. f (f (f (f x))))")
+            .unwrap();
+        let sixteen = (0..16).fold(untyped::variable("x"), |t, _| {
+            untyped::application(untyped::variable("f"), t)
+        });
+        let sixteen = untyped::abstraction("f", untyped::abstraction("x", sixteen));
+        assert_eq!(t.normalise(), sixteen);
+    }
+}
@@ -0,0 +1,102 @@
+//! The symbols terms are printed with.
+//!
+//! Terms are printed with Unicode symbols by default, and with ASCII spellings that every
+//! parser also accepts when formatted with the alternate flag, as in `format!("{:#}", t)`.
+
+use std::fmt;
+
+/// Whether terms are printed with Unicode or ASCII symbols.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    Unicode,
+    Ascii,
+}
+
+impl Notation {
+    /// The notation asked for by a formatter: ASCII if the alternate flag is set.
+    pub fn of(f: &fmt::Formatter<'_>) -> Self {
+        if f.alternate() {
+            Self::Ascii
+        } else {
+            Self::Unicode
+        }
+    }
+
+    /// The first symbol in Unicode notation, or the second in ASCII notation.
+    pub fn pick(self, unicode: &'static str, ascii: &'static str) -> &'static str {
+        match self {
+            Self::Unicode => unicode,
+            Self::Ascii => ascii,
+        }
+    }
+
+    pub fn lambda(self) -> &'static str {
+        self.pick("λ", "\\")
+    }
+
+    pub fn big_lambda(self) -> &'static str {
+        self.pick("Λ", "/\\")
+    }
+
+    pub fn pi(self) -> &'static str {
+        self.pick("Π", "Pi ")
+    }
+
+    pub fn for_all(self) -> &'static str {
+        self.pick("∀", "forall ")
+    }
+
+    pub fn universe(self) -> &'static str {
+        self.pick("□", "Box")
+    }
+
+    pub fn base(self) -> &'static str {
+        self.pick("ι", "iota")
+    }
+
+    /// Display a subterm in this notation.
+    ///
+    /// Formatting arguments don't inherit the alternate flag, so subterms written with `{}`
+    /// must be wrapped in this.
+    pub fn show<T: fmt::Display + ?Sized>(self, t: &T) -> Show<'_, T> {
+        Show(self, t)
+    }
+}
+
+/// A value displayed in a given notation.
+pub struct Show<'a, T: ?Sized>(Notation, &'a T);
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Show<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Notation::Unicode => write!(f, "{}", self.1),
+            Notation::Ascii => write!(f, "{:#}", self.1),
+        }
+    }
+}
+
+/// Write a subterm in parentheses, in the formatter's notation.
+pub fn parens<T: fmt::Display + ?Sized>(t: &T, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "(")?;
+    t.fmt(f)?;
+    write!(f, ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Symbol;
+
+    impl fmt::Display for Symbol {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", Notation::of(f).lambda())
+        }
+    }
+
+    #[test]
+    fn alternate_flag_reaches_subterms() {
+        assert_eq!(format!("{}", Notation::Ascii.show(&Symbol)), "\\");
+        assert_eq!(format!("{:#}", Notation::Unicode.show(&Symbol)), "λ");
+    }
+}
@@ -0,0 +1,142 @@
+//! Recursive descent parsing of the grammar shared by the untyped calculus and the calculus
+//! of constructions, used when the crate is built without tree-sitter.
+//!
+//! A calculus says how to parse its terms by implementing [`Grammar`]; spans and error
+//! recovery are handled here, in the same way as by the tree-sitter walker.
+
+use std::marker::PhantomData;
+
+use super::lexer::Tokens;
+use super::ParseError;
+use crate::span::{Span, SpanTable};
+
+/// How a calculus parses its terms from a token stream.
+pub(crate) trait Grammar: Sized {
+    type Term;
+
+    /// A placeholder for a subterm that failed to parse.
+    fn hole(name: String) -> Self::Term;
+
+    /// Parse a term starting at the next token.
+    ///
+    /// Subterms should be parsed with [`Descent::child`], numbered in the order they appear in
+    /// the term's constructor, so that their spans are recorded.
+    fn term(d: &mut Descent<'_, Self>) -> Result<Self::Term, ParseError>;
+}
+
+pub(crate) fn parse_with_spans<G: Grammar>(
+    source: &[u8],
+) -> Result<(G::Term, SpanTable), ParseError> {
+    let mut d = Descent::<G>::new(Tokens::new(source, &[])?, false);
+    let term = d.term(G::term)?;
+    d.tokens.finish()?;
+    let mut spans = SpanTable::new();
+    for (path, span) in d.spans {
+        spans.insert(path, span);
+    }
+    Ok((term, spans))
+}
+
+pub(crate) fn parse_recovering<G: Grammar>(source: &[u8]) -> (G::Term, Vec<ParseError>) {
+    let tokens = match Tokens::new(source, &[]) {
+        Ok(tokens) => tokens,
+        Err(e) => return (G::hole("?0".to_owned()), vec![e]),
+    };
+    let mut d = Descent::<G>::new(tokens, true);
+    let term = match d.term(G::term) {
+        Ok(term) => term,
+        Err(e) => d.hole_for(e).expect("parser should be recovering"),
+    };
+    if let Err(e) = d.tokens.finish() {
+        // The leftover token is usually the one an earlier error was already about.
+        if d.errors.last().map(ParseError::span) != Some(e.span()) {
+            d.errors.push(e);
+        }
+    }
+    (term, d.errors)
+}
+
+/// A recursive descent parser, recording the span of each subterm it builds.
+pub(crate) struct Descent<'a, G> {
+    pub tokens: Tokens<'a>,
+    path: Vec<usize>,
+    /// Spans in the order they were recorded, so that later ones win for repeated paths.
+    spans: Vec<(Vec<usize>, Span)>,
+    /// Whether to replace subterms that fail to parse with holes instead of failing.
+    recovering: bool,
+    holes: usize,
+    errors: Vec<ParseError>,
+    grammar: PhantomData<G>,
+}
+
+impl<'a, G: Grammar> Descent<'a, G> {
+    fn new(tokens: Tokens<'a>, recovering: bool) -> Self {
+        Self {
+            tokens,
+            path: Vec::new(),
+            spans: Vec::new(),
+            recovering,
+            holes: 0,
+            errors: Vec::new(),
+            grammar: PhantomData,
+        }
+    }
+
+    /// A fresh hole standing for a subterm that failed with the given error, if recovering.
+    fn hole_for(&mut self, e: ParseError) -> Result<G::Term, ParseError> {
+        if !self.recovering {
+            return Err(e);
+        }
+        self.errors.push(e);
+        let hole = G::hole(format!("?{}", self.holes));
+        self.holes += 1;
+        Ok(hole)
+    }
+
+    /// Parse the subterm at the given child index of the current term.
+    pub fn child(
+        &mut self,
+        index: usize,
+        f: impl FnOnce(&mut Self) -> Result<G::Term, ParseError>,
+    ) -> Result<G::Term, ParseError> {
+        self.path.push(index);
+        let res = self.term(f);
+        self.path.pop();
+        res
+    }
+
+    fn term(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<G::Term, ParseError>,
+    ) -> Result<G::Term, ParseError> {
+        let start = self.tokens.offset();
+        match f(self) {
+            Ok(term) => {
+                self.record(start);
+                Ok(term)
+            }
+            Err(e) => self.hole_for(e),
+        }
+    }
+
+    /// Record the span of the current term, from `start` to the last token consumed.
+    fn record(&mut self, start: usize) {
+        let span = self.tokens.span_since(start);
+        self.spans.push((self.path.clone(), span));
+    }
+
+    /// A marker for the spans of the term about to be parsed, to be passed to [`Self::wrap`].
+    pub fn mark(&self) -> (usize, usize) {
+        (self.spans.len(), self.tokens.offset())
+    }
+
+    /// Make the term parsed since `mark` the first child of a larger term, for left-nested
+    /// terms such as applications whose size isn't known until they have been parsed.
+    pub fn wrap(&mut self, (index, start): (usize, usize)) {
+        self.record(start);
+        let depth = self.path.len();
+        for (path, _) in &mut self.spans[index..] {
+            path.insert(depth, 0);
+        }
+    }
+}
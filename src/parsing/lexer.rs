@@ -0,0 +1,390 @@
+//! A lexer and token stream for the hand-written recursive descent parsers.
+
+use std::fmt;
+
+use super::ParseError;
+use crate::span::{Lines, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Ident(String),
+    /// An integer literal, which may be negative.
+    Int(isize),
+    Lambda,
+    BigLambda,
+    Pi,
+    ForAll,
+    Dot,
+    Colon,
+    DoubleColon,
+    Subtype,
+    Arrow,
+    Equals,
+    Star,
+    Box,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Bar,
+    Semicolon,
+    Plus,
+    Minus,
+    Times,
+    Greater,
+    Or,
+    Not,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Ident(x) => x,
+            Self::Int(n) => return write!(f, "`{}`", n),
+            Self::Lambda => "λ",
+            Self::BigLambda => "Λ",
+            Self::Pi => "Π",
+            Self::ForAll => "∀",
+            Self::Dot => ".",
+            Self::Colon => ":",
+            Self::DoubleColon => "::",
+            Self::Subtype => "<:",
+            Self::Arrow => "->",
+            Self::Equals => "=",
+            Self::Star => "*",
+            Self::Box => "□",
+            Self::LParen => "(",
+            Self::RParen => ")",
+            Self::LBracket => "[",
+            Self::RBracket => "]",
+            Self::Bar => "|",
+            Self::Semicolon => ";",
+            Self::Plus => "+",
+            Self::Minus => "−",
+            Self::Times => "×",
+            Self::Greater => ">",
+            Self::Or => "∨",
+            Self::Not => "¬",
+        };
+        write!(f, "`{}`", s)
+    }
+}
+
+/// ASCII spellings of symbols, which aren't variable names in any calculus.
+#[cfg(test)]
+pub(crate) const KEYWORDS: &[&str] = &["forall", "Pi", "Box"];
+
+fn is_letter(c: char) -> bool {
+    c.is_ascii_alphabetic()
+        || c == '_'
+        || ('α'..='κ').contains(&c)
+        || ('μ'..='ω').contains(&c)
+        || ('Α'..='Κ').contains(&c)
+        || ('Μ'..='Ο').contains(&c)
+        || ('Ρ'..='Ω').contains(&c)
+}
+
+fn lex(source: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    let (tokens, errors) = lex_recovering(source);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(tokens),
+    }
+}
+
+/// Lex the source, skipping anything that isn't a token and returning an error for each.
+pub(crate) fn lex_recovering(source: &str) -> (Vec<(Token, Span)>, Vec<ParseError>) {
+    let lines = Lines::new(source.as_bytes());
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let token = match c {
+            'λ' | '\\' => Token::Lambda,
+            '/' if chars.next_if(|&(_, c)| c == '\\').is_some() => Token::BigLambda,
+            'Λ' => Token::BigLambda,
+            'Π' => Token::Pi,
+            '∀' => Token::ForAll,
+            '.' => Token::Dot,
+            '=' => Token::Equals,
+            '*' => Token::Star,
+            '□' => Token::Box,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '|' => Token::Bar,
+            ';' => Token::Semicolon,
+            '+' => Token::Plus,
+            '−' => Token::Minus,
+            '×' => Token::Times,
+            '>' => Token::Greater,
+            '∨' => Token::Or,
+            '¬' => Token::Not,
+            ':' if chars.next_if(|&(_, c)| c == ':').is_some() => Token::DoubleColon,
+            ':' => Token::Colon,
+            '<' if chars.next_if(|&(_, c)| c == ':').is_some() => Token::Subtype,
+            '-' if chars.next_if(|&(_, c)| c == '>').is_some() => Token::Arrow,
+            c if c.is_ascii_digit()
+                || c == '-' && chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) =>
+            {
+                while chars.next_if(|(_, c)| c.is_ascii_digit()).is_some() {}
+                let end = chars.peek().map_or(source.len(), |&(i, _)| i);
+                match source[start..end].parse() {
+                    Ok(n) => Token::Int(n),
+                    Err(_) => {
+                        let span = lines.span(start, end);
+                        errors.push(ParseError::Syntax("integer out of range".into(), span));
+                        continue;
+                    }
+                }
+            }
+            c if is_letter(c) => {
+                while chars
+                    .next_if(|&(_, c)| is_letter(c) || c.is_ascii_digit())
+                    .is_some()
+                {}
+                while chars.next_if(|&(_, c)| c == '\'').is_some() {}
+                let end = chars.peek().map_or(source.len(), |&(i, _)| i);
+                match &source[start..end] {
+                    "forall" => Token::ForAll,
+                    "Pi" => Token::Pi,
+                    "Box" => Token::Box,
+                    word => Token::Ident(word.to_owned()),
+                }
+            }
+            c => {
+                let span = lines.span(start, start + c.len_utf8());
+                errors.push(ParseError::Syntax(format!("unexpected `{}`", c), span));
+                continue;
+            }
+        };
+        let end = chars.peek().map_or(source.len(), |&(i, _)| i);
+        tokens.push((token, lines.span(start, end)));
+    }
+    (tokens, errors)
+}
+
+/// The tokens of a source being parsed, with the position of the parser in them.
+pub(crate) struct Tokens<'a> {
+    source: &'a [u8],
+    lines: Lines,
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+    /// Words that are not variable names in the calculus being parsed.
+    reserved: &'static [&'static str],
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(source: &'a [u8], reserved: &'static [&'static str]) -> Result<Self, ParseError> {
+        Ok(Self {
+            source,
+            lines: Lines::new(source),
+            tokens: lex(std::str::from_utf8(source)?)?,
+            pos: 0,
+            reserved,
+        })
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    /// Whether the next token is the given reserved word.
+    pub fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(x)) if x == keyword)
+    }
+
+    /// Whether the next token is the base type `ι`, which can also be spelt `iota`.
+    pub fn at_base(&self) -> bool {
+        self.at_keyword("ι") || self.at_keyword("iota")
+    }
+
+    pub fn eat_base(&mut self) -> bool {
+        self.eat_keyword("ι") || self.eat_keyword("iota")
+    }
+
+    /// Whether the next token is a variable name.
+    pub fn at_identifier(&self) -> bool {
+        matches!(self.peek(), Some(Token::Ident(x)) if !self.reserved.contains(&x.as_str()))
+    }
+
+    pub fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn expect(&mut self, token: &Token) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&token.to_string()))
+        }
+    }
+
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", keyword)))
+        }
+    }
+
+    pub fn eat_int(&mut self) -> Option<isize> {
+        let Some(&Token::Int(n)) = self.peek() else {
+            return None;
+        };
+        self.pos += 1;
+        Some(n)
+    }
+
+    pub fn identifier(&mut self) -> Result<String, ParseError> {
+        if !self.at_identifier() {
+            return Err(self.unexpected("a variable"));
+        }
+        let Some((Token::Ident(x), _)) = self.tokens.get(self.pos) else {
+            unreachable!("identifier should have been checked")
+        };
+        self.pos += 1;
+        Ok(x.to_owned())
+    }
+
+    /// The byte offset of the next token.
+    #[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+    pub fn offset(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.source.len(), |(_, span)| span.start)
+    }
+
+    /// The span from the given offset to the end of the last token consumed.
+    #[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+    pub fn span_since(&self, start: usize) -> Span {
+        let end = self
+            .pos
+            .checked_sub(1)
+            .map_or(start, |i| self.tokens[i].1.end);
+        self.lines.span(start, end.max(start))
+    }
+
+    /// Skip the next token, returning its span.
+    #[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+    pub fn skip(&mut self) -> Option<Span> {
+        let span = self.tokens.get(self.pos).map(|(_, span)| *span);
+        if span.is_some() {
+            self.pos += 1;
+        }
+        span
+    }
+
+    /// An error saying that `expected` was wanted instead of the next token.
+    pub fn unexpected(&self, expected: &str) -> ParseError {
+        match self.tokens.get(self.pos) {
+            Some((token, span)) => {
+                ParseError::Syntax(format!("expected {} but found {}", expected, token), *span)
+            }
+            None => {
+                let end = self.source.len();
+                ParseError::Syntax(
+                    format!("expected {} but found end of input", expected),
+                    self.lines.span(end, end),
+                )
+            }
+        }
+    }
+
+    /// Check that every token has been consumed.
+    pub fn finish(&self) -> Result<(), ParseError> {
+        match self.tokens.get(self.pos) {
+            Some((token, span)) => Err(ParseError::Syntax(format!("unexpected {}", token), *span)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lexes_symbols_and_identifiers() {
+        let tokens: Vec<_> = lex("Λa <: Top. λx': a -> a. x' [a]")
+            .unwrap()
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+        let ident = |x: &str| Token::Ident(x.to_owned());
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BigLambda,
+                ident("a"),
+                Token::Subtype,
+                ident("Top"),
+                Token::Dot,
+                Token::Lambda,
+                ident("x'"),
+                Token::Colon,
+                ident("a"),
+                Token::Arrow,
+                ident("a"),
+                Token::Dot,
+                ident("x'"),
+                Token::LBracket,
+                ident("a"),
+                Token::RBracket,
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_integers() {
+        let tokens: Vec<_> = lex("f 12 -3 x2 a->b").unwrap();
+        let tokens: Vec<_> = tokens.into_iter().map(|(t, _)| t).collect();
+        let ident = |x: &str| Token::Ident(x.to_owned());
+        assert_eq!(
+            tokens,
+            vec![
+                ident("f"),
+                Token::Int(12),
+                Token::Int(-3),
+                ident("x2"),
+                ident("a"),
+                Token::Arrow,
+                ident("b"),
+            ]
+        );
+        assert!(lex("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn lexes_ascii_spellings() {
+        let unicode: Vec<_> = lex("Λa. ∀b. Πx: □. λy. y").unwrap();
+        let ascii: Vec<_> = lex("/\\a. forall b. Pi x: Box. \\y. y").unwrap();
+        let tokens = |ts: Vec<(Token, Span)>| ts.into_iter().map(|(t, _)| t).collect::<Vec<_>>();
+        assert_eq!(tokens(unicode), tokens(ascii));
+        assert_eq!(tokens(lex("forall").unwrap()), [Token::ForAll]);
+        assert_eq!(tokens(lex("Pix").unwrap()), [Token::Ident("Pix".into())]);
+    }
+
+    #[test]
+    fn lambda_is_not_a_letter() {
+        let tokens = lex("λx.x").unwrap();
+        assert_eq!(tokens[0].0, Token::Lambda);
+        assert_eq!(tokens[1].1, Span::new("λx.x".as_bytes(), 2, 3));
+    }
+}
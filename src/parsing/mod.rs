@@ -0,0 +1,85 @@
+//! Infrastructure shared by the parsers of each calculus.
+//!
+//! The untyped calculus and the calculus of constructions are parsed with the tree-sitter
+//! grammar when the `tree-sitter` feature is enabled, and otherwise with recursive descent
+//! parsers built on [`lexer`] and [`descent`]. The other calculi always use recursive descent.
+//!
+//! The recursive descent parsers also accept the ASCII spellings of symbols printed by the
+//! alternate `{:#}` format (see [`crate::notation`]).
+
+use std::{error, fmt, str::Utf8Error};
+
+use crate::span::Span;
+
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+pub(crate) mod descent;
+pub(crate) mod lexer;
+#[cfg(feature = "tree-sitter")]
+pub(crate) mod tree;
+
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+pub(crate) use descent::{parse_recovering, parse_with_spans};
+#[cfg(feature = "tree-sitter")]
+pub(crate) use tree::{parse_recovering, parse_with_spans};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseError {
+    Encoding(Utf8Error),
+    Other(String),
+    Syntax(String, Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Syntax(_, span) => Some(*span),
+            Self::Encoding(_) | Self::Other(_) => None,
+        }
+    }
+
+    /// Move the error's location as if the parsed source started at byte `offset` of `source`.
+    pub(crate) fn shift(self, source: &[u8], offset: usize) -> Self {
+        match self {
+            Self::Syntax(s, span) => Self::Syntax(s, span.shift(source, offset)),
+            e => e,
+        }
+    }
+
+    /// Describe the error with the offending source underlined.
+    pub fn render(&self, source: impl AsRef<[u8]>) -> String {
+        match self {
+            Self::Syntax(s, span) => format!(
+                "parse error: {}\n --> {}\n{}",
+                s,
+                span,
+                span.excerpt(source.as_ref())
+            ),
+            e => e.to_string(),
+        }
+    }
+}
+
+impl From<Utf8Error> for ParseError {
+    fn from(value: Utf8Error) -> Self {
+        Self::Encoding(value)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Other(s) => write!(f, "parse error: {}", s),
+            Self::Syntax(s, span) => write!(f, "parse error at {}: {}", span, s),
+            Self::Encoding(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Encoding(e) => Some(e),
+            Self::Other(_) | Self::Syntax(_, _) => None,
+        }
+    }
+}
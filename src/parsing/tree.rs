@@ -0,0 +1,297 @@
+//! Parsing with the tree-sitter grammar shared by all calculi.
+//!
+//! A calculus only has to say how to build its terms from the node kinds it supports by
+//! implementing [`Syntax`]; walking the tree, spans, error reporting and error recovery are
+//! handled here.
+
+use std::marker::PhantomData;
+
+use tree_sitter::{Language, Node, Parser, Tree, TreeCursor};
+
+use super::ParseError;
+use crate::span::{Span, SpanTable};
+
+extern "C" {
+    fn tree_sitter_lambda() -> Language;
+}
+
+impl ParseError {
+    pub(crate) fn msg(s: impl Into<String>) -> Self {
+        Self::Other(s.into())
+    }
+
+    pub(crate) fn at(node: &Node<'_>, s: impl Into<String>) -> Self {
+        Self::Syntax(s.into(), Span::from_node(node))
+    }
+
+    /// Build an error describing an `ERROR` or `MISSING` node.
+    fn from_error_node(node: &Node<'_>, source: &[u8]) -> Self {
+        if node.is_missing() {
+            return Self::at(node, format!("missing {}", node.kind()));
+        }
+        let text = String::from_utf8_lossy(&source[node.start_byte()..node.end_byte()]);
+        let text = text.lines().next().unwrap_or("");
+        if text.is_empty() {
+            Self::at(node, "unexpected end of input")
+        } else if text.chars().count() > 20 {
+            let text: String = text.chars().take(20).collect();
+            Self::at(node, format!("unexpected `{}…`", text))
+        } else {
+            Self::at(node, format!("unexpected `{}`", text))
+        }
+    }
+}
+
+/// How a calculus builds its terms from the nodes of the shared grammar.
+pub(crate) trait Syntax: Sized {
+    type Term;
+
+    /// A placeholder for a subterm that failed to parse.
+    fn hole(name: String) -> Self::Term;
+
+    /// Build the term for the given named node, which the cursor is on.
+    ///
+    /// Subterms should be walked with [`Walker::child`], numbered in the order they appear in
+    /// the term's constructor, so that their spans are recorded.
+    fn node<'a>(
+        w: &mut Walker<'a, Self>,
+        node: Node<'a>,
+        cursor: &mut TreeCursor<'a>,
+    ) -> Result<Self::Term, ParseError>;
+}
+
+pub(crate) fn parse_with_spans<S: Syntax>(
+    source: &[u8],
+) -> Result<(S::Term, SpanTable), ParseError> {
+    let tree = parse_tree(source);
+    if let Some(node) = first_error(tree.root_node()) {
+        return Err(ParseError::from_error_node(&node, source));
+    }
+    let mut walker = Walker::<S>::new(source, false);
+    let term = walker.root(&tree)?;
+    Ok((term, walker.spans))
+}
+
+pub(crate) fn parse_recovering<S: Syntax>(source: &[u8]) -> (S::Term, Vec<ParseError>) {
+    let tree = parse_tree(source);
+    let mut walker = Walker::<S>::new(source, true);
+    let term = match walker.root(&tree) {
+        Ok(term) => term,
+        Err(e) => walker.hole_for(e).expect("walker should be recovering"),
+    };
+    let errors: Vec<_> = error_nodes(tree.root_node())
+        .iter()
+        .map(|node| ParseError::from_error_node(node, source))
+        .collect();
+    if errors.is_empty() {
+        (term, walker.errors)
+    } else {
+        (term, errors)
+    }
+}
+
+fn parse_tree(source: &[u8]) -> Tree {
+    let mut parser = Parser::new();
+    let language = unsafe { tree_sitter_lambda() };
+    parser
+        .set_language(language)
+        .expect("language should be compiled with a compatible version of tree-sitter");
+    parser.parse(source, None).expect("parser should succeed")
+}
+
+/// The first `ERROR` or `MISSING` node in the tree, if any.
+fn first_error(node: Node<'_>) -> Option<Node<'_>> {
+    if !node.has_error() {
+        return None;
+    }
+    if node.is_error() || node.is_missing() {
+        return Some(node);
+    }
+    let mut cursor = node.walk();
+    let found = node.children(&mut cursor).find_map(first_error);
+    found.or(Some(node))
+}
+
+/// Every `ERROR` or `MISSING` node in the tree, outermost first and in source order.
+///
+/// Errors nested inside an `ERROR` node are not reported separately.
+fn error_nodes(node: Node<'_>) -> Vec<Node<'_>> {
+    if !node.has_error() {
+        return Vec::new();
+    }
+    if node.is_error() || node.is_missing() {
+        return vec![node];
+    }
+    let mut cursor = node.walk();
+    let found: Vec<_> = node.children(&mut cursor).flat_map(error_nodes).collect();
+    found
+}
+
+/// Walks the syntax tree, recording the span of each subterm it builds.
+pub(crate) struct Walker<'a, S> {
+    source: &'a [u8],
+    path: Vec<usize>,
+    spans: SpanTable,
+    /// Whether to replace subterms that fail to parse with holes instead of failing.
+    recovering: bool,
+    holes: usize,
+    errors: Vec<ParseError>,
+    syntax: PhantomData<S>,
+}
+
+impl<'a, S: Syntax> Walker<'a, S> {
+    fn new(source: &'a [u8], recovering: bool) -> Self {
+        Self {
+            source,
+            path: Vec::new(),
+            spans: SpanTable::new(),
+            recovering,
+            holes: 0,
+            errors: Vec::new(),
+            syntax: PhantomData,
+        }
+    }
+
+    fn root(&mut self, tree: &'a Tree) -> Result<S::Term, ParseError> {
+        let mut cursor = tree.walk();
+        let node = advance(self.source, &mut cursor)?.ok_or(ParseError::msg("missing root"))?;
+        if node.kind() != "source" {
+            return Err(ParseError::at(
+                &node,
+                format!("expected root node but got {}", node.kind()),
+            ));
+        }
+        if !cursor.goto_first_child() {
+            return Err(ParseError::at(&node, "expected a term"));
+        }
+        self.term(&mut cursor)
+    }
+
+    /// A fresh hole standing for a subterm that failed with the given error, if recovering.
+    fn hole_for(&mut self, e: ParseError) -> Result<S::Term, ParseError> {
+        if !self.recovering {
+            return Err(e);
+        }
+        self.errors.push(e);
+        let hole = S::hole(format!("?{}", self.holes));
+        self.holes += 1;
+        Ok(hole)
+    }
+
+    pub fn text(&self, node: &Node<'a>) -> Result<&'a str, ParseError> {
+        Ok(node.utf8_text(self.source)?)
+    }
+
+    pub fn identifier(&mut self, cursor: &mut TreeCursor<'a>) -> Result<String, ParseError> {
+        let node = advance(self.source, cursor)?.ok_or(ParseError::msg("missing variable"))?;
+        if node.kind() != "variable" {
+            return Err(ParseError::at(
+                &node,
+                format!("expected variable but got {}", node.kind()),
+            ));
+        }
+        Ok(self.text(&node)?.to_owned())
+    }
+
+    /// Walk the subterm at the given child index of the current term.
+    pub fn child(
+        &mut self,
+        index: usize,
+        cursor: &mut TreeCursor<'a>,
+    ) -> Result<S::Term, ParseError> {
+        self.path.push(index);
+        let res = self.term(cursor);
+        self.path.pop();
+        res
+    }
+
+    fn term(&mut self, cursor: &mut TreeCursor<'a>) -> Result<S::Term, ParseError> {
+        let node = match advance(self.source, cursor) {
+            Ok(Some(node)) => node,
+            Ok(None) => return self.hole_for(ParseError::msg("missing term")),
+            Err(e) => return self.hole_for(e),
+        };
+        self.spans.insert(self.path.clone(), Span::from_node(&node));
+        let saved = cursor.clone();
+        let res = S::node(self, node, cursor);
+        if res.is_err() && self.recovering {
+            *cursor = saved;
+        }
+        res.or_else(|e| self.hole_for(e))
+    }
+
+    /// Move to the first child of the given node.
+    pub fn enter(&self, node: &Node<'a>, cursor: &mut TreeCursor<'a>) -> Result<(), ParseError> {
+        if cursor.goto_first_child() {
+            Ok(())
+        } else {
+            Err(ParseError::at(
+                node,
+                format!("{} should have children", node.kind()),
+            ))
+        }
+    }
+
+    /// Move to the next child of the given node, failing with `missing` if there is none.
+    pub fn next(
+        &self,
+        node: &Node<'a>,
+        cursor: &mut TreeCursor<'a>,
+        missing: &str,
+    ) -> Result<(), ParseError> {
+        if cursor.goto_next_sibling() {
+            Ok(())
+        } else {
+            Err(ParseError::at(
+                node,
+                format!("{} should have {}", node.kind(), missing),
+            ))
+        }
+    }
+
+    /// Move back up to the node whose children were being walked.
+    pub fn leave(&self, cursor: &mut TreeCursor<'a>) {
+        assert!(cursor.goto_parent());
+    }
+
+    /// The field name of the next named child.
+    pub fn field(&self, cursor: &mut TreeCursor<'a>) -> Result<Option<&'static str>, ParseError> {
+        advance(self.source, cursor)?;
+        Ok(cursor.field_name())
+    }
+
+    /// Walk a node with exactly two subterms, such as an application.
+    pub fn pair(
+        &mut self,
+        node: &Node<'a>,
+        cursor: &mut TreeCursor<'a>,
+        second: &str,
+    ) -> Result<(S::Term, S::Term), ParseError> {
+        self.enter(node, cursor)?;
+        let first = self.child(0, cursor)?;
+        self.next(node, cursor, second)?;
+        let second = self.child(1, cursor)?;
+        self.leave(cursor);
+        Ok((first, second))
+    }
+}
+
+/// An error for a node that a calculus does not expect at the cursor.
+pub(crate) fn unexpected(cursor: &TreeCursor<'_>, context: &str) -> ParseError {
+    let node = cursor.node();
+    ParseError::at(&node, format!("unexpected {} in {}", node.kind(), context))
+}
+
+fn advance<'a>(source: &[u8], cursor: &mut TreeCursor<'a>) -> Result<Option<Node<'a>>, ParseError> {
+    while cursor.node().is_extra() || !cursor.node().is_named() {
+        if !cursor.goto_next_sibling() {
+            return Ok(None);
+        }
+    }
+    let node = cursor.node();
+    if node.is_error() || node.is_missing() {
+        Err(ParseError::from_error_node(&node, source))
+    } else {
+        Ok(Some(node))
+    }
+}
@@ -0,0 +1,242 @@
+//! Width-aware pretty printing with Wadler-style document combinators.
+//!
+//! Each calculus implements [`Pretty`] for its terms and types by building a [`Doc`] with
+//! the helpers here, which only add the parentheses required by precedence and break lines
+//! when a group doesn't fit in the target width.
+
+use std::ops::Add;
+use std::rc::Rc;
+
+use crate::notation::Notation;
+
+/// How far nested subterms are indented when a group is broken over several lines.
+const INDENT: usize = 2;
+
+/// A document, which can be laid out at different widths.
+#[derive(Debug, Clone)]
+pub struct Doc(Rc<Node>);
+
+#[derive(Debug)]
+enum Node {
+    Nil,
+    Text(String),
+    /// A space, or a newline if the enclosing group doesn't fit on one line.
+    Line,
+    Cat(Doc, Doc),
+    Nest(usize, Doc),
+    Group(Doc),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+impl Doc {
+    pub fn nil() -> Self {
+        Self(Rc::new(Node::Nil))
+    }
+
+    pub fn text(s: impl Into<String>) -> Self {
+        Self(Rc::new(Node::Text(s.into())))
+    }
+
+    /// A space, or a newline if the enclosing group doesn't fit on one line.
+    pub fn line() -> Self {
+        Self(Rc::new(Node::Line))
+    }
+
+    /// Indent the lines this document is broken into by the given amount.
+    pub fn nest(self, indent: usize) -> Self {
+        Self(Rc::new(Node::Nest(indent, self)))
+    }
+
+    /// Lay this document out on one line if it fits, and otherwise break all of its lines.
+    pub fn group(self) -> Self {
+        Self(Rc::new(Node::Group(self)))
+    }
+
+    /// Wrap this document in parentheses.
+    pub fn parens(self) -> Self {
+        Doc::text("(") + self + Doc::text(")")
+    }
+
+    /// Lay out this document, trying to keep lines within the given width.
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut column = 0;
+        let mut stack = vec![(0, Mode::Break, self.clone())];
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match &*doc.0 {
+                Node::Nil => {}
+                Node::Text(s) => {
+                    out.push_str(s);
+                    column += s.chars().count();
+                }
+                Node::Line if mode == Mode::Flat => {
+                    out.push(' ');
+                    column += 1;
+                }
+                Node::Line => {
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', indent));
+                    column = indent;
+                }
+                Node::Cat(a, b) => {
+                    stack.push((indent, mode, b.clone()));
+                    stack.push((indent, mode, a.clone()));
+                }
+                Node::Nest(i, d) => stack.push((indent + i, mode, d.clone())),
+                Node::Group(d) => {
+                    let remaining = width as isize - column as isize;
+                    let mode = if mode == Mode::Flat || fits(remaining, d, &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, d.clone()));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Whether `doc` laid out flat, followed by the rest of the line, fits in `remaining` columns.
+fn fits(mut remaining: isize, doc: &Doc, rest: &[(usize, Mode, Doc)]) -> bool {
+    let mut work = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    while remaining >= 0 {
+        let Some((mode, doc)) = work.pop().or_else(|| rest.next().map(|(_, m, d)| (*m, d))) else {
+            return true;
+        };
+        match &*doc.0 {
+            Node::Nil => {}
+            Node::Text(s) => remaining -= s.chars().count() as isize,
+            Node::Line if mode == Mode::Break => return true,
+            Node::Line => remaining -= 1,
+            Node::Cat(a, b) => {
+                work.push((mode, b));
+                work.push((mode, a));
+            }
+            Node::Nest(_, d) | Node::Group(d) => work.push((mode, d)),
+        }
+    }
+    false
+}
+
+impl Add for Doc {
+    type Output = Doc;
+
+    fn add(self, other: Doc) -> Doc {
+        Self(Rc::new(Node::Cat(self, other)))
+    }
+}
+
+/// How tightly a term binds, from loosest to tightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Prec {
+    /// Abstractions, quantifiers and other binders, whose bodies extend as far right as
+    /// possible.
+    Binder,
+    /// Infix operators such as `->` and type annotations.
+    Infix,
+    Application,
+    Atom,
+}
+
+pub trait Pretty {
+    /// The document for this value in the given notation, and how tightly it binds.
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec);
+
+    fn to_doc(&self, n: Notation) -> Doc {
+        self.to_doc_prec(n).0
+    }
+
+    /// Print this value with Unicode symbols, trying to keep lines within the given width.
+    fn pretty(&self, width: usize) -> String {
+        self.to_doc(Notation::Unicode).render(width)
+    }
+
+    fn pretty_in(&self, n: Notation, width: usize) -> String {
+        self.to_doc(n).render(width)
+    }
+}
+
+/// The document for `t`, parenthesised if it binds more loosely than `prec`.
+pub fn at<T: Pretty + ?Sized>(t: &T, n: Notation, prec: Prec) -> Doc {
+    let (doc, p) = t.to_doc_prec(n);
+    if p < prec {
+        doc.parens()
+    } else {
+        doc
+    }
+}
+
+/// A binder such as `λx.` followed by its body, which is indented if it doesn't fit.
+pub fn binder(head: Doc, body: Doc) -> (Doc, Prec) {
+    (
+        (head + (Doc::line() + body).nest(INDENT)).group(),
+        Prec::Binder,
+    )
+}
+
+/// A function applied to arguments, which are put on separate lines if they don't fit.
+pub fn application(head: Doc, args: impl IntoIterator<Item = Doc>) -> (Doc, Prec) {
+    let args = args
+        .into_iter()
+        .fold(Doc::nil(), |doc, arg| doc + Doc::line() + arg);
+    ((head + args.nest(INDENT)).group(), Prec::Application)
+}
+
+/// An infix operator such as `" ->"`, written straight after the left operand, with a line
+/// break after it if the whole doesn't fit.
+pub fn infix(lhs: Doc, op: &str, rhs: Doc) -> (Doc, Prec) {
+    let doc = lhs + Doc::text(op) + (Doc::line() + rhs).nest(INDENT);
+    (doc.group(), Prec::Infix)
+}
+
+/// A word such as a variable name or a constant.
+pub fn atom(s: impl Into<String>) -> (Doc, Prec) {
+    (Doc::text(s), Prec::Atom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_break_only_when_too_wide() {
+        let (doc, _) = application(Doc::text("f"), ["a", "b", "c"].map(Doc::text));
+        assert_eq!(doc.render(80), "f a b c");
+        assert_eq!(doc.render(4), "f\n  a\n  b\n  c");
+    }
+
+    #[test]
+    fn inner_groups_stay_flat_if_they_fit() {
+        let (body, _) = application(Doc::text("x"), [Doc::text("y")]);
+        let (doc, _) = binder(Doc::text("λx."), body);
+        let (doc, _) = binder(Doc::text("λlong_name."), doc);
+        assert_eq!(doc.render(12), "λlong_name.\n  λx. x y");
+    }
+
+    // Only the pure-Rust parser reads `λf x.` back.
+    #[test]
+    #[cfg(not(feature = "tree-sitter"))]
+    fn nested_abstractions_are_collapsed() {
+        let two = crate::untyped::parser::parse("λf. λx. f (f x)").unwrap();
+        assert_eq!(two.pretty(80), "λf x. f (f x)");
+        assert_eq!(two.pretty(10), "λf x.\n  f (f x)");
+    }
+
+    #[test]
+    fn only_required_parentheses() {
+        let t = crate::calc_of_cons::parser::parse("Πa: *. (F a) -> ((λx: a. x) a)").unwrap();
+        assert_eq!(t.pretty(80), "Πa: *. F a -> (λx: a. x) a");
+        assert_eq!(
+            t.pretty_in(Notation::Ascii, 80),
+            "Pi a: *. F a -> (\\x: a. x) a"
+        );
+    }
+}
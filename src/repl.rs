@@ -0,0 +1,622 @@
+//! A line-based interpreter exposing the calculi in this crate without writing Rust.
+
+use std::{error, fmt, fs, io, str::FromStr};
+
+use super::*;
+
+use calc_of_cons::infer;
+use trace::{Fuel, OutOfFuel, Subterms, Trace};
+
+/// How much evaluation may do before giving up, unless changed with `:fuel`.
+const DEFAULT_FUEL: usize = 10_000;
+
+/// The largest term evaluation may produce.
+const MAX_TERM_SIZE: usize = 100_000;
+
+/// The calculi the REPL can switch between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Calculus {
+    Untyped,
+    HindleyMilner,
+    SystemF,
+    CalcOfCons,
+    CalcOfConsInfer,
+    Lang,
+}
+
+impl Calculus {
+    pub const ALL: [Calculus; 6] = [
+        Self::Untyped,
+        Self::HindleyMilner,
+        Self::SystemF,
+        Self::CalcOfCons,
+        Self::CalcOfConsInfer,
+        Self::Lang,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Untyped => "untyped",
+            Self::HindleyMilner => "hm",
+            Self::SystemF => "system_f",
+            Self::CalcOfCons => "coc",
+            Self::CalcOfConsInfer => "coc-infer",
+            Self::Lang => "lang",
+        }
+    }
+}
+
+impl FromStr for Calculus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.name() == s)
+            .ok_or_else(|| Error::UnknownCalculus(s.to_owned()))
+    }
+}
+
+impl fmt::Display for Calculus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(String),
+    Type(String),
+    Unsupported {
+        calculus: Calculus,
+        command: &'static str,
+    },
+    UnknownCalculus(String),
+    UnknownCommand(String),
+    Usage(&'static str),
+    OutOfFuel(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(s) => s.fmt(f),
+            Self::Type(s) => write!(f, "type error: {}", s),
+            Self::Unsupported { calculus, command } => {
+                write!(f, ":{} is not supported for {}", command, calculus)
+            }
+            Self::UnknownCalculus(s) => write!(
+                f,
+                "unknown calculus {} (expected one of untyped, hm, system_f, coc, coc-infer, lang)",
+                s
+            ),
+            Self::UnknownCommand(s) => write!(f, "unknown command :{} (try :help)", s),
+            Self::Usage(s) => write!(f, "usage: {}", s),
+            Self::OutOfFuel(s) => write!(f, "{} (the limit can be raised with :fuel)", s),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+const HELP: &str = "\
+commands:
+  <term>                    evaluate a term
+  let <name> = <term>       bind a name for the rest of the session
+  :calculus [<name>]        show or switch the calculus
+                            (untyped, hm, system_f, coc, coc-infer, lang)
+  :type <term>              show the type of a term
+  :eval <term>              evaluate a term to normal form
+  :step <term>              show each reduction step, highlighting redexes
+  :fuel [<steps>]           show or set how many steps evaluation may take
+  :deriv <term>             show a typing derivation (hm only)
+  :load <file>              run each line of a file
+  :help                     show this message
+  :quit                     exit";
+
+/// The state of an interactive session.
+///
+/// Bindings are kept per calculus, so switching calculus and back does not lose them.
+#[derive(Debug, Clone)]
+pub struct Repl {
+    calculus: Calculus,
+    untyped: Vec<(String, untyped::Term)>,
+    hm: Vec<(String, hindley_milner::Term)>,
+    system_f: Vec<(String, system_f::Term)>,
+    coc: Vec<(String, calc_of_cons::Term)>,
+    coc_infer: Vec<(String, infer::Term)>,
+    lang: Vec<(String, lang::Term)>,
+    fuel: Fuel,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            calculus: Calculus::Untyped,
+            untyped: Vec::new(),
+            hm: Vec::new(),
+            system_f: Vec::new(),
+            coc: Vec::new(),
+            coc_infer: Vec::new(),
+            lang: Vec::new(),
+            fuel: Fuel::steps(DEFAULT_FUEL).with_size_limit(MAX_TERM_SIZE),
+        }
+    }
+
+    pub fn calculus(&self) -> Calculus {
+        self.calculus
+    }
+
+    /// Run one line of input, returning the text to show the user, if any.
+    pub fn execute(&mut self, line: &str) -> Result<Option<String>, Error> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("--") {
+            return Ok(None);
+        }
+        if let Some(rest) = line.strip_prefix(':') {
+            let (command, arg) = rest
+                .split_once(char::is_whitespace)
+                .map(|(c, a)| (c, a.trim()))
+                .unwrap_or((rest, ""));
+            return match command {
+                "help" | "h" => Ok(Some(HELP.to_owned())),
+                "calculus" | "c" if arg.is_empty() => Ok(Some(self.calculus.to_string())),
+                "calculus" | "c" => {
+                    self.calculus = arg.parse()?;
+                    Ok(None)
+                }
+                "type" | "t" => self.type_of(non_empty(arg, ":type <term>")?).map(Some),
+                "eval" | "e" => self.eval(non_empty(arg, ":eval <term>")?).map(Some),
+                "step" | "s" => self.step(non_empty(arg, ":step <term>")?).map(Some),
+                "deriv" | "d" => self.deriv(non_empty(arg, ":deriv <term>")?).map(Some),
+                "fuel" | "f" if arg.is_empty() => Ok(Some(self.fuel.steps.to_string())),
+                "fuel" | "f" => {
+                    self.fuel.steps = arg.parse().map_err(|_| Error::Usage(":fuel [<steps>]"))?;
+                    Ok(None)
+                }
+                "load" | "l" => self.load(non_empty(arg, ":load <file>")?),
+                _ => Err(Error::UnknownCommand(command.to_owned())),
+            };
+        }
+        // `let x = b in t` is a term in Hindley-Milner rather than a binding.
+        let is_hm_let =
+            self.calculus == Calculus::HindleyMilner && hindley_milner::parser::parse(line).is_ok();
+        if let Some(rest) = line.strip_prefix("let ").filter(|_| !is_hm_let) {
+            let (name, source) = rest
+                .split_once('=')
+                .map(|(n, s)| (n.trim(), s.trim()))
+                .filter(|(n, s)| !n.is_empty() && !s.is_empty())
+                .ok_or(Error::Usage("let <name> = <term>"))?;
+            self.define(name, source)?;
+            return Ok(None);
+        }
+        self.eval(line).map(Some)
+    }
+
+    fn load(&mut self, path: &str) -> Result<Option<String>, Error> {
+        let contents = fs::read_to_string(path)?;
+        let mut output = Vec::new();
+        for line in contents.lines() {
+            if let Some(out) = self.execute(line)? {
+                output.push(out);
+            }
+        }
+        Ok(if output.is_empty() {
+            None
+        } else {
+            Some(output.join("\n"))
+        })
+    }
+
+    fn define(&mut self, name: &str, source: &str) -> Result<(), Error> {
+        match self.calculus {
+            Calculus::Untyped => {
+                let term = self.untyped_term(source)?;
+                self.untyped.push((name.to_owned(), term));
+            }
+            Calculus::HindleyMilner => {
+                let term = parse_hm(source)?;
+                self.hm_type(&self.hm_term(term.clone()))?;
+                self.hm.push((name.to_owned(), term));
+            }
+            Calculus::SystemF => {
+                let term = self.system_f_term(source)?;
+                system_f_type(&term)?;
+                self.system_f.push((name.to_owned(), term));
+            }
+            Calculus::CalcOfCons => {
+                let term = self.coc_term(source)?;
+                coc_type(&term)?;
+                self.coc.push((name.to_owned(), term));
+            }
+            Calculus::CalcOfConsInfer => {
+                let term = self.coc_infer_term(source)?;
+                let ty = term
+                    .synthesise_type_closed()
+                    .map_err(|e| Error::Type(e.to_string()))?;
+                // Keep the annotation so that unannotated abstractions stay inferrable.
+                let term = match term {
+                    infer::Term::Annotation(_, _) => term,
+                    _ => infer::Term::Annotation(Box::new(term), Box::new(ty)),
+                };
+                self.coc_infer.push((name.to_owned(), term));
+            }
+            Calculus::Lang => {
+                let term = self.lang_term(source)?;
+                lang_type(&term)?;
+                self.lang.push((name.to_owned(), term));
+            }
+        }
+        Ok(())
+    }
+
+    fn type_of(&self, source: &str) -> Result<String, Error> {
+        match self.calculus {
+            Calculus::Untyped => Err(Error::Unsupported {
+                calculus: self.calculus,
+                command: "type",
+            }),
+            Calculus::HindleyMilner => {
+                let term = self.hm_term(parse_hm(source)?);
+                Ok(self.hm_type(&term)?.to_string())
+            }
+            Calculus::SystemF => Ok(system_f_type(&self.system_f_term(source)?)?.to_string()),
+            Calculus::CalcOfCons => Ok(coc_type(&self.coc_term(source)?)?.to_string()),
+            Calculus::CalcOfConsInfer => Ok(self
+                .coc_infer_term(source)?
+                .synthesise_type_closed()
+                .map_err(|e| Error::Type(e.to_string()))?
+                .to_string()),
+            Calculus::Lang => Ok(lang_type(&self.lang_term(source)?)?.to_string()),
+        }
+    }
+
+    fn eval(&self, source: &str) -> Result<String, Error> {
+        match self.calculus {
+            Calculus::Untyped => Ok(self
+                .untyped_term(source)?
+                .evaluate_with_fuel(self.fuel)
+                .map_err(out_of_fuel)?
+                .to_string()),
+            Calculus::HindleyMilner => {
+                let term = self.hm_term(parse_hm(source)?);
+                self.hm_type(&term)?;
+                Ok(term
+                    .evaluate_with_fuel(self.fuel)
+                    .map_err(out_of_fuel)?
+                    .to_string())
+            }
+            Calculus::SystemF => {
+                let term = self.system_f_term(source)?;
+                system_f_type(&term)?;
+                Ok(term
+                    .evaluate_with_fuel(self.fuel)
+                    .map_err(out_of_fuel)?
+                    .to_string())
+            }
+            Calculus::CalcOfCons => {
+                let term = self.coc_term(source)?;
+                coc_type(&term)?;
+                Ok(term
+                    .evaluate_with_fuel(self.fuel)
+                    .map_err(out_of_fuel)?
+                    .to_string())
+            }
+            Calculus::CalcOfConsInfer => {
+                let term = self.coc_infer_term(source)?;
+                term.synthesise_type_closed()
+                    .map_err(|e| Error::Type(e.to_string()))?;
+                Ok(term
+                    .evaluate_with_fuel(self.fuel)
+                    .map_err(out_of_fuel)?
+                    .to_string())
+            }
+            Calculus::Lang => {
+                let term = self.lang_term(source)?;
+                lang_type(&term)?;
+                Ok(term
+                    .evaluate_with_fuel(self.fuel)
+                    .map_err(out_of_fuel)?
+                    .to_string())
+            }
+        }
+    }
+
+    fn step(&self, source: &str) -> Result<String, Error> {
+        match self.calculus {
+            Calculus::Untyped => Ok(steps(
+                self.untyped_term(source)?.reduction_trace(),
+                self.fuel,
+            )),
+            Calculus::HindleyMilner => {
+                let term = self.hm_term(parse_hm(source)?);
+                self.hm_type(&term)?;
+                Ok(steps(term.reduction_trace(), self.fuel))
+            }
+            Calculus::SystemF => {
+                let term = self.system_f_term(source)?;
+                system_f_type(&term)?;
+                Ok(steps(term.reduction_trace(), self.fuel))
+            }
+            Calculus::CalcOfCons => {
+                let term = self.coc_term(source)?;
+                coc_type(&term)?;
+                Ok(steps(term.reduction_trace(), self.fuel))
+            }
+            Calculus::CalcOfConsInfer => {
+                let term = self.coc_infer_term(source)?;
+                term.synthesise_type_closed()
+                    .map_err(|e| Error::Type(e.to_string()))?;
+                Ok(steps(term.reduction_trace(), self.fuel))
+            }
+            Calculus::Lang => {
+                let term = self.lang_term(source)?;
+                lang_type(&term)?;
+                Ok(steps(term.reduction_trace(), self.fuel))
+            }
+        }
+    }
+
+    fn deriv(&self, source: &str) -> Result<String, Error> {
+        match self.calculus {
+            Calculus::HindleyMilner => {
+                let term = self.hm_term(parse_hm(source)?);
+                hindley_milner::derivation::type_deriv(&term, &Vec::new())
+                    .map(|p| p.to_string())
+                    .map_err(|e| Error::Type(e.to_string()))
+            }
+            calculus => Err(Error::Unsupported {
+                calculus,
+                command: "deriv",
+            }),
+        }
+    }
+
+    fn untyped_term(&self, source: &str) -> Result<untyped::Term, Error> {
+        let term = untyped::parser::parse(source).map_err(|e| Error::Parse(e.render(source)))?;
+        Ok(self
+            .untyped
+            .iter()
+            .rev()
+            .fold(term, |t, (x, b)| t.substitute(x, b)))
+    }
+
+    /// Wrap the term in the session's bindings so that they are let-polymorphic.
+    fn hm_term(&self, term: hindley_milner::Term) -> hindley_milner::Term {
+        self.hm.iter().rev().fold(term, |t, (x, b)| {
+            hindley_milner::Term::Let(x.to_owned(), Box::new(b.clone()), Box::new(t))
+        })
+    }
+
+    fn hm_type(&self, term: &hindley_milner::Term) -> Result<hindley_milner::Type, Error> {
+        term.type_closed().map_err(|e| Error::Type(e.to_string()))
+    }
+
+    fn system_f_term(&self, source: &str) -> Result<system_f::Term, Error> {
+        let term = system_f::parser::parse(source).map_err(|e| Error::Parse(e.render(source)))?;
+        Ok(self
+            .system_f
+            .iter()
+            .rev()
+            .fold(term, |t, (x, b)| t.substitute(x, b)))
+    }
+
+    fn coc_term(&self, source: &str) -> Result<calc_of_cons::Term, Error> {
+        let term =
+            calc_of_cons::parser::parse(source).map_err(|e| Error::Parse(e.render(source)))?;
+        Ok(self
+            .coc
+            .iter()
+            .rev()
+            .fold(term, |t, (x, b)| t.substitute(x, b)))
+    }
+
+    fn coc_infer_term(&self, source: &str) -> Result<infer::Term, Error> {
+        let term = infer::parser::parse(source).map_err(|e| Error::Parse(e.render(source)))?;
+        Ok(self
+            .coc_infer
+            .iter()
+            .rev()
+            .fold(term, |t, (x, b)| t.substitute(x, b)))
+    }
+
+    fn lang_term(&self, source: &str) -> Result<lang::Term, Error> {
+        let term = lang::parser::parse(source).map_err(|e| Error::Parse(e.render(source)))?;
+        Ok(self
+            .lang
+            .iter()
+            .rev()
+            .fold(term, |t, (x, b)| t.substitute(x, b)))
+    }
+}
+
+fn non_empty<'a>(arg: &'a str, usage: &'static str) -> Result<&'a str, Error> {
+    if arg.is_empty() {
+        Err(Error::Usage(usage))
+    } else {
+        Ok(arg)
+    }
+}
+
+fn parse_hm(source: &str) -> Result<hindley_milner::Term, Error> {
+    hindley_milner::parser::parse(source).map_err(|e| Error::Parse(e.render(source)))
+}
+
+fn system_f_type(term: &system_f::Term) -> Result<system_f::Type, Error> {
+    term.type_closed().map_err(|e| Error::Type(e.to_string()))
+}
+
+fn coc_type(term: &calc_of_cons::Term) -> Result<calc_of_cons::Term, Error> {
+    term.type_closed().map_err(|e| Error::Type(e.to_string()))
+}
+
+fn lang_type(term: &lang::Term) -> Result<lang::Term, Error> {
+    term.type_closed().map_err(|e| Error::Type(e.to_string()))
+}
+
+/// Each term in a trace on its own line, with the redex contracted next highlighted and the
+/// rule used written before the term it gives.
+fn steps<T: Subterms + fmt::Display>(trace: Trace<T>, fuel: Fuel) -> String {
+    let mut lines = Vec::new();
+    let mut rule = None;
+    for (steps, step) in trace.enumerate() {
+        match rule {
+            None => lines.push(format!("  {}", step.highlighted())),
+            Some(rule) => lines.push(format!("→{} {}", rule, step.highlighted())),
+        }
+        rule = step.redex.as_ref().map(|r| r.rule);
+        if rule.is_none() {
+            break;
+        }
+        if let Some(limit) = fuel.limit_reached(steps, &step.term) {
+            let partial_term = step.term;
+            let e = OutOfFuel {
+                steps,
+                partial_term,
+                limit,
+            };
+            lines.push(Error::OutOfFuel(e.to_string()).to_string());
+            break;
+        }
+    }
+    lines.join("\n")
+}
+
+fn out_of_fuel<T>(e: OutOfFuel<T>) -> Error {
+    Error::OutOfFuel(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculus_names_round_trip() {
+        for c in Calculus::ALL {
+            assert_eq!(c.to_string().parse::<Calculus>().unwrap(), c);
+        }
+        assert!("pure".parse::<Calculus>().is_err());
+    }
+
+    #[test]
+    fn switching_calculus() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.execute(":calculus coc").unwrap(), None);
+        assert_eq!(repl.calculus(), Calculus::CalcOfCons);
+        assert_eq!(repl.execute(":calculus").unwrap(), Some("coc".to_owned()));
+        assert!(repl.execute(":calculus nope").is_err());
+        assert!(repl.execute(":nope").is_err());
+    }
+
+    #[test]
+    fn untyped_bindings_persist() {
+        let mut repl = Repl::new();
+        repl.execute("let id = λx. x").unwrap();
+        repl.execute("let k = λx. λy. x").unwrap();
+        let out = repl.execute("k id z").unwrap().unwrap();
+        assert_eq!(out, "λx. x");
+        assert!(repl.execute(":type id").is_err());
+    }
+
+    #[test]
+    fn hm_bindings_are_polymorphic() {
+        let mut repl = Repl::new();
+        repl.execute(":calculus hm").unwrap();
+        repl.execute("let id = λx. x").unwrap();
+        assert!(repl.execute(":type id id").is_ok());
+        assert!(repl
+            .execute(":deriv id")
+            .unwrap()
+            .unwrap()
+            .contains("[Var]"));
+        assert!(repl.execute(":type λx. x x").is_err());
+        let err = repl.execute(":deriv λx. x x").unwrap_err().to_string();
+        assert!(err.contains("⊢ λx. x x: ?"));
+    }
+
+    #[test]
+    fn coc_infer_bindings_keep_types() {
+        let mut repl = Repl::new();
+        repl.execute(":calculus coc-infer").unwrap();
+        repl.execute("let id = λa: *. λx: a. x").unwrap();
+        assert_eq!(repl.execute(":type id").unwrap().unwrap(), "Πa: *. a -> a");
+    }
+
+    #[test]
+    fn hm_let_is_a_term() {
+        let mut repl = Repl::new();
+        repl.execute(":calculus hm").unwrap();
+        let out = repl
+            .execute(":type let id = λx. x in id id")
+            .unwrap()
+            .unwrap();
+        let (input, output) = out.split_once(" -> ").unwrap();
+        assert_eq!(input, output);
+        assert!(repl.execute("let id = λx. x in id").unwrap().is_some());
+    }
+
+    #[test]
+    fn system_f_bindings() {
+        let mut repl = Repl::new();
+        repl.execute(":calculus system_f").unwrap();
+        repl.execute("let id = Λa. λx: a. x").unwrap();
+        assert_eq!(
+            repl.execute(":type id [∀b. b -> b] id").unwrap().unwrap(),
+            "∀b. b -> b"
+        );
+        assert_eq!(repl.execute("id [ι]").unwrap().unwrap(), "λx: ι. x");
+        assert!(repl.execute(":type id id").is_err());
+    }
+
+    #[test]
+    fn lang_bindings() {
+        let mut repl = Repl::new();
+        repl.execute(":calculus lang").unwrap();
+        repl.execute("let double = λx: int. + x x").unwrap();
+        assert_eq!(repl.execute(":type double").unwrap().unwrap(), "int -> int");
+        assert_eq!(repl.execute("double 21").unwrap().unwrap(), "42");
+        assert!(repl.execute("double true").is_err());
+    }
+
+    #[test]
+    fn step_labels_rules() {
+        let mut repl = Repl::new();
+        let out = repl.execute(":step (λx. x) (λy. f y)").unwrap().unwrap();
+        assert_eq!(
+            console::strip_ansi_codes(&out),
+            "  (λx. x) (λy. f y)\n→β λy. f y\n→η f"
+        );
+    }
+
+    #[test]
+    fn divergence_runs_out_of_fuel() {
+        let mut repl = Repl::new();
+        repl.execute(":fuel 50").unwrap();
+        let omega = "(λx. x x) (λx. x x)";
+        assert!(matches!(repl.execute(omega), Err(Error::OutOfFuel(_))));
+        let out = repl.execute(&format!(":step {}", omega)).unwrap().unwrap();
+        assert_eq!(out.lines().count(), 52);
+        assert_eq!(repl.execute(":fuel").unwrap().unwrap(), "50");
+    }
+}
@@ -1,7 +1,10 @@
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
 
 use super::{fresh_var, untyped};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 /// The set of base types and its associated constants.
 pub trait TypeConstant: Eq + Sized + Clone {
@@ -38,7 +41,7 @@ impl From<BasicConstant> for untyped::Term {
 
 impl fmt::Display for Basic {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "ι")
+        write!(f, "{}", Notation::of(f).base())
     }
 }
 
@@ -67,6 +70,22 @@ pub enum Term<B: TypeConstant> {
 
 pub type TypeEnvironment<B> = Vec<(String, Type<B>)>;
 
+/// Why a term is ill-typed.
+#[derive(Clone, PartialEq, Eq)]
+pub enum TypeError<B: TypeConstant> {
+    UndefinedVar(String),
+    /// A term is applied to an argument, but its type isn't a function type.
+    NotFunType {
+        term: Term<B>,
+        ty: Type<B>,
+    },
+    /// An argument's type isn't the one the function expects.
+    Mismatch {
+        expected: Type<B>,
+        actual: Type<B>,
+    },
+}
+
 impl<B: TypeConstant> Term<B> {
     pub fn vars(&self) -> HashSet<String> {
         match self {
@@ -168,23 +187,31 @@ impl<B: TypeConstant> Term<B> {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
-            Self::Abstraction(x, ty, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(t2))),
+            Self::Abstraction(x, ty, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (Self::Abstraction(x.to_owned(), ty.clone(), t2), r.within(0))
+            }),
             _ => None,
         }
     }
@@ -220,27 +247,36 @@ impl<B: TypeConstant> Term<B> {
     /// Eta-reduce the left-outermost simplification if one exists.
     /// This does not check the type.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, ty, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                    let u2 = Box::new(u2);
+                    (Self::Abstraction(x.to_owned(), ty.clone(), u2), r.within(0))
+                })
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             _ => None,
@@ -259,41 +295,95 @@ impl<B: TypeConstant> Term<B> {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given type environment, if it is well-typed.
-    pub fn type_in(&self, type_env: &TypeEnvironment<B>) -> Option<Type<B>> {
+    pub fn type_in(&self, type_env: &TypeEnvironment<B>) -> Result<Type<B>, TypeError<B>> {
         match self {
-            Self::Constant(c) => Some(Type::Base(c.ty())),
+            Self::Constant(c) => Ok(Type::Base(c.ty())),
             Self::Variable(x) => type_env
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
             Self::Abstraction(x, ty, t) => {
                 let mut inner_env = type_env.clone();
                 inner_env.push((x.to_owned(), ty.clone()));
                 t.type_in(&inner_env)
             }
-            Self::Application(t, u) => {
-                if let Some(Type::Fn(a1, b)) = t.type_in(type_env) {
+            Self::Application(t, u) => match t.type_in(type_env)? {
+                Type::Fn(a1, b) => {
                     let a2 = u.type_in(type_env)?;
-                    if a1.as_ref() == &a2 {
-                        Some(b.as_ref().clone())
+                    if *a1 == a2 {
+                        Ok(*b)
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
         }
     }
 
     /// The type of this term in the empty type environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Type<B>> {
+    pub fn type_closed(&self) -> Result<Type<B>, TypeError<B>> {
         self.type_in(&TypeEnvironment::new())
     }
 }
 
+impl<B: TypeConstant> Subterms for Term<B> {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) => 1,
+            Self::Application(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, _, t), 0) | (Self::Application(t, _), 0) => Some(t),
+            (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, ty, _), 0) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
 impl<B: TypeConstant> PartialEq for Term<B> {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -306,10 +396,13 @@ where
     B::Constant: fmt::Display,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Constant(c) => c.fmt(f),
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -319,13 +412,105 @@ where
     }
 }
 
+impl<B> Pretty for Term<B>
+where
+    B: fmt::Display + TypeConstant,
+    B::Constant: fmt::Display,
+{
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Constant(c) => pretty::atom(n.show(c).to_string()),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
+impl<B> fmt::Display for TypeError<B>
+where
+    B: fmt::Display + TypeConstant,
+    B::Constant: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl<B> fmt::Debug for TypeError<B>
+where
+    B: fmt::Debug + TypeConstant,
+    B::Constant: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => f.debug_tuple("UndefinedVar").field(x).finish(),
+            Self::NotFunType { term, ty } => f
+                .debug_struct("NotFunType")
+                .field("term", term)
+                .field("ty", ty)
+                .finish(),
+            Self::Mismatch { expected, actual } => f
+                .debug_struct("Mismatch")
+                .field("expected", expected)
+                .field("actual", actual)
+                .finish(),
+        }
+    }
+}
+
+impl<B> error::Error for TypeError<B>
+where
+    B: fmt::Debug + fmt::Display + TypeConstant,
+    B::Constant: fmt::Debug + fmt::Display,
+{
+}
+
 impl<B: fmt::Display> fmt::Display for Type<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Base(b) => b.fmt(f),
             Self::Fn(t, u) => {
                 write_ty(t, f)?;
-                write!(f, " -> {}", u)
+                write!(f, " -> {}", n.show(u))
+            }
+        }
+    }
+}
+
+impl<B: fmt::Display> Pretty for Type<B> {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Base(b) => pretty::atom(n.show(b).to_string()),
+            Self::Fn(t, u) => {
+                pretty::infix(at(t.as_ref(), n, Prec::Application), " ->", u.to_doc(n))
             }
         }
     }
@@ -338,7 +523,7 @@ where
 {
     match t {
         Term::Variable(_) | Term::Constant(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -349,14 +534,14 @@ where
 {
     match t {
         Term::Variable(_) | Term::Constant(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_ty<B: fmt::Display>(ty: &Type<B>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match ty {
         Type::Base(_) => fmt::Display::fmt(ty, f),
-        Type::Fn(_, _) => write!(f, "({})", ty),
+        Type::Fn(_, _) => parens(ty, f),
     }
 }
 
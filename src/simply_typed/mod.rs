@@ -1,11 +1,20 @@
 pub mod general;
+pub mod parser;
 
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 use super::*;
+use crate::locally_nameless::{Ln, LocallyNameless, Scope};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::strategy::{Form, Reduce};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Base,
     Fn(Box<Type>, Box<Type>),
@@ -29,6 +38,22 @@ pub enum Term {
 
 pub type TypeEnvironment = Vec<(String, Type)>;
 
+/// Why a term is ill-typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    UndefinedVar(String),
+    /// A term is applied to an argument, but its type isn't a function type.
+    NotFunType {
+        term: Term,
+        ty: Type,
+    },
+    /// An argument's type isn't the one the function expects.
+    Mismatch {
+        expected: Type,
+        actual: Type,
+    },
+}
+
 impl Term {
     pub fn vars(&self) -> HashSet<String> {
         match self {
@@ -91,17 +116,7 @@ impl Term {
     }
 
     pub fn alpha_equivalent(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Variable(x), Self::Variable(y)) => x == y,
-            (Self::Abstraction(x, ty1, t), Self::Abstraction(y, ty2, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                ty1 == ty2 && t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            (Self::Application(t1, u1), Self::Application(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            _ => false,
-        }
+        self.to_locally_nameless() == other.to_locally_nameless()
     }
 
     /// Substitute the given term avoiding capture.
@@ -128,23 +143,31 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
-            Self::Abstraction(x, ty, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(t2))),
+            Self::Abstraction(x, ty, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (Self::Abstraction(x.to_owned(), ty.clone(), t2), r.within(0))
+            }),
             _ => None,
         }
     }
@@ -180,27 +203,36 @@ impl Term {
     /// Eta-reduce the left-outermost simplification if one exists.
     /// This does not check the type.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, ty, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                    let u2 = Box::new(u2);
+                    (Self::Abstraction(x.to_owned(), ty.clone(), u2), r.within(0))
+                })
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             _ => None,
@@ -219,41 +251,187 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given type environment, if it is well-typed.
-    pub fn type_in(&self, type_env: &TypeEnvironment) -> Option<Type> {
+    pub fn type_in(&self, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
         match self {
             Self::Variable(x) => type_env
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
             Self::Abstraction(x, ty, t) => {
                 let mut inner_env = type_env.clone();
                 inner_env.push((x.to_owned(), ty.clone()));
                 t.type_in(&inner_env)
                     .map(|t| Type::Fn(Box::new(ty.clone()), Box::new(t)))
             }
-            Self::Application(t, u) => {
-                if let Some(Type::Fn(a1, b)) = t.type_in(type_env) {
+            Self::Application(t, u) => match t.type_in(type_env)? {
+                Type::Fn(a1, b) => {
                     let a2 = u.type_in(type_env)?;
-                    if a1.as_ref() == &a2 {
-                        Some(b.as_ref().clone())
+                    if *a1 == a2 {
+                        Ok(*b)
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
         }
     }
 
     /// The type of this term in the empty type environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Type> {
+    pub fn type_closed(&self) -> Result<Type, TypeError> {
         self.type_in(&TypeEnvironment::new())
     }
 }
 
+#[cfg(test)]
+impl Arbitrary for Type {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_type().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_type() -> impl Strategy<Value = Type> {
+    Just(Type::Base).prop_recursive(4, 16, 2, |inner| {
+        (inner.clone(), inner).prop_map(|(t, u)| Type::Fn(Box::new(t), Box::new(u)))
+    })
+}
+
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = var().prop_map(Term::Variable);
+    leaf.prop_recursive(16, 256, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (var(), arb_type(), inner).prop_map(|(x, ty, b)| Term::Abstraction(x, ty, Box::new(b))),
+        ]
+    })
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) => 1,
+            Self::Application(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, _, t), 0) | (Self::Application(t, _), 0) => Some(t),
+            (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, ty, _), 0) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+impl Reduce for Term {
+    fn form(&self) -> Form {
+        match self {
+            Self::Application(..) => Form::Application {
+                term_argument: true,
+            },
+            Self::Abstraction(..) => Form::Binder { body: 0 },
+            _ => Form::Other,
+        }
+    }
+
+    fn contract(&self) -> Option<Self> {
+        let Self::Application(t, u) = self else {
+            return None;
+        };
+        let Self::Abstraction(x, _, b) = t.as_ref() else {
+            return None;
+        };
+        Some(b.substitute(x, u))
+    }
+}
+
+/// The constructors of a [`Term`] in locally nameless form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    /// An abstraction over a variable of the given type.
+    Abstraction(Type),
+    Application,
+}
+
+impl LocallyNameless for Term {
+    type Node = Node;
+
+    fn to_locally_nameless(&self) -> Ln<Node> {
+        to_locally_nameless(self, &mut Scope::new())
+    }
+}
+
+fn to_locally_nameless(term: &Term, scope: &mut Scope) -> Ln<Node> {
+    match term {
+        Term::Variable(x) => scope.variable((), x),
+        Term::Abstraction(x, ty, t) => Ln::node(
+            Node::Abstraction(ty.clone()),
+            [scope.bind((), x, |scope| to_locally_nameless(t, scope))],
+        ),
+        Term::Application(t, u) => Ln::node(
+            Node::Application,
+            [to_locally_nameless(t, scope), to_locally_nameless(u, scope)],
+        ),
+    }
+}
+
 impl PartialEq for Term {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -262,9 +440,12 @@ impl PartialEq for Term {
 
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -274,27 +455,83 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+        }
     }
 }
 
+impl error::Error for TypeError {}
+
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
-            Self::Base => write!(f, "ι"),
+            Self::Base => write!(f, "{}", n.base()),
             Self::Fn(t, u) => {
                 write_ty(t, f)?;
-                write!(f, " -> {}", u)
+                write!(f, " -> {}", n.show(u))
+            }
+        }
+    }
+}
+
+impl Pretty for Type {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Base => pretty::atom(n.base()),
+            Self::Fn(t, u) => {
+                pretty::infix(at(t.as_ref(), n, Prec::Application), " ->", u.to_doc(n))
             }
         }
     }
@@ -303,7 +540,7 @@ impl fmt::Display for Type {
 fn write_ty(ty: &Type, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match ty {
         Type::Base => fmt::Display::fmt(ty, f),
-        Type::Fn(_, _) => write!(f, "({})", ty),
+        Type::Fn(_, _) => parens(ty, f),
     }
 }
 
@@ -362,7 +599,7 @@ mod tests {
     fn i_type() {
         let i = Term::Abstraction("x".into(), Type::Base, Box::new(Term::Variable("x".into())));
         let expected = Type::Fn(Box::new(Type::Base), Box::new(Type::Base));
-        assert_eq!(i.type_closed(), Some(expected));
+        assert_eq!(i.type_closed(), Ok(expected));
     }
 
     #[test]
@@ -380,7 +617,7 @@ mod tests {
             Box::new(Type::Base),
             Box::new(Type::Fn(Box::new(Type::Base), Box::new(Type::Base))),
         );
-        assert_eq!(k.type_closed(), Some(expected));
+        assert_eq!(k.type_closed(), Ok(expected));
     }
 
     #[test]
@@ -420,6 +657,6 @@ mod tests {
                 Box::new(Type::Fn(Box::new(Type::Base), Box::new(Type::Base))),
             )),
         );
-        assert_eq!(s.type_closed(), Some(expected));
+        assert_eq!(s.type_closed(), Ok(expected));
     }
 }
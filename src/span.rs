@@ -0,0 +1,287 @@
+//! Source locations for parsed terms and parse errors.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[cfg(feature = "tree-sitter")]
+use tree_sitter::Node;
+
+/// A zero-based line and byte column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of the given byte offset in the source.
+    pub fn of_offset(source: &[u8], offset: usize) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.iter().filter(|&&b| b == b'\n').count();
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        Self {
+            line,
+            column: before.len() - line_start,
+        }
+    }
+
+    /// This position, as if the source it is relative to started at `base`.
+    fn shift(self, base: Position) -> Self {
+        if self.line == 0 {
+            Self {
+                line: base.line,
+                column: base.column + self.column,
+            }
+        } else {
+            Self {
+                line: base.line + self.line,
+                column: self.column,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    /// One-based, as editors show it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+/// A byte range in the source together with the positions of its ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub start_position: Position,
+    pub end_position: Position,
+}
+
+/// The offsets at which the lines of a source start, so that the positions of many offsets
+/// can be found without rescanning the source for each.
+#[derive(Debug, Clone)]
+pub struct Lines {
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl Lines {
+    pub fn new(source: &[u8]) -> Self {
+        let breaks = source.iter().enumerate().filter(|&(_, &b)| b == b'\n');
+        Self {
+            starts: std::iter::once(0)
+                .chain(breaks.map(|(i, _)| i + 1))
+                .collect(),
+            len: source.len(),
+        }
+    }
+
+    /// Like [`Position::of_offset`].
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.len);
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        Position {
+            line,
+            column: offset - self.starts[line],
+        }
+    }
+
+    /// Like [`Span::new`].
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            start_position: self.position(start),
+            end_position: self.position(end),
+        }
+    }
+}
+
+impl Span {
+    pub fn new(source: &[u8], start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            start_position: Position::of_offset(source, start),
+            end_position: Position::of_offset(source, end),
+        }
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    pub fn from_node(node: &Node<'_>) -> Self {
+        let start = node.start_position();
+        let end = node.end_position();
+        Self {
+            start: node.start_byte(),
+            end: node.end_byte(),
+            start_position: Position {
+                line: start.row,
+                column: start.column,
+            },
+            end_position: Position {
+                line: end.row,
+                column: end.column,
+            },
+        }
+    }
+
+    /// This span, as if the source it is relative to started at byte `offset` of `source`.
+    pub fn shift(self, source: &[u8], offset: usize) -> Self {
+        let base = Position::of_offset(source, offset);
+        Self {
+            start: self.start + offset,
+            end: self.end + offset,
+            start_position: self.start_position.shift(base),
+            end_position: self.end_position.shift(base),
+        }
+    }
+
+    /// The lines of the source covered by this span, with the span underlined by carets.
+    pub fn excerpt(&self, source: &[u8]) -> String {
+        let text = String::from_utf8_lossy(source);
+        let lines: Vec<&str> = text.split('\n').collect();
+        let first = self.start_position.line;
+        let last = self.end_position.line.max(first).min(lines.len() - 1);
+        let gutter = (last + 1).to_string().len();
+        let mut out = format!("{:gutter$} |\n", "");
+        for (n, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+            let line = line.trim_end_matches('\r');
+            let from = if n == first {
+                self.start_position.column.min(line.len())
+            } else {
+                0
+            };
+            let to = if n == self.end_position.line {
+                self.end_position.column.min(line.len())
+            } else {
+                line.len()
+            };
+            let pad = char_width(line, 0, from);
+            let carets = char_width(line, from, to).max(1);
+            out.push_str(&format!("{:gutter$} | {}\n", n + 1, line));
+            out.push_str(&format!(
+                "{:gutter$} | {}{}\n",
+                "",
+                " ".repeat(pad),
+                "^".repeat(carets)
+            ));
+        }
+        out.pop();
+        out
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.start_position.fmt(f)
+    }
+}
+
+/// The number of characters in the given byte range of a line, clamped to character boundaries.
+fn char_width(line: &str, from: usize, to: usize) -> usize {
+    line.char_indices()
+        .filter(|(i, _)| *i >= from && *i < to)
+        .count()
+}
+
+/// Spans of the subterms of a parsed term.
+///
+/// Subterms are addressed by the path of child indices leading to them from the root, where
+/// the children of each node are its subterms in the order they appear in the constructor
+/// (for example the function then the argument of an application, or the type then the body
+/// of a typed abstraction). The empty path is the whole term.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpanTable {
+    spans: HashMap<Vec<usize>, Span>,
+}
+
+impl SpanTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: Vec<usize>, span: Span) {
+        self.spans.insert(path, span);
+    }
+
+    pub fn get(&self, path: &[usize]) -> Option<Span> {
+        self.spans.get(path).copied()
+    }
+
+    /// The span of the given subterm, or of its closest spanned ancestor.
+    pub fn enclosing(&self, path: &[usize]) -> Option<Span> {
+        (0..=path.len()).rev().find_map(|n| self.get(&path[..n]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[usize], Span)> {
+        self.spans.iter().map(|(p, s)| (p.as_slice(), *s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let source = "ab\ncλ\nd".as_bytes();
+        assert_eq!(
+            Position::of_offset(source, 0),
+            Position { line: 0, column: 0 }
+        );
+        assert_eq!(
+            Position::of_offset(source, 3),
+            Position { line: 1, column: 0 }
+        );
+        assert_eq!(
+            Position::of_offset(source, 7),
+            Position { line: 2, column: 0 }
+        );
+        assert_eq!(Position::of_offset(source, 7).to_string(), "3:1");
+    }
+
+    #[test]
+    fn line_table_matches_scanning() {
+        let source = "ab\ncλ\n\nd\n".as_bytes();
+        let lines = Lines::new(source);
+        for offset in 0..=source.len() + 1 {
+            assert_eq!(lines.position(offset), Position::of_offset(source, offset));
+        }
+    }
+
+    #[test]
+    fn excerpt_underlines_span() {
+        let source = "λx. x y )".as_bytes();
+        let span = Span::new(source, 9, 10);
+        assert_eq!(span.excerpt(source), "  |\n1 | λx. x y )\n  |         ^");
+    }
+
+    #[test]
+    fn shifted_span_matches_direct() {
+        let source = "a = b;\nc = λx. d;".as_bytes();
+        let inner = &source[11..];
+        let span = Span::new(inner, 5, 6);
+        assert_eq!(span.shift(source, 11), Span::new(source, 16, 17));
+    }
+
+    #[test]
+    fn enclosing_span() {
+        let source = b"f x";
+        let mut table = SpanTable::new();
+        table.insert(vec![], Span::new(source, 0, 3));
+        table.insert(vec![1], Span::new(source, 2, 3));
+        assert_eq!(table.enclosing(&[1, 0]), table.get(&[1]));
+        assert_eq!(table.enclosing(&[0]), table.get(&[]));
+    }
+}
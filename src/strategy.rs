@@ -0,0 +1,198 @@
+//! Evaluation strategies other than the normal order used by each calculus's `evaluate`.
+//!
+//! A calculus supports the strategies by implementing [`Reduce`], which says how its terms are
+//! shaped and contracts their redexes; [`step`] then finds the redex each strategy contracts
+//! next. The strategies are those described in Sestoft's "Demonstrating Lambda Calculus
+//! Reduction".
+
+use crate::trace::{Fuel, OutOfFuel, Redex, Reducer, Rule, Subterms, Trace};
+
+/// An order in which to contract redexes.
+///
+/// There is no call-by-need strategy. It reaches the same weak head normal form as
+/// [`Strategy::CallByName`], but shares each argument between the variables it is substituted
+/// for, which rewriting a term can't express. Untyped terms are instead evaluated by need by
+/// the [`Lazy`](crate::untyped::machine::Lazy) machine, which counts the β-redexes it
+/// contracts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The leftmost outermost redex, under binders and in arguments, reaching the normal form
+    /// if there is one.
+    NormalOrder,
+    /// The leftmost innermost redex, so that functions and arguments are normalised before
+    /// being applied.
+    ApplicativeOrder,
+    /// Arguments are reduced before being substituted, but nothing is reduced under binders.
+    CallByValue,
+    /// Arguments are substituted unreduced, and nothing is reduced under binders or in the
+    /// arguments of a stuck application, reaching a weak head normal form: an abstraction, or
+    /// a variable applied to arguments.
+    CallByName,
+    /// Reduce to a head normal form, reducing under binders but not in arguments.
+    HeadNormalForm,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Self::NormalOrder,
+        Self::ApplicativeOrder,
+        Self::CallByValue,
+        Self::CallByName,
+        Self::HeadNormalForm,
+    ];
+
+    /// The terms reached by reducing `term` with this strategy until it has no more redexes.
+    pub fn trace<T: Reduce>(self, term: &T) -> Trace<T> {
+        let reducer: Reducer<T> = match self {
+            Self::NormalOrder => |t| step(t, Strategy::NormalOrder),
+            Self::ApplicativeOrder => |t| step(t, Strategy::ApplicativeOrder),
+            Self::CallByValue => |t| step(t, Strategy::CallByValue),
+            Self::CallByName => |t| step(t, Strategy::CallByName),
+            Self::HeadNormalForm => |t| step(t, Strategy::HeadNormalForm),
+        };
+        Trace::new(term.clone(), vec![reducer])
+    }
+
+    /// Reduce `term` with this strategy until it has no more redexes, also returning the
+    /// number of steps taken, or give up once the fuel runs out.
+    pub fn evaluate<T: Reduce>(
+        self,
+        term: &T,
+        fuel: impl Into<Fuel>,
+    ) -> Result<(T, usize), OutOfFuel<T>> {
+        self.trace(term).run_counting(fuel.into())
+    }
+}
+
+/// How a term is built, as far as the evaluation strategies are concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    /// A function, child 0, applied to an argument, which is child 1 if it is a term rather
+    /// than a type.
+    Application { term_argument: bool },
+    /// A binder such as an abstraction, whose body is the child with the given index.
+    Binder { body: usize },
+    /// Any other term, such as a variable.
+    Other,
+}
+
+/// Terms which can be reduced with any [`Strategy`].
+pub trait Reduce: Subterms + Clone {
+    fn form(&self) -> Form;
+
+    /// Contract this term if it is itself a β-redex.
+    fn contract(&self) -> Option<Self>;
+}
+
+/// Contract the redex of `term` that `strategy` reduces next, if there is one.
+pub fn step<T: Reduce>(term: &T, strategy: Strategy) -> Option<(T, Redex)> {
+    let root = || term.contract().map(|t| (t, Redex::root(Rule::Beta)));
+    let child = |index: usize, strategy| {
+        let (child, redex) = step(term.child(index)?, strategy)?;
+        Some((term.with_child(index, child), redex.within(index)))
+    };
+    // Reduce under binders, body first, and then in every other child.
+    let strong = |strategy| {
+        let body = match term.form() {
+            Form::Binder { body } => Some(body),
+            _ => None,
+        };
+        body.into_iter()
+            .chain((0..term.arity()).filter(|&i| Some(i) != body))
+            .find_map(|i| child(i, strategy))
+    };
+    match (strategy, term.form()) {
+        (Strategy::NormalOrder, Form::Application { term_argument }) => root()
+            .or_else(|| child(0, strategy))
+            .or_else(|| term_argument.then(|| child(1, strategy)).flatten()),
+        (
+            Strategy::ApplicativeOrder | Strategy::CallByValue,
+            Form::Application { term_argument },
+        ) => child(0, strategy)
+            .or_else(|| term_argument.then(|| child(1, strategy)).flatten())
+            .or_else(root),
+        (Strategy::NormalOrder | Strategy::ApplicativeOrder, _) => strong(strategy),
+        (Strategy::CallByName | Strategy::HeadNormalForm, Form::Application { .. }) => {
+            root().or_else(|| child(0, Strategy::CallByName))
+        }
+        (Strategy::HeadNormalForm, Form::Binder { body }) => child(body, strategy),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::untyped::{parser::parse, Term};
+    use proptest::prelude::{any, prop_assert_eq, proptest};
+
+    fn steps(source: &str) -> Vec<(Strategy, usize)> {
+        let t = parse(source).unwrap();
+        Strategy::ALL
+            .iter()
+            .map(|&s| (s, s.evaluate(&t, 100).unwrap().1))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn normal_order_agrees_with_beta_reduce_lazy(t in any::<Term>()) {
+            prop_assert_eq!(
+                step(&t, Strategy::NormalOrder),
+                t.beta_reduce_lazy_with_redex()
+            );
+        }
+    }
+
+    #[test]
+    fn strategies_take_different_numbers_of_steps() {
+        use Strategy::*;
+        assert_eq!(
+            steps("(λx. x x) ((λy. y) z)"),
+            vec![
+                (NormalOrder, 3),
+                (ApplicativeOrder, 2),
+                (CallByValue, 2),
+                (CallByName, 2),
+                (HeadNormalForm, 2),
+            ]
+        );
+        let t = parse("(λx. x x) ((λy. y) z)").unwrap();
+        let (whnf, _) = CallByName.evaluate(&t, 100).unwrap();
+        assert_eq!(whnf, parse("z ((λy. y) z)").unwrap());
+    }
+
+    #[test]
+    fn weak_strategies_stop_at_binders() {
+        use Strategy::*;
+        let t = parse("λx. (λy. y) x ((λz. z) w)").unwrap();
+        let normal_form = |s: Strategy| s.evaluate(&t, 100).unwrap().0;
+        assert_eq!(normal_form(CallByValue), t);
+        assert_eq!(normal_form(CallByName), t);
+        assert_eq!(
+            normal_form(HeadNormalForm),
+            parse("λx. x ((λz. z) w)").unwrap()
+        );
+        assert_eq!(normal_form(NormalOrder), parse("λx. x w").unwrap());
+    }
+
+    #[test]
+    fn call_by_value_evaluates_unused_arguments() {
+        let t = parse("(λx. y) ((λx. x x) (λx. x x))").unwrap();
+        assert_eq!(
+            Strategy::CallByName.evaluate(&t, 100).map(|(_, n)| n),
+            Ok(1)
+        );
+        assert!(Strategy::CallByValue.evaluate(&t, 100).is_err());
+        assert!(Strategy::ApplicativeOrder.evaluate(&t, 100).is_err());
+    }
+
+    #[test]
+    fn type_applications_are_contracted() {
+        let t = crate::system_f::parser::parse("(Λa. λx: a. x) [b] y").unwrap();
+        for s in Strategy::ALL {
+            let (result, steps) = s.evaluate(&t, 100).unwrap();
+            assert_eq!((result.to_string(), steps), ("y".to_owned(), 2));
+        }
+    }
+}
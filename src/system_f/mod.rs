@@ -1,9 +1,18 @@
+pub mod parser;
 pub mod self_interpret;
 
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 use super::{fresh_var, untyped};
+use crate::locally_nameless::{Ln, LocallyNameless, Scope};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::strategy::{Form, Reduce};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 #[derive(Debug, Clone, Eq)]
 pub enum Type {
@@ -97,21 +106,36 @@ impl Type {
     }
 
     pub fn alpha_equivalent(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Base, Self::Base) => true,
-            (Self::Variable(x), Self::Variable(y)) => x == y,
-            (Self::ForAll(x, t), Self::ForAll(y, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            (Self::Fn(t1, u1), Self::Fn(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            _ => false,
+        self.to_locally_nameless() == other.to_locally_nameless()
+    }
+
+    fn to_locally_nameless_in(&self, scope: &mut Scope<Namespace>) -> Ln<Node> {
+        match self {
+            Self::Base => Ln::node(Node::Base, []),
+            Self::Variable(x) => scope.variable(Namespace::Type, x),
+            Self::ForAll(x, t) => Ln::node(
+                Node::ForAll,
+                [scope.bind(Namespace::Type, x, |scope| t.to_locally_nameless_in(scope))],
+            ),
+            Self::Fn(t, u) => Ln::node(
+                Node::Fn,
+                [
+                    t.to_locally_nameless_in(scope),
+                    u.to_locally_nameless_in(scope),
+                ],
+            ),
         }
     }
 }
 
+impl LocallyNameless for Type {
+    type Node = Node;
+
+    fn to_locally_nameless(&self) -> Ln<Node> {
+        self.to_locally_nameless_in(&mut Scope::new())
+    }
+}
+
 impl PartialEq for Type {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -133,6 +157,28 @@ pub struct TypeEnvironment {
     type_variables: HashSet<String>,
 }
 
+/// Why a term is ill-typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    UndefinedVar(String),
+    UndefinedTypeVar(String),
+    /// A term is applied to an argument, but its type isn't a function type.
+    NotFunType {
+        term: Term,
+        ty: Type,
+    },
+    /// A term is applied to a type, but its type isn't polymorphic.
+    NotForAll {
+        term: Term,
+        ty: Type,
+    },
+    /// An argument's type isn't the one the function expects.
+    Mismatch {
+        expected: Type,
+        actual: Type,
+    },
+}
+
 impl TypeEnvironment {
     fn new() -> TypeEnvironment {
         Self {
@@ -322,23 +368,37 @@ impl Term {
     }
 
     pub fn alpha_equivalent(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Variable(x), Self::Variable(y)) => x == y,
-            (Self::Abstraction(x, ty1, t), Self::Abstraction(y, ty2, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                ty1.alpha_equivalent(ty2) && t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            (Self::Application(t1, u1), Self::Application(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            (Self::TypeAbstraction(x, t), Self::TypeAbstraction(y, u)) => {
-                let w = fresh_var(&(&self.type_vars() | &other.type_vars()));
-                t.type_rename(x, &w).alpha_equivalent(&u.type_rename(y, &w))
-            }
-            (Self::TypeApplication(t1, u1), Self::TypeApplication(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            _ => false,
+        self.to_locally_nameless() == other.to_locally_nameless()
+    }
+
+    fn to_locally_nameless_in(&self, scope: &mut Scope<Namespace>) -> Ln<Node> {
+        match self {
+            Self::Variable(x) => scope.variable(Namespace::Term, x),
+            Self::Abstraction(x, ty, t) => Ln::node(
+                Node::Abstraction,
+                [
+                    ty.to_locally_nameless_in(scope),
+                    scope.bind(Namespace::Term, x, |scope| t.to_locally_nameless_in(scope)),
+                ],
+            ),
+            Self::Application(t, u) => Ln::node(
+                Node::Application,
+                [
+                    t.to_locally_nameless_in(scope),
+                    u.to_locally_nameless_in(scope),
+                ],
+            ),
+            Self::TypeAbstraction(x, t) => Ln::node(
+                Node::TypeAbstraction,
+                [scope.bind(Namespace::Type, x, |scope| t.to_locally_nameless_in(scope))],
+            ),
+            Self::TypeApplication(t, ty) => Ln::node(
+                Node::TypeApplication,
+                [
+                    t.to_locally_nameless_in(scope),
+                    ty.to_locally_nameless_in(scope),
+                ],
+            ),
         }
     }
 
@@ -372,34 +432,44 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::TypeApplication(t, u) => {
                 if let Self::TypeAbstraction(x, b) = t.as_ref() {
-                    Some(b.type_substitute(x, u))
+                    Some((b.type_substitute(x, u), Redex::root(Rule::Beta)))
                 } else {
-                    t.beta_reduce_lazy()
-                        .map(|t2| Self::TypeApplication(Box::new(t2), u.clone()))
+                    t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                        (Self::TypeApplication(Box::new(t2), u.clone()), r.within(0))
+                    })
                 }
             }
-            Self::Abstraction(x, ty, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(t2))),
-            Self::TypeAbstraction(x, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::TypeAbstraction(x.to_owned(), Box::new(t2))),
+            Self::Abstraction(x, ty, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (Self::Abstraction(x.to_owned(), ty.clone(), t2), r.within(0))
+            }),
+            Self::TypeAbstraction(x, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (Self::TypeAbstraction(x.to_owned(), t2), r.within(0))
+            }),
             _ => None,
         }
     }
@@ -447,41 +517,52 @@ impl Term {
     /// Eta-reduce the left-outermost simplification if one exists.
     /// This does not check the type.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, ty, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                    let u2 = Box::new(u2);
+                    (Self::Abstraction(x.to_owned(), ty.clone(), u2), r.within(0))
+                })
             }
             Self::TypeAbstraction(x, t) => {
                 if let Self::TypeApplication(f, Type::Variable(y)) = t.as_ref() {
                     if x == y && !f.free_vars().contains(x) {
-                        return Some(f.as_ref().clone());
+                        return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::TypeAbstraction(x.to_owned(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(t2, r)| {
+                    let t2 = Box::new(t2);
+                    (Self::TypeAbstraction(x.to_owned(), t2), r.within(0))
+                })
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::TypeApplication(t, u) => t
-                .eta_reduce_lazy()
-                .map(|t2| Self::TypeApplication(Box::new(t2), u.clone())),
+                .eta_reduce_lazy_with_redex()
+                .map(|(t2, r)| (Self::TypeApplication(Box::new(t2), u.clone()), r.within(0))),
             _ => None,
         }
     }
@@ -498,58 +579,227 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given type environment, if it is well-typed.
-    pub fn type_in(&self, type_env: &TypeEnvironment) -> Option<Type> {
+    pub fn type_in(&self, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
         match self {
             Self::Variable(x) => type_env
                 .term_variables
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
             Self::Abstraction(x, ty, t) => {
-                if !ty.free_vars().is_subset(&type_env.type_variables) {
-                    return None;
+                let free = ty.free_vars();
+                if let Some(a) = free.difference(&type_env.type_variables).min() {
+                    return Err(TypeError::UndefinedTypeVar(a.clone()));
                 }
                 let mut inner_env = type_env.clone();
                 inner_env.term_variables.push((x.to_owned(), ty.clone()));
                 t.type_in(&inner_env)
                     .map(|t| Type::Fn(Box::new(ty.clone()), Box::new(t)))
             }
-            Self::Application(t, u) => {
-                if let Some(Type::Fn(a1, b)) = t.type_in(type_env) {
+            Self::Application(t, u) => match t.type_in(type_env)? {
+                Type::Fn(a1, b) => {
                     let a2 = u.type_in(type_env)?;
-                    if a1.as_ref() == &a2 {
-                        Some(b.as_ref().clone())
+                    if *a1 == a2 {
+                        Ok(*b)
                     } else {
-                        None
+                        Err(TypeError::Mismatch {
+                            expected: *a1,
+                            actual: a2,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
             Self::TypeAbstraction(a, t) => {
                 let mut inner_env = type_env.clone();
                 inner_env.type_variables.insert(a.to_owned());
                 t.type_in(&inner_env)
                     .map(|t| Type::ForAll(a.to_owned(), Box::new(t)))
             }
-            Self::TypeApplication(t, u) => {
-                if let Some(Type::ForAll(v, b)) = t.type_in(type_env) {
-                    Some(b.substitute(&v, u))
-                } else {
-                    None
-                }
-            }
+            Self::TypeApplication(t, u) => match t.type_in(type_env)? {
+                Type::ForAll(v, b) => Ok(b.substitute(&v, u)),
+                ty => Err(TypeError::NotForAll {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
         }
     }
 
     /// The type of this term in the empty type environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Type> {
+    pub fn type_closed(&self) -> Result<Type, TypeError> {
         self.type_in(&TypeEnvironment::new())
     }
 }
 
+#[cfg(test)]
+impl Arbitrary for Type {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_type().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_type() -> impl Strategy<Value = Type> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = prop_oneof![Just(Type::Base), var().prop_map(Type::Variable)];
+    leaf.prop_recursive(8, 64, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(t, u)| Type::Fn(Box::new(t), Box::new(u))),
+            (var(), inner).prop_map(|(a, t)| Type::ForAll(a, Box::new(t))),
+        ]
+    })
+}
+
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = var().prop_map(Term::Variable);
+    leaf.prop_recursive(16, 256, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (var(), arb_type(), inner.clone()).prop_map(|(x, ty, b)| Term::Abstraction(
+                x,
+                ty,
+                Box::new(b)
+            )),
+            (var(), inner.clone()).prop_map(|(a, b)| Term::TypeAbstraction(a, Box::new(b))),
+            (inner, arb_type()).prop_map(|(t, ty)| Term::TypeApplication(Box::new(t), ty)),
+        ]
+    })
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) | Self::TypeAbstraction(..) | Self::TypeApplication(..) => 1,
+            Self::Application(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, _, t), 0)
+            | (Self::Application(t, _), 0)
+            | (Self::TypeAbstraction(_, t), 0)
+            | (Self::TypeApplication(t, _), 0) => Some(t),
+            (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, ty, _), 0) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            (Self::TypeAbstraction(x, _), 0) => Self::TypeAbstraction(x.clone(), child),
+            (Self::TypeApplication(_, ty), 0) => Self::TypeApplication(child, ty.clone()),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+impl Reduce for Term {
+    fn form(&self) -> Form {
+        match self {
+            Self::Application(..) => Form::Application {
+                term_argument: true,
+            },
+            Self::TypeApplication(..) => Form::Application {
+                term_argument: false,
+            },
+            Self::Abstraction(..) | Self::TypeAbstraction(..) => Form::Binder { body: 0 },
+            _ => Form::Other,
+        }
+    }
+
+    fn contract(&self) -> Option<Self> {
+        match self {
+            Self::Application(t, u) => match t.as_ref() {
+                Self::Abstraction(x, _, b) => Some(b.substitute(x, u)),
+                _ => None,
+            },
+            Self::TypeApplication(t, ty) => match t.as_ref() {
+                Self::TypeAbstraction(x, b) => Some(b.type_substitute(x, ty)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The constructors of a [`Term`] or [`Type`] in locally nameless form.
+///
+/// The type of an abstraction is its first child, before the binder for its variable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Base,
+    ForAll,
+    Fn,
+    Abstraction,
+    Application,
+    TypeAbstraction,
+    TypeApplication,
+}
+
+impl LocallyNameless for Term {
+    type Node = Node;
+
+    fn to_locally_nameless(&self) -> Ln<Node> {
+        self.to_locally_nameless_in(&mut Scope::new())
+    }
+}
+
+/// Term and type variables are bound separately, so that neither shadows the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    Term,
+    Type,
+}
+
 impl PartialEq for Term {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -558,19 +808,56 @@ impl PartialEq for Term {
 
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
                 write_term(u, f)
             }
-            Self::TypeAbstraction(x, t) => write!(f, "Λ{}. {}", x, t),
+            Self::TypeAbstraction(x, t) => write!(f, "{}{}. {}", n.big_lambda(), x, n.show(t)),
             Self::TypeApplication(t, u) => {
                 write_func(t, f)?;
-                write!(f, " ")?;
-                write_ty(u, f)
+                write!(f, " [{}]", n.show(u))
+            }
+        }
+    }
+}
+
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::TypeAbstraction(x, t) => {
+                pretty::binder(Doc::text(format!("{}{}.", n.big_lambda(), x)), t.to_doc(n))
+            }
+            Self::Application(_, _) | Self::TypeApplication(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        Self::TypeApplication(t, u) => {
+                            args.push(Doc::text("[") + u.to_doc(n) + Doc::text("]"));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
             }
         }
     }
@@ -579,7 +866,7 @@ impl fmt::Display for Term {
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -588,20 +875,36 @@ fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         Term::Variable(_) | Term::Application(_, _) | Term::TypeApplication(_, _) => {
             fmt::Display::fmt(t, f)
         }
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
-            Self::Base => write!(f, "ι"),
+            Self::Base => write!(f, "{}", n.base()),
             Self::Variable(x) => x.fmt(f),
             Self::Fn(t, u) => {
                 write_ty(t, f)?;
-                write!(f, " -> {}", u)
+                write!(f, " -> {}", n.show(u))
+            }
+            Self::ForAll(a, t) => write!(f, "{}{}. {}", n.for_all(), a, n.show(t)),
+        }
+    }
+}
+
+impl Pretty for Type {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Base => pretty::atom(n.base()),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Fn(t, u) => {
+                pretty::infix(at(t.as_ref(), n, Prec::Application), " ->", u.to_doc(n))
+            }
+            Self::ForAll(a, t) => {
+                pretty::binder(Doc::text(format!("{}{}.", n.for_all(), a)), t.to_doc(n))
             }
-            Self::ForAll(a, t) => write!(f, "∀{}. {}", a, t),
         }
     }
 }
@@ -609,12 +912,33 @@ impl fmt::Display for Type {
 fn write_ty(ty: &Type, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match ty {
         Type::Base | Type::Variable(_) => fmt::Display::fmt(ty, f),
-        Type::Fn(_, _) | Type::ForAll(_, _) => write!(f, "({})", ty),
+        Type::Fn(_, _) | Type::ForAll(_, _) => parens(ty, f),
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::UndefinedTypeVar(a) => write!(f, "undefined type variable {}", a),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::NotForAll { term, ty } => {
+                write!(f, "{} is applied to a type, but has type {}", term, ty)
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "expected type {}, found {}", expected, actual)
+            }
+        }
     }
 }
 
+impl error::Error for TypeError {}
+
 impl fmt::Display for TypeEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         let mut any_elems = false;
         for v in &self.type_variables {
             if any_elems {
@@ -626,9 +950,9 @@ impl fmt::Display for TypeEnvironment {
         }
         for (v, t) in &self.term_variables {
             if any_elems {
-                write!(f, ", {v}: {t}")?;
+                write!(f, ", {v}: {}", n.show(t))?;
             } else {
-                write!(f, "{v}: {t}")?;
+                write!(f, "{v}: {}", n.show(t))?;
                 any_elems = true;
             }
         }
@@ -675,7 +999,7 @@ mod tests {
                 Box::new(Type::Variable("a".into())),
             )),
         );
-        assert_eq!(i.type_closed(), Some(expected));
+        assert_eq!(i.type_closed(), Ok(expected));
     }
 
     #[test]
@@ -708,7 +1032,7 @@ mod tests {
                 )),
             )),
         );
-        assert_eq!(k.type_closed(), Some(expected));
+        assert_eq!(k.type_closed(), Ok(expected));
     }
 
     #[test]
@@ -781,7 +1105,7 @@ mod tests {
                 )),
             )),
         );
-        assert_eq!(s.type_closed(), Some(expected));
+        assert_eq!(s.type_closed(), Ok(expected));
     }
 
     #[test]
@@ -825,6 +1149,6 @@ mod tests {
                 )),
             )),
         );
-        assert_eq!(xx.type_closed(), Some(expected));
+        assert_eq!(xx.type_closed(), Ok(expected));
     }
 }
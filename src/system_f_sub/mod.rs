@@ -1,7 +1,15 @@
+pub mod parser;
+
 use std::collections::HashSet;
-use std::fmt;
+use std::{error, fmt};
+
+#[cfg(test)]
+use proptest::prelude::*;
 
 use super::fresh_var;
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
 #[derive(Debug, Clone, Eq)]
 pub enum Type {
@@ -174,6 +182,28 @@ pub struct TypeEnvironment {
     type_variables: Vec<(String, Type)>,
 }
 
+/// Why a term is ill-typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    UndefinedVar(String),
+    UndefinedTypeVar(String),
+    /// A term is applied to an argument, but its type isn't a function type.
+    NotFunType {
+        term: Term,
+        ty: Type,
+    },
+    /// A term is applied to a type, but its type isn't polymorphic.
+    NotForAll {
+        term: Term,
+        ty: Type,
+    },
+    /// An argument's type, or a type argument, isn't a subtype of the one expected.
+    NotSubtype {
+        sub: Type,
+        sup: Type,
+    },
+}
+
 impl TypeEnvironment {
     fn new() -> TypeEnvironment {
         Self {
@@ -182,6 +212,15 @@ impl TypeEnvironment {
         }
     }
 
+    /// Check that the type variables of `ty` are all bound.
+    fn check_bound(&self, ty: &Type) -> Result<(), TypeError> {
+        let bound = self.type_variables.iter().map(|(v, _)| v.clone()).collect();
+        match ty.free_vars().difference(&bound).min() {
+            Some(a) => Err(TypeError::UndefinedTypeVar(a.clone())),
+            None => Ok(()),
+        }
+    }
+
     // fn lowest_common_supertype(&self, a: &Type, b: &Type) -> Type {
     //     todo!()
     // }
@@ -433,34 +472,47 @@ impl Term {
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     /// This does not check the type.
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, _, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::TypeApplication(t, u) => {
                 if let Self::TypeAbstraction(x, _, b) = t.as_ref() {
-                    Some(b.type_substitute(x, u))
+                    Some((b.type_substitute(x, u), Redex::root(Rule::Beta)))
                 } else {
-                    t.beta_reduce_lazy()
-                        .map(|t2| Self::TypeApplication(Box::new(t2), u.clone()))
+                    t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                        (Self::TypeApplication(Box::new(t2), u.clone()), r.within(0))
+                    })
                 }
             }
-            Self::Abstraction(x, ty, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(t2))),
-            Self::TypeAbstraction(x, b, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::TypeAbstraction(x.to_owned(), b.clone(), Box::new(t2))),
+            Self::Abstraction(x, ty, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (Self::Abstraction(x.to_owned(), ty.clone(), t2), r.within(0))
+            }),
+            Self::TypeAbstraction(x, b, t) => t.beta_reduce_lazy_with_redex().map(|(t2, r)| {
+                let t2 = Box::new(t2);
+                (
+                    Self::TypeAbstraction(x.to_owned(), b.clone(), t2),
+                    r.within(0),
+                )
+            }),
             _ => None,
         }
     }
@@ -508,41 +560,55 @@ impl Term {
     /// Eta-reduce the left-outermost simplification if one exists.
     /// This does not check the type.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, ty, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), ty.clone(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                    let u2 = Box::new(u2);
+                    (Self::Abstraction(x.to_owned(), ty.clone(), u2), r.within(0))
+                })
             }
             Self::TypeAbstraction(x, b, t) => {
                 if let Self::TypeApplication(f, Type::Variable(y)) = t.as_ref() {
                     if x == y && !f.free_vars().contains(x) {
-                        return Some(f.as_ref().clone());
+                        return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::TypeAbstraction(x.to_owned(), b.clone(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex().map(|(t2, r)| {
+                    let t2 = Box::new(t2);
+                    (
+                        Self::TypeAbstraction(x.to_owned(), b.clone(), t2),
+                        r.within(0),
+                    )
+                })
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::TypeApplication(t, u) => t
-                .eta_reduce_lazy()
-                .map(|t2| Self::TypeApplication(Box::new(t2), u.clone())),
+                .eta_reduce_lazy_with_redex()
+                .map(|(t2, r)| (Self::TypeApplication(Box::new(t2), u.clone()), r.within(0))),
             _ => None,
         }
     }
@@ -559,74 +625,82 @@ impl Term {
         res
     }
 
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
     /// The type of this term in the given type environment, if it is well-typed.
-    pub fn type_in(&self, type_env: &TypeEnvironment) -> Option<Type> {
+    pub fn type_in(&self, type_env: &TypeEnvironment) -> Result<Type, TypeError> {
         match self {
-            Self::Top => Some(Type::Top),
+            Self::Top => Ok(Type::Top),
             Self::Variable(x) => type_env
                 .term_variables
                 .iter()
                 .rev()
                 .find(|(v, _)| v == x)
-                .map(|(_, t)| t.clone()),
+                .map(|(_, t)| t.clone())
+                .ok_or_else(|| TypeError::UndefinedVar(x.clone())),
             Self::Abstraction(x, ty, t) => {
-                if !ty.free_vars().is_subset(
-                    &type_env
-                        .type_variables
-                        .iter()
-                        .map(|(v, _)| v.clone())
-                        .collect(),
-                ) {
-                    return None;
-                }
+                type_env.check_bound(ty)?;
                 let mut inner_env = type_env.clone();
                 inner_env.term_variables.push((x.to_owned(), ty.clone()));
                 t.type_in(&inner_env)
                     .map(|t| Type::Fn(Box::new(ty.clone()), Box::new(t)))
             }
-            Self::Application(t, u) => {
-                if let Some(Type::Fn(a1, b)) = t.type_in(type_env) {
+            Self::Application(t, u) => match t.type_in(type_env)? {
+                Type::Fn(a1, b) => {
                     let a2 = u.type_in(type_env)?;
-                    if a2.is_subtype_in(type_env, a1.as_ref()) {
-                        Some(b.as_ref().clone())
+                    if a2.is_subtype_in(type_env, &a1) {
+                        Ok(*b)
                     } else {
-                        None
+                        Err(TypeError::NotSubtype { sub: a2, sup: *a1 })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotFunType {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
             Self::TypeAbstraction(a, b, t) => {
-                if !b.free_vars().is_subset(
-                    &type_env
-                        .type_variables
-                        .iter()
-                        .map(|(v, _)| v.clone())
-                        .collect(),
-                ) {
-                    return None;
-                }
+                type_env.check_bound(b)?;
                 let mut inner_env = type_env.clone();
                 inner_env.type_variables.push((a.to_owned(), b.clone()));
                 t.type_in(&inner_env)
                     .map(|t| Type::ForAll(a.to_owned(), Box::new(b.clone()), Box::new(t)))
             }
-            Self::TypeApplication(t, u) => {
-                if let Some(Type::ForAll(v, bound, body)) = t.type_in(type_env) {
-                    if u.is_subtype_in(type_env, bound.as_ref()) {
-                        Some(body.substitute(&v, u))
+            Self::TypeApplication(t, u) => match t.type_in(type_env)? {
+                Type::ForAll(v, bound, body) => {
+                    if u.is_subtype_in(type_env, &bound) {
+                        Ok(body.substitute(&v, u))
                     } else {
-                        None
+                        Err(TypeError::NotSubtype {
+                            sub: u.clone(),
+                            sup: *bound,
+                        })
                     }
-                } else {
-                    None
                 }
-            }
+                ty => Err(TypeError::NotForAll {
+                    term: t.as_ref().clone(),
+                    ty,
+                }),
+            },
         }
     }
 
     /// The type of this term in the empty type environment, if it is well-typed.
-    pub fn type_closed(&self) -> Option<Type> {
+    pub fn type_closed(&self) -> Result<Type, TypeError> {
         self.type_in(&TypeEnvironment::new())
     }
 
@@ -682,6 +756,104 @@ impl Term {
     // }
 }
 
+#[cfg(test)]
+impl Arbitrary for Type {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_type().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_type() -> impl Strategy<Value = Type> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = prop_oneof![Just(Type::Top), var().prop_map(Type::Variable)];
+    leaf.prop_recursive(8, 64, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(t, u)| Type::Fn(Box::new(t), Box::new(u))),
+            (var(), inner.clone(), inner).prop_map(|(a, b, t)| Type::ForAll(
+                a,
+                Box::new(b),
+                Box::new(t)
+            )),
+        ]
+    })
+}
+
+#[cfg(test)]
+impl Arbitrary for Term {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        arb_term().boxed()
+    }
+}
+
+#[cfg(test)]
+fn arb_term() -> impl Strategy<Value = Term> {
+    let var = || crate::arb_var_except(parser::RESERVED);
+    let leaf = prop_oneof![Just(Term::Top), var().prop_map(Term::Variable)];
+    leaf.prop_recursive(16, 256, 2, move |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone())
+                .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
+            (var(), arb_type(), inner.clone()).prop_map(|(x, ty, b)| Term::Abstraction(
+                x,
+                ty,
+                Box::new(b)
+            )),
+            (var(), arb_type(), inner.clone()).prop_map(|(a, ty, b)| Term::TypeAbstraction(
+                a,
+                ty,
+                Box::new(b)
+            )),
+            (inner, arb_type()).prop_map(|(t, ty)| Term::TypeApplication(Box::new(t), ty)),
+        ]
+    })
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) | Self::TypeAbstraction(..) | Self::TypeApplication(..) => 1,
+            Self::Application(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, _, t), 0)
+            | (Self::Application(t, _), 0)
+            | (Self::TypeAbstraction(_, _, t), 0)
+            | (Self::TypeApplication(t, _), 0) => Some(t),
+            (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, ty, _), 0) => Self::Abstraction(x.clone(), ty.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            (Self::TypeAbstraction(x, b, _), 0) => {
+                Self::TypeAbstraction(x.clone(), b.clone(), child)
+            }
+            (Self::TypeApplication(_, ty), 0) => Self::TypeApplication(child, ty.clone()),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
 impl PartialEq for Term {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -690,20 +862,63 @@ impl PartialEq for Term {
 
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Top => write!(f, "top"),
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, ty, t) => write!(f, "λ{}: {}. {}", x, ty, t),
+            Self::Abstraction(x, ty, t) => {
+                write!(f, "{}{}: {}. {}", n.lambda(), x, n.show(ty), n.show(t))
+            }
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
                 write_term(u, f)
             }
-            Self::TypeAbstraction(x, b, t) => write!(f, "Λ{} <: {}. {}", x, b, t),
+            Self::TypeAbstraction(x, b, t) => {
+                write!(f, "{}{} <: {}. {}", n.big_lambda(), x, n.show(b), n.show(t))
+            }
             Self::TypeApplication(t, u) => {
                 write_func(t, f)?;
-                write!(f, " ")?;
-                write_ty(u, f)
+                write!(f, " [{}]", n.show(u))
+            }
+        }
+    }
+}
+
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Top => pretty::atom("top"),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, ty, t) => {
+                let head =
+                    Doc::text(format!("{}{}: ", n.lambda(), x)) + ty.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::TypeAbstraction(x, b, t) => {
+                let head = Doc::text(format!("{}{} <: ", n.big_lambda(), x))
+                    + b.to_doc(n)
+                    + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
+            }
+            Self::Application(_, _) | Self::TypeApplication(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        Self::TypeApplication(t, u) => {
+                            args.push(Doc::text("[") + u.to_doc(n) + Doc::text("]"));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
             }
         }
     }
@@ -712,7 +927,7 @@ impl fmt::Display for Term {
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Top => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
@@ -721,20 +936,40 @@ fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         Term::Variable(_) | Term::Top | Term::Application(_, _) | Term::TypeApplication(_, _) => {
             fmt::Display::fmt(t, f)
         }
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Top => write!(f, "Top"),
             Self::Variable(x) => x.fmt(f),
             Self::Fn(t, u) => {
                 write_ty(t, f)?;
-                write!(f, " -> {}", u)
+                write!(f, " -> {}", n.show(u))
+            }
+            Self::ForAll(a, b, t) => {
+                write!(f, "{}{} <: {}. {}", n.for_all(), a, n.show(b), n.show(t))
+            }
+        }
+    }
+}
+
+impl Pretty for Type {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Top => pretty::atom("Top"),
+            Self::Variable(x) => pretty::atom(x),
+            Self::Fn(t, u) => {
+                pretty::infix(at(t.as_ref(), n, Prec::Application), " ->", u.to_doc(n))
+            }
+            Self::ForAll(a, b, t) => {
+                let head =
+                    Doc::text(format!("{}{} <: ", n.for_all(), a)) + b.to_doc(n) + Doc::text(".");
+                pretty::binder(head, t.to_doc(n))
             }
-            Self::ForAll(a, b, t) => write!(f, "∀{} <: {}. {}", a, b, t),
         }
     }
 }
@@ -742,26 +977,45 @@ impl fmt::Display for Type {
 fn write_ty(ty: &Type, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match ty {
         Type::Top | Type::Variable(_) => fmt::Display::fmt(ty, f),
-        Type::Fn(_, _) | Type::ForAll(_, _, _) => write!(f, "({})", ty),
+        Type::Fn(_, _) | Type::ForAll(_, _, _) => parens(ty, f),
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedVar(x) => write!(f, "undefined variable {}", x),
+            Self::UndefinedTypeVar(a) => write!(f, "undefined type variable {}", a),
+            Self::NotFunType { term, ty } => {
+                write!(f, "{} is applied to an argument, but has type {}", term, ty)
+            }
+            Self::NotForAll { term, ty } => {
+                write!(f, "{} is applied to a type, but has type {}", term, ty)
+            }
+            Self::NotSubtype { sub, sup } => write!(f, "{} is not a subtype of {}", sub, sup),
+        }
     }
 }
 
+impl error::Error for TypeError {}
+
 impl fmt::Display for TypeEnvironment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         let mut any_elems = false;
         for (v, b) in &self.type_variables {
             if any_elems {
-                write!(f, ", {v} <: {b}")?;
+                write!(f, ", {v} <: {}", n.show(b))?;
             } else {
-                write!(f, "{v} <: {b}")?;
+                write!(f, "{v} <: {}", n.show(b))?;
                 any_elems = true;
             }
         }
         for (v, t) in &self.term_variables {
             if any_elems {
-                write!(f, ", {v}: {t}")?;
+                write!(f, ", {v}: {}", n.show(t))?;
             } else {
-                write!(f, "{v}: {t}")?;
+                write!(f, "{v}: {}", n.show(t))?;
                 any_elems = true;
             }
         }
@@ -770,4 +1024,23 @@ impl fmt::Display for TypeEnvironment {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::parser::parse;
+    use super::*;
+
+    #[test]
+    fn type_arguments_must_be_subtypes_of_their_bound() {
+        let t = parse("(Λa <: Top -> Top. λx: a. x) [Top]").unwrap();
+        let err = t.type_closed().unwrap_err();
+        assert_eq!(err.to_string(), "Top is not a subtype of Top -> Top");
+    }
+
+    #[test]
+    fn type_variables_must_be_bound() {
+        let t = parse("λx: a. x").unwrap();
+        assert_eq!(
+            t.type_closed(),
+            Err(TypeError::UndefinedTypeVar("a".into()))
+        );
+    }
+}
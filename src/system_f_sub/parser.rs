@@ -0,0 +1,149 @@
+use super::*;
+use crate::parsing::lexer::{Token, Tokens};
+
+pub use crate::parsing::ParseError;
+
+/// `top` is the term of type `Top`.
+pub(super) const RESERVED: &[&str] = &["top", "Top"];
+
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let t = term(&mut tokens)?;
+    tokens.finish()?;
+    Ok(t)
+}
+
+pub fn parse_type(source: impl AsRef<[u8]>) -> Result<Type, ParseError> {
+    let mut tokens = Tokens::new(source.as_ref(), RESERVED)?;
+    let ty = ty(&mut tokens)?;
+    tokens.finish()?;
+    Ok(ty)
+}
+
+fn term(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::Lambda) {
+        let x = tokens.identifier()?;
+        tokens.expect(&Token::Colon)?;
+        let ty = ty(tokens)?;
+        tokens.expect(&Token::Dot)?;
+        Ok(Term::Abstraction(x, ty, Box::new(term(tokens)?)))
+    } else if tokens.eat(&Token::BigLambda) {
+        let a = tokens.identifier()?;
+        tokens.expect(&Token::Subtype)?;
+        let bound = ty(tokens)?;
+        tokens.expect(&Token::Dot)?;
+        Ok(Term::TypeAbstraction(a, bound, Box::new(term(tokens)?)))
+    } else {
+        application(tokens)
+    }
+}
+
+/// A sequence of arguments applied to a function, where the last argument may be an
+/// unparenthesised abstraction.
+fn application(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    let mut t = atom(tokens)?;
+    loop {
+        t = match tokens.peek() {
+            Some(Token::Lambda | Token::BigLambda) => {
+                return Ok(Term::Application(Box::new(t), Box::new(term(tokens)?)))
+            }
+            Some(Token::LBracket) => {
+                tokens.expect(&Token::LBracket)?;
+                let ty = ty(tokens)?;
+                tokens.expect(&Token::RBracket)?;
+                Term::TypeApplication(Box::new(t), ty)
+            }
+            Some(Token::LParen) => Term::Application(Box::new(t), Box::new(atom(tokens)?)),
+            _ if tokens.at_identifier() || tokens.at_keyword("top") => {
+                Term::Application(Box::new(t), Box::new(atom(tokens)?))
+            }
+            _ => return Ok(t),
+        }
+    }
+}
+
+fn atom(tokens: &mut Tokens) -> Result<Term, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = term(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.eat_keyword("top") {
+        Ok(Term::Top)
+    } else if tokens.at_identifier() {
+        Ok(Term::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a term"))
+    }
+}
+
+fn ty(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    if tokens.eat(&Token::ForAll) {
+        let a = tokens.identifier()?;
+        tokens.expect(&Token::Subtype)?;
+        let bound = ty(tokens)?;
+        tokens.expect(&Token::Dot)?;
+        return Ok(Type::ForAll(a, Box::new(bound), Box::new(ty(tokens)?)));
+    }
+    let t = ty_atom(tokens)?;
+    if tokens.eat(&Token::Arrow) {
+        Ok(Type::Fn(Box::new(t), Box::new(ty(tokens)?)))
+    } else {
+        Ok(t)
+    }
+}
+
+fn ty_atom(tokens: &mut Tokens) -> Result<Type, ParseError> {
+    if tokens.eat(&Token::LParen) {
+        let t = ty(tokens)?;
+        tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if tokens.eat_keyword("Top") {
+        Ok(Type::Top)
+    } else if tokens.at_identifier() {
+        Ok(Type::Variable(tokens.identifier()?))
+    } else {
+        Err(tokens.unexpected("a type"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::Pretty;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn printed_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn printed_type_parses_correctly(t in any::<Type>()) {
+            let s = format!("{}", t);
+            let t2 = parse_type(s).expect("printed output should be parseable");
+            prop_assert_eq!(t2, t);
+        }
+
+        #[test]
+        fn ascii_output_parses_correctly(f in any::<Type>()) {
+            let s = format!("{:#}", f);
+            let f2 = parse_type(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn pretty_output_parses_correctly(f in any::<Term>(), width in 1usize..80) {
+            let s = f.pretty(width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn parser_doesnt_crash(s in "\\PC*") {
+            let _ = parse(s);
+        }
+    }
+}
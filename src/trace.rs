@@ -0,0 +1,346 @@
+//! Step-by-step reduction traces.
+//!
+//! Each calculus has a `reduction_trace` method returning a [`Trace`], which yields every term
+//! reached while evaluating, together with the redex contracted next. A redex is located by
+//! its [`Redex::path`], the child indices leading to it from the root. Children are numbered
+//! among the subterms of the same type in the order they appear in the constructor, as in the
+//! span tables returned by the parsers.
+//!
+//! Since evaluation may not halt, a trace can also be run with [`Fuel`] limiting how many steps
+//! it takes and how large the term may grow.
+
+use std::{error, fmt};
+
+use console::style;
+
+/// A reduction rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    Beta,
+    Eta,
+    /// The reduction of a primitive function applied to literals.
+    Delta,
+    /// The unfolding of a `let`.
+    Zeta,
+    /// The choice of a `case` branch by the constructor of the scrutinee.
+    Iota,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unicode, ascii) = match self {
+            Self::Beta => ("β", "beta"),
+            Self::Eta => ("η", "eta"),
+            Self::Delta => ("δ", "delta"),
+            Self::Zeta => ("ζ", "zeta"),
+            Self::Iota => ("ι", "iota"),
+        };
+        write!(f, "{}", if f.alternate() { ascii } else { unicode })
+    }
+}
+
+/// The position of a redex in a term and the rule contracting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redex {
+    /// The child indices leading from the root of the term to the redex.
+    pub path: Vec<usize>,
+    pub rule: Rule,
+}
+
+impl Redex {
+    /// A redex at the root of a term.
+    pub fn root(rule: Rule) -> Self {
+        Self {
+            path: Vec::new(),
+            rule,
+        }
+    }
+
+    /// This redex, in a term which is the child at `index` of a larger term.
+    pub fn within(mut self, index: usize) -> Self {
+        self.path.insert(0, index);
+        self
+    }
+}
+
+/// A function contracting one redex of a term, if it has any.
+pub type Reducer<T> = fn(&T) -> Option<(T, Redex)>;
+
+/// A term reached during evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<T> {
+    pub term: T,
+    /// The redex contracted to get the next term, or `None` if this is the normal form.
+    pub redex: Option<Redex>,
+}
+
+impl<T: Subterms + fmt::Display> Step<T> {
+    /// The term with the redex contracted next highlighted, for printing to a terminal.
+    pub fn highlighted(&self) -> String {
+        match &self.redex {
+            Some(redex) => highlight(&self.term, &redex.path),
+            None => self.term.to_string(),
+        }
+    }
+}
+
+/// The terms reached while evaluating a term, ending with its normal form if it has one.
+///
+/// Reduction proceeds in phases, such as all β-reductions followed by all η-reductions, with
+/// each phase applied until the term has no more redexes for it.
+pub struct Trace<T> {
+    term: Option<T>,
+    phases: Vec<Reducer<T>>,
+    phase: usize,
+}
+
+impl<T> Trace<T> {
+    pub fn new(term: T, phases: Vec<Reducer<T>>) -> Self {
+        Self {
+            term: Some(term),
+            phases,
+            phase: 0,
+        }
+    }
+}
+
+impl<T> Iterator for Trace<T> {
+    type Item = Step<T>;
+
+    fn next(&mut self) -> Option<Step<T>> {
+        let term = self.term.take()?;
+        while let Some(reduce) = self.phases.get(self.phase) {
+            if let Some((next, redex)) = reduce(&term) {
+                self.term = Some(next);
+                return Some(Step {
+                    term,
+                    redex: Some(redex),
+                });
+            }
+            self.phase += 1;
+        }
+        Some(Step { term, redex: None })
+    }
+}
+
+impl<T: Subterms> Trace<T> {
+    /// The normal form this trace ends with, or the term reached when the fuel runs out.
+    pub fn run(self, fuel: Fuel) -> Result<T, OutOfFuel<T>> {
+        self.run_counting(fuel).map(|(term, _)| term)
+    }
+
+    /// Like [`Trace::run`], also returning the number of steps taken.
+    pub fn run_counting(self, fuel: Fuel) -> Result<(T, usize), OutOfFuel<T>> {
+        for (steps, step) in self.enumerate() {
+            if step.redex.is_none() {
+                return Ok((step.term, steps));
+            }
+            let Some(limit) = fuel.limit_reached(steps, &step.term) else {
+                continue;
+            };
+            return Err(OutOfFuel {
+                steps,
+                partial_term: step.term,
+                limit,
+            });
+        }
+        unreachable!("a trace should end with a normal form")
+    }
+}
+
+/// Limits on how much work evaluation may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fuel {
+    /// The most reduction steps to take.
+    pub steps: usize,
+    /// The largest size a term may grow to, if limited.
+    pub size: Option<usize>,
+}
+
+impl Fuel {
+    pub fn steps(steps: usize) -> Self {
+        Self { steps, size: None }
+    }
+
+    /// These limits, also stopping evaluation if the term grows past the given size.
+    pub fn with_size_limit(self, size: usize) -> Self {
+        Self {
+            size: Some(size),
+            ..self
+        }
+    }
+
+    /// The limit reached by evaluation at the given term after the given number of steps, if
+    /// any.
+    pub fn limit_reached<T: Subterms>(&self, steps: usize, term: &T) -> Option<Limit> {
+        self.limit_reached_with(steps, || term.size())
+    }
+
+    /// Like [`Fuel::limit_reached`], with the size of the term found by `size` if needed.
+    pub(crate) fn limit_reached_with(
+        &self,
+        steps: usize,
+        size: impl FnOnce() -> usize,
+    ) -> Option<Limit> {
+        if self.size.is_some_and(|limit| size() > limit) {
+            Some(Limit::Size)
+        } else if steps >= self.steps {
+            Some(Limit::Steps)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<usize> for Fuel {
+    fn from(steps: usize) -> Self {
+        Self::steps(steps)
+    }
+}
+
+/// The limit in a [`Fuel`] that stopped evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Size,
+}
+
+/// Evaluation stopped before reaching a normal form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfFuel<T> {
+    /// The number of steps taken.
+    pub steps: usize,
+    /// The term reached when evaluation stopped.
+    pub partial_term: T,
+    pub limit: Limit,
+}
+
+impl<T> fmt::Display for OutOfFuel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            Limit::Steps => write!(f, "no normal form after {} steps", self.steps),
+            Limit::Size => write!(f, "term grew too large after {} steps", self.steps),
+        }
+    }
+}
+
+impl<T: fmt::Debug> error::Error for OutOfFuel<T> {}
+
+/// Terms whose subterms can be addressed by the paths in a [`Redex`].
+pub trait Subterms: Sized {
+    /// The number of child indices this term has, some of which may be unused.
+    fn arity(&self) -> usize;
+
+    /// The child at the given index, if there is one.
+    fn child(&self, index: usize) -> Option<&Self>;
+
+    /// This term with the child at the given index replaced.
+    fn with_child(&self, index: usize, child: Self) -> Self;
+
+    /// A variable with the given name.
+    fn variable(name: String) -> Self;
+
+    /// The number of subterms of this term, including itself.
+    fn size(&self) -> usize {
+        let children = (0..self.arity()).filter_map(|i| self.child(i));
+        1 + children.map(Self::size).sum::<usize>()
+    }
+
+    /// The subterm at the given path, if there is one.
+    fn subterm(&self, path: &[usize]) -> Option<&Self> {
+        path.iter().try_fold(self, |t, &i| t.child(i))
+    }
+
+    /// This term with the subterm at the given path replaced, if there is one.
+    fn replace(&self, path: &[usize], with: Self) -> Option<Self> {
+        match path.split_first() {
+            None => Some(with),
+            Some((&i, rest)) => {
+                let child = self.child(i)?.replace(rest, with)?;
+                Some(self.with_child(i, child))
+            }
+        }
+    }
+}
+
+/// A variable name no parser accepts, used to find where a subterm is printed.
+const MARKER: &str = "\u{fffc}";
+
+/// The term as printed by `Display`, with the subterm at `path` in colour.
+///
+/// The subterm is found by printing the term again with a marker in its place, so any
+/// parentheses around it are highlighted too. If the rest of the term is printed differently
+/// around the marker, nothing is highlighted.
+pub fn highlight<T: Subterms + fmt::Display>(term: &T, path: &[usize]) -> String {
+    let whole = term.to_string();
+    let Some(marked) = term.replace(path, T::variable(MARKER.to_owned())) else {
+        return whole;
+    };
+    let marked = marked.to_string();
+    let Some((before, after)) = marked.split_once(MARKER) else {
+        return whole;
+    };
+    if whole.len() < before.len() + after.len()
+        || !whole.starts_with(before)
+        || !whole.ends_with(after)
+    {
+        return whole;
+    }
+    let redex = &whole[before.len()..whole.len() - after.len()];
+    format!("{}{}{}", before, style(redex).red().bold(), after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::untyped::parser::parse;
+
+    #[test]
+    fn highlights_parenthesised_redex() {
+        console::set_colors_enabled(true);
+        let t = parse("f ((λx. x) y) z").unwrap();
+        let step = t.reduction_trace().next().unwrap();
+        let path = step.redex.as_ref().map(|r| r.path.clone());
+        assert_eq!(path, Some(vec![0, 1]));
+        let redex = style("((λx. x) y)").red().bold();
+        assert_eq!(step.highlighted(), format!("f {} z", redex));
+        assert_eq!(
+            console::strip_ansi_codes(&step.highlighted()),
+            "f ((λx. x) y) z"
+        );
+    }
+
+    #[test]
+    fn trace_ends_with_normal_form() {
+        let t = parse("(λx. x) g (λz. f z)").unwrap();
+        let steps: Vec<_> = t.reduction_trace().collect();
+        let redexes: Vec<_> = steps.iter().map(|s| s.redex.clone()).collect();
+        assert_eq!(
+            redexes,
+            vec![
+                Some(Redex::root(Rule::Beta).within(0)),
+                Some(Redex::root(Rule::Eta).within(1)),
+                None
+            ]
+        );
+        assert_eq!(steps.last().unwrap().term, t.evaluate());
+    }
+
+    #[test]
+    fn fuel_limits_steps_and_size() {
+        let omega = parse("(λx. x x) (λx. x x)").unwrap();
+        let err = omega.evaluate_with_fuel(10).unwrap_err();
+        assert_eq!((err.steps, err.limit), (10, Limit::Steps));
+        assert_eq!(err.partial_term, omega);
+
+        let growing = parse("(λx. x x x) (λx. x x x)").unwrap();
+        let err = growing
+            .evaluate_with_fuel(Fuel::steps(1000).with_size_limit(50))
+            .unwrap_err();
+        assert_eq!(err.limit, Limit::Size);
+        assert!(err.partial_term.size() > 50);
+
+        let id = parse("(λx. x) y").unwrap();
+        assert_eq!(id.evaluate_with_fuel(1), Ok(parse("y").unwrap()));
+    }
+}
@@ -0,0 +1,345 @@
+//! A hash-consed store of untyped terms, in which equal subterms are only stored once.
+//!
+//! Terms in an [`Arena`] are referred to by [`TermId`]s. Building a term that is already in the
+//! arena returns the id it already has, so terms with the same structure, including the names
+//! of their binders, have equal ids and can be compared and hashed in constant time. The free
+//! variables and size of each term are cached when it is added.
+//!
+//! Substitution and reduction are memoised by id, so a subterm occurring in many places is only
+//! processed once, and the result is shared in the same way. This keeps terms such as Church
+//! numerals small even when their trees are huge. Nothing is ever removed from an arena, so it
+//! should be dropped once it is no longer needed.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::Term;
+use crate::fresh_var;
+use crate::trace::{Fuel, OutOfFuel};
+
+/// A term in an [`Arena`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TermId(usize);
+
+/// A term whose children are in an [`Arena`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Variable(String),
+    Abstraction(String, TermId),
+    Application(TermId, TermId),
+}
+
+#[derive(Debug)]
+struct Entry {
+    node: Node,
+    /// Shared with a child when they have the same free variables.
+    free_vars: Rc<HashSet<String>>,
+    /// The size of the term as a tree, or `usize::MAX` if it is larger.
+    size: usize,
+}
+
+/// A store of terms, each of which is only stored once.
+#[derive(Debug, Default)]
+pub struct Arena {
+    entries: Vec<Entry>,
+    ids: HashMap<Node, TermId>,
+    /// The result of one step of normal order reduction, for each term reduced so far.
+    reducts: HashMap<TermId, Option<TermId>>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of distinct terms stored.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The id of the term built by `node`, adding it if it isn't already stored.
+    pub fn intern(&mut self, node: Node) -> TermId {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        let (free_vars, size) = match &node {
+            Node::Variable(x) => (Rc::new(HashSet::from([x.clone()])), 1),
+            Node::Abstraction(x, body) => {
+                let body = &self.entries[body.0];
+                let free_vars = if body.free_vars.contains(x) {
+                    let mut vars = (*body.free_vars).clone();
+                    vars.remove(x);
+                    Rc::new(vars)
+                } else {
+                    body.free_vars.clone()
+                };
+                (free_vars, body.size.saturating_add(1))
+            }
+            Node::Application(t, u) => {
+                let (t, u) = (&self.entries[t.0], &self.entries[u.0]);
+                let free_vars = if t.free_vars.is_superset(&u.free_vars) {
+                    t.free_vars.clone()
+                } else if u.free_vars.is_superset(&t.free_vars) {
+                    u.free_vars.clone()
+                } else {
+                    Rc::new(&*t.free_vars | &*u.free_vars)
+                };
+                (free_vars, t.size.saturating_add(u.size).saturating_add(1))
+            }
+        };
+        let id = TermId(self.entries.len());
+        self.entries.push(Entry {
+            node: node.clone(),
+            free_vars,
+            size,
+        });
+        self.ids.insert(node, id);
+        id
+    }
+
+    pub fn variable(&mut self, x: impl Into<String>) -> TermId {
+        self.intern(Node::Variable(x.into()))
+    }
+
+    pub fn abstraction(&mut self, x: impl Into<String>, body: TermId) -> TermId {
+        self.intern(Node::Abstraction(x.into(), body))
+    }
+
+    pub fn application(&mut self, t: TermId, u: TermId) -> TermId {
+        self.intern(Node::Application(t, u))
+    }
+
+    /// Add a term, sharing its equal subterms.
+    pub fn insert(&mut self, term: &Term) -> TermId {
+        match term {
+            Term::Variable(x) => self.variable(x.as_str()),
+            Term::Abstraction(x, t) => {
+                let t = self.insert(t);
+                self.abstraction(x.as_str(), t)
+            }
+            Term::Application(t, u) => {
+                let (t, u) = (self.insert(t), self.insert(u));
+                self.application(t, u)
+            }
+        }
+    }
+
+    /// The term with the given id as a tree, which has [`Arena::size`] nodes.
+    pub fn term(&self, id: TermId) -> Term {
+        match self.node(id) {
+            Node::Variable(x) => Term::Variable(x.clone()),
+            Node::Abstraction(x, t) => Term::Abstraction(x.clone(), Box::new(self.term(*t))),
+            Node::Application(t, u) => {
+                Term::Application(Box::new(self.term(*t)), Box::new(self.term(*u)))
+            }
+        }
+    }
+
+    pub fn node(&self, id: TermId) -> &Node {
+        &self.entries[id.0].node
+    }
+
+    pub fn free_vars(&self, id: TermId) -> &HashSet<String> {
+        &self.entries[id.0].free_vars
+    }
+
+    /// The size of the term as a tree, which may be far more than the space it takes up here.
+    /// Sizes too large to represent are `usize::MAX`.
+    pub fn size(&self, id: TermId) -> usize {
+        self.entries[id.0].size
+    }
+
+    /// Substitute `with` for the free variable `x` in `id`, avoiding capture.
+    ///
+    /// Binders are only renamed when they would capture a variable, and subterms not mentioning
+    /// `x` are kept as they are.
+    pub fn substitute(&mut self, id: TermId, x: &str, with: TermId) -> TermId {
+        self.substitute_memoised(id, x, with, &mut HashMap::new())
+    }
+
+    fn substitute_memoised(
+        &mut self,
+        id: TermId,
+        x: &str,
+        with: TermId,
+        done: &mut HashMap<TermId, TermId>,
+    ) -> TermId {
+        if !self.free_vars(id).contains(x) {
+            return id;
+        }
+        if let Some(&result) = done.get(&id) {
+            return result;
+        }
+        let result = match self.node(id).clone() {
+            Node::Variable(_) => with,
+            Node::Abstraction(y, body) if self.free_vars(with).contains(&y) => {
+                let mut used = self.free_vars(body).clone();
+                used.extend(self.free_vars(with).iter().cloned());
+                used.insert(x.to_owned());
+                let w = fresh_var(&used);
+                let var = self.variable(w.as_str());
+                let body = self.substitute(body, &y, var);
+                let body = self.substitute_memoised(body, x, with, done);
+                self.abstraction(w, body)
+            }
+            Node::Abstraction(y, body) => {
+                let body = self.substitute_memoised(body, x, with, done);
+                self.abstraction(y, body)
+            }
+            Node::Application(t, u) => {
+                let t = self.substitute_memoised(t, x, with, done);
+                let u = self.substitute_memoised(u, x, with, done);
+                self.application(t, u)
+            }
+        };
+        done.insert(id, result);
+        result
+    }
+
+    /// Beta-reduce the left-outermost redex if one exists ("normal order").
+    pub fn beta_reduce_lazy(&mut self, id: TermId) -> Option<TermId> {
+        if let Some(&reduct) = self.reducts.get(&id) {
+            return reduct;
+        }
+        let reduct = match self.node(id).clone() {
+            Node::Variable(_) => None,
+            Node::Abstraction(x, body) => self
+                .beta_reduce_lazy(body)
+                .map(|body| self.abstraction(x, body)),
+            Node::Application(t, u) => match self.node(t).clone() {
+                Node::Abstraction(x, body) => Some(self.substitute(body, &x, u)),
+                _ => match self.beta_reduce_lazy(t) {
+                    Some(t) => Some(self.application(t, u)),
+                    None => self.beta_reduce_lazy(u).map(|u| self.application(t, u)),
+                },
+            },
+        };
+        self.reducts.insert(id, reduct);
+        reduct
+    }
+
+    /// Beta-reduce to normal form, in normal order. This may not halt.
+    pub fn evaluate(&mut self, id: TermId) -> TermId {
+        let mut id = id;
+        while let Some(next) = self.beta_reduce_lazy(id) {
+            id = next;
+        }
+        id
+    }
+
+    /// Beta-reduce to normal form, in normal order, or give up once the fuel runs out.
+    ///
+    /// The size limit applies to the size of the term as a tree.
+    pub fn evaluate_with_fuel(
+        &mut self,
+        id: TermId,
+        fuel: impl Into<Fuel>,
+    ) -> Result<TermId, OutOfFuel<TermId>> {
+        let fuel = fuel.into();
+        let mut id = id;
+        for steps in 0.. {
+            let Some(next) = self.beta_reduce_lazy(id) else {
+                return Ok(id);
+            };
+            if let Some(limit) = fuel.limit_reached_with(steps, || self.size(id)) {
+                return Err(OutOfFuel {
+                    steps,
+                    partial_term: id,
+                    limit,
+                });
+            }
+            id = next;
+        }
+        unreachable!("there should be a step count before overflow")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::Strategy;
+    use crate::untyped::{self, parser::parse};
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn insert_and_extract_are_inverse(t in any::<Term>()) {
+            let mut arena = Arena::new();
+            let id = arena.insert(&t);
+            prop_assert_eq!(arena.term(id).to_string(), t.to_string());
+            prop_assert_eq!(arena.insert(&t), id);
+        }
+
+        #[test]
+        fn free_vars_are_cached(t in any::<Term>()) {
+            let mut arena = Arena::new();
+            let id = arena.insert(&t);
+            prop_assert_eq!(arena.free_vars(id), &t.free_vars());
+        }
+
+        #[test]
+        fn evaluation_agrees_with_normal_order(t in any::<Term>()) {
+            let fuel = Fuel::steps(200).with_size_limit(2000);
+            let mut arena = Arena::new();
+            let id = arena.insert(&t);
+            match Strategy::NormalOrder.evaluate(&t, fuel) {
+                Ok((normal_form, _)) => {
+                    let id = arena.evaluate_with_fuel(id, fuel).unwrap();
+                    prop_assert_eq!(arena.term(id), normal_form);
+                }
+                Err(err) => {
+                    let arena_err = arena.evaluate_with_fuel(id, fuel).unwrap_err();
+                    prop_assert_eq!((arena_err.steps, arena_err.limit), (err.steps, err.limit));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn equal_subterms_are_shared() {
+        let mut arena = Arena::new();
+        let id = arena.insert(&parse("(λx. x) (λx. x)").unwrap());
+        assert_eq!(arena.len(), 3);
+        let Node::Application(t, u) = arena.node(id) else {
+            panic!("expected an application")
+        };
+        assert_eq!(t, u);
+    }
+
+    #[test]
+    fn substitution_keeps_sharing() {
+        let mut arena = Arena::new();
+        let id = arena.insert(&parse("(λx. x x) (f y)").unwrap());
+        let reduct = arena.beta_reduce_lazy(id).unwrap();
+        let f_y = arena.insert(&parse("f y").unwrap());
+        assert_eq!(arena.node(reduct), &Node::Application(f_y, f_y));
+    }
+
+    #[test]
+    fn substitution_avoids_capture() {
+        let mut arena = Arena::new();
+        let id = arena.insert(&parse("(λx. λy. x y) y").unwrap());
+        let reduct = arena.beta_reduce_lazy(id).unwrap();
+        assert_eq!(arena.term(reduct), parse("λz. y z").unwrap());
+    }
+
+    #[test]
+    fn church_exponentiation() {
+        let mut arena = Arena::new();
+        // 2 ^ 3 = 8, and then 2 ^ 8 = 256
+        let source = "(λtwo. λthree. three two two) (λf. λx. f (f x)) (λf. λx. f (f (f x)))";
+        let id = arena.insert(&parse(source).unwrap());
+        let id = arena.evaluate(id);
+        let n = (0..256).fold(untyped::variable("x"), |t, _| {
+            untyped::application(untyped::variable("f"), t)
+        });
+        assert_eq!(
+            arena.term(id),
+            untyped::abstraction("f", untyped::abstraction("x", n))
+        );
+    }
+}
@@ -176,6 +176,14 @@ pub fn compile_toplevel(term: &Term) -> target::Ast {
     out
 }
 
+/// Compile a program whose main term is an abstraction. The definitions are bound inside
+/// the main function, so each is built once per call rather than copied into every use.
+pub fn compile_program(program: &Program) -> Option<target::Ast> {
+    program
+        .elaborate_toplevel(Elaboration::Application)
+        .map(|t| compile_toplevel(&t))
+}
+
 fn compile_impl(
     term: &Term,
     ast: &mut target::Ast,
@@ -1,6 +1,8 @@
-use std::collections::{HashSet, VecDeque};
-use std::fmt;
+use std::collections::HashSet;
+use std::{error, fmt};
 
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
 #[cfg(test)]
 use proptest::prelude::*;
 
@@ -59,27 +61,30 @@ impl Term {
         }
     }
 
+    /// Substitute `arg` for the variable bound by an abstraction with this body.
+    fn instantiate(&self, arg: &Term) -> Self {
+        fn shift1(i: u64) -> Term {
+            Term::Variable(i + 1)
+        }
+        fn shiftm1(i: u64) -> Term {
+            Term::Variable(i - 1)
+        }
+        self.substitute(&|i| {
+            if i == 0 {
+                arg.substitute(&shift1)
+            } else {
+                Term::Variable(i)
+            }
+        })
+        .substitute(&shiftm1)
+    }
+
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(b) = t.as_ref() {
-                    fn shift1(i: u64) -> Term {
-                        Term::Variable(i + 1)
-                    }
-                    fn shiftm1(i: u64) -> Term {
-                        Term::Variable(i - 1)
-                    }
-                    Some(
-                        b.substitute(&|i| {
-                            if i == 0 {
-                                u.substitute(&shift1)
-                            } else {
-                                Term::Variable(i)
-                            }
-                        })
-                        .substitute(&shiftm1),
-                    )
+                    Some(b.instantiate(u))
                 } else if let Some(t2) = t.beta_reduce_lazy() {
                     Some(Self::Application(
                         Box::new(t2),
@@ -97,84 +102,74 @@ impl Term {
         }
     }
 
-    // /// Beta-reduce all redexes simultaneously if any exist.
-    // pub fn parallel_reduct(&self) -> Option<Self> {
-    //     match self {
-    //         Self::Application(t, u) => {
-    //             if let Self::Abstraction(b) = t.as_ref() {
-    //                 let b2 = b.parallel_reduct().unwrap_or_else(|| b.as_ref().clone());
-    //                 let u2 = u.parallel_reduct().unwrap_or_else(|| u.as_ref().clone());
-    //                 Some(b2.substitute(&|i| {
-    //                     if i == 0 {
-    //                         u2.clone()
-    //                     } else {
-    //                         Term::Variable(i)
-    //                     }
-    //                 }))
-    //             } else {
-    //                 match (t.parallel_reduct(), u.parallel_reduct()) {
-    //                     (None, None) => None,
-    //                     (t2, u2) => {
-    //                         let t2 = t2.unwrap_or_else(|| t.as_ref().clone());
-    //                         let u2 = u2.unwrap_or_else(|| u.as_ref().clone());
-
-    //                         Some(Self::Application(Box::new(t2), Box::new(u2)))
-    //                     }
-    //                 }
-    //             }
-    //         }
-    //         Self::Abstraction(t) => t
-    //             .parallel_reduct()
-    //             .map(|t2| Self::Abstraction(Box::new(t2))),
-    //         _ => None,
-    //     }
-    // }
-
-    // /// Eta-reduce the left-outermost simplification if one exists.
-    // pub fn eta_reduce_lazy(&self) -> Option<Self> {
-    //     match self {
-    //         Self::Abstraction(t) => {
-    //             if let Self::Application(f, u) = t.as_ref() {
-    //                 if let Self::Variable(y) = u.as_ref() {
-    //                     if *y == 1 && !f.free_vars().contains(&1) {
-    //                         return Some(f.substitute(&|i| Term::Variable(i - 1)));
-    //                     }
-    //                 }
-    //             }
-    //             t.eta_reduce_lazy()
-    //                 .map(|u2| Self::Abstraction(Box::new(u2)))
-    //         }
-    //         Self::Application(t, u) => {
-    //             if let Some(t2) = t.eta_reduce_lazy() {
-    //                 Some(Self::Application(
-    //                     Box::new(t2),
-    //                     Box::new(u.as_ref().clone()),
-    //                 ))
-    //             } else if let Some(u2) = u.eta_reduce_lazy() {
-    //                 Some(Self::Application(
-    //                     Box::new(t.as_ref().clone()),
-    //                     Box::new(u2),
-    //                 ))
-    //             } else {
-    //                 None
-    //             }
-    //         }
-    //         _ => None,
-    //     }
-    // }
-
-    // /// Fully beta- and eta-reduce the term. Since the lambda calculus is Turing-complete,
-    // /// this may not halt.
-    // pub fn evaluate(&self) -> Self {
-    //     let mut res = self.clone();
-    //     while let Some(new_res) = res.beta_reduce_lazy() {
-    //         res = new_res;
-    //     }
-    //     while let Some(new_res) = res.eta_reduce_lazy() {
-    //         res = new_res;
-    //     }
-    //     res
-    // }
+    /// Beta-reduce all redexes simultaneously if any exist.
+    pub fn parallel_reduct(&self) -> Option<Self> {
+        match self {
+            Self::Application(t, u) => {
+                if let Self::Abstraction(b) = t.as_ref() {
+                    let b2 = b.parallel_reduct().unwrap_or_else(|| b.as_ref().clone());
+                    let u2 = u.parallel_reduct().unwrap_or_else(|| u.as_ref().clone());
+                    Some(b2.instantiate(&u2))
+                } else {
+                    match (t.parallel_reduct(), u.parallel_reduct()) {
+                        (None, None) => None,
+                        (t2, u2) => {
+                            let t2 = t2.unwrap_or_else(|| t.as_ref().clone());
+                            let u2 = u2.unwrap_or_else(|| u.as_ref().clone());
+
+                            Some(Self::Application(Box::new(t2), Box::new(u2)))
+                        }
+                    }
+                }
+            }
+            Self::Abstraction(t) => t
+                .parallel_reduct()
+                .map(|t2| Self::Abstraction(Box::new(t2))),
+            _ => None,
+        }
+    }
+
+    /// Eta-reduce the left-outermost simplification if one exists.
+    pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        match self {
+            Self::Abstraction(t) => {
+                if let Self::Application(f, u) = t.as_ref() {
+                    if let Self::Variable(0) = u.as_ref() {
+                        if !f.free_vars().contains(&0) {
+                            return Some(f.substitute(&|i| Term::Variable(i - 1)));
+                        }
+                    }
+                }
+                t.eta_reduce_lazy()
+                    .map(|u2| Self::Abstraction(Box::new(u2)))
+            }
+            Self::Application(t, u) => {
+                if let Some(t2) = t.eta_reduce_lazy() {
+                    Some(Self::Application(
+                        Box::new(t2),
+                        Box::new(u.as_ref().clone()),
+                    ))
+                } else {
+                    u.eta_reduce_lazy()
+                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Fully beta- and eta-reduce the term. Since the lambda calculus is Turing-complete,
+    /// this may not halt.
+    pub fn evaluate(&self) -> Self {
+        let mut res = self.clone();
+        while let Some(new_res) = res.beta_reduce_lazy() {
+            res = new_res;
+        }
+        while let Some(new_res) = res.eta_reduce_lazy() {
+            res = new_res;
+        }
+        res
+    }
 }
 
 #[cfg(test)]
@@ -232,10 +227,11 @@ impl Arbitrary for Term {
 
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Variable(x) => (x + 1).fmt(f),
             // Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(t) => write!(f, "λ {}", t),
+            Self::Abstraction(t) => write!(f, "{} {}", n.lambda(), n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -245,46 +241,135 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Variable(x) => pretty::atom((x + 1).to_string()),
+            Self::Abstraction(t) => pretty::binder(Doc::text(n.lambda()), t.to_doc(n)),
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                loop {
+                    head = match head {
+                        Self::Application(t, u) => {
+                            args.push(at(u.as_ref(), n, Prec::Atom));
+                            t
+                        }
+                        _ => break,
+                    };
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
+    }
+}
+
+impl Term {
+    /// This term with named variables, where `context` names its free variables as in
+    /// [`super::Term::to_de_bruijn`].
+    ///
+    /// Each binder takes the name of the binder in the same place in `like`, if there is one and
+    /// the name isn't already in scope, and is otherwise given a fresh name.
+    pub fn to_named(
+        &self,
+        context: &[String],
+        like: Option<&super::Term>,
+    ) -> Result<super::Term, UnboundIndex> {
+        to_named(self, like, &mut context.to_vec())
     }
 }
 
+/// Panics if the term isn't closed; [`Term::to_named`] handles free variables.
 impl From<Term> for super::Term {
     fn from(val: Term) -> Self {
-        // TODO: handle non-closed terms
-        give_names(val, &VecDeque::new())
+        val.to_named(&[], None)
+            .expect("term should be closed to be given names")
     }
 }
 
-fn give_names(val: Term, to: &VecDeque<String>) -> super::Term {
+fn to_named(
+    val: &Term,
+    like: Option<&super::Term>,
+    scope: &mut Vec<String>,
+) -> Result<super::Term, UnboundIndex> {
     match val {
-        Term::Variable(v) => super::Term::Variable(to[v as usize].clone()),
+        Term::Variable(v) => match scope.len().checked_sub(*v as usize + 1) {
+            Some(i) => Ok(super::Term::Variable(scope[i].clone())),
+            None => Err(UnboundIndex(*v - scope.len() as u64)),
+        },
         Term::Abstraction(t) => {
-            let new_var = super::fresh_var(&to.iter().cloned().collect());
-            let mut inner = to.clone();
-            inner.push_front(new_var.clone());
-            super::Term::Abstraction(new_var, Box::new(give_names(*t, &inner)))
+            let (name, body) = match like {
+                Some(super::Term::Abstraction(x, body)) if !scope.contains(x) => {
+                    (x.clone(), Some(body.as_ref()))
+                }
+                Some(super::Term::Abstraction(_, body)) => (
+                    super::fresh_var(&scope.iter().cloned().collect()),
+                    Some(body.as_ref()),
+                ),
+                _ => (super::fresh_var(&scope.iter().cloned().collect()), None),
+            };
+            scope.push(name);
+            let t = to_named(t, body, scope);
+            let name = scope.pop().expect("binder should be in scope");
+            Ok(super::Term::Abstraction(name, Box::new(t?)))
         }
         Term::Application(t, u) => {
-            super::Term::Application(Box::new(give_names(*t, to)), Box::new(give_names(*u, to)))
+            let (t_like, u_like) = match like {
+                Some(super::Term::Application(t, u)) => (Some(t.as_ref()), Some(u.as_ref())),
+                _ => (None, None),
+            };
+            Ok(super::Term::Application(
+                Box::new(to_named(t, t_like, scope)?),
+                Box::new(to_named(u, u_like, scope)?),
+            ))
         }
     }
 }
 
+/// A named variable which is neither bound nor in the context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnboundVariable(pub String);
+
+impl fmt::Display for UnboundVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unbound variable {}", self.0)
+    }
+}
+
+impl error::Error for UnboundVariable {}
+
+/// A De Bruijn index pointing past the end of the context, by how far past it points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnboundIndex(pub u64);
+
+impl fmt::Display for UnboundIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unbound variable {} outside the context", self.0 + 1)
+    }
+}
+
+impl error::Error for UnboundIndex {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::trace::Fuel;
     use crate::untyped;
 
     #[test]
@@ -333,6 +418,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn open_terms_convert_with_context() {
+        let t = untyped::parser::parse("λf. f (g x)").unwrap();
+        let context = ["x".to_owned(), "g".to_owned()];
+        let d = t.to_de_bruijn(&context).unwrap();
+        assert_eq!(d.to_string(), "λ 1 (2 3)");
+        assert_eq!(
+            d.to_named(&context, Some(&t)).unwrap().to_string(),
+            "λf. f (g x)"
+        );
+        assert_eq!(
+            d.to_named(&context, None).unwrap().to_string(),
+            "λx0. x0 (g x)"
+        );
+        assert_eq!(
+            t.to_de_bruijn(&context[..1]),
+            Err(UnboundVariable("g".into()))
+        );
+        assert_eq!(d.to_named(&context[..1], None), Err(UnboundIndex(0)));
+    }
+
+    #[test]
+    fn names_are_reused_unless_they_would_capture() {
+        let t = untyped::parser::parse("λx. λy. x y").unwrap();
+        let like = untyped::parser::parse("λy. λy. y").unwrap();
+        let d = Term::try_from(t.clone()).unwrap();
+        let named = d.to_named(&[], Some(&like)).unwrap();
+        assert_eq!(named.to_string(), "λy. λx0. y x0");
+        assert_eq!(named, t);
+    }
+
     proptest! {
         #[test]
         fn conversion_matches(f in any::<Term>()) {
@@ -353,5 +469,37 @@ mod tests {
                 }
             }
         }
+
+        #[test]
+        fn parallel_reduct_matches(f in any::<Term>()) {
+            let f2 = untyped::Term::from(f.clone());
+            let reduct = f2.parallel_reduct().map(|t| Term::try_from(t).unwrap());
+            prop_assert_eq!(reduct, f.parallel_reduct());
+        }
+
+        #[test]
+        fn eta_reduce_matches(f in any::<Term>()) {
+            let f2 = untyped::Term::from(f.clone());
+            let reduct = f2.eta_reduce_lazy().map(|t| Term::try_from(t).unwrap());
+            prop_assert_eq!(reduct, f.eta_reduce_lazy());
+        }
+
+        #[test]
+        fn evaluate_matches(f in any::<Term>()) {
+            let f2 = untyped::Term::from(f.clone());
+            if let Ok(normal_form) = f2.evaluate_with_fuel(Fuel::steps(200).with_size_limit(2000)) {
+                prop_assert_eq!(Term::try_from(normal_form).unwrap(), f.evaluate());
+            }
+        }
+
+        #[test]
+        fn open_conversion_round_trips(f in any::<untyped::Term>()) {
+            let mut context: Vec<_> = f.free_vars().into_iter().collect();
+            context.sort();
+            let d = f.to_de_bruijn(&context).unwrap();
+            let named = d.to_named(&context, Some(&f)).unwrap();
+            prop_assert_eq!(&named, &f);
+            prop_assert_eq!(named.free_vars(), f.free_vars());
+        }
     }
 }
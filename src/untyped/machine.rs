@@ -0,0 +1,460 @@
+//! Abstract machines evaluating De Bruijn terms with closures and environments rather than
+//! substitution.
+//!
+//! The [`Krivine`] machine evaluates by name, the [`Lazy`] machine by need and the [`Cek`]
+//! machine by value, all to weak head normal form. Their states have public fields so that
+//! they can be inspected between calls to `step`, and any state can be read back into the
+//! term it stands for.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use super::de_bruijn::Term;
+
+/// A term together with the values of its free variables.
+#[derive(Debug, Clone)]
+pub struct Closure<'a> {
+    pub term: &'a Term,
+    pub env: Env<'a>,
+}
+
+impl<'a> Closure<'a> {
+    /// The term with the values of its variables substituted in.
+    pub fn read_back(&self) -> Term {
+        let depth = self.env.len() as u64;
+        self.term.substitute(&|i| match self.env.get(i) {
+            Some(c) => c.borrow().read_back(),
+            None => Term::Variable(i - depth),
+        })
+    }
+}
+
+/// A closure shared by every variable bound to it, so that the [`Lazy`] machine can overwrite
+/// it with its weak head normal form once that has been found.
+pub type Thunk<'a> = Rc<RefCell<Closure<'a>>>;
+
+/// The values of the variables bound around a term, innermost first.
+#[derive(Clone, Default)]
+pub struct Env<'a>(Option<Rc<(Thunk<'a>, Env<'a>)>>);
+
+impl<'a> Env<'a> {
+    /// This environment with a value for a newly bound variable.
+    pub fn bind(&self, value: Closure<'a>) -> Self {
+        self.share(Rc::new(RefCell::new(value)))
+    }
+
+    /// This environment with a newly bound variable sharing its value with other variables.
+    pub fn share(&self, value: Thunk<'a>) -> Self {
+        Self(Some(Rc::new((value, self.clone()))))
+    }
+
+    /// The value of the variable with the given index.
+    pub fn get(&self, index: u64) -> Option<&Thunk<'a>> {
+        self.iter().nth(index as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Thunk<'a>> {
+        std::iter::successors(self.0.as_deref(), |(_, rest)| rest.0.as_deref()).map(|(c, _)| c)
+    }
+}
+
+impl fmt::Debug for Env<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.iter().map(|c| c.borrow()))
+            .finish()
+    }
+}
+
+/// A call-by-name machine, which pushes arguments on a stack unevaluated and pops them off
+/// when it reaches an abstraction.
+#[derive(Debug, Clone)]
+pub struct Krivine<'a> {
+    /// The closure being evaluated.
+    pub closure: Closure<'a>,
+    /// The arguments it is applied to, the first argument last.
+    pub stack: Vec<Closure<'a>>,
+}
+
+impl<'a> Krivine<'a> {
+    pub fn new(term: &'a Term) -> Self {
+        Self {
+            closure: Closure {
+                term,
+                env: Env::default(),
+            },
+            stack: Vec::new(),
+        }
+    }
+
+    /// Take one step, returning `false` if the machine has halted instead.
+    ///
+    /// The machine halts at an abstraction with no arguments, or at a free variable.
+    pub fn step(&mut self) -> bool {
+        let Closure { term, env } = &self.closure;
+        self.closure = match term {
+            Term::Variable(i) => match env.get(*i) {
+                Some(c) => c.borrow().clone(),
+                None => return false,
+            },
+            Term::Application(t, u) => {
+                self.stack.push(Closure {
+                    term: u,
+                    env: env.clone(),
+                });
+                Closure {
+                    term: t,
+                    env: env.clone(),
+                }
+            }
+            Term::Abstraction(b) => match self.stack.pop() {
+                Some(arg) => Closure {
+                    term: b,
+                    env: env.bind(arg),
+                },
+                None => return false,
+            },
+        };
+        true
+    }
+
+    /// Run the machine until it halts, returning the weak head normal form reached. This may
+    /// not halt.
+    pub fn run(mut self) -> Term {
+        while self.step() {}
+        self.read_back()
+    }
+
+    /// The term this state stands for.
+    pub fn read_back(&self) -> Term {
+        self.stack
+            .iter()
+            .rev()
+            .fold(self.closure.read_back(), |t, arg| {
+                Term::Application(Box::new(t), Box::new(arg.read_back()))
+            })
+    }
+}
+
+/// An entry on the stack of a [`Lazy`] machine.
+#[derive(Debug, Clone)]
+pub enum Entry<'a> {
+    /// An argument, shared with every variable it is bound to.
+    Argument(Thunk<'a>),
+    /// A variable's value, to overwrite with the weak head normal form of the closure being
+    /// evaluated once it is reached.
+    Update(Thunk<'a>),
+}
+
+/// A call-by-need machine, which is a Krivine machine sharing each argument between the
+/// variables it is bound to, so that it is evaluated at most once.
+#[derive(Debug, Clone)]
+pub struct Lazy<'a> {
+    /// The closure being evaluated.
+    pub closure: Closure<'a>,
+    /// The arguments it is applied to, the first argument last, and the variables whose value
+    /// it is.
+    pub stack: Vec<Entry<'a>>,
+    /// The number of β-redexes contracted so far.
+    pub contractions: usize,
+}
+
+impl<'a> Lazy<'a> {
+    pub fn new(term: &'a Term) -> Self {
+        Self {
+            closure: Closure {
+                term,
+                env: Env::default(),
+            },
+            stack: Vec::new(),
+            contractions: 0,
+        }
+    }
+
+    /// Take one step, returning `false` if the machine has halted instead.
+    ///
+    /// The machine halts at an abstraction with no arguments, or at a free variable.
+    pub fn step(&mut self) -> bool {
+        let Closure { term, env } = &self.closure;
+        self.closure = match term {
+            Term::Variable(i) => match env.get(*i) {
+                Some(c) => {
+                    let value = c.borrow().clone();
+                    // Values need no updating.
+                    if !matches!(value.term, Term::Abstraction(_)) {
+                        self.stack.push(Entry::Update(c.clone()));
+                    }
+                    value
+                }
+                None => return false,
+            },
+            Term::Application(t, u) => {
+                let arg = Closure {
+                    term: u,
+                    env: env.clone(),
+                };
+                self.stack.push(Entry::Argument(Rc::new(RefCell::new(arg))));
+                Closure {
+                    term: t,
+                    env: env.clone(),
+                }
+            }
+            Term::Abstraction(b) => match self.stack.pop() {
+                Some(Entry::Argument(arg)) => {
+                    self.contractions += 1;
+                    Closure {
+                        term: b,
+                        env: env.share(arg),
+                    }
+                }
+                Some(Entry::Update(c)) => {
+                    *c.borrow_mut() = self.closure.clone();
+                    return true;
+                }
+                None => return false,
+            },
+        };
+        true
+    }
+
+    /// Run the machine until it halts, returning the weak head normal form reached. This may
+    /// not halt.
+    pub fn run(mut self) -> Term {
+        while self.step() {}
+        self.read_back()
+    }
+
+    /// The term this state stands for.
+    pub fn read_back(&self) -> Term {
+        self.stack
+            .iter()
+            .rev()
+            .fold(self.closure.read_back(), |t, entry| match entry {
+                Entry::Argument(arg) => {
+                    Term::Application(Box::new(t), Box::new(arg.borrow().read_back()))
+                }
+                Entry::Update(_) => t,
+            })
+    }
+}
+
+/// What a [`Cek`] machine is doing.
+#[derive(Debug, Clone)]
+pub enum Control<'a> {
+    /// Evaluating a term.
+    Eval(Closure<'a>),
+    /// Returning a value, which is the closure of an abstraction, to the continuation.
+    Return(Closure<'a>),
+}
+
+/// Work left to do once the current value has been found.
+#[derive(Debug, Clone)]
+pub enum Frame<'a> {
+    /// Evaluate the argument of an application whose function is the current value.
+    Argument(Closure<'a>),
+    /// Apply this function value to the current value.
+    Function(Closure<'a>),
+}
+
+/// A call-by-value machine, which evaluates the function and then the argument of an
+/// application before substituting, using an explicit continuation.
+#[derive(Debug, Clone)]
+pub struct Cek<'a> {
+    pub control: Control<'a>,
+    /// The frames of the continuation, innermost last.
+    pub continuation: Vec<Frame<'a>>,
+}
+
+impl<'a> Cek<'a> {
+    pub fn new(term: &'a Term) -> Self {
+        Self {
+            control: Control::Eval(Closure {
+                term,
+                env: Env::default(),
+            }),
+            continuation: Vec::new(),
+        }
+    }
+
+    /// Take one step, returning `false` if the machine has halted instead.
+    ///
+    /// The machine halts when it returns a value to an empty continuation, or at a free
+    /// variable.
+    pub fn step(&mut self) -> bool {
+        self.control = match &self.control {
+            Control::Eval(Closure { term, env }) => match term {
+                Term::Variable(i) => match env.get(*i) {
+                    Some(v) => Control::Return(v.borrow().clone()),
+                    None => return false,
+                },
+                Term::Abstraction(_) => Control::Return(Closure {
+                    term,
+                    env: env.clone(),
+                }),
+                Term::Application(t, u) => {
+                    self.continuation.push(Frame::Argument(Closure {
+                        term: u,
+                        env: env.clone(),
+                    }));
+                    Control::Eval(Closure {
+                        term: t,
+                        env: env.clone(),
+                    })
+                }
+            },
+            Control::Return(value) => match self.continuation.pop() {
+                None => return false,
+                Some(Frame::Argument(arg)) => {
+                    self.continuation.push(Frame::Function(value.clone()));
+                    Control::Eval(arg)
+                }
+                Some(Frame::Function(Closure {
+                    term: Term::Abstraction(b),
+                    env,
+                })) => Control::Eval(Closure {
+                    term: b,
+                    env: env.bind(value.clone()),
+                }),
+                Some(Frame::Function(_)) => unreachable!("values should be abstractions"),
+            },
+        };
+        true
+    }
+
+    /// Run the machine until it halts, returning the weak head normal form reached. This may
+    /// not halt.
+    pub fn run(mut self) -> Term {
+        while self.step() {}
+        self.read_back()
+    }
+
+    /// The term this state stands for.
+    pub fn read_back(&self) -> Term {
+        let (Control::Eval(c) | Control::Return(c)) = &self.control;
+        self.continuation
+            .iter()
+            .rev()
+            .fold(c.read_back(), |t, frame| match frame {
+                Frame::Argument(arg) => Term::Application(Box::new(t), Box::new(arg.read_back())),
+                Frame::Function(f) => Term::Application(Box::new(f.read_back()), Box::new(t)),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::Strategy;
+    use crate::trace::Fuel;
+    use crate::untyped::{self, parser::parse};
+    use proptest::prelude::{any, prop_assert_eq, proptest};
+
+    fn de_bruijn(source: &str) -> Term {
+        parse(source).unwrap().try_into().unwrap()
+    }
+
+    /// Whether the machine halts within a bound on its steps.
+    fn halts(mut step: impl FnMut() -> bool) -> bool {
+        (0..10_000).any(|_| !step())
+    }
+
+    proptest! {
+        #[test]
+        fn krivine_agrees_with_call_by_name(t in any::<Term>()) {
+            let named = untyped::Term::from(t.clone());
+            if let Ok((whnf, _)) = Strategy::CallByName.evaluate(&named, Fuel::steps(50).with_size_limit(2000)) {
+                let mut m = Krivine::new(&t);
+                prop_assert_eq!(halts(|| m.step()), true);
+                prop_assert_eq!(Some(m.read_back()), whnf.try_into().ok());
+            }
+        }
+
+        #[test]
+        fn lazy_agrees_with_call_by_name(t in any::<Term>()) {
+            let named = untyped::Term::from(t.clone());
+            if let Ok((whnf, _)) = Strategy::CallByName.evaluate(&named, Fuel::steps(50).with_size_limit(2000)) {
+                let mut m = Lazy::new(&t);
+                prop_assert_eq!(halts(|| m.step()), true);
+                // Shared arguments may have been evaluated under the abstraction reached.
+                let fuel = || Fuel::steps(200).with_size_limit(2000);
+                let lazy = untyped::Term::from(m.read_back());
+                if let Ok((normal_form, _)) = Strategy::NormalOrder.evaluate(&whnf, fuel()) {
+                    let lazy = Strategy::NormalOrder.evaluate(&lazy, fuel()).map(|(t, _)| t);
+                    prop_assert_eq!(lazy, Ok(normal_form));
+                }
+            }
+        }
+
+        #[test]
+        fn cek_agrees_with_call_by_value(t in any::<Term>()) {
+            let named = untyped::Term::from(t.clone());
+            if let Ok((value, _)) = Strategy::CallByValue.evaluate(&named, Fuel::steps(50).with_size_limit(2000)) {
+                let mut m = Cek::new(&t);
+                prop_assert_eq!(halts(|| m.step()), true);
+                prop_assert_eq!(Some(m.read_back()), value.try_into().ok());
+            }
+        }
+
+        #[test]
+        fn every_state_reads_back_to_a_reduct(t in any::<Term>()) {
+            let mut m = Krivine::new(&t);
+            for _ in 0..20 {
+                let before = untyped::Term::from(m.read_back());
+                if !m.step() {
+                    break;
+                }
+                let after = untyped::Term::from(m.read_back());
+                let reached = Strategy::CallByName.trace(&before).take(2).any(|s| s.term == after);
+                prop_assert_eq!(reached, true);
+            }
+        }
+    }
+
+    #[test]
+    fn krivine_doesnt_evaluate_unused_arguments() {
+        let t = de_bruijn("(λx. λy. y) ((λx. x x) (λx. x x))");
+        assert_eq!(Krivine::new(&t).run(), de_bruijn("λy. y"));
+    }
+
+    #[test]
+    fn lazy_evaluates_shared_arguments_once() {
+        let source = "(λx. x x) ((λy. y) (λz. z))";
+        let t = de_bruijn(source);
+        let mut m = Lazy::new(&t);
+        while m.step() {}
+        assert_eq!(m.read_back(), de_bruijn("λz. z"));
+        let named = parse(source).unwrap();
+        let (_, by_name) = Strategy::CallByName.evaluate(&named, 100).unwrap();
+        assert_eq!((m.contractions, by_name), (3, 4));
+    }
+
+    #[test]
+    fn cek_evaluates_arguments_first() {
+        let t = de_bruijn("(λx. λy. x) ((λx. x) (λz. z))");
+        let mut m = Cek::new(&t);
+        let mut bound = None;
+        while m.step() {
+            // Entering `λy. x` with `x` bound.
+            if let Control::Eval(Closure {
+                term: Term::Abstraction(body),
+                env,
+            }) = &m.control
+            {
+                if **body == Term::Variable(1) {
+                    bound = env.get(0).map(|c| c.borrow().read_back());
+                }
+            }
+        }
+        assert_eq!(bound, Some(de_bruijn("λz. z")));
+        assert_eq!(m.read_back(), de_bruijn("λy. λz. z"));
+    }
+}
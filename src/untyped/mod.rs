@@ -5,11 +5,24 @@ use std::fmt;
 use proptest::prelude::*;
 
 use super::fresh_var;
+use crate::locally_nameless::{Ln, LocallyNameless, Scope};
+use crate::nbe::{self, Core};
+use crate::notation::{parens, Notation};
+use crate::pretty::{self, at, Doc, Prec, Pretty};
+use crate::strategy::{Form, Reduce};
+use crate::trace::{Fuel, OutOfFuel, Redex, Rule, Subterms, Trace};
 
+pub mod arena;
 pub mod compile;
 pub mod continuation;
 pub mod de_bruijn;
+pub mod machine;
+pub mod net;
 pub mod parser;
+pub mod program;
+
+pub use de_bruijn::{UnboundIndex, UnboundVariable};
+pub use program::{Elaboration, Program};
 
 #[derive(Debug, Clone, Eq)]
 pub enum Term {
@@ -79,17 +92,7 @@ impl Term {
     }
 
     pub fn alpha_equivalent(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Variable(x), Self::Variable(y)) => x == y,
-            (Self::Abstraction(x, t), Self::Abstraction(y, u)) => {
-                let w = fresh_var(&(&self.vars() | &other.vars()));
-                t.rename(x, &w).alpha_equivalent(&u.rename(y, &w))
-            }
-            (Self::Application(t1, u1), Self::Application(t2, u2)) => {
-                t1.alpha_equivalent(t2) && u1.alpha_equivalent(u2)
-            }
-            _ => false,
-        }
+        self.to_locally_nameless() == other.to_locally_nameless()
     }
 
     /// Substitute the given term avoiding capture.
@@ -98,6 +101,10 @@ impl Term {
             Self::Variable(v) if v == from => to.to_owned(),
             Self::Variable(_) => self.clone(),
             Self::Abstraction(v, _) if v == from => self.clone(),
+            // Only rename the binder if it would capture a variable of the replacement.
+            Self::Abstraction(v, t) if !to.free_vars().contains(v) => {
+                Self::Abstraction(v.to_owned(), Box::new(t.substitute(from, to)))
+            }
             Self::Abstraction(v, t) => {
                 let mut vars = self.vars();
                 vars.extend(to.vars());
@@ -115,23 +122,30 @@ impl Term {
 
     /// Beta-reduce the left-outermost redex if one exists ("normal order").
     pub fn beta_reduce_lazy(&self) -> Option<Self> {
+        self.beta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::beta_reduce_lazy`], also returning the redex contracted.
+    pub fn beta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Application(t, u) => {
                 if let Self::Abstraction(x, b) = t.as_ref() {
-                    Some(b.substitute(x, u))
-                } else if let Some(t2) = t.beta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                    Some((b.substitute(x, u), Redex::root(Rule::Beta)))
+                } else if let Some((t2, r)) = t.beta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.beta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.beta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             Self::Abstraction(x, t) => t
-                .beta_reduce_lazy()
-                .map(|t2| Self::Abstraction(x.to_owned(), Box::new(t2))),
+                .beta_reduce_lazy_with_redex()
+                .map(|(t2, r)| (Self::Abstraction(x.to_owned(), Box::new(t2)), r.within(0))),
             _ => None,
         }
     }
@@ -165,27 +179,34 @@ impl Term {
 
     /// Eta-reduce the left-outermost simplification if one exists.
     pub fn eta_reduce_lazy(&self) -> Option<Self> {
+        self.eta_reduce_lazy_with_redex().map(|(t, _)| t)
+    }
+
+    /// Like [`Self::eta_reduce_lazy`], also returning the redex contracted.
+    pub fn eta_reduce_lazy_with_redex(&self) -> Option<(Self, Redex)> {
         match self {
             Self::Abstraction(x, t) => {
                 if let Self::Application(f, u) = t.as_ref() {
                     if let Self::Variable(y) = u.as_ref() {
                         if x == y && !f.free_vars().contains(x) {
-                            return Some(f.as_ref().clone());
+                            return Some((f.as_ref().clone(), Redex::root(Rule::Eta)));
                         }
                     }
                 }
-                t.eta_reduce_lazy()
-                    .map(|u2| Self::Abstraction(x.to_owned(), Box::new(u2)))
+                t.eta_reduce_lazy_with_redex()
+                    .map(|(u2, r)| (Self::Abstraction(x.to_owned(), Box::new(u2)), r.within(0)))
             }
             Self::Application(t, u) => {
-                if let Some(t2) = t.eta_reduce_lazy() {
-                    Some(Self::Application(
-                        Box::new(t2),
-                        Box::new(u.as_ref().clone()),
+                if let Some((t2, r)) = t.eta_reduce_lazy_with_redex() {
+                    Some((
+                        Self::Application(Box::new(t2), Box::new(u.as_ref().clone())),
+                        r.within(0),
                     ))
                 } else {
-                    u.eta_reduce_lazy()
-                        .map(|u2| Self::Application(Box::new(t.as_ref().clone()), Box::new(u2)))
+                    u.eta_reduce_lazy_with_redex().map(|(u2, r)| {
+                        let t = Box::new(t.as_ref().clone());
+                        (Self::Application(t, Box::new(u2)), r.within(1))
+                    })
                 }
             }
             _ => None,
@@ -193,7 +214,7 @@ impl Term {
     }
 
     /// Fully beta- and eta-reduce the term. Since the lambda calculus is Turing-complete,
-    /// this may not halt.
+    /// this may not halt; [`Self::evaluate_with_fuel`] always does.
     pub fn evaluate(&self) -> Self {
         let mut res = self.clone();
         while let Some(new_res) = res.beta_reduce_lazy() {
@@ -204,6 +225,124 @@ impl Term {
         }
         res
     }
+
+    /// The terms reached by [`Self::evaluate`], with the redex contracted at each step.
+    pub fn reduction_trace(&self) -> Trace<Self> {
+        Trace::new(
+            self.clone(),
+            vec![
+                Self::beta_reduce_lazy_with_redex,
+                Self::eta_reduce_lazy_with_redex,
+            ],
+        )
+    }
+
+    /// Like [`Self::evaluate`], but giving up once the fuel runs out.
+    pub fn evaluate_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        self.reduction_trace().run(fuel.into())
+    }
+
+    /// The same normal form as [`Self::evaluate`], found by normalisation by evaluation
+    /// rather than by repeatedly reducing the whole term. This may not halt either.
+    pub fn normalise(&self) -> Self {
+        self.normalise_with_fuel(usize::MAX)
+            .expect("the fuel should be unlimited")
+    }
+
+    /// Like [`Self::normalise`], but giving up once the fuel runs out, with this term as the
+    /// partial term.
+    pub fn normalise_with_fuel(&self, fuel: impl Into<Fuel>) -> Result<Self, OutOfFuel<Self>> {
+        let core = self.to_core(&mut Vec::new());
+        let normal_form = nbe::normalise(&core, true, fuel.into(), self)?;
+        Ok(Self::from_core(&normal_form, &mut Vec::new()))
+    }
+
+    fn to_core(&self, scope: &mut Vec<String>) -> Core {
+        match self {
+            Self::Variable(x) => Core::variable(x, scope),
+            Self::Abstraction(x, t) => {
+                scope.push(x.clone());
+                let t = t.to_core(scope);
+                scope.pop();
+                Core::Abstraction(x.clone(), None, Box::new(t))
+            }
+            Self::Application(t, u) => {
+                Core::Application(Box::new(t.to_core(scope)), Box::new(u.to_core(scope)))
+            }
+        }
+    }
+
+    fn from_core(t: &Core, scope: &mut Vec<String>) -> Self {
+        match t {
+            Core::Bound(i) => Self::Variable(Core::name(*i, scope)),
+            Core::Free(x) => Self::Variable(x.clone()),
+            Core::Abstraction(x, _, t) => {
+                scope.push(x.clone());
+                let t = Self::from_core(t, scope);
+                scope.pop();
+                Self::Abstraction(x.clone(), Box::new(t))
+            }
+            Core::Application(t, u) => Self::Application(
+                Box::new(Self::from_core(t, scope)),
+                Box::new(Self::from_core(u, scope)),
+            ),
+            Core::Sort(_) | Core::Product(..) => unreachable!("untyped terms have no types"),
+        }
+    }
+}
+
+impl Subterms for Term {
+    fn arity(&self) -> usize {
+        match self {
+            Self::Abstraction(..) => 1,
+            Self::Application(..) => 2,
+            _ => 0,
+        }
+    }
+
+    fn child(&self, index: usize) -> Option<&Self> {
+        match (self, index) {
+            (Self::Abstraction(_, t), 0) | (Self::Application(t, _), 0) => Some(t),
+            (Self::Application(_, u), 1) => Some(u),
+            _ => None,
+        }
+    }
+
+    fn with_child(&self, index: usize, child: Self) -> Self {
+        let child = Box::new(child);
+        match (self, index) {
+            (Self::Abstraction(x, _), 0) => Self::Abstraction(x.clone(), child),
+            (Self::Application(_, u), 0) => Self::Application(child, u.clone()),
+            (Self::Application(t, _), 1) => Self::Application(t.clone(), child),
+            _ => self.clone(),
+        }
+    }
+
+    fn variable(name: String) -> Self {
+        Self::Variable(name)
+    }
+}
+
+impl Reduce for Term {
+    fn form(&self) -> Form {
+        match self {
+            Self::Application(..) => Form::Application {
+                term_argument: true,
+            },
+            Self::Abstraction(..) => Form::Binder { body: 0 },
+            Self::Variable(_) => Form::Other,
+        }
+    }
+
+    fn contract(&self) -> Option<Self> {
+        let Self::Application(t, u) = self else {
+            return None;
+        };
+        let Self::Abstraction(x, b) = t.as_ref() else {
+            return None;
+        };
+        Some(b.substitute(x, u))
+    }
 }
 
 #[cfg(test)]
@@ -218,20 +357,45 @@ impl Arbitrary for Term {
 
 #[cfg(test)]
 fn arb_term() -> impl Strategy<Value = Term> {
-    let leaf = "[a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω_][a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω0-9_]*'*".prop_map(Term::Variable);
+    let leaf = crate::arb_var().prop_map(Term::Variable);
     leaf.prop_recursive(16, 256, 2, |inner| {
         prop_oneof![
             (inner.clone(), inner.clone())
                 .prop_map(|(f, x)| Term::Application(Box::new(f), Box::new(x))),
-            (
-                "[a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω_][a-zA-Zα-κμ-ωΑ-ΚΜ-ΟΡ-Ω0-9_]*'*",
-                inner
-            )
-                .prop_map(|(x, b)| Term::Abstraction(x, Box::new(b))),
+            (crate::arb_var(), inner).prop_map(|(x, b)| Term::Abstraction(x, Box::new(b))),
         ]
     })
 }
 
+/// The constructors of a [`Term`] in locally nameless form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Abstraction,
+    Application,
+}
+
+impl LocallyNameless for Term {
+    type Node = Node;
+
+    fn to_locally_nameless(&self) -> Ln<Node> {
+        to_locally_nameless(self, &mut Scope::new())
+    }
+}
+
+fn to_locally_nameless(term: &Term, scope: &mut Scope) -> Ln<Node> {
+    match term {
+        Term::Variable(x) => scope.variable((), x),
+        Term::Abstraction(x, t) => Ln::node(
+            Node::Abstraction,
+            [scope.bind((), x, |scope| to_locally_nameless(t, scope))],
+        ),
+        Term::Application(t, u) => Ln::node(
+            Node::Application,
+            [to_locally_nameless(t, scope), to_locally_nameless(u, scope)],
+        ),
+    }
+}
+
 impl PartialEq for Term {
     fn eq(&self, other: &Self) -> bool {
         self.alpha_equivalent(other)
@@ -240,9 +404,10 @@ impl PartialEq for Term {
 
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
         match self {
             Self::Variable(x) => x.fmt(f),
-            Self::Abstraction(x, t) => write!(f, "λ{}. {}", x, t),
+            Self::Abstraction(x, t) => write!(f, "{}{}. {}", n.lambda(), x, n.show(t)),
             Self::Application(t, u) => {
                 write_func(t, f)?;
                 write!(f, " ")?;
@@ -252,45 +417,85 @@ impl fmt::Display for Term {
     }
 }
 
+impl Pretty for Term {
+    fn to_doc_prec(&self, n: Notation) -> (Doc, Prec) {
+        match self {
+            Self::Variable(x) => pretty::atom(x),
+            Self::Abstraction(x, t) => {
+                let mut bound = vec![x.as_str()];
+                let mut body = t.as_ref();
+                // The tree-sitter grammar doesn't abbreviate nested abstractions.
+                if cfg!(not(feature = "tree-sitter")) {
+                    while let Self::Abstraction(y, u) = body {
+                        bound.push(y);
+                        body = u;
+                    }
+                }
+                let head = Doc::text(format!("{}{}.", n.lambda(), bound.join(" ")));
+                pretty::binder(head, body.to_doc(n))
+            }
+            Self::Application(_, _) => {
+                let mut head = self;
+                let mut args = Vec::new();
+                while let Self::Application(t, u) = head {
+                    args.push(at(u.as_ref(), n, Prec::Atom));
+                    head = t;
+                }
+                args.reverse();
+                pretty::application(at(head, n, Prec::Application), args)
+            }
+        }
+    }
+}
+
 fn write_term(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
     }
 }
 
 fn write_func(t: &Term, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match t {
         Term::Variable(_) | Term::Application(_, _) => fmt::Display::fmt(t, f),
-        _ => write!(f, "({})", t),
+        _ => parens(t, f),
+    }
+}
+
+impl Term {
+    /// This term with De Bruijn indices, where `context` names its free variables with the
+    /// innermost last, so that the last name has index 0 outside the term's own binders.
+    pub fn to_de_bruijn(&self, context: &[String]) -> Result<de_bruijn::Term, UnboundVariable> {
+        to_de_bruijn(self, &mut context.to_vec())
     }
 }
 
-/// Convert a term to De Bruijn indexing.
-fn to_de_bruijn(term: &Term, binds: &HashMap<String, u64>) -> Option<de_bruijn::Term> {
+/// Convert a term to De Bruijn indexing, where `scope` names the variables bound around it.
+fn to_de_bruijn(term: &Term, scope: &mut Vec<String>) -> Result<de_bruijn::Term, UnboundVariable> {
     match term {
-        Term::Variable(x) => binds.get(x).map(|i| de_bruijn::Term::Variable(*i)),
+        Term::Variable(x) => match scope.iter().rev().position(|y| y == x) {
+            Some(i) => Ok(de_bruijn::Term::Variable(i as u64)),
+            None => Err(UnboundVariable(x.clone())),
+        },
         Term::Abstraction(x, t) => {
-            let mut new_binds: HashMap<_, _> =
-                binds.iter().map(|(v, i)| (v.clone(), i + 1)).collect();
-            new_binds.insert(x.to_owned(), 0);
-            Some(de_bruijn::Term::Abstraction(Box::new(to_de_bruijn(
-                t, &new_binds,
-            )?)))
+            scope.push(x.clone());
+            let t = to_de_bruijn(t, scope);
+            scope.pop();
+            Ok(de_bruijn::Term::Abstraction(Box::new(t?)))
         }
-        Term::Application(t, u) => Some(de_bruijn::Term::Application(
-            Box::new(to_de_bruijn(t, binds)?),
-            Box::new(to_de_bruijn(u, binds)?),
+        Term::Application(t, u) => Ok(de_bruijn::Term::Application(
+            Box::new(to_de_bruijn(t, scope)?),
+            Box::new(to_de_bruijn(u, scope)?),
         )),
     }
 }
 
 /// Convert a closed term to De Bruijn indexing.
 impl TryFrom<Term> for de_bruijn::Term {
-    type Error = ();
+    type Error = UnboundVariable;
 
     fn try_from(value: Term) -> Result<Self, Self::Error> {
-        to_de_bruijn(&value, &HashMap::new()).ok_or(())
+        value.to_de_bruijn(&[])
     }
 }
 
@@ -304,3 +509,20 @@ pub fn abstraction(v: impl Into<String>, t: impl Into<Term>) -> Term {
 pub fn application(t: impl Into<Term>, u: impl Into<Term>) -> Term {
     Term::Application(Box::new(t.into()), Box::new(u.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitution_renames_only_capturing_binders() {
+        // Nothing to capture, so the binder keeps its name.
+        let t = abstraction("y", application(variable("x"), variable("y")));
+        assert_eq!(t.substitute("x", &variable("z")).to_string(), "λy. z y");
+        // Substituting y under λy must rename the binder to keep y free.
+        let t = abstraction("y", variable("x"));
+        let u = t.substitute("x", &variable("y"));
+        assert!(matches!(&u, Term::Abstraction(v, _) if v != "y"));
+        assert_eq!(u, abstraction("w", variable("y")));
+    }
+}
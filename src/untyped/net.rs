@@ -0,0 +1,518 @@
+//! Optimal reduction of untyped terms with interaction nets.
+//!
+//! A term is compiled into a net of constructor nodes, which stand for both abstractions and
+//! applications, duplicators, which share a subnet between the uses of a variable, and erasers,
+//! which discard an unused one. The net is reduced by local rewrites at the pairs of nodes
+//! whose principal ports are connected, and read back into a term once none are left.
+//!
+//! A duplicator copies a subnet incrementally, as the copies are needed, so work inside an
+//! argument is never duplicated. This is Lamping's abstract algorithm without the bookkeeping
+//! of his brackets, so the term read back is only guaranteed to be the normal form for terms
+//! typable in elementary affine logic, which includes the usual programs on Church numerals.
+//! Since every pair is reduced, even in arguments that end up discarded, reduction may not
+//! halt for a term with a normal form that normal order would reach.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::Term;
+use crate::fresh_var;
+use crate::trace::{Fuel, Limit, OutOfFuel};
+
+/// A port of a node, as the node's index and the port's slot.
+type Port = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Where the term's value goes, which never interacts.
+    Root,
+    /// An abstraction, with ports for its value, its variable and its body, or an
+    /// application, with ports for its function, its argument and its value. Abstractions
+    /// remember the index of their variable's name in [`Net::names`].
+    Constructor(Option<usize>),
+    /// A duplicator, with ports for the value being shared and its two copies. Duplicators
+    /// with the same label annihilate, and those with different labels copy each other.
+    Duplicator(usize),
+    Eraser,
+    /// A free variable, by the index of its name in [`Net::names`].
+    Free(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    kind: Kind,
+    /// The port each slot is connected to, where slot 0 is the principal port.
+    ports: [Port; 3],
+}
+
+/// How much work reducing a net has taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of pairs of nodes rewritten.
+    pub interactions: usize,
+    /// The number of nodes in the net.
+    pub nodes: usize,
+    /// The most nodes the net has had at once.
+    pub max_nodes: usize,
+}
+
+/// An interaction net standing for an untyped term.
+#[derive(Debug, Clone)]
+pub struct Net {
+    nodes: Vec<Node>,
+    /// Deleted nodes, whose places can be reused.
+    free: Vec<usize>,
+    /// Pairs of nodes connected by their principal ports, waiting to be rewritten.
+    active: Vec<(usize, usize)>,
+    /// The names of free variables and binders.
+    names: Vec<String>,
+    labels: usize,
+    stats: Stats,
+}
+
+const ROOT: usize = 0;
+
+impl Net {
+    /// Compile a term into a net.
+    pub fn new(term: &Term) -> Self {
+        let mut net = Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            active: Vec::new(),
+            names: Vec::new(),
+            labels: 0,
+            stats: Stats::default(),
+        };
+        let root = net.node(Kind::Root);
+        let value = net.compile(term, &mut Vec::new());
+        net.link((root, 0), value);
+        net
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Whether no more pairs of nodes can be rewritten.
+    pub fn is_normal(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    fn node(&mut self, kind: Kind) -> usize {
+        let node = Node {
+            kind,
+            ports: [(usize::MAX, 0); 3],
+        };
+        self.stats.nodes += 1;
+        self.stats.max_nodes = self.stats.max_nodes.max(self.stats.nodes);
+        match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn delete(&mut self, i: usize) {
+        self.stats.nodes -= 1;
+        self.free.push(i);
+    }
+
+    fn name(&mut self, x: &str) -> usize {
+        self.names.push(x.to_owned());
+        self.names.len() - 1
+    }
+
+    /// The port connected to the given one.
+    fn target(&self, (i, slot): Port) -> Port {
+        self.nodes[i].ports[slot]
+    }
+
+    fn link(&mut self, a: Port, b: Port) {
+        self.nodes[a.0].ports[a.1] = b;
+        self.nodes[b.0].ports[b.1] = a;
+        if a.1 == 0 && b.1 == 0 && self.interacts(a.0, b.0) {
+            self.active.push((a.0, b.0));
+        }
+    }
+
+    fn interacts(&self, a: usize, b: usize) -> bool {
+        match (self.nodes[a].kind, self.nodes[b].kind) {
+            (Kind::Root, _) | (_, Kind::Root) => false,
+            // A free variable applied to an argument.
+            (Kind::Free(_), Kind::Constructor(_)) | (Kind::Constructor(_), Kind::Free(_)) => false,
+            _ => true,
+        }
+    }
+
+    /// Compile `term`, returning the port its value comes out of. `scope` has the bound
+    /// variables, innermost last, with the port the value of each comes out of and the number
+    /// of uses of it still to be compiled.
+    fn compile(&mut self, term: &Term, scope: &mut Vec<(String, Port, usize)>) -> Port {
+        match term {
+            Term::Variable(x) => match scope.iter().rposition(|(y, _, _)| y == x) {
+                Some(i) => {
+                    let (_, source, uses) = &mut scope[i];
+                    *uses -= 1;
+                    if *uses == 0 {
+                        return *source;
+                    }
+                    // Share the value between this use and the rest.
+                    let dup = self.node(Kind::Duplicator(self.labels));
+                    self.labels += 1;
+                    self.link((dup, 0), *source);
+                    *source = (dup, 2);
+                    (dup, 1)
+                }
+                None => {
+                    let name = self.name(x);
+                    (self.node(Kind::Free(name)), 0)
+                }
+            },
+            Term::Abstraction(x, t) => {
+                let name = self.name(x);
+                let lam = self.node(Kind::Constructor(Some(name)));
+                let uses = uses(t, x);
+                if uses == 0 {
+                    let era = self.node(Kind::Eraser);
+                    self.link((lam, 1), (era, 0));
+                }
+                scope.push((x.clone(), (lam, 1), uses));
+                let body = self.compile(t, scope);
+                scope.pop();
+                self.link((lam, 2), body);
+                (lam, 0)
+            }
+            Term::Application(t, u) => {
+                let app = self.node(Kind::Constructor(None));
+                let function = self.compile(t, scope);
+                self.link((app, 0), function);
+                let argument = self.compile(u, scope);
+                self.link((app, 1), argument);
+                (app, 2)
+            }
+        }
+    }
+
+    /// Rewrite one pair of nodes, returning `false` if there are none left.
+    pub fn step(&mut self) -> bool {
+        let Some((a, b)) = self.active.pop() else {
+            return false;
+        };
+        self.stats.interactions += 1;
+        match (self.nodes[a].kind, self.nodes[b].kind) {
+            (Kind::Constructor(_), Kind::Constructor(_)) => self.annihilate(a, b),
+            (Kind::Duplicator(l), Kind::Duplicator(m)) if l == m => self.annihilate(a, b),
+            (Kind::Eraser, Kind::Eraser)
+            | (Kind::Eraser, Kind::Free(_))
+            | (Kind::Free(_), Kind::Eraser) => {
+                self.delete(a);
+                self.delete(b);
+            }
+            (Kind::Eraser | Kind::Free(_), _) => self.copy(a, b),
+            (_, Kind::Eraser | Kind::Free(_)) => self.copy(b, a),
+            _ => self.commute(a, b),
+        }
+        true
+    }
+
+    /// Connect the auxiliary ports of two nodes of the same kind to each other.
+    fn annihilate(&mut self, a: usize, b: usize) {
+        for slot in 1..3 {
+            let (x, y) = (self.target((a, slot)), self.target((b, slot)));
+            self.link(x, y);
+        }
+        self.delete(a);
+        self.delete(b);
+    }
+
+    /// Put a copy of the node `leaf`, which has no auxiliary ports, at each auxiliary port of
+    /// `node`.
+    fn copy(&mut self, leaf: usize, node: usize) {
+        let kind = self.nodes[leaf].kind;
+        for slot in 1..3 {
+            let copy = self.node(kind);
+            let target = self.target((node, slot));
+            self.link((copy, 0), target);
+        }
+        self.delete(leaf);
+        self.delete(node);
+    }
+
+    /// Let two nodes of different kinds pass through each other, copying each.
+    fn commute(&mut self, a: usize, b: usize) {
+        let (ka, kb) = (self.nodes[a].kind, self.nodes[b].kind);
+        let a_copies = [self.node(ka), self.node(ka)];
+        let b_copies = [self.node(kb), self.node(kb)];
+        for i in 0..2 {
+            let target = self.target((a, i + 1));
+            self.link((b_copies[i], 0), target);
+            let target = self.target((b, i + 1));
+            self.link((a_copies[i], 0), target);
+        }
+        for (i, &b_copy) in b_copies.iter().enumerate() {
+            for (j, &a_copy) in a_copies.iter().enumerate() {
+                self.link((b_copy, j + 1), (a_copy, i + 1));
+            }
+        }
+        self.delete(a);
+        self.delete(b);
+    }
+
+    /// Reduce the net until no pairs of nodes can be rewritten, or give up once the fuel runs
+    /// out. The size limit applies to the number of nodes.
+    pub fn reduce(&mut self, fuel: impl Into<Fuel>) -> Result<(), Limit> {
+        let fuel = fuel.into();
+        while !self.is_normal() {
+            let Stats {
+                interactions,
+                nodes,
+                ..
+            } = self.stats;
+            if let Some(limit) = fuel.limit_reached_with(interactions, || nodes) {
+                return Err(limit);
+            }
+            self.step();
+        }
+        Ok(())
+    }
+
+    /// The term the net stands for, which should be normal, or `None` if it doesn't stand for
+    /// one. Nets of terms outside elementary affine logic, such as `(λx. x x) (λx. x x)`, can
+    /// reduce to such nets.
+    ///
+    /// Binders keep their names unless that would capture a variable, or shadow another binder.
+    pub fn read_back(&self) -> Option<Term> {
+        let deleted: HashSet<_> = self.free.iter().collect();
+        let live = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !deleted.contains(i));
+        let mut free = HashSet::new();
+        let mut duplicators = 0;
+        for (_, node) in live {
+            match node.kind {
+                Kind::Free(x) => {
+                    free.insert(self.names[x].clone());
+                }
+                Kind::Duplicator(_) => duplicators += 1,
+                _ => {}
+            }
+        }
+        let mut read_back = ReadBack {
+            net: self,
+            free,
+            binders: HashMap::new(),
+            scope: Vec::new(),
+            paths: BTreeMap::new(),
+            depth: 0,
+            duplicators,
+            visiting: HashSet::new(),
+        };
+        read_back.term(self.target((ROOT, 0)))
+    }
+}
+
+/// The number of times `x` is free in `term`.
+fn uses(term: &Term, x: &str) -> usize {
+    match term {
+        Term::Variable(y) => usize::from(x == y),
+        Term::Abstraction(y, _) if x == y => 0,
+        Term::Abstraction(_, t) => uses(t, x),
+        Term::Application(t, u) => uses(t, x) + uses(u, x),
+    }
+}
+
+struct ReadBack<'a> {
+    net: &'a Net,
+    free: HashSet<String>,
+    /// The names given to the abstractions being read, innermost last for each.
+    binders: HashMap<usize, Vec<String>>,
+    scope: Vec<String>,
+    /// For each duplicator label, the copies that were entered and not yet left, innermost last.
+    paths: BTreeMap<usize, Vec<usize>>,
+    /// The number of copies in `paths`, which can't be more than the number of duplicators
+    /// unless the net is cyclic.
+    depth: usize,
+    duplicators: usize,
+    /// The ports being read from, with the paths they were reached by. Reaching one again the
+    /// same way would repeat forever.
+    visiting: HashSet<(Port, BTreeMap<usize, Vec<usize>>)>,
+}
+
+impl ReadBack<'_> {
+    /// The term whose value comes out of the given port.
+    fn term(&mut self, port: Port) -> Option<Term> {
+        let key = (port, self.paths.clone());
+        if !self.visiting.insert(key.clone()) {
+            return None;
+        }
+        let term = self.term_at(port);
+        self.visiting.remove(&key);
+        term
+    }
+
+    fn term_at(&mut self, (i, slot): Port) -> Option<Term> {
+        let net = self.net;
+        match (net.nodes[i].kind, slot) {
+            (Kind::Free(x), _) => Some(Term::Variable(net.names[x].clone())),
+            (Kind::Constructor(name), 0) => {
+                let hint = name.map_or("x", |x| &net.names[x]);
+                let x = if self.free.contains(hint) || self.scope.iter().any(|y| y == hint) {
+                    let mut used = self.free.clone();
+                    used.extend(self.scope.iter().cloned());
+                    fresh_var(&used)
+                } else {
+                    hint.to_owned()
+                };
+                self.binders.entry(i).or_default().push(x.clone());
+                self.scope.push(x.clone());
+                let body = self.term(net.target((i, 2)));
+                self.scope.pop();
+                self.binders.get_mut(&i).and_then(Vec::pop);
+                Some(Term::Abstraction(x, Box::new(body?)))
+            }
+            (Kind::Constructor(_), 1) => {
+                let x = self.binders.get(&i).and_then(|names| names.last());
+                Some(Term::Variable(x?.clone()))
+            }
+            (Kind::Constructor(_), _) => {
+                let t = self.term(net.target((i, 0)))?;
+                let u = self.term(net.target((i, 1)))?;
+                Some(Term::Application(Box::new(t), Box::new(u)))
+            }
+            (Kind::Duplicator(label), 0) => {
+                let copy = self.leave(label)?;
+                let t = self.term(net.target((i, copy)));
+                self.enter(label, copy);
+                t
+            }
+            (Kind::Duplicator(_), _) if self.depth == self.duplicators => None,
+            (Kind::Duplicator(label), copy) => {
+                self.enter(label, copy);
+                let t = self.term(net.target((i, 0)));
+                self.leave(label);
+                t
+            }
+            (Kind::Root | Kind::Eraser, _) => None,
+        }
+    }
+
+    fn enter(&mut self, label: usize, copy: usize) {
+        self.paths.entry(label).or_default().push(copy);
+        self.depth += 1;
+    }
+
+    /// The copy of the duplicators with the given label most recently entered.
+    fn leave(&mut self, label: usize) -> Option<usize> {
+        let copies = self.paths.get_mut(&label)?;
+        let copy = copies.pop();
+        self.depth -= 1;
+        if copies.is_empty() {
+            self.paths.remove(&label);
+        }
+        copy
+    }
+}
+
+/// The normal form of a term found by reducing its net, if the net stands for one, or the net
+/// reached when the fuel runs out. The statistics can be compared with the number of steps
+/// normal order takes.
+pub fn evaluate_with_fuel(
+    term: &Term,
+    fuel: impl Into<Fuel>,
+) -> Result<(Option<Term>, Stats), OutOfFuel<Box<Net>>> {
+    let mut net = Net::new(term);
+    match net.reduce(fuel) {
+        Ok(()) => Ok((net.read_back(), net.stats())),
+        Err(limit) => Err(OutOfFuel {
+            steps: net.stats().interactions,
+            partial_term: Box::new(net),
+            limit,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::Strategy;
+    use crate::untyped::parser::parse;
+    use proptest::prelude::*;
+
+    fn normal_form(source: &str) -> Term {
+        let (t, stats) = evaluate_with_fuel(&parse(source).unwrap(), 100_000).unwrap();
+        assert!(stats.max_nodes >= stats.nodes);
+        t.unwrap()
+    }
+
+    fn is_affine(term: &Term) -> bool {
+        match term {
+            Term::Variable(_) => true,
+            Term::Abstraction(x, t) => uses(t, x) <= 1 && is_affine(t),
+            Term::Application(t, u) => is_affine(t) && is_affine(u),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn affine_terms_agree_with_normal_order(t in any::<Term>()) {
+            if is_affine(&t) {
+                let (normal_form, _) = Strategy::NormalOrder.evaluate(&t, 10_000).unwrap();
+                let (result, _) = evaluate_with_fuel(&t, 10_000).unwrap();
+                prop_assert_eq!(result, Some(normal_form));
+            }
+        }
+    }
+
+    #[test]
+    fn church_arithmetic() {
+        let church = |n: usize| {
+            let body = (0..n).fold("x".to_owned(), |t, _| format!("f ({t})"));
+            parse(format!("λf. λx. {body}")).unwrap()
+        };
+        let two = "(λf. λx. f (f x))";
+        let three = "(λf. λx. f (f (f x)))";
+        let plus = "(λm. λn. λf. λx. m f (n f x))";
+        let times = "(λm. λn. λf. m (n f))";
+        assert_eq!(normal_form(&format!("{plus} {two} {three}")), church(5));
+        assert_eq!(normal_form(&format!("{times} {three} {three}")), church(9));
+        assert_eq!(normal_form(&format!("{three} {two}")), church(8));
+        assert_eq!(normal_form(&format!("{two} {two} {two}")), church(16));
+    }
+
+    #[test]
+    fn free_variables_are_shared_and_erased() {
+        assert_eq!(normal_form("(λx. x x) (f y)"), parse("f y (f y)").unwrap());
+        assert_eq!(normal_form("(λx. λy. y) z"), parse("λy. y").unwrap());
+        assert_eq!(normal_form("f ((λx. x) y)"), parse("f y").unwrap());
+    }
+
+    #[test]
+    fn binders_are_renamed_to_avoid_capture() {
+        let t = normal_form("(λf. λx. f x) (λy. x y)");
+        assert_eq!(t, parse("λz. x z").unwrap());
+        assert_eq!(t.to_string(), "λx0. x x0");
+    }
+
+    #[test]
+    fn fuel_limits_interactions_and_nodes() {
+        let t = parse("(λf. λx. f (f (f x))) (λf. λx. f (f x))").unwrap();
+        let err = evaluate_with_fuel(&t, 5).unwrap_err();
+        assert_eq!((err.steps, err.limit), (5, Limit::Steps));
+        assert!(!err.partial_term.is_normal());
+        let err = evaluate_with_fuel(&t, Fuel::steps(1000).with_size_limit(15)).unwrap_err();
+        assert_eq!(err.limit, Limit::Size);
+    }
+
+    #[test]
+    fn some_nets_dont_stand_for_terms() {
+        let omega = parse("(λx. x x) (λx. x x)").unwrap();
+        let (result, _) = evaluate_with_fuel(&omega, 1000).unwrap();
+        assert_eq!(result, None);
+    }
+}
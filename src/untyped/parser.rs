@@ -1,144 +1,202 @@
-use std::{error, fmt, str::Utf8Error};
-
-use tree_sitter::{Language, Node, Parser, TreeCursor};
+#[cfg(feature = "tree-sitter")]
+use tree_sitter::{Node, TreeCursor};
 
 use super::*;
+use crate::parsing;
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+use crate::parsing::descent::{Descent, Grammar};
+use crate::parsing::lexer::{lex_recovering, Token};
+#[cfg(feature = "tree-sitter")]
+use crate::parsing::tree::{Syntax, Walker};
+use crate::span::{Span, SpanTable};
 
-extern "C" {
-    fn tree_sitter_lambda() -> Language;
-}
+pub use crate::parsing::ParseError;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum ParseError {
-    Encoding(Utf8Error),
-    Other(String),
-}
+struct Untyped;
 
-impl ParseError {
-    fn msg(s: impl Into<String>) -> Self {
-        Self::Other(s.into())
-    }
-}
+#[cfg(feature = "tree-sitter")]
+impl Syntax for Untyped {
+    type Term = Term;
 
-impl From<Utf8Error> for ParseError {
-    fn from(value: Utf8Error) -> Self {
-        Self::Encoding(value)
+    fn hole(name: String) -> Term {
+        Term::Variable(name)
     }
-}
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Other(s) => write!(f, "parse error: {}", s),
-            Self::Encoding(e) => e.fmt(f),
+    fn node<'a>(
+        w: &mut Walker<'a, Self>,
+        node: Node<'a>,
+        cursor: &mut TreeCursor<'a>,
+    ) -> Result<Term, ParseError> {
+        match node.kind() {
+            "variable" => Ok(Term::Variable(w.identifier(cursor)?)),
+            "abstraction" => {
+                w.enter(&node, cursor)?;
+                let bound = w.identifier(cursor)?;
+                w.next(&node, cursor, "body")?;
+                let body = Box::new(w.child(0, cursor)?);
+                w.leave(cursor);
+                Ok(Term::Abstraction(bound, body))
+            }
+            "application" => {
+                let (func, arg) = w.pair(&node, cursor, "RHS")?;
+                Ok(Term::Application(Box::new(func), Box::new(arg)))
+            }
+            _ => Err(ParseError::at(
+                &node,
+                format!("unknown term type {}", node.kind()),
+            )),
         }
     }
 }
 
-impl error::Error for ParseError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match self {
-            Self::Encoding(e) => Some(e),
-            Self::Other(_) => None,
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+impl Grammar for Untyped {
+    type Term = Term;
+
+    fn hole(name: String) -> Term {
+        Term::Variable(name)
+    }
+
+    fn term(d: &mut Descent<'_, Self>) -> Result<Term, ParseError> {
+        if d.tokens.eat(&Token::Lambda) {
+            return abstraction(d);
+        }
+        let mark = d.mark();
+        let mut t = atom(d)?;
+        loop {
+            let lambda = d.tokens.peek() == Some(&Token::Lambda);
+            if !(lambda || d.tokens.peek() == Some(&Token::LParen) || d.tokens.at_identifier()) {
+                return Ok(t);
+            }
+            d.wrap(mark);
+            // An abstraction extends as far right as possible, so can only be the last argument.
+            let arg = d.child(1, if lambda { Self::term } else { atom })?;
+            t = Term::Application(Box::new(t), Box::new(arg));
         }
     }
 }
 
-pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
-    let mut parser = Parser::new();
-    let language = unsafe { tree_sitter_lambda() };
-    parser
-        .set_language(language)
-        .expect("language should be compiled with a compatible version of tree-sitter");
-
-    let source = source.as_ref();
+/// The rest of an abstraction after the `λ`, where `λx y. t` abbreviates `λx. λy. t`.
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn abstraction(d: &mut Descent<'_, Untyped>) -> Result<Term, ParseError> {
+    let bound = d.tokens.identifier()?;
+    let body = if d.tokens.at_identifier() {
+        d.child(0, abstraction)?
+    } else {
+        d.tokens.expect(&Token::Dot)?;
+        d.child(0, Untyped::term)?
+    };
+    Ok(Term::Abstraction(bound, Box::new(body)))
+}
 
-    let tree = parser.parse(source, None).expect("parser should succeed");
-    let mut cursor = tree.walk();
-    let node = advance(&mut cursor)?.ok_or(ParseError::msg("missing root"))?;
-    if node.kind() != "source" {
-        return Err(ParseError::msg(format!(
-            "expected root node but got {}",
-            node.kind()
-        )));
-    }
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("root node should have children"));
+#[cfg(all(feature = "rust-parser", not(feature = "tree-sitter")))]
+fn atom(d: &mut Descent<'_, Untyped>) -> Result<Term, ParseError> {
+    if d.tokens.eat(&Token::LParen) {
+        let t = Untyped::term(d)?;
+        d.tokens.expect(&Token::RParen)?;
+        Ok(t)
+    } else if d.tokens.at_identifier() {
+        Ok(Term::Variable(d.tokens.identifier()?))
+    } else {
+        Err(d.tokens.unexpected("a term"))
     }
-    walk_term(source, &mut cursor)
 }
 
-fn walk_identifier<'a>(
-    source: &'a [u8],
-    cursor: &mut TreeCursor<'a>,
-) -> Result<String, ParseError> {
-    let node = advance(cursor)?.ok_or(ParseError::msg("missing variable"))?;
-    if node.kind() != "variable" {
-        return Err(ParseError::msg(format!(
-            "expected variable but got {}",
-            node.kind()
-        )));
-    }
-    let text = node.utf8_text(source)?.to_owned();
-    Ok(text)
+pub fn parse(source: impl AsRef<[u8]>) -> Result<Term, ParseError> {
+    parse_with_spans(source).map(|(term, _)| term)
 }
 
-fn walk_term<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    let node = advance(cursor)?.ok_or(ParseError::msg("missing term"))?;
-    match node.kind() {
-        "variable" => Ok(Term::Variable(walk_identifier(source, cursor)?)),
-        "abstraction" => Ok(walk_abstraction(source, cursor)?),
-        "application" => Ok(walk_application(source, cursor)?),
-        _ => Err(ParseError::msg(format!(
-            "unknown term type {}",
-            node.kind()
-        ))),
-    }
+/// Parse a term, also returning the source span of each of its subterms.
+pub fn parse_with_spans(source: impl AsRef<[u8]>) -> Result<(Term, SpanTable), ParseError> {
+    parsing::parse_with_spans::<Untyped>(source.as_ref())
 }
 
-fn walk_application<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("application should have children"));
-    }
-    let func = Box::new(walk_term(source, cursor)?);
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("application should have RHS"));
-    }
-    let arg = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Application(func, arg))
+/// Parse a term even if it has syntax errors, returning every error in the source.
+///
+/// Subterms that could not be parsed are replaced by holes, variables named `?0`, `?1`, …
+/// which cannot be written in the source.
+pub fn parse_recovering(source: impl AsRef<[u8]>) -> (Term, Vec<ParseError>) {
+    parsing::parse_recovering::<Untyped>(source.as_ref())
 }
 
-fn walk_abstraction<'a>(source: &'a [u8], cursor: &mut TreeCursor<'a>) -> Result<Term, ParseError> {
-    if !cursor.goto_first_child() {
-        return Err(ParseError::msg("abstraction should have children"));
+/// Parse a sequence of `name = term;` definitions followed by a main term.
+pub fn parse_program(source: impl AsRef<[u8]>) -> Result<Program, ParseError> {
+    let (program, errors) = parse_program_recovering(source);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(program),
     }
-    let bound = walk_identifier(source, cursor)?;
-    if !cursor.goto_next_sibling() {
-        return Err(ParseError::msg("abstraction should have body"));
-    }
-    let body = Box::new(walk_term(source, cursor)?);
-    assert!(cursor.goto_parent());
-    Ok(Term::Abstraction(bound, body))
 }
 
-fn advance<'a>(cursor: &mut TreeCursor<'a>) -> Result<Option<Node<'a>>, ParseError> {
-    while cursor.node().is_extra() || !cursor.node().is_named() {
-        if !cursor.goto_next_sibling() {
-            return Ok(None);
+/// Parse a program even if it has syntax errors, returning every error in the source.
+///
+/// A definition missing its `=` is still parsed, and one missing its name is dropped. Broken
+/// subterms are replaced by holes as in [`parse_recovering`].
+pub fn parse_program_recovering(source: impl AsRef<[u8]>) -> (Program, Vec<ParseError>) {
+    let source = source.as_ref();
+    let Ok(text) = std::str::from_utf8(source) else {
+        let (main, errors) = parse_recovering(source);
+        return (Program::from(main), errors);
+    };
+    // Tokens the lexer can't make sense of are reported when the part they are in is parsed.
+    let (tokens, _) = lex_recovering(text);
+    let mut definitions = Vec::new();
+    let mut errors = Vec::new();
+    let term_at = |start: usize, end: usize, errors: &mut Vec<ParseError>| {
+        let (term, errs) = parse_recovering(&source[start..end]);
+        errors.extend(errs.into_iter().map(|e| e.shift(source, start)));
+        term
+    };
+    let mut depth = 0usize;
+    let mut part = 0;
+    let mut from = 0;
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth = depth.saturating_sub(1),
+            // Only a `;` outside parentheses ends a definition.
+            Token::Semicolon if depth == 0 => {
+                let definition = match &tokens[part..i] {
+                    [(Token::Ident(name), _), (Token::Equals, eq), ..] => {
+                        Some((name, term_at(eq.end, span.start, &mut errors)))
+                    }
+                    [(Token::Ident(name), x), rest @ ..] => {
+                        let found = rest.first().map_or(*span, |(_, s)| *s);
+                        let message = format!("expected `=` after definition name {:?}", name);
+                        errors.push(ParseError::Syntax(message, found));
+                        Some((name, term_at(x.end, span.start, &mut errors)))
+                    }
+                    [(token, found), ..] => {
+                        let message = format!("expected definition name but found {}", token);
+                        errors.push(ParseError::Syntax(message, *found));
+                        None
+                    }
+                    [] => {
+                        errors.push(ParseError::Syntax(
+                            "definition should have the form `name = term`".into(),
+                            Span::new(source, from, span.end),
+                        ));
+                        None
+                    }
+                };
+                definitions.extend(definition.map(|(name, term)| (name.clone(), term)));
+                part = i + 1;
+                from = span.end;
+            }
+            _ => {}
         }
     }
-    let node = cursor.node();
-    if node.is_error() {
-        Err(ParseError::msg(format!("parse error: {}", node.to_sexp())))
-    } else {
-        Ok(Some(node))
-    }
+    let main = term_at(from, source.len(), &mut errors);
+    (Program { definitions, main }, errors)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{parse, Term};
+    use super::{
+        parse, parse_program, parse_program_recovering, parse_recovering, parse_with_spans,
+        ParseError, Program, Term,
+    };
+    use crate::trace::Subterms;
     use proptest::prelude::*;
 
     proptest! {
@@ -149,9 +207,124 @@ mod tests {
             prop_assert_eq!(f2, f);
         }
 
+        // The tree-sitter grammar only has the Unicode spellings.
+        #[test]
+        #[cfg(not(feature = "tree-sitter"))]
+        fn ascii_output_parses_correctly(f in any::<Term>()) {
+            let s = format!("{:#}", f);
+            let f2 = parse(s).expect("printed output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
+        #[test]
+        fn pretty_output_parses_correctly(f in any::<Term>(), width in 1usize..80) {
+            let s = crate::pretty::Pretty::pretty(&f, width);
+            let f2 = parse(s).expect("pretty output should be parseable");
+            prop_assert_eq!(f2, f);
+        }
+
         #[test]
         fn parser_doesnt_crash(s in "\\PC*") {
             let _ = parse(s);
         }
+
+        #[test]
+        fn recovering_agrees_with_parse(s in "\\PC*") {
+            let (term, errors) = parse_recovering(&s);
+            match parse(&s) {
+                Ok(t) => {
+                    prop_assert!(errors.is_empty());
+                    prop_assert_eq!(term, t);
+                }
+                Err(_) => prop_assert!(!errors.is_empty()),
+            }
+        }
+
+        #[test]
+        fn spans_cover_printed_subterms(f in any::<Term>()) {
+            let s = format!("{}", f);
+            let (_, spans) = parse_with_spans(&s).expect("printed output should be parseable");
+            let whole = spans.get(&[]).expect("root should have a span");
+            prop_assert_eq!((whole.start, whole.end), (0, s.len()));
+        }
+
+        #[test]
+        fn redex_paths_agree_with_spans(f in any::<Term>()) {
+            for step in f.reduction_trace().take(8) {
+                let Some(redex) = step.redex else { break };
+                let s = format!("{}", step.term);
+                let (_, spans) = parse_with_spans(&s).expect("printed output should be parseable");
+                let span = spans.get(&redex.path).expect("redex should have a span");
+                let printed = parse(&s[span.start..span.end]).expect("span should be a term");
+                prop_assert_eq!(Some(&printed), step.term.subterm(&redex.path));
+            }
+        }
+
+        #[test]
+        fn printed_program_parses_correctly(p in any::<Program>()) {
+            let s = format!("{}", p);
+            let p2 = parse_program(s).expect("printed output should be parseable");
+            prop_assert_eq!(p2, p);
+        }
+    }
+
+    #[test]
+    fn application_spans() {
+        let (_, spans) = parse_with_spans("f (λx. x) b").unwrap();
+        let text = |path: &[usize]| {
+            let span = spans.get(path).expect("subterm should have a span");
+            &"f (λx. x) b"[span.start..span.end]
+        };
+        assert_eq!(text(&[0]), "f (λx. x)");
+        assert_eq!(text(&[0, 0]), "f");
+        assert_eq!(text(&[0, 1, 0]), "x");
+        assert_eq!(text(&[1]), "b");
+    }
+
+    #[test]
+    fn error_locations() {
+        let err = parse("λx. x )").unwrap_err();
+        let span = err.span().expect("syntax errors should have a location");
+        assert_eq!(span.start_position.line, 0);
+        assert!(err.render("λx. x )").contains('^'));
+    }
+
+    #[test]
+    fn program_error_locations() {
+        let source = "id = λx. x;\nbad = λ;\nid";
+        let Err(ParseError::Syntax(_, span)) = parse_program(source) else {
+            panic!("expected a syntax error")
+        };
+        assert_eq!(span.start_position.line, 1);
+    }
+
+    #[test]
+    fn program_reports_every_bad_definition() {
+        let source = "1x = a;\nid = λx. x;\nno equals;\nid";
+        let (program, errors) = parse_program_recovering(source);
+        let lines: Vec<_> = errors
+            .iter()
+            .filter_map(|e| e.span())
+            .map(|s| s.start_position.line)
+            .collect();
+        assert_eq!(lines, vec![0, 2]);
+        let names: Vec<_> = program
+            .definitions
+            .iter()
+            .map(|(x, _)| x.as_str())
+            .collect();
+        assert_eq!(names, ["id", "no"]);
+    }
+
+    #[test]
+    fn only_top_level_semicolons_end_definitions() {
+        let source = "k = λx. λy. (x; y);\nk";
+        let (program, errors) = parse_program_recovering(source);
+        assert_eq!(program.definitions.len(), 1);
+        let [ParseError::Syntax(_, span)] = errors.as_slice() else {
+            panic!("expected one syntax error")
+        };
+        assert_eq!(&source[span.start..span.end], ";");
+        assert_eq!(span.start, 16);
     }
 }
@@ -0,0 +1,228 @@
+use super::*;
+use crate::notation::Notation;
+
+/// A sequence of named definitions followed by a main term.
+///
+/// Each definition may refer to the definitions before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub definitions: Vec<(String, Term)>,
+    pub main: Term,
+}
+
+/// How to turn the definitions of a program into a single term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Elaboration {
+    /// Substitute each definition into the terms that use it.
+    Substitution,
+    /// Bind each definition with an applied abstraction, `(λname. rest) definition`.
+    Application,
+}
+
+impl Program {
+    pub fn elaborate(&self, how: Elaboration) -> Term {
+        self.elaborate_into(self.main.clone(), how)
+    }
+
+    /// Elaborate the definitions around the given term instead of the main term.
+    fn elaborate_into(&self, body: Term, how: Elaboration) -> Term {
+        self.definitions
+            .iter()
+            .rev()
+            .fold(body, |t, (x, b)| match how {
+                Elaboration::Substitution => t.substitute(x, b),
+                Elaboration::Application => Term::Application(
+                    Box::new(Term::Abstraction(x.to_owned(), Box::new(t))),
+                    Box::new(b.clone()),
+                ),
+            })
+    }
+
+    /// Elaborate the program, keeping the main term's outer abstraction outermost
+    /// so that it can be compiled with `compile::compile_toplevel`.
+    pub fn elaborate_toplevel(&self, how: Elaboration) -> Option<Term> {
+        let Term::Abstraction(v, b) = &self.main else {
+            return None;
+        };
+        // The binder would capture the definitions' free variables, and be captured by a
+        // definition of the same name.
+        let mut captured: HashSet<String> = self
+            .definitions
+            .iter()
+            .flat_map(|(x, d)| d.free_vars().into_iter().chain([x.to_owned()]))
+            .collect();
+        let v2 = if captured.contains(v) {
+            captured.extend(self.main.vars());
+            fresh_var(&captured)
+        } else {
+            v.to_owned()
+        };
+        let body = b.substitute(v, &Term::Variable(v2.clone()));
+        Some(Term::Abstraction(
+            v2,
+            Box::new(self.elaborate_into(body, how)),
+        ))
+    }
+}
+
+impl From<Term> for Program {
+    fn from(main: Term) -> Self {
+        Self {
+            definitions: Vec::new(),
+            main,
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = Notation::of(f);
+        for (x, t) in &self.definitions {
+            writeln!(f, "{} = {};", x, n.show(t))?;
+        }
+        self.main.fmt(f)
+    }
+}
+
+#[cfg(test)]
+impl Arbitrary for Program {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (
+            prop::collection::vec((crate::arb_var(), any::<Term>()), 0..4),
+            any::<Term>(),
+        )
+            .prop_map(|(definitions, main)| Program { definitions, main })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn church_program() -> Program {
+        let two = abstraction(
+            "f",
+            abstraction(
+                "x",
+                application(variable("f"), application(variable("f"), variable("x"))),
+            ),
+        );
+        let add = abstraction(
+            "m",
+            abstraction(
+                "n",
+                abstraction(
+                    "f",
+                    abstraction(
+                        "x",
+                        application(
+                            application(variable("m"), variable("f")),
+                            application(application(variable("n"), variable("f")), variable("x")),
+                        ),
+                    ),
+                ),
+            ),
+        );
+        let four = abstraction(
+            "y",
+            application(
+                application(variable("add"), variable("two")),
+                variable("two"),
+            ),
+        );
+        Program {
+            definitions: vec![("two".into(), two), ("add".into(), add)],
+            main: four,
+        }
+    }
+
+    fn four() -> Term {
+        abstraction(
+            "y",
+            abstraction(
+                "f",
+                abstraction(
+                    "x",
+                    application(
+                        variable("f"),
+                        application(
+                            variable("f"),
+                            application(variable("f"), application(variable("f"), variable("x"))),
+                        ),
+                    ),
+                ),
+            ),
+        )
+    }
+
+    #[test]
+    fn elaborations_agree() {
+        let program = church_program();
+        assert_eq!(
+            program.elaborate(Elaboration::Substitution).evaluate(),
+            four()
+        );
+        assert_eq!(
+            program.elaborate(Elaboration::Application).evaluate(),
+            four()
+        );
+        assert_eq!(
+            program
+                .elaborate_toplevel(Elaboration::Application)
+                .unwrap()
+                .evaluate(),
+            four()
+        );
+    }
+
+    #[test]
+    fn later_definitions_shadow_earlier_ones() {
+        let program = Program {
+            definitions: vec![
+                ("x".into(), variable("a")),
+                ("x".into(), application(variable("x"), variable("x"))),
+            ],
+            main: variable("x"),
+        };
+        let expected = application(variable("a"), variable("a"));
+        assert_eq!(program.elaborate(Elaboration::Substitution), expected);
+        assert_eq!(
+            program.elaborate(Elaboration::Application).evaluate(),
+            expected
+        );
+    }
+
+    #[test]
+    fn toplevel_binder_is_not_captured() {
+        let program = Program {
+            definitions: vec![("k".into(), variable("v"))],
+            main: abstraction("v", application(variable("k"), variable("v"))),
+        };
+        let Term::Abstraction(v, b) = program
+            .elaborate_toplevel(Elaboration::Substitution)
+            .unwrap()
+        else {
+            panic!("toplevel should be abstraction")
+        };
+        assert_ne!(v, "v");
+        assert_eq!(*b, application(variable("v"), variable(v.as_str())));
+    }
+
+    #[test]
+    fn toplevel_binder_is_not_shadowed() {
+        let program = Program {
+            definitions: vec![("v".into(), variable("a"))],
+            main: abstraction("v", variable("v")),
+        };
+        let toplevel = program.elaborate_toplevel(Elaboration::Substitution);
+        assert_eq!(toplevel, Some(program.elaborate(Elaboration::Substitution)));
+        let applied = program
+            .elaborate_toplevel(Elaboration::Application)
+            .unwrap();
+        assert_eq!(applied.evaluate(), abstraction("x0", variable("x0")));
+    }
+}